@@ -0,0 +1,433 @@
+//! Portfolio balances of a set of addresses.
+//!
+//! The live cells of every address are read in one pass, by a
+//! [`CellCollector`] or the indexer `get_cells`, and every cell is counted in
+//! exactly one capacity category:
+//!   * immature: the cellbase cells not mature yet (by [`is_mature`])
+//!   * time locked: the multisig (with since args) and Omnilock time lock
+//!     cells not unlockable at the tip
+//!   * DAO deposited and DAO prepared (phase 1 of withdrawing)
+//!   * occupied: the cells with a type script or data, e.g. the UDT cells
+//!   * free: the plain cells
+//!
+//! The sUDT/xUDT amounts are counted by the token type script hash, no matter
+//! which capacity category the cell is in.
+
+use std::collections::HashMap;
+
+use ckb_types::{core::EpochNumberWithFraction, packed::Script, prelude::*, H256};
+use thiserror::Error;
+
+use crate::{
+    constants::{DAO_TYPE_HASH, MULTISIG_TYPE_HASH},
+    rpc::{
+        ckb_indexer::{ScriptType, SearchKey},
+        pagination::PageOptions,
+        CkbRpcClient, IndexerRpcClient, RpcError,
+    },
+    traits::{CellCollector, CellCollectorError, CellQueryOptions, LiveCell, MaturityOption},
+    types::{Since, SinceType},
+    unlock::omni_lock::OmniLockFlags,
+    util::{get_max_mature_number, is_mature},
+    Address, ScriptId,
+};
+
+#[derive(Error, Debug)]
+pub enum BalanceError {
+    #[error("rpc error: `{0}`")]
+    Rpc(#[from] RpcError),
+
+    #[error(transparent)]
+    CellCollector(#[from] CellCollectorError),
+
+    #[error("other error: `{0}`")]
+    Other(#[from] anyhow::Error),
+}
+
+/// The chain state to check the maturity and the time locks.
+#[derive(Clone, Debug)]
+pub struct ChainState {
+    pub tip_number: u64,
+    pub tip_epoch: EpochNumberWithFraction,
+    /// The tip block timestamp in milliseconds
+    pub tip_timestamp: u64,
+    pub max_mature_number: u64,
+}
+
+impl ChainState {
+    pub fn from_rpc(ckb_client: &CkbRpcClient) -> Result<ChainState, BalanceError> {
+        let tip = ckb_client.get_tip_header()?;
+        let max_mature_number =
+            get_max_mature_number(ckb_client).map_err(|err| anyhow::anyhow!(err))?;
+        Ok(ChainState {
+            tip_number: tip.inner.number.value(),
+            tip_epoch: EpochNumberWithFraction::from_full_value(tip.inner.epoch.value()),
+            tip_timestamp: tip.inner.timestamp.value(),
+            max_mature_number,
+        })
+    }
+
+    /// Check the since of a time lock, the relative since is checked by the
+    /// block number only, the relative epoch or timestamp is regarded as not
+    /// satisfied since the header of the cell is unknown.
+    pub fn is_since_satisfied(&self, since: Since, cell_block_number: u64) -> bool {
+        if since.value() == 0 {
+            return true;
+        }
+        let (ty, value) = match since.extract_metric() {
+            Some(metric) if since.flags_is_valid() => metric,
+            _ => return false,
+        };
+        match (since.is_relative(), ty) {
+            (false, SinceType::BlockNumber) => self.tip_number >= value,
+            (false, SinceType::EpochNumberWithFraction) => {
+                let epoch = EpochNumberWithFraction::from_full_value(value);
+                self.tip_epoch.to_rational() >= epoch.to_rational()
+            }
+            // The median time of the past blocks is not later than the tip
+            // timestamp, the since value is in seconds
+            (false, SinceType::Timestamp) => self.tip_timestamp / 1000 >= value,
+            (true, SinceType::BlockNumber) => {
+                self.tip_number >= cell_block_number.saturating_add(value)
+            }
+            (true, _) => false,
+        }
+    }
+}
+
+/// The scripts to recognize the cells, `None` to not recognize.
+#[derive(Clone, Debug, Default)]
+pub struct BalanceConfig {
+    pub sudt_script_id: Option<ScriptId>,
+    pub xudt_script_id: Option<ScriptId>,
+    /// For the Omnilock time lock
+    pub omnilock_script_id: Option<ScriptId>,
+}
+
+/// The amount of a UDT.
+#[derive(Clone, Debug)]
+pub struct UdtBalance {
+    pub type_script: Script,
+    pub amount: u128,
+}
+
+/// The balance of an address or the sum of the addresses, the capacities are
+/// in shannons.
+#[derive(Clone, Debug, Default)]
+pub struct Balance {
+    pub free: u64,
+    pub occupied: u64,
+    pub immature: u64,
+    pub time_locked: u64,
+    pub dao_deposited: u64,
+    pub dao_prepared: u64,
+    /// By the type script hash
+    pub udt: HashMap<H256, UdtBalance>,
+    pub cell_count: u64,
+}
+
+impl Balance {
+    /// The total capacity.
+    pub fn total(&self) -> u64 {
+        self.free
+            + self.occupied
+            + self.immature
+            + self.time_locked
+            + self.dao_deposited
+            + self.dao_prepared
+    }
+
+    pub fn merge(&mut self, other: &Balance) {
+        self.free += other.free;
+        self.occupied += other.occupied;
+        self.immature += other.immature;
+        self.time_locked += other.time_locked;
+        self.dao_deposited += other.dao_deposited;
+        self.dao_prepared += other.dao_prepared;
+        self.cell_count += other.cell_count;
+        for (type_hash, udt) in &other.udt {
+            let balance = self
+                .udt
+                .entry(type_hash.clone())
+                .or_insert_with(|| UdtBalance {
+                    type_script: udt.type_script.clone(),
+                    amount: 0,
+                });
+            balance.amount = balance.amount.saturating_add(udt.amount);
+        }
+    }
+
+    /// Count a live cell.
+    pub fn add_cell(&mut self, cell: &LiveCell, config: &BalanceConfig, chain: &ChainState) {
+        let capacity: u64 = cell.output.capacity().unpack();
+        let type_script = cell.output.type_().to_opt();
+        let type_id = type_script.as_ref().map(ScriptId::from);
+        self.cell_count += 1;
+
+        if let (Some(type_script), Some(type_id)) = (type_script.as_ref(), type_id.as_ref()) {
+            let is_udt = Some(type_id) == config.sudt_script_id.as_ref()
+                || Some(type_id) == config.xudt_script_id.as_ref();
+            if is_udt && cell.output_data.len() >= 16 {
+                let mut amount_bytes = [0u8; 16];
+                amount_bytes.copy_from_slice(&cell.output_data[0..16]);
+                let balance = self
+                    .udt
+                    .entry(type_script.calc_script_hash().unpack())
+                    .or_insert_with(|| UdtBalance {
+                        type_script: type_script.clone(),
+                        amount: 0,
+                    });
+                balance.amount = balance
+                    .amount
+                    .saturating_add(u128::from_le_bytes(amount_bytes));
+            }
+        }
+
+        let is_dao = type_id
+            .as_ref()
+            .map(|type_id| type_id == &ScriptId::new_type(DAO_TYPE_HASH.clone()))
+            .unwrap_or(false);
+        if !is_mature(cell, chain.max_mature_number) {
+            self.immature += capacity;
+        } else if lock_since(&cell.output.lock(), config)
+            .map(|since| !chain.is_since_satisfied(since, cell.block_number))
+            .unwrap_or(false)
+        {
+            self.time_locked += capacity;
+        } else if is_dao {
+            if cell.output_data.iter().all(|byte| *byte == 0) {
+                self.dao_deposited += capacity;
+            } else {
+                self.dao_prepared += capacity;
+            }
+        } else if type_id.is_some() || !cell.output_data.is_empty() {
+            self.occupied += capacity;
+        } else {
+            self.free += capacity;
+        }
+    }
+}
+
+/// The since of a time lock in the lock script args.
+fn lock_since(lock: &Script, config: &BalanceConfig) -> Option<Since> {
+    let args = lock.args().raw_data();
+    let lock_id = ScriptId::from(lock);
+    let since_at = |offset: usize| {
+        let mut since_bytes = [0u8; 8];
+        since_bytes.copy_from_slice(args.get(offset..offset + 8)?);
+        Some(Since::from_raw_value(u64::from_le_bytes(since_bytes)))
+    };
+    if lock_id == ScriptId::new_type(MULTISIG_TYPE_HASH.clone()) {
+        // blake160(multisig script) | since
+        if args.len() == 28 {
+            return since_at(20);
+        }
+    } else if Some(&lock_id) == config.omnilock_script_id.as_ref() {
+        let flags = OmniLockFlags::from_bits_truncate(*args.get(21)?);
+        if flags.contains(OmniLockFlags::TIME_LOCK) {
+            let mut offset = 22;
+            if flags.contains(OmniLockFlags::ADMIN) {
+                offset += 32;
+            }
+            if flags.contains(OmniLockFlags::ACP) {
+                offset += 2;
+            }
+            return since_at(offset);
+        }
+    }
+    None
+}
+
+/// The balances of a set of addresses.
+#[derive(Clone, Debug, Default)]
+pub struct Portfolio {
+    pub balances: Vec<(Address, Balance)>,
+    pub total: Balance,
+}
+
+impl Portfolio {
+    fn push(&mut self, address: Address, balance: Balance) {
+        self.total.merge(&balance);
+        self.balances.push((address, balance));
+    }
+
+    /// Read the balances by a cell collector, the offchain locked cells of
+    /// the collector are not counted.
+    pub fn collect(
+        collector: &mut dyn CellCollector,
+        addresses: &[Address],
+        config: &BalanceConfig,
+        chain: &ChainState,
+    ) -> Result<Portfolio, BalanceError> {
+        let mut portfolio = Portfolio::default();
+        for address in addresses {
+            let mut query = CellQueryOptions::new_lock(Script::from(address));
+            query.maturity = MaturityOption::Both;
+            query.min_total_capacity = u64::MAX;
+            let (cells, _) = collector.collect_live_cells(&query, false)?;
+            let mut balance = Balance::default();
+            for cell in &cells {
+                balance.add_cell(cell, config, chain);
+            }
+            portfolio.push(address.clone(), balance);
+        }
+        Ok(portfolio)
+    }
+
+    /// Read the balances by the indexer.
+    pub fn from_indexer(
+        indexer: &IndexerRpcClient,
+        addresses: &[Address],
+        config: &BalanceConfig,
+        chain: &ChainState,
+        options: &PageOptions,
+    ) -> Result<Portfolio, BalanceError> {
+        let mut portfolio = Portfolio::default();
+        for address in addresses {
+            let search_key = SearchKey {
+                script: Script::from(address).into(),
+                script_type: ScriptType::Lock,
+                script_search_mode: None,
+                filter: None,
+                with_data: Some(true),
+                group_by_transaction: None,
+            };
+            let mut balance = Balance::default();
+            for cell in indexer.cells_iter(search_key, options) {
+                balance.add_cell(&LiveCell::from(cell?), config, chain);
+            }
+            portfolio.push(address.clone(), balance);
+        }
+        Ok(portfolio)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ckb_types::{
+        bytes::Bytes,
+        core::ScriptHashType,
+        packed::{CellOutput, OutPoint},
+    };
+
+    fn script(code_hash: &H256, args: Vec<u8>) -> Script {
+        Script::new_builder()
+            .code_hash(code_hash.pack())
+            .hash_type(ScriptHashType::Type.into())
+            .args(Bytes::from(args).pack())
+            .build()
+    }
+
+    fn cell(
+        lock: &Script,
+        capacity: u64,
+        type_script: Option<Script>,
+        data: Vec<u8>,
+        block_number: u64,
+        tx_index: u32,
+    ) -> LiveCell {
+        LiveCell {
+            output: CellOutput::new_builder()
+                .lock(lock.clone())
+                .type_(type_script.pack())
+                .capacity(capacity.pack())
+                .build(),
+            output_data: Bytes::from(data),
+            out_point: OutPoint::default(),
+            block_number,
+            tx_index,
+        }
+    }
+
+    #[test]
+    fn test_balance() {
+        let sudt_id = ScriptId::new_type(H256([2u8; 32]));
+        let config = BalanceConfig {
+            sudt_script_id: Some(sudt_id.clone()),
+            ..Default::default()
+        };
+        let chain = ChainState {
+            tip_number: 1000,
+            tip_epoch: EpochNumberWithFraction::new(10, 0, 1),
+            tip_timestamp: 1_600_000_000_000,
+            max_mature_number: 900,
+        };
+        let lock = script(&H256([1u8; 32]), vec![1u8; 20]);
+        let token = script(&sudt_id.code_hash, vec![3u8; 32]);
+        let dao = script(&DAO_TYPE_HASH, Vec::new());
+        let multisig = |since: Since| {
+            let mut args = vec![4u8; 20];
+            args.extend_from_slice(&since.value().to_le_bytes());
+            script(&MULTISIG_TYPE_HASH, args)
+        };
+
+        let cells = vec![
+            cell(&lock, 100, None, Vec::new(), 10, 1),
+            // immature cellbase
+            cell(&lock, 200, None, Vec::new(), 950, 0),
+            cell(&lock, 300, Some(dao.clone()), vec![0u8; 8], 10, 1),
+            cell(&lock, 400, Some(dao), vec![1u8; 8], 10, 1),
+            cell(
+                &lock,
+                142,
+                Some(token.clone()),
+                7u128.to_le_bytes().to_vec(),
+                10,
+                1,
+            ),
+            cell(
+                &lock,
+                142,
+                Some(token.clone()),
+                8u128.to_le_bytes().to_vec(),
+                10,
+                1,
+            ),
+            // locked until epoch 20, unlocked at block 999
+            cell(
+                &multisig(Since::new_absolute_epoch(20)),
+                500,
+                None,
+                Vec::new(),
+                10,
+                1,
+            ),
+            cell(
+                &multisig(Since::new(SinceType::BlockNumber, 999, false)),
+                600,
+                None,
+                Vec::new(),
+                10,
+                1,
+            ),
+            cell(
+                &multisig(Since::new(SinceType::BlockNumber, 100, true)),
+                700,
+                None,
+                Vec::new(),
+                950,
+                1,
+            ),
+        ];
+        let mut balance = Balance::default();
+        for cell in &cells {
+            balance.add_cell(cell, &config, &chain);
+        }
+        assert_eq!(balance.free, 100 + 600);
+        assert_eq!(balance.immature, 200);
+        assert_eq!(balance.dao_deposited, 300);
+        assert_eq!(balance.dao_prepared, 400);
+        assert_eq!(balance.occupied, 284);
+        assert_eq!(balance.time_locked, 500 + 700);
+        assert_eq!(balance.total(), 3084);
+        let token_hash: H256 = token.calc_script_hash().unpack();
+        assert_eq!(balance.udt[&token_hash].amount, 15);
+
+        let mut total = Balance::default();
+        total.merge(&balance);
+        total.merge(&balance);
+        assert_eq!(total.udt[&token_hash].amount, 30);
+        assert_eq!(total.cell_count, 18);
+    }
+}
@@ -0,0 +1,572 @@
+//! Transaction history of a set of lock scripts, e.g. a wallet activity list.
+//!
+//! The transactions are listed by the indexer `get_transactions` (grouped by
+//! transaction), merged across the lock scripts in the order of the chain,
+//! and decoded into [`TxRecord`]s with the net balance changes of the locks.
+//!
+//! The input cells are resolved by the transactions creating them, so the
+//! [`TransactionDependencyProvider`] must be able to get committed
+//! transactions (the live cell lookup is not enough for the spent cells).
+
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::iter::Peekable;
+
+use ckb_types::{
+    bytes::Bytes,
+    core::TransactionView,
+    packed::{CellOutput, Script},
+    prelude::*,
+    H256,
+};
+use thiserror::Error;
+
+use crate::{
+    constants::DAO_TYPE_HASH,
+    rpc::{
+        ckb_indexer::{Order, ScriptType, SearchKey, Tx},
+        pagination::{PageIter, PageOptions},
+        CkbRpcClient, IndexerRpcClient, RpcError,
+    },
+    traits::{TransactionDependencyError, TransactionDependencyProvider},
+    Address, AddressPayload, NetworkType, ScriptId,
+};
+
+#[derive(Error, Debug)]
+pub enum HistoryError {
+    #[error("rpc error: `{0}`")]
+    Rpc(#[from] RpcError),
+
+    #[error("transaction dependency error: `{0}`")]
+    TxDep(#[from] TransactionDependencyError),
+
+    #[error("block not found: `{0}`")]
+    BlockNotFound(u64),
+}
+
+/// The classification of a transaction.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
+pub enum TxKind {
+    /// The mining reward
+    Cellbase,
+    Transfer,
+    DaoDeposit,
+    /// The phase 1 of withdrawing
+    DaoPrepare,
+    /// The phase 2 of withdrawing
+    DaoWithdraw,
+    /// Create cheque cells
+    ChequeCreate,
+    /// Claim cheque cells by the receiver
+    ChequeClaim,
+    /// Withdraw cheque cells by the sender
+    ChequeWithdraw,
+}
+
+/// A transaction of the lock scripts.
+#[derive(Clone, Debug)]
+pub struct TxRecord {
+    pub tx_hash: H256,
+    pub block_number: u64,
+    pub block_hash: H256,
+    /// The block timestamp in milliseconds
+    pub timestamp: u64,
+    pub tx_index: u32,
+    pub kind: TxKind,
+    /// The net capacity change of the locks
+    pub capacity_delta: i128,
+    /// The net sUDT changes of the locks, by type script hash, the amounts
+    /// over `i128::MAX` are saturated
+    pub udt_deltas: HashMap<H256, i128>,
+    /// The fee, available only if all the inputs are of the locks
+    pub fee: Option<u64>,
+    /// The other locks in the inputs and outputs
+    pub counterparties: Vec<Address>,
+}
+
+/// The settings to decode the transactions.
+#[derive(Clone, Debug)]
+pub struct HistoryConfig {
+    pub network: NetworkType,
+    /// The sUDT script used to decode the sUDT amount
+    pub sudt_script_id: Option<ScriptId>,
+    /// The cheque lock script used to classify cheque transactions
+    pub cheque_script_id: Option<ScriptId>,
+}
+
+impl HistoryConfig {
+    pub fn new(network: NetworkType) -> HistoryConfig {
+        HistoryConfig {
+            network,
+            sudt_script_id: None,
+            cheque_script_id: None,
+        }
+    }
+}
+
+/// The block of a transaction.
+#[derive(Clone, Debug)]
+pub struct TxPosition {
+    pub block_number: u64,
+    pub block_hash: H256,
+    pub timestamp: u64,
+    pub tx_index: u32,
+}
+
+fn saturating_i128(value: u128) -> i128 {
+    i128::try_from(value).unwrap_or(i128::MAX)
+}
+
+/// Decode a transaction of the `locks`, `inputs` are the resolved input cells
+/// (empty for cellbase).
+pub fn decode_transaction(
+    config: &HistoryConfig,
+    locks: &[Script],
+    tx: &TransactionView,
+    inputs: &[(CellOutput, Bytes)],
+    position: TxPosition,
+) -> TxRecord {
+    let lock_hashes: HashSet<H256> = locks
+        .iter()
+        .map(|lock| lock.calc_script_hash().unpack())
+        .collect();
+    let is_own = |output: &CellOutput| {
+        let lock_hash: H256 = output.lock().calc_script_hash().unpack();
+        lock_hashes.contains(&lock_hash)
+    };
+    let dao_script_id = ScriptId::new_type(DAO_TYPE_HASH.clone());
+    let outputs: Vec<(CellOutput, Bytes)> = tx.outputs_with_data_iter().collect();
+
+    let mut capacity_delta: i128 = 0;
+    let mut udt_deltas: HashMap<H256, i128> = HashMap::default();
+    let mut counterparties: Vec<Address> = Vec::new();
+    for (is_input, (output, data)) in inputs
+        .iter()
+        .map(|cell| (true, cell))
+        .chain(outputs.iter().map(|cell| (false, cell)))
+    {
+        if !is_own(output) {
+            let address = Address::new(config.network, AddressPayload::from(output.lock()), true);
+            if !counterparties.contains(&address) {
+                counterparties.push(address);
+            }
+            continue;
+        }
+        let capacity: u64 = output.capacity().unpack();
+        let sign = if is_input { -1 } else { 1 };
+        capacity_delta += sign * i128::from(capacity);
+        if let Some(type_script) = output.type_().to_opt() {
+            if config.sudt_script_id.as_ref() == Some(&ScriptId::from(&type_script))
+                && data.len() >= 16
+            {
+                let mut amount_bytes = [0u8; 16];
+                amount_bytes.copy_from_slice(&data[0..16]);
+                let amount = saturating_i128(u128::from_le_bytes(amount_bytes));
+                let delta = udt_deltas
+                    .entry(type_script.calc_script_hash().unpack())
+                    .or_default();
+                *delta = delta.saturating_add(sign * amount);
+            }
+        }
+    }
+    udt_deltas.retain(|_, delta| *delta != 0);
+
+    let fee = if !inputs.is_empty() && inputs.iter().all(|(output, _)| is_own(output)) {
+        let input_capacity: u64 = inputs
+            .iter()
+            .map(|(output, _)| Unpack::<u64>::unpack(&output.capacity()))
+            .sum();
+        let output_capacity: u64 = outputs
+            .iter()
+            .map(|(output, _)| Unpack::<u64>::unpack(&output.capacity()))
+            .sum();
+        input_capacity.checked_sub(output_capacity)
+    } else {
+        None
+    };
+
+    let is_dao = |output: &CellOutput| {
+        output
+            .type_()
+            .to_opt()
+            .map(|type_script| ScriptId::from(&type_script))
+            .as_ref()
+            == Some(&dao_script_id)
+    };
+    let is_cheque = |output: &CellOutput| {
+        config.cheque_script_id.as_ref() == Some(&ScriptId::from(&output.lock()))
+    };
+    let cheque_input = inputs
+        .iter()
+        .find(|(output, _)| is_cheque(output))
+        .map(|(output, _)| output.lock().args().raw_data());
+    let kind = if position.tx_index == 0 {
+        TxKind::Cellbase
+    } else if let Some(args) = cheque_input {
+        // The cheque args: receiver lock hash[0..20] | sender lock hash[0..20],
+        // the receiver provides an input to claim
+        let receiver_input = args.len() == 40
+            && inputs.iter().any(|(output, _)| {
+                output.lock().calc_script_hash().as_slice()[0..20] == args[0..20]
+            });
+        if receiver_input {
+            TxKind::ChequeClaim
+        } else {
+            TxKind::ChequeWithdraw
+        }
+    } else if outputs.iter().any(|(output, _)| is_cheque(output)) {
+        TxKind::ChequeCreate
+    } else if let Some((_, data)) = outputs.iter().find(|(output, _)| is_dao(output)) {
+        if data.iter().all(|byte| *byte == 0) {
+            TxKind::DaoDeposit
+        } else {
+            TxKind::DaoPrepare
+        }
+    } else if inputs.iter().any(|(output, _)| is_dao(output)) {
+        TxKind::DaoWithdraw
+    } else {
+        TxKind::Transfer
+    };
+
+    TxRecord {
+        tx_hash: tx.hash().unpack(),
+        block_number: position.block_number,
+        block_hash: position.block_hash,
+        timestamp: position.timestamp,
+        tx_index: position.tx_index,
+        kind,
+        capacity_delta,
+        udt_deltas,
+        // The withdrawn DAO cells have the interest, the fee is unknown
+        fee: if kind == TxKind::DaoWithdraw {
+            None
+        } else {
+            fee
+        },
+        counterparties,
+    }
+}
+
+/// The transaction history of a set of lock scripts.
+pub struct TxHistory<'a> {
+    indexer: &'a IndexerRpcClient,
+    ckb_client: &'a CkbRpcClient,
+    tx_dep_provider: &'a dyn TransactionDependencyProvider,
+    config: HistoryConfig,
+    locks: Vec<Script>,
+}
+
+impl<'a> TxHistory<'a> {
+    pub fn new(
+        indexer: &'a IndexerRpcClient,
+        ckb_client: &'a CkbRpcClient,
+        tx_dep_provider: &'a dyn TransactionDependencyProvider,
+        config: HistoryConfig,
+        locks: Vec<Script>,
+    ) -> TxHistory<'a> {
+        TxHistory {
+            indexer,
+            ckb_client,
+            tx_dep_provider,
+            config,
+            locks,
+        }
+    }
+
+    /// Iterate the transactions in the order, `options.after` is ignored
+    /// since every lock has its own cursor.
+    pub fn iter(&self, options: &PageOptions) -> HistoryIter<'_> {
+        let options = PageOptions {
+            after: None,
+            ..options.clone()
+        };
+        let pages = self
+            .locks
+            .iter()
+            .map(|lock| {
+                let search_key = SearchKey {
+                    script: lock.clone().into(),
+                    script_type: ScriptType::Lock,
+                    script_search_mode: None,
+                    filter: None,
+                    with_data: None,
+                    group_by_transaction: Some(true),
+                };
+                self.indexer
+                    .transactions_iter(search_key, &options)
+                    .peekable()
+            })
+            .collect();
+        HistoryIter {
+            history: self,
+            pages,
+            order: options.order,
+            last_tx_hash: None,
+            headers: HashMap::default(),
+        }
+    }
+
+    /// Fetch and decode a transaction.
+    pub fn get_record(
+        &self,
+        tx_hash: &H256,
+        block_number: u64,
+        tx_index: u32,
+    ) -> Result<TxRecord, HistoryError> {
+        let mut headers = HashMap::default();
+        self.fetch_record(tx_hash, block_number, tx_index, &mut headers)
+    }
+
+    fn fetch_record(
+        &self,
+        tx_hash: &H256,
+        block_number: u64,
+        tx_index: u32,
+        headers: &mut HashMap<u64, (H256, u64)>,
+    ) -> Result<TxRecord, HistoryError> {
+        let tx = self.tx_dep_provider.get_transaction(&tx_hash.pack())?;
+        let mut inputs = Vec::new();
+        if tx_index > 0 {
+            for out_point in tx.input_pts_iter() {
+                let index: u32 = out_point.index().unpack();
+                let prev_tx = self.tx_dep_provider.get_transaction(&out_point.tx_hash())?;
+                let cell = prev_tx.output_with_data(index as usize).ok_or_else(|| {
+                    TransactionDependencyError::NotFound(format!("cell: {}", out_point))
+                })?;
+                inputs.push(cell);
+            }
+        }
+        let (block_hash, timestamp) = match headers.get(&block_number) {
+            Some(header) => header.clone(),
+            None => {
+                let header = self
+                    .ckb_client
+                    .get_header_by_number(block_number.into())?
+                    .ok_or(HistoryError::BlockNotFound(block_number))?;
+                let header = (header.hash, header.inner.timestamp.value());
+                // Only the block of the last transaction is cached
+                headers.clear();
+                headers.insert(block_number, header.clone());
+                header
+            }
+        };
+        Ok(decode_transaction(
+            &self.config,
+            &self.locks,
+            &tx,
+            &inputs,
+            TxPosition {
+                block_number,
+                block_hash,
+                timestamp,
+                tx_index,
+            },
+        ))
+    }
+}
+
+/// An iterator over the transaction records, it stops after an error.
+pub struct HistoryIter<'a> {
+    history: &'a TxHistory<'a>,
+    pages: Vec<Peekable<PageIter<'a, Tx>>>,
+    order: Order,
+    last_tx_hash: Option<H256>,
+    headers: HashMap<u64, (H256, u64)>,
+}
+
+fn tx_position(tx: &Tx) -> (u64, u32) {
+    match tx {
+        Tx::Ungrouped(tx) => (tx.block_number.value(), tx.tx_index.value()),
+        Tx::Grouped(tx) => (tx.block_number.value(), tx.tx_index.value()),
+    }
+}
+
+impl<'a> Iterator for HistoryIter<'a> {
+    type Item = Result<TxRecord, HistoryError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            // Pick the next transaction across the locks
+            let mut next: Option<(usize, (u64, u32))> = None;
+            let mut failed = None;
+            for (idx, page) in self.pages.iter_mut().enumerate() {
+                let position = match page.peek() {
+                    Some(Ok(tx)) => tx_position(tx),
+                    Some(Err(_)) => {
+                        failed = Some(idx);
+                        break;
+                    }
+                    None => continue,
+                };
+                let is_next = match (&next, &self.order) {
+                    (None, _) => true,
+                    (Some((_, current)), Order::Asc) => position < *current,
+                    (Some((_, current)), Order::Desc) => position > *current,
+                };
+                if is_next {
+                    next = Some((idx, position));
+                }
+            }
+            if let Some(idx) = failed {
+                let err = self.pages[idx].next()?.err()?;
+                self.pages.clear();
+                return Some(Err(err.into()));
+            }
+            let (idx, (block_number, tx_index)) = next?;
+            let tx = self.pages[idx].next()?.ok()?;
+            let tx_hash = tx.tx_hash();
+            // The transaction of several locks is listed by each of them
+            if self.last_tx_hash.as_ref() == Some(&tx_hash) {
+                continue;
+            }
+            self.last_tx_hash = Some(tx_hash.clone());
+            let result =
+                self.history
+                    .fetch_record(&tx_hash, block_number, tx_index, &mut self.headers);
+            if result.is_err() {
+                self.pages.clear();
+            }
+            return Some(result);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ckb_types::{
+        core::{ScriptHashType, TransactionBuilder},
+        packed::CellInput,
+        packed::OutPoint,
+    };
+
+    fn lock(byte: u8) -> Script {
+        Script::new_builder()
+            .code_hash(H256([1u8; 32]).pack())
+            .hash_type(ScriptHashType::Type.into())
+            .args(Bytes::from(vec![byte; 20]).pack())
+            .build()
+    }
+
+    fn cell(
+        lock: &Script,
+        capacity: u64,
+        type_script: Option<Script>,
+        data: Bytes,
+    ) -> (CellOutput, Bytes) {
+        let output = CellOutput::new_builder()
+            .lock(lock.clone())
+            .type_(type_script.pack())
+            .capacity(capacity.pack())
+            .build();
+        (output, data)
+    }
+
+    fn build_tx(inputs: usize, outputs: &[(CellOutput, Bytes)]) -> TransactionView {
+        let mut builder = TransactionBuilder::default();
+        for idx in 0..inputs {
+            builder = builder.input(CellInput::new(
+                OutPoint::new(Default::default(), idx as u32),
+                0,
+            ));
+        }
+        for (output, data) in outputs {
+            builder = builder.output(output.clone()).output_data(data.pack());
+        }
+        builder.build()
+    }
+
+    fn position() -> TxPosition {
+        TxPosition {
+            block_number: 100,
+            block_hash: H256::default(),
+            timestamp: 0,
+            tx_index: 1,
+        }
+    }
+
+    #[test]
+    fn test_decode_transaction() {
+        let (alice, bob) = (lock(1), lock(2));
+        let sudt_id = ScriptId::new_type(H256([2u8; 32]));
+        let mut config = HistoryConfig::new(NetworkType::Testnet);
+        config.sudt_script_id = Some(sudt_id.clone());
+        let token = Script::new_builder()
+            .code_hash(sudt_id.code_hash.pack())
+            .hash_type(sudt_id.hash_type.into())
+            .build();
+        let amount = |value: u128| Bytes::from(value.to_le_bytes().to_vec());
+
+        // alice sends 100 CKB and 30 tokens to bob
+        let inputs = vec![
+            cell(&alice, 500, None, Bytes::new()),
+            cell(&alice, 300, Some(token.clone()), amount(50)),
+        ];
+        let outputs = vec![
+            cell(&bob, 100, None, Bytes::new()),
+            cell(&bob, 142, Some(token.clone()), amount(30)),
+            cell(&alice, 399, None, Bytes::new()),
+            cell(&alice, 142, Some(token.clone()), amount(20)),
+        ];
+        let tx = build_tx(inputs.len(), &outputs);
+        let record = decode_transaction(&config, &[alice.clone()], &tx, &inputs, position());
+        assert_eq!(record.kind, TxKind::Transfer);
+        assert_eq!(record.capacity_delta, 399 + 142 - 800);
+        assert_eq!(record.fee, Some(17));
+        let token_hash: H256 = token.calc_script_hash().unpack();
+        assert_eq!(record.udt_deltas.get(&token_hash), Some(&-30));
+        assert_eq!(record.counterparties.len(), 1);
+
+        // bob receives, the fee is not paid by bob
+        let record = decode_transaction(&config, &[bob.clone()], &tx, &inputs, position());
+        assert_eq!(record.capacity_delta, 242);
+        assert_eq!(record.fee, None);
+        assert_eq!(record.udt_deltas.get(&token_hash), Some(&30));
+
+        // DAO deposit
+        let dao = Script::new_builder()
+            .code_hash(DAO_TYPE_HASH.pack())
+            .hash_type(ScriptHashType::Type.into())
+            .build();
+        let outputs = vec![cell(
+            &alice,
+            400,
+            Some(dao.clone()),
+            Bytes::from(vec![0u8; 8]),
+        )];
+        let inputs = vec![cell(&alice, 500, None, Bytes::new())];
+        let tx = build_tx(inputs.len(), &outputs);
+        let record = decode_transaction(&config, &[alice.clone()], &tx, &inputs, position());
+        assert_eq!(record.kind, TxKind::DaoDeposit);
+        assert_eq!(record.capacity_delta, -100);
+
+        // cheque claim by bob
+        let cheque_id = ScriptId::new_type(H256([3u8; 32]));
+        config.cheque_script_id = Some(cheque_id.clone());
+        let alice_hash = alice.calc_script_hash();
+        let bob_hash = bob.calc_script_hash();
+        let cheque = Script::new_builder()
+            .code_hash(cheque_id.code_hash.pack())
+            .hash_type(cheque_id.hash_type.into())
+            .args(
+                Bytes::from([&bob_hash.as_slice()[0..20], &alice_hash.as_slice()[0..20]].concat())
+                    .pack(),
+            )
+            .build();
+        let inputs = vec![
+            cell(&cheque, 162, Some(token.clone()), amount(10)),
+            cell(&bob, 142, Some(token.clone()), amount(0)),
+        ];
+        let outputs = vec![
+            cell(&bob, 142, Some(token), amount(10)),
+            cell(&alice, 162, None, Bytes::new()),
+        ];
+        let tx = build_tx(inputs.len(), &outputs);
+        let record = decode_transaction(&config, &[bob], &tx, &inputs, position());
+        assert_eq!(record.kind, TxKind::ChequeClaim);
+        assert_eq!(record.udt_deltas.get(&token_hash), Some(&10));
+        let record = decode_transaction(&config, &[alice], &tx, &inputs, position());
+        assert_eq!(record.kind, TxKind::ChequeClaim);
+        assert_eq!(record.capacity_delta, 162);
+    }
+}
@@ -0,0 +1,891 @@
+//! An embedded cell indexer, following the chain by blocks from a CKB node.
+//!
+//! Only the cells of the tracked lock/type scripts are indexed, so it is
+//! light enough for a wallet. The indexer answers the same queries as the
+//! node's indexer (`get_cells`, `get_transactions`, `get_cells_capacity`),
+//! and implements [`CellCollector`].
+//!
+//! [`EmbeddedIndexer::sync`] appends the blocks after the tip. When the
+//! parent of the next block is not the tip, the tip block is rolled back and
+//! the sync retries, until it reaches the fork point. The fork must not be
+//! deeper than the undo log of the store.
+
+mod store;
+
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+
+use anyhow::anyhow;
+use ckb_jsonrpc_types::{self as json_types, JsonBytes, Uint32};
+use ckb_types::{
+    core::BlockView,
+    packed::{self, OutPoint, Script, Transaction},
+    prelude::*,
+    H256,
+};
+use thiserror::Error;
+
+pub use store::{
+    out_point_key, BlockChanges, BlockTip, FileStore, IndexedTx, IndexerStore, MemoryStore,
+    OutPointKey, DEFAULT_KEEP_BLOCKS, DEFAULT_SNAPSHOT_INTERVAL,
+};
+
+use crate::{
+    rpc::{
+        ckb_indexer::{
+            Cell, CellType, CellsCapacity, Order, OutputDataFilterMode, Pagination,
+            ScriptSearchMode, ScriptType, SearchKey, Tip, Tx, TxWithCell, TxWithCells,
+        },
+        CkbRpcClient, RpcError,
+    },
+    traits::{
+        offchain_impls::CollectResult, CellCollector, CellCollectorError, CellQueryOptions,
+        DataFilter, LiveCell, OffchainCellCollector, QueryOrder,
+    },
+    util::get_max_mature_number,
+};
+
+#[derive(Error, Debug)]
+pub enum IndexerError {
+    #[error("rpc error: `{0}`")]
+    Rpc(#[from] RpcError),
+
+    #[error("io error: `{0}`")]
+    Io(#[from] std::io::Error),
+
+    #[error("json error: `{0}`")]
+    Json(#[from] serde_json::Error),
+
+    #[error("block `{0}` is not next to the tip `{1}`")]
+    NotContinuous(u64, u64),
+
+    #[error("the fork is deeper than the undo log")]
+    RollbackTooDeep,
+
+    #[error("invalid cursor: `{0}`")]
+    InvalidCursor(String),
+
+    #[error("store error: `{0}`")]
+    Store(String),
+
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// Where the indexer gets blocks from.
+pub trait BlockSource {
+    fn get_block_by_number(&self, number: u64) -> Result<Option<BlockView>, IndexerError>;
+
+    /// The max block number of the mature cellbase cells, `None` if unknown.
+    fn max_mature_number(&self) -> Result<Option<u64>, IndexerError> {
+        Ok(None)
+    }
+}
+
+impl BlockSource for CkbRpcClient {
+    fn get_block_by_number(&self, number: u64) -> Result<Option<BlockView>, IndexerError> {
+        Ok(CkbRpcClient::get_block_by_number(self, number.into())?.map(Into::into))
+    }
+
+    fn max_mature_number(&self) -> Result<Option<u64>, IndexerError> {
+        get_max_mature_number(self)
+            .map(Some)
+            .map_err(|err| IndexerError::Other(anyhow!(err)))
+    }
+}
+
+pub struct EmbeddedIndexer<S> {
+    store: S,
+    scripts: Vec<(Script, ScriptType)>,
+    start_number: u64,
+    max_mature_number: u64,
+    offchain: OffchainCellCollector,
+}
+
+impl<S: IndexerStore> EmbeddedIndexer<S> {
+    /// Index the cells of `scripts` from the block `start_number`, the
+    /// scripts must not change once indexed.
+    pub fn new(store: S, scripts: Vec<(Script, ScriptType)>, start_number: u64) -> Self {
+        EmbeddedIndexer {
+            store,
+            scripts,
+            start_number,
+            max_mature_number: 0,
+            offchain: OffchainCellCollector::default(),
+        }
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    pub fn tip(&self) -> Option<BlockTip> {
+        self.store.tip()
+    }
+
+    /// Set the max block number of the mature cellbase cells, updated by
+    /// [`EmbeddedIndexer::sync`] if the source knows it.
+    pub fn set_max_mature_number(&mut self, max_mature_number: u64) {
+        self.max_mature_number = max_mature_number;
+    }
+
+    fn is_tracked(&self, output: &packed::CellOutput) -> bool {
+        self.scripts
+            .iter()
+            .any(|(script, script_type)| match script_type {
+                ScriptType::Lock => &output.lock() == script,
+                ScriptType::Type => output.type_().to_opt().as_ref() == Some(script),
+            })
+    }
+
+    /// Index the block next to the tip.
+    pub fn append_block(&mut self, block: &BlockView) -> Result<(), IndexerError> {
+        let number = block.number();
+        match self.store.tip() {
+            Some(tip) => {
+                if number != tip.number + 1 || block.parent_hash().unpack() != tip.hash {
+                    return Err(IndexerError::NotContinuous(number, tip.number));
+                }
+            }
+            None => {
+                if number != self.start_number {
+                    return Err(IndexerError::NotContinuous(number, self.start_number));
+                }
+            }
+        }
+
+        let mut created: Vec<Cell> = Vec::new();
+        let mut consumed: Vec<OutPointKey> = Vec::new();
+        let mut transactions = Vec::new();
+        for (tx_index, tx) in block.transactions().iter().enumerate() {
+            let tx_hash: H256 = tx.hash().unpack();
+            let tx_index = tx_index as u32;
+            let mut record =
+                |io_index: usize, io_type: CellType, output: json_types::CellOutput| {
+                    transactions.push(IndexedTx {
+                        tx_hash: tx_hash.clone(),
+                        block_number: number,
+                        tx_index,
+                        io_index: io_index as u32,
+                        io_type,
+                        output,
+                    });
+                };
+            // The input of cellbase is not a cell
+            if tx_index > 0 {
+                for (io_index, out_point) in tx.input_pts_iter().enumerate() {
+                    let key: OutPointKey =
+                        (out_point.tx_hash().unpack(), out_point.index().unpack());
+                    if let Some(pos) = created
+                        .iter()
+                        .position(|cell| out_point_key(&cell.out_point) == key)
+                    {
+                        let cell = created.remove(pos);
+                        record(io_index, CellType::Input, cell.output);
+                    } else if let Some(cell) = self.store.get_cell(&key) {
+                        record(io_index, CellType::Input, cell.output.clone());
+                        consumed.push(key);
+                    }
+                }
+            }
+            for (io_index, (output, data)) in tx.outputs_with_data_iter().enumerate() {
+                if !self.is_tracked(&output) {
+                    continue;
+                }
+                let cell = Cell {
+                    output: output.into(),
+                    output_data: Some(JsonBytes::from_bytes(data)),
+                    out_point: OutPoint::new(tx.hash(), io_index as u32).into(),
+                    block_number: number.into(),
+                    tx_index: tx_index.into(),
+                };
+                record(io_index, CellType::Output, cell.output.clone());
+                created.push(cell);
+            }
+        }
+        self.store.apply(BlockChanges {
+            tip: BlockTip {
+                number,
+                hash: block.hash().unpack(),
+            },
+            parent_hash: block.parent_hash().unpack(),
+            created,
+            consumed,
+            transactions,
+        })
+    }
+
+    /// Revert the tip block.
+    pub fn rollback(&mut self) -> Result<Option<BlockTip>, IndexerError> {
+        self.store.rollback()
+    }
+
+    /// Append the blocks after the tip until the tip of the source, rolling
+    /// back forks. Return the number of blocks appended.
+    pub fn sync(&mut self, source: &dyn BlockSource) -> Result<u64, IndexerError> {
+        let mut appended = 0;
+        loop {
+            let tip = self.store.tip();
+            let next = tip
+                .as_ref()
+                .map(|tip| tip.number + 1)
+                .unwrap_or(self.start_number);
+            let block = match source.get_block_by_number(next)? {
+                Some(block) => block,
+                None => break,
+            };
+            if let Some(tip) = tip {
+                if block.parent_hash().unpack() != tip.hash {
+                    log::info!("fork detected at block {}, rolling back", tip.number);
+                    self.store.rollback()?;
+                    continue;
+                }
+            }
+            self.append_block(&block)?;
+            appended += 1;
+        }
+        if let Some(max_mature_number) = source.max_mature_number()? {
+            self.max_mature_number = max_mature_number;
+        }
+        Ok(appended)
+    }
+
+    /// Handle a block from the `new_tip_block` subscription, sync from the
+    /// source if it is not next to the tip.
+    pub fn on_new_tip_block(
+        &mut self,
+        block: &BlockView,
+        source: &dyn BlockSource,
+    ) -> Result<u64, IndexerError> {
+        let is_next = match self.store.tip() {
+            Some(tip) => {
+                block.number() == tip.number + 1 && block.parent_hash().unpack() == tip.hash
+            }
+            None => block.number() == self.start_number,
+        };
+        if is_next {
+            self.append_block(block)?;
+            Ok(1)
+        } else {
+            self.sync(source)
+        }
+    }
+
+    pub fn get_indexer_tip(&self) -> Option<Tip> {
+        self.store.tip().map(|tip| Tip {
+            block_hash: tip.hash,
+            block_number: tip.number.into(),
+        })
+    }
+
+    /// The cursor is `block_number (u64 BE) | tx_index (u32 BE) | index (u32 BE)`.
+    pub fn get_cells(
+        &self,
+        search_key: SearchKey,
+        order: Order,
+        limit: Uint32,
+        after: Option<JsonBytes>,
+    ) -> Result<Pagination<Cell>, IndexerError> {
+        let after = after
+            .map(|cursor| {
+                let bytes: [u8; 16] = cursor.as_bytes().try_into().map_err(|_| {
+                    IndexerError::InvalidCursor(format!("length: {}", cursor.len()))
+                })?;
+                Ok::<_, IndexerError>(bytes)
+            })
+            .transpose()?;
+        let matcher = SearchKeyMatcher::new(&search_key);
+        let cells: Box<dyn Iterator<Item = &Cell>> = match order {
+            Order::Asc => self.store.cells(),
+            Order::Desc => Box::new(self.store.cells().rev()),
+        };
+        let mut objects = Vec::new();
+        let mut last_cursor = after;
+        for cell in cells {
+            let cursor = cell_cursor(cell);
+            let is_after = match (&order, after.as_ref()) {
+                (_, None) => true,
+                (Order::Asc, Some(after)) => &cursor > after,
+                (Order::Desc, Some(after)) => &cursor < after,
+            };
+            if !is_after || !matcher.match_cell(cell) {
+                continue;
+            }
+            if objects.len() >= limit.value() as usize {
+                break;
+            }
+            let mut cell = cell.clone();
+            if search_key.with_data == Some(false) {
+                cell.output_data = None;
+            }
+            objects.push(cell);
+            last_cursor = Some(cursor);
+        }
+        Ok(Pagination {
+            objects,
+            last_cursor: JsonBytes::from_vec(last_cursor.map(|c| c.to_vec()).unwrap_or_default()),
+        })
+    }
+
+    /// The cursor is the position in the history `(u64 BE)`.
+    pub fn get_transactions(
+        &self,
+        search_key: SearchKey,
+        order: Order,
+        limit: Uint32,
+        after: Option<JsonBytes>,
+    ) -> Result<Pagination<Tx>, IndexerError> {
+        let after = after
+            .map(|cursor| {
+                let bytes: [u8; 8] = cursor.as_bytes().try_into().map_err(|_| {
+                    IndexerError::InvalidCursor(format!("length: {}", cursor.len()))
+                })?;
+                Ok::<_, IndexerError>(u64::from_be_bytes(bytes) as usize)
+            })
+            .transpose()?;
+        let matcher = SearchKeyMatcher::new(&search_key);
+        let history = self.store.transactions();
+        let positions: Box<dyn Iterator<Item = usize>> = match (&order, after) {
+            (Order::Asc, None) => Box::new(0..history.len()),
+            (Order::Asc, Some(after)) => Box::new(after + 1..history.len()),
+            (Order::Desc, None) => Box::new((0..history.len()).rev()),
+            (Order::Desc, Some(after)) => Box::new((0..after.min(history.len())).rev()),
+        };
+        let group_by_transaction = search_key.group_by_transaction.unwrap_or(false);
+        let limit = limit.value() as usize;
+        let mut objects: Vec<Tx> = Vec::new();
+        let mut last_cursor = after;
+        for pos in positions {
+            let record = &history[pos];
+            if !matcher.match_tx(record) {
+                continue;
+            }
+            let cell = (record.io_type.clone(), Uint32::from(record.io_index));
+            if group_by_transaction {
+                if let Some(Tx::Grouped(tx)) = objects.last_mut() {
+                    if tx.tx_hash == record.tx_hash {
+                        tx.cells.push(cell);
+                        last_cursor = Some(pos);
+                        continue;
+                    }
+                }
+            }
+            if objects.len() >= limit {
+                break;
+            }
+            objects.push(if group_by_transaction {
+                Tx::Grouped(TxWithCells {
+                    tx_hash: record.tx_hash.clone(),
+                    block_number: record.block_number.into(),
+                    tx_index: record.tx_index.into(),
+                    cells: vec![cell],
+                })
+            } else {
+                Tx::Ungrouped(TxWithCell {
+                    tx_hash: record.tx_hash.clone(),
+                    block_number: record.block_number.into(),
+                    tx_index: record.tx_index.into(),
+                    io_index: record.io_index.into(),
+                    io_type: record.io_type.clone(),
+                })
+            });
+            last_cursor = Some(pos);
+        }
+        Ok(Pagination {
+            objects,
+            last_cursor: JsonBytes::from_vec(
+                last_cursor
+                    .map(|pos| (pos as u64).to_be_bytes().to_vec())
+                    .unwrap_or_default(),
+            ),
+        })
+    }
+
+    pub fn get_cells_capacity(&self, search_key: SearchKey) -> Option<CellsCapacity> {
+        let tip = self.store.tip()?;
+        let matcher = SearchKeyMatcher::new(&search_key);
+        let capacity: u64 = self
+            .store
+            .cells()
+            .filter(|cell| matcher.match_cell(cell))
+            .map(|cell| cell.output.capacity.value())
+            .sum();
+        Some(CellsCapacity {
+            capacity: capacity.into(),
+            block_hash: tip.hash,
+            block_number: tip.number.into(),
+        })
+    }
+}
+
+fn cell_cursor(cell: &Cell) -> [u8; 16] {
+    let mut cursor = [0u8; 16];
+    cursor[0..8].copy_from_slice(&cell.block_number.value().to_be_bytes());
+    cursor[8..12].copy_from_slice(&cell.tx_index.value().to_be_bytes());
+    cursor[12..16].copy_from_slice(&cell.out_point.index.value().to_be_bytes());
+    cursor
+}
+
+/// Match the cells by a search key as the node's indexer does, the ranges
+/// are `[start, end)`.
+struct SearchKeyMatcher {
+    script: Script,
+    script_type: ScriptType,
+    exact: bool,
+    filter_script: Option<Script>,
+    script_len_range: Option<(u64, u64)>,
+    data_filter: Option<DataFilter>,
+    data_len_range: Option<(u64, u64)>,
+    capacity_range: Option<(u64, u64)>,
+    block_range: Option<(u64, u64)>,
+}
+
+impl SearchKeyMatcher {
+    fn new(search_key: &SearchKey) -> SearchKeyMatcher {
+        let filter = search_key.filter.clone().unwrap_or_default();
+        let range = |range: Option<[json_types::Uint64; 2]>| {
+            range.map(|[start, end]| (start.value(), end.value()))
+        };
+        let data_filter_mode = filter.output_data_filter_mode.clone();
+        SearchKeyMatcher {
+            script: search_key.script.clone().into(),
+            script_type: search_key.script_type.clone(),
+            exact: search_key.script_search_mode == Some(ScriptSearchMode::Exact),
+            filter_script: filter.script.map(Into::into),
+            script_len_range: range(filter.script_len_range),
+            data_filter: filter.output_data.map(|data| {
+                let data = data.into_bytes();
+                // Prefix by default as the node's indexer does
+                match data_filter_mode {
+                    Some(OutputDataFilterMode::Exact) => DataFilter::Exact(data),
+                    Some(OutputDataFilterMode::Partial) => DataFilter::Partial(data),
+                    Some(OutputDataFilterMode::Prefix) | None => DataFilter::Prefix(data),
+                }
+            }),
+            data_len_range: range(filter.output_data_len_range),
+            capacity_range: range(filter.output_capacity_range),
+            block_range: range(filter.block_range),
+        }
+    }
+
+    fn match_script(&self, output: &packed::CellOutput) -> bool {
+        let (primary, secondary) = match self.script_type {
+            ScriptType::Lock => (Some(output.lock()), output.type_().to_opt()),
+            ScriptType::Type => (output.type_().to_opt(), Some(output.lock())),
+        };
+        let primary_matched = primary
+            .map(|script| {
+                if self.exact {
+                    script == self.script
+                } else {
+                    is_prefix_of(&self.script, &script)
+                }
+            })
+            .unwrap_or(false);
+        if !primary_matched {
+            return false;
+        }
+        if let Some(filter_script) = self.filter_script.as_ref() {
+            if !secondary
+                .as_ref()
+                .map(|script| is_prefix_of(filter_script, script))
+                .unwrap_or(false)
+            {
+                return false;
+            }
+        }
+        if let Some(range) = self.script_len_range {
+            let len = secondary
+                .map(|script| 32 + 1 + script.args().raw_data().len() as u64)
+                .unwrap_or(0);
+            if !in_range(range, len) {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn match_cell(&self, cell: &Cell) -> bool {
+        let output: packed::CellOutput = cell.output.clone().into();
+        let data_len = cell
+            .output_data
+            .as_ref()
+            .map(|data| data.len() as u64)
+            .unwrap_or(0);
+        self.match_script(&output)
+            && self
+                .data_filter
+                .as_ref()
+                .map(|filter| {
+                    let data = cell.output_data.as_ref().map(|data| data.as_bytes());
+                    filter.match_data(data.unwrap_or_default())
+                })
+                .unwrap_or(true)
+            && self
+                .data_len_range
+                .map(|range| in_range(range, data_len))
+                .unwrap_or(true)
+            && self
+                .capacity_range
+                .map(|range| in_range(range, cell.output.capacity.value()))
+                .unwrap_or(true)
+            && self
+                .block_range
+                .map(|range| in_range(range, cell.block_number.value()))
+                .unwrap_or(true)
+    }
+
+    fn match_tx(&self, tx: &IndexedTx) -> bool {
+        self.match_script(&tx.output.clone().into())
+            && self
+                .block_range
+                .map(|range| in_range(range, tx.block_number))
+                .unwrap_or(true)
+    }
+}
+
+fn is_prefix_of(prefix: &Script, script: &Script) -> bool {
+    prefix.code_hash() == script.code_hash()
+        && prefix.hash_type() == script.hash_type()
+        && script
+            .args()
+            .raw_data()
+            .starts_with(&prefix.args().raw_data())
+}
+
+fn in_range((start, end): (u64, u64), value: u64) -> bool {
+    start <= value && value < end
+}
+
+impl<S: IndexerStore> CellCollector for EmbeddedIndexer<S> {
+    fn collect_live_cells(
+        &mut self,
+        query: &CellQueryOptions,
+        apply_changes: bool,
+    ) -> Result<(Vec<LiveCell>, u64), CellCollectorError> {
+        let tip_num = self.store.tip().map(|tip| tip.number).unwrap_or(0);
+        self.offchain.max_mature_number = self.max_mature_number;
+        let CollectResult {
+            cells,
+            rest_cells,
+            mut total_capacity,
+        } = self.offchain.collect(query, tip_num);
+        let mut cells: Vec<_> = cells.into_iter().map(|c| c.0).collect();
+
+        if total_capacity < query.min_total_capacity {
+            let mut collected: HashSet<OutPointKey> = cells
+                .iter()
+                .map(|cell| {
+                    (
+                        cell.out_point.tx_hash().unpack(),
+                        cell.out_point.index().unpack(),
+                    )
+                })
+                .collect();
+            let locked_cells: &HashMap<(H256, u32), u64> = &self.offchain.locked_cells;
+            let store_cells: Box<dyn Iterator<Item = &Cell>> = match query.order {
+                QueryOrder::Asc => self.store.cells(),
+                QueryOrder::Desc => Box::new(self.store.cells().rev()),
+            };
+            for cell in store_cells {
+                let key = out_point_key(&cell.out_point);
+                if locked_cells.contains_key(&key) || collected.contains(&key) {
+                    continue;
+                }
+                let live_cell = LiveCell::from(cell.clone());
+                if !query.match_cell(&live_cell, self.max_mature_number) {
+                    continue;
+                }
+                let capacity: u64 = live_cell.output.capacity().unpack();
+                total_capacity += capacity;
+                collected.insert(key);
+                cells.push(live_cell);
+                if total_capacity >= query.min_total_capacity {
+                    break;
+                }
+            }
+        }
+        if apply_changes {
+            self.offchain.live_cells = rest_cells;
+            for cell in &cells {
+                self.lock_cell(cell.out_point.clone(), tip_num)?;
+            }
+        }
+        Ok((cells, total_capacity))
+    }
+
+    fn lock_cell(
+        &mut self,
+        out_point: OutPoint,
+        tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        self.offchain.lock_cell(out_point, tip_block_number)
+    }
+
+    fn apply_tx(
+        &mut self,
+        tx: Transaction,
+        tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        self.offchain.apply_tx(tx, tip_block_number)
+    }
+
+    fn reset(&mut self) {
+        self.offchain.reset();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+
+    use ckb_types::{
+        bytes::Bytes,
+        core::{BlockBuilder, HeaderBuilder, ScriptHashType, TransactionBuilder},
+        h256,
+        packed::{CellInput, CellOutput},
+    };
+
+    fn lock(byte: u8) -> Script {
+        Script::new_builder()
+            .code_hash(h256!("0x1").pack())
+            .hash_type(ScriptHashType::Type.into())
+            .args(Bytes::from(vec![byte; 20]).pack())
+            .build()
+    }
+
+    /// An in-memory chain, blocks are replaced to make forks.
+    #[derive(Default)]
+    struct MockChain {
+        blocks: RefCell<Vec<BlockView>>,
+    }
+
+    impl MockChain {
+        /// Build a block on the block `number - 1`, dropping the blocks after it.
+        fn build(&self, number: u64, txs: Vec<ckb_types::core::TransactionView>, nonce: u128) {
+            let mut blocks = self.blocks.borrow_mut();
+            blocks.truncate(number as usize);
+            let parent_hash = blocks.last().map(|block| block.hash()).unwrap_or_default();
+            let cellbase = TransactionBuilder::default()
+                .input(CellInput::new_cellbase_input(number))
+                .build();
+            let block = BlockBuilder::default()
+                .header(
+                    HeaderBuilder::default()
+                        .number(number.pack())
+                        .parent_hash(parent_hash)
+                        .nonce(nonce.pack())
+                        .build(),
+                )
+                .transaction(cellbase)
+                .transactions(txs)
+                .build();
+            blocks.push(block);
+        }
+    }
+
+    impl BlockSource for MockChain {
+        fn get_block_by_number(&self, number: u64) -> Result<Option<BlockView>, IndexerError> {
+            Ok(self.blocks.borrow().get(number as usize).cloned())
+        }
+    }
+
+    fn transfer(
+        inputs: Vec<OutPoint>,
+        outputs: Vec<(Script, u64)>,
+    ) -> ckb_types::core::TransactionView {
+        let mut builder = TransactionBuilder::default();
+        for out_point in inputs {
+            builder = builder.input(CellInput::new(out_point, 0));
+        }
+        for (lock, capacity) in outputs {
+            builder = builder
+                .output(
+                    CellOutput::new_builder()
+                        .lock(lock)
+                        .capacity(capacity.pack())
+                        .build(),
+                )
+                .output_data(Bytes::from(vec![1u8; capacity as usize % 7]).pack());
+        }
+        builder.build()
+    }
+
+    fn search_key(script: &Script) -> SearchKey {
+        SearchKey {
+            script: script.clone().into(),
+            script_type: ScriptType::Lock,
+            script_search_mode: None,
+            filter: None,
+            with_data: None,
+            group_by_transaction: None,
+        }
+    }
+
+    #[test]
+    fn test_embedded_indexer() {
+        let (alice, bob, carol) = (lock(1), lock(2), lock(3));
+        let chain = MockChain::default();
+        chain.build(0, Vec::new(), 0);
+        let tx1 = transfer(Vec::new(), vec![(alice.clone(), 100), (carol.clone(), 50)]);
+        chain.build(1, vec![tx1.clone()], 0);
+        // alice pays bob, and bob spends it in the same block
+        let tx2 = transfer(
+            vec![OutPoint::new(tx1.hash(), 0)],
+            vec![(bob.clone(), 60), (alice.clone(), 40)],
+        );
+        let tx3 = transfer(vec![OutPoint::new(tx2.hash(), 0)], vec![(bob.clone(), 59)]);
+        chain.build(2, vec![tx2.clone(), tx3.clone()], 0);
+
+        let mut indexer = EmbeddedIndexer::new(
+            MemoryStore::new(10),
+            vec![
+                (alice.clone(), ScriptType::Lock),
+                (bob.clone(), ScriptType::Lock),
+            ],
+            0,
+        );
+        assert_eq!(indexer.sync(&chain).unwrap(), 3);
+        assert_eq!(indexer.tip().unwrap().number, 2);
+
+        let capacity = |script: &Script| {
+            indexer
+                .get_cells_capacity(search_key(script))
+                .unwrap()
+                .capacity
+                .value()
+        };
+        assert_eq!(capacity(&alice), 40);
+        assert_eq!(capacity(&bob), 59);
+        assert_eq!(capacity(&carol), 0);
+
+        // tx1 output, tx2 input and output, no more
+        let page = indexer
+            .get_transactions(search_key(&alice), Order::Asc, 2.into(), None)
+            .unwrap();
+        assert_eq!(page.objects.len(), 2);
+        let rest = indexer
+            .get_transactions(
+                search_key(&alice),
+                Order::Asc,
+                10.into(),
+                Some(page.last_cursor),
+            )
+            .unwrap();
+        assert_eq!(rest.objects.len(), 1);
+        assert_eq!(rest.objects[0].tx_hash(), tx2.hash().unpack());
+        let mut grouped_key = search_key(&bob);
+        grouped_key.group_by_transaction = Some(true);
+        let grouped = indexer
+            .get_transactions(grouped_key, Order::Desc, 10.into(), None)
+            .unwrap();
+        // tx3 (input, output), tx2 (output)
+        assert_eq!(grouped.objects.len(), 2);
+        assert_eq!(grouped.objects[0].tx_hash(), tx3.hash().unpack());
+        assert!(matches!(&grouped.objects[0], Tx::Grouped(tx) if tx.cells.len() == 2));
+
+        // prefix search matches both alice and bob
+        let mut prefix_key = search_key(&alice);
+        prefix_key.script.args = JsonBytes::default();
+        let cells = indexer
+            .get_cells(prefix_key, Order::Desc, 10.into(), None)
+            .unwrap();
+        assert_eq!(cells.objects.len(), 2);
+        assert_eq!(cells.objects[0].output.capacity.value(), 59);
+
+        // fork at block 2: alice pays carol instead
+        let tx4 = transfer(vec![OutPoint::new(tx1.hash(), 0)], vec![(carol, 100)]);
+        chain.build(2, vec![tx4], 1);
+        chain.build(3, Vec::new(), 0);
+        assert_eq!(indexer.sync(&chain).unwrap(), 2);
+        assert_eq!(indexer.tip().unwrap().number, 3);
+        assert_eq!(capacity(&alice), 0);
+        assert_eq!(capacity(&bob), 0);
+        assert_eq!(indexer.store().transactions().len(), 2);
+
+        // the cell collector
+        let tx5 = transfer(Vec::new(), vec![(alice.clone(), 10), (alice.clone(), 20)]);
+        chain.build(4, vec![tx5], 0);
+        let block = chain.get_block_by_number(4).unwrap().unwrap();
+        assert_eq!(indexer.on_new_tip_block(&block, &chain).unwrap(), 1);
+        let mut query = CellQueryOptions::new_lock(alice);
+        query.min_total_capacity = 25;
+        let (cells, total) = indexer.collect_live_cells(&query, true).unwrap();
+        assert_eq!((cells.len(), total), (2, 30));
+        let (cells, _) = indexer.collect_live_cells(&query, true).unwrap();
+        assert!(cells.is_empty());
+    }
+
+    #[test]
+    fn test_file_store() {
+        let dir = std::env::temp_dir().join(format!("ckb-sdk-indexer-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("store.json");
+        let log_path = dir.join("store.json.log");
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&log_path);
+
+        let alice = lock(1);
+        let chain = MockChain::default();
+        chain.build(0, Vec::new(), 0);
+        chain.build(1, vec![transfer(Vec::new(), vec![(alice.clone(), 100)])], 0);
+        let scripts = vec![(alice.clone(), ScriptType::Lock)];
+        let mut indexer =
+            EmbeddedIndexer::new(FileStore::open(&path, 10).unwrap(), scripts.clone(), 0);
+        indexer.sync(&chain).unwrap();
+
+        let mut reopened = EmbeddedIndexer::new(FileStore::open(&path, 10).unwrap(), scripts, 0);
+        assert_eq!(reopened.tip(), indexer.tip());
+        assert_eq!(
+            reopened
+                .get_cells_capacity(search_key(&alice))
+                .unwrap()
+                .capacity
+                .value(),
+            100
+        );
+        reopened.rollback().unwrap();
+        assert_eq!(reopened.tip().unwrap().number, 0);
+
+        // the changes are appended to the log, a snapshot is written every
+        // `snapshot_interval` changes
+        let mut store = FileStore::open(&path, 10).unwrap();
+        assert_eq!(store.tip().unwrap().number, 0);
+        assert_eq!(std::fs::metadata(&log_path).unwrap().len(), 0);
+        store.set_snapshot_interval(2);
+        let mut indexer = EmbeddedIndexer::new(store, vec![(alice.clone(), ScriptType::Lock)], 0);
+        indexer.sync(&chain).unwrap();
+        assert!(std::fs::metadata(&log_path).unwrap().len() > 0);
+        chain.build(2, Vec::new(), 0);
+        indexer.sync(&chain).unwrap();
+        assert_eq!(std::fs::metadata(&log_path).unwrap().len(), 0);
+        chain.build(3, vec![transfer(Vec::new(), vec![(alice.clone(), 50)])], 0);
+        indexer.sync(&chain).unwrap();
+        assert!(std::fs::metadata(&log_path).unwrap().len() > 0);
+
+        // a partially written entry is dropped when replaying the log
+        let mut log = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&log_path)
+            .unwrap();
+        std::io::Write::write_all(&mut log, b"{\"seq\":").unwrap();
+        let reopened = EmbeddedIndexer::new(
+            FileStore::open(&path, 10).unwrap(),
+            vec![(alice.clone(), ScriptType::Lock)],
+            0,
+        );
+        assert_eq!(reopened.tip(), indexer.tip());
+        assert_eq!(reopened.tip().unwrap().number, 3);
+        assert_eq!(
+            reopened
+                .get_cells_capacity(search_key(&alice))
+                .unwrap()
+                .capacity
+                .value(),
+            150
+        );
+        assert_eq!(std::fs::metadata(&log_path).unwrap().len(), 0);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,401 @@
+//! Storage of the embedded indexer.
+//!
+//! The store keeps the live cells and the transaction history of the tracked
+//! scripts, and the undo log of the recent blocks for rolling back forks.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use ckb_jsonrpc_types::{CellOutput, OutPoint};
+use ckb_types::H256;
+use serde::{Deserialize, Serialize};
+
+use super::IndexerError;
+use crate::rpc::ckb_indexer::{Cell, CellType};
+
+/// The number of recent blocks able to roll back by default
+pub const DEFAULT_KEEP_BLOCKS: usize = 100;
+/// The number of the log entries written between two snapshots by default
+pub const DEFAULT_SNAPSHOT_INTERVAL: u64 = 1000;
+
+/// `(tx_hash, index)` of an out point
+pub type OutPointKey = (H256, u32);
+
+// `(block_number, tx_index, index)`, the order of cells in the chain
+type CellOrder = (u64, u32, u32);
+
+pub fn out_point_key(out_point: &OutPoint) -> OutPointKey {
+    (out_point.tx_hash.clone(), out_point.index.value())
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct BlockTip {
+    pub number: u64,
+    pub hash: H256,
+}
+
+/// An input or output of a tracked cell.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IndexedTx {
+    pub tx_hash: H256,
+    pub block_number: u64,
+    pub tx_index: u32,
+    pub io_index: u32,
+    pub io_type: CellType,
+    /// The output of the tracked cell
+    pub output: CellOutput,
+}
+
+/// The changes of a block to the tracked cells.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BlockChanges {
+    pub tip: BlockTip,
+    pub parent_hash: H256,
+    pub created: Vec<Cell>,
+    pub consumed: Vec<OutPointKey>,
+    pub transactions: Vec<IndexedTx>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct BlockUndo {
+    tip: BlockTip,
+    parent_hash: H256,
+    created: Vec<OutPoint>,
+    consumed: Vec<Cell>,
+    tx_count: usize,
+}
+
+/// The storage backend of the embedded indexer.
+pub trait IndexerStore {
+    fn tip(&self) -> Option<BlockTip>;
+
+    /// Apply the changes of the block next to the tip.
+    fn apply(&mut self, changes: BlockChanges) -> Result<(), IndexerError>;
+
+    /// Revert the tip block, return the new tip.
+    fn rollback(&mut self) -> Result<Option<BlockTip>, IndexerError>;
+
+    fn get_cell(&self, out_point: &OutPointKey) -> Option<&Cell>;
+
+    /// The live cells in the order of the chain.
+    fn cells(&self) -> Box<dyn DoubleEndedIterator<Item = &Cell> + '_>;
+
+    /// The transaction history in the order of the chain.
+    fn transactions(&self) -> &[IndexedTx];
+}
+
+/// An in-memory store.
+#[derive(Clone, Debug)]
+pub struct MemoryStore {
+    tip: Option<BlockTip>,
+    cells: BTreeMap<CellOrder, Cell>,
+    out_points: HashMap<OutPointKey, CellOrder>,
+    transactions: Vec<IndexedTx>,
+    undo: VecDeque<BlockUndo>,
+    keep_blocks: usize,
+}
+
+impl Default for MemoryStore {
+    fn default() -> MemoryStore {
+        MemoryStore::new(DEFAULT_KEEP_BLOCKS)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    /// The sequence number of the last log entry included
+    #[serde(default)]
+    seq: u64,
+    tip: Option<BlockTip>,
+    cells: Vec<Cell>,
+    transactions: Vec<IndexedTx>,
+    undo: VecDeque<BlockUndo>,
+}
+
+impl MemoryStore {
+    /// `keep_blocks` is the max depth of a fork able to roll back.
+    pub fn new(keep_blocks: usize) -> MemoryStore {
+        MemoryStore {
+            tip: None,
+            cells: BTreeMap::new(),
+            out_points: HashMap::new(),
+            transactions: Vec::new(),
+            undo: VecDeque::new(),
+            keep_blocks,
+        }
+    }
+
+    fn insert_cell(&mut self, cell: Cell) {
+        let order = (
+            cell.block_number.value(),
+            cell.tx_index.value(),
+            cell.out_point.index.value(),
+        );
+        self.out_points
+            .insert(out_point_key(&cell.out_point), order);
+        self.cells.insert(order, cell);
+    }
+
+    fn remove_cell(&mut self, out_point: &OutPointKey) -> Option<Cell> {
+        let order = self.out_points.remove(out_point)?;
+        self.cells.remove(&order)
+    }
+
+    fn to_snapshot(&self, seq: u64) -> Snapshot {
+        Snapshot {
+            seq,
+            tip: self.tip.clone(),
+            cells: self.cells.values().cloned().collect(),
+            transactions: self.transactions.clone(),
+            undo: self.undo.clone(),
+        }
+    }
+
+    fn from_snapshot(snapshot: Snapshot, keep_blocks: usize) -> MemoryStore {
+        let mut store = MemoryStore::new(keep_blocks);
+        store.tip = snapshot.tip;
+        store.transactions = snapshot.transactions;
+        store.undo = snapshot.undo;
+        for cell in snapshot.cells {
+            store.insert_cell(cell);
+        }
+        store
+    }
+}
+
+impl IndexerStore for MemoryStore {
+    fn tip(&self) -> Option<BlockTip> {
+        self.tip.clone()
+    }
+
+    fn apply(&mut self, changes: BlockChanges) -> Result<(), IndexerError> {
+        if let Some(tip) = self.tip.as_ref() {
+            if changes.tip.number != tip.number + 1 || changes.parent_hash != tip.hash {
+                return Err(IndexerError::NotContinuous(changes.tip.number, tip.number));
+            }
+        }
+        let mut consumed = Vec::with_capacity(changes.consumed.len());
+        for out_point in &changes.consumed {
+            let cell = self.remove_cell(out_point).ok_or_else(|| {
+                IndexerError::Store(format!("consumed cell not found: {:?}", out_point))
+            })?;
+            consumed.push(cell);
+        }
+        let created = changes
+            .created
+            .iter()
+            .map(|cell| cell.out_point.clone())
+            .collect();
+        for cell in changes.created {
+            self.insert_cell(cell);
+        }
+        let tx_count = changes.transactions.len();
+        self.transactions.extend(changes.transactions);
+        self.undo.push_back(BlockUndo {
+            tip: changes.tip.clone(),
+            parent_hash: changes.parent_hash,
+            created,
+            consumed,
+            tx_count,
+        });
+        while self.undo.len() > self.keep_blocks {
+            self.undo.pop_front();
+        }
+        self.tip = Some(changes.tip);
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> Result<Option<BlockTip>, IndexerError> {
+        let undo = self.undo.pop_back().ok_or(IndexerError::RollbackTooDeep)?;
+        for out_point in &undo.created {
+            self.remove_cell(&out_point_key(out_point));
+        }
+        for cell in undo.consumed {
+            self.insert_cell(cell);
+        }
+        let tx_count = self.transactions.len() - undo.tx_count;
+        self.transactions.truncate(tx_count);
+        self.tip = if undo.tip.number == 0 {
+            None
+        } else {
+            Some(BlockTip {
+                number: undo.tip.number - 1,
+                hash: undo.parent_hash,
+            })
+        };
+        Ok(self.tip.clone())
+    }
+
+    fn get_cell(&self, out_point: &OutPointKey) -> Option<&Cell> {
+        self.out_points
+            .get(out_point)
+            .and_then(|order| self.cells.get(order))
+    }
+
+    fn cells(&self) -> Box<dyn DoubleEndedIterator<Item = &Cell> + '_> {
+        Box::new(self.cells.values())
+    }
+
+    fn transactions(&self) -> &[IndexedTx] {
+        &self.transactions
+    }
+}
+
+// The changes are borrowed when the entry is written
+#[derive(Serialize, Deserialize)]
+enum LogOp<T> {
+    Apply(T),
+    Rollback,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LogEntry<T> {
+    seq: u64,
+    op: LogOp<T>,
+}
+
+/// A [`MemoryStore`] persisted to files. Every change is appended to the log
+/// file `<path>.log`, and the whole store is written to the snapshot file
+/// `<path>` (replaced atomically) every `snapshot_interval` changes, then the
+/// log is cleared. Opening the store replays the log after the snapshot.
+pub struct FileStore {
+    path: PathBuf,
+    inner: MemoryStore,
+    log: File,
+    // The sequence number of the last log entry, and of the snapshot
+    seq: u64,
+    snapshot_seq: u64,
+    snapshot_interval: u64,
+}
+
+impl FileStore {
+    /// Open the store file, create an empty store if it does not exist.
+    pub fn open<P: AsRef<Path>>(path: P, keep_blocks: usize) -> Result<FileStore, IndexerError> {
+        let path = path.as_ref().to_path_buf();
+        let (mut inner, snapshot_seq) = if path.exists() {
+            let snapshot: Snapshot = serde_json::from_slice(&fs::read(&path)?)?;
+            let seq = snapshot.seq;
+            (MemoryStore::from_snapshot(snapshot, keep_blocks), seq)
+        } else {
+            (MemoryStore::new(keep_blocks), 0)
+        };
+        let log_path = Self::log_path(&path);
+        let mut seq = snapshot_seq;
+        if log_path.exists() {
+            for line in fs::read(&log_path)?.split(|byte| *byte == b'\n') {
+                // The last entry is partially written if the process crashed
+                let entry: LogEntry<BlockChanges> = match serde_json::from_slice(line) {
+                    Ok(entry) => entry,
+                    Err(_) => break,
+                };
+                // The entries before the snapshot are left if the process
+                // crashed before the log was cleared
+                if entry.seq <= seq {
+                    continue;
+                }
+                match entry.op {
+                    LogOp::Apply(changes) => inner.apply(changes)?,
+                    LogOp::Rollback => {
+                        inner.rollback()?;
+                    }
+                }
+                seq = entry.seq;
+            }
+        }
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_path)?;
+        let mut store = FileStore {
+            path,
+            inner,
+            log,
+            seq,
+            snapshot_seq,
+            snapshot_interval: DEFAULT_SNAPSHOT_INTERVAL,
+        };
+        // Compact the replayed entries, and drop the partially written one
+        if store.log.metadata()?.len() > 0 {
+            store.snapshot()?;
+        }
+        Ok(store)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Set the number of the log entries written between two snapshots.
+    pub fn set_snapshot_interval(&mut self, snapshot_interval: u64) {
+        self.snapshot_interval = snapshot_interval.max(1);
+    }
+
+    fn log_path(path: &Path) -> PathBuf {
+        let mut path = path.to_path_buf().into_os_string();
+        path.push(".log");
+        PathBuf::from(path)
+    }
+
+    /// Write the whole store to the snapshot file, then clear the log.
+    pub fn snapshot(&mut self) -> Result<(), IndexerError> {
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(
+            &tmp_path,
+            serde_json::to_vec(&self.inner.to_snapshot(self.seq))?,
+        )?;
+        fs::rename(&tmp_path, &self.path)?;
+        self.log.set_len(0)?;
+        self.snapshot_seq = self.seq;
+        Ok(())
+    }
+
+    /// Append the entry of a change already applied to the inner store.
+    fn append(&mut self, mut entry: Vec<u8>) -> Result<(), IndexerError> {
+        entry.push(b'\n');
+        self.log.write_all(&entry)?;
+        self.seq += 1;
+        if self.seq - self.snapshot_seq >= self.snapshot_interval {
+            self.snapshot()?;
+        }
+        Ok(())
+    }
+}
+
+impl IndexerStore for FileStore {
+    fn tip(&self) -> Option<BlockTip> {
+        self.inner.tip()
+    }
+
+    fn apply(&mut self, changes: BlockChanges) -> Result<(), IndexerError> {
+        let entry = serde_json::to_vec(&LogEntry {
+            seq: self.seq + 1,
+            op: LogOp::Apply(&changes),
+        })?;
+        self.inner.apply(changes)?;
+        self.append(entry)
+    }
+
+    fn rollback(&mut self) -> Result<Option<BlockTip>, IndexerError> {
+        let entry = serde_json::to_vec(&LogEntry {
+            seq: self.seq + 1,
+            op: LogOp::<&BlockChanges>::Rollback,
+        })?;
+        let tip = self.inner.rollback()?;
+        self.append(entry)?;
+        Ok(tip)
+    }
+
+    fn get_cell(&self, out_point: &OutPointKey) -> Option<&Cell> {
+        self.inner.get_cell(out_point)
+    }
+
+    fn cells(&self) -> Box<dyn DoubleEndedIterator<Item = &Cell> + '_> {
+        self.inner.cells()
+    }
+
+    fn transactions(&self) -> &[IndexedTx] {
+        self.inner.transactions()
+    }
+}
@@ -0,0 +1,678 @@
+//! Encrypted keystore files (Web3 Secret Storage Definition version 3).
+//!
+//! The keystore is compatible with ckb-cli and ethereum: the key is encrypted
+//! by `aes-128-ctr`, the encryption key is derived from the password by
+//! `scrypt` or `pbkdf2`, and the mac is `keccak256(derived_key[16..32] | ciphertext)`.
+//! ckb-cli stores the 64 bytes master key (secret key | chain code), ethereum
+//! stores the 32 bytes secret key.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use aes::Aes128;
+use ckb_types::{bytes::Bytes, core::TransactionView, H160};
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use hmac::Hmac;
+use parking_lot::Mutex;
+use serde_derive::{Deserialize, Serialize};
+use sha2::Sha256;
+use sha3::{Digest, Keccak256};
+use thiserror::Error;
+
+use crate::{
+    traits::{SecpCkbRawKeySigner, Signer, SignerError},
+    types::ExtendedPrivKey,
+    util::{blake160, zeroize_privkey, zeroize_slice},
+    SECP256K1,
+};
+
+type Aes128Ctr = ctr::Ctr128BE<Aes128>;
+
+pub const KEYSTORE_VERSION: u32 = 3;
+const CIPHER_AES_128_CTR: &str = "aes-128-ctr";
+const KDF_SCRYPT: &str = "scrypt";
+const KDF_PBKDF2: &str = "pbkdf2";
+const PRF_HMAC_SHA256: &str = "hmac-sha256";
+const DKLEN: u32 = 32;
+// The kdf params are read from the keystore file, cap the cost of deriving
+// the key from them
+const MAX_PBKDF2_ROUNDS: u32 = 10_000_000;
+const MAX_SCRYPT_MEMORY: u64 = 1 << 30;
+
+#[derive(Error, Debug)]
+pub enum KeystoreError {
+    #[error("io error: `{0}`")]
+    Io(#[from] std::io::Error),
+
+    #[error("invalid json: `{0}`")]
+    Json(#[from] serde_json::Error),
+
+    #[error("invalid hex string: `{0}`")]
+    Hex(#[from] hex::FromHexError),
+
+    #[error("unsupported keystore version: `{0}`")]
+    UnsupportedVersion(u32),
+
+    #[error("unsupported cipher: `{0}`")]
+    UnsupportedCipher(String),
+
+    #[error("unsupported kdf: `{0}`")]
+    UnsupportedKdf(String),
+
+    #[error("invalid kdf params: `{0}`")]
+    InvalidKdfParams(String),
+
+    #[error("wrong password")]
+    WrongPassword,
+
+    #[error("invalid key: `{0}`")]
+    InvalidKey(#[from] secp256k1::Error),
+
+    #[error("account is locked: `{0:x}`")]
+    AccountLocked(H160),
+
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq)]
+pub struct CipherParams {
+    pub iv: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq)]
+#[serde(untagged)]
+pub enum KdfParams {
+    Scrypt {
+        dklen: u32,
+        n: u32,
+        p: u32,
+        r: u32,
+        salt: String,
+    },
+    Pbkdf2 {
+        c: u32,
+        dklen: u32,
+        prf: String,
+        salt: String,
+    },
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq)]
+pub struct Crypto {
+    pub cipher: String,
+    pub cipherparams: CipherParams,
+    pub ciphertext: String,
+    pub kdf: String,
+    pub kdfparams: KdfParams,
+    pub mac: String,
+}
+
+/// The scrypt parameters used to encrypt a key.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ScryptParams {
+    pub log_n: u8,
+    pub r: u32,
+    pub p: u32,
+}
+
+impl ScryptParams {
+    /// `n = 2^12`, only used for test or low security requirement, the same
+    /// as the light params of ckb-cli and geth
+    pub fn light() -> ScryptParams {
+        ScryptParams {
+            log_n: 12,
+            r: 8,
+            p: 1,
+        }
+    }
+}
+
+impl Default for ScryptParams {
+    /// `n = 2^18`, the same as ckb-cli and geth
+    fn default() -> ScryptParams {
+        ScryptParams {
+            log_n: 18,
+            r: 8,
+            p: 1,
+        }
+    }
+}
+
+/// A keystore file
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq)]
+pub struct Keystore {
+    pub crypto: Crypto,
+    pub id: String,
+    pub version: u32,
+    /// `blake160(pubkey)` of the key, it is an extension field written by this library
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash160: Option<H160>,
+}
+
+impl Keystore {
+    /// Encrypt a secret key (ethereum compatible layout)
+    pub fn encrypt_secret_key(
+        key: &secp256k1::SecretKey,
+        password: &[u8],
+        params: ScryptParams,
+    ) -> Result<Keystore, KeystoreError> {
+        let mut data = key.secret_bytes();
+        let keystore = Keystore::encrypt(&data, password, params);
+        zeroize_slice(&mut data);
+        let mut keystore = keystore?;
+        keystore.hash160 = Some(pubkey_hash(key));
+        Ok(keystore)
+    }
+
+    /// Encrypt a BIP32 master key (ckb-cli compatible layout)
+    pub fn encrypt_master_key(
+        key: &ExtendedPrivKey,
+        password: &[u8],
+        params: ScryptParams,
+    ) -> Result<Keystore, KeystoreError> {
+        let mut data = [0u8; 64];
+        data[0..32].copy_from_slice(&key.private_key.secret_bytes());
+        data[32..64].copy_from_slice(&key.chain_code);
+        let keystore = Keystore::encrypt(&data, password, params);
+        zeroize_slice(&mut data);
+        let mut keystore = keystore?;
+        keystore.hash160 = Some(pubkey_hash(&key.private_key));
+        Ok(keystore)
+    }
+
+    fn encrypt(
+        data: &[u8],
+        password: &[u8],
+        params: ScryptParams,
+    ) -> Result<Keystore, KeystoreError> {
+        let mut salt = [0u8; 32];
+        let mut iv = [0u8; 16];
+        let mut id = [0u8; 16];
+        getrandom::getrandom(&mut salt).map_err(|err| anyhow::anyhow!(err))?;
+        getrandom::getrandom(&mut iv).map_err(|err| anyhow::anyhow!(err))?;
+        getrandom::getrandom(&mut id).map_err(|err| anyhow::anyhow!(err))?;
+        let kdfparams = KdfParams::Scrypt {
+            dklen: DKLEN,
+            n: 1 << params.log_n,
+            p: params.p,
+            r: params.r,
+            salt: hex::encode(salt),
+        };
+        let mut derived_key = derive_key(&kdfparams, password)?;
+        let mut ciphertext = data.to_vec();
+        Aes128Ctr::new_from_slices(&derived_key[0..16], &iv)
+            .expect("aes-128-ctr key and iv length")
+            .apply_keystream(&mut ciphertext);
+        let mac = calc_mac(&derived_key, &ciphertext);
+        zeroize_slice(&mut derived_key);
+
+        // uuid version 4
+        id[6] = (id[6] & 0x0f) | 0x40;
+        id[8] = (id[8] & 0x3f) | 0x80;
+        let id = hex::encode(id);
+        Ok(Keystore {
+            crypto: Crypto {
+                cipher: CIPHER_AES_128_CTR.to_string(),
+                cipherparams: CipherParams {
+                    iv: hex::encode(iv),
+                },
+                ciphertext: hex::encode(ciphertext),
+                kdf: KDF_SCRYPT.to_string(),
+                kdfparams,
+                mac: hex::encode(mac),
+            },
+            id: format!(
+                "{}-{}-{}-{}-{}",
+                &id[0..8],
+                &id[8..12],
+                &id[12..16],
+                &id[16..20],
+                &id[20..32]
+            ),
+            version: KEYSTORE_VERSION,
+            hash160: None,
+        })
+    }
+
+    /// Decrypt the key data, the caller should zeroize the result after use.
+    pub fn decrypt(&self, password: &[u8]) -> Result<Vec<u8>, KeystoreError> {
+        if self.version != KEYSTORE_VERSION {
+            return Err(KeystoreError::UnsupportedVersion(self.version));
+        }
+        if self.crypto.cipher != CIPHER_AES_128_CTR {
+            return Err(KeystoreError::UnsupportedCipher(self.crypto.cipher.clone()));
+        }
+        let kdf_matched = matches!(
+            (self.crypto.kdf.as_str(), &self.crypto.kdfparams),
+            (KDF_SCRYPT, KdfParams::Scrypt { .. }) | (KDF_PBKDF2, KdfParams::Pbkdf2 { .. })
+        );
+        if !kdf_matched {
+            return Err(KeystoreError::UnsupportedKdf(self.crypto.kdf.clone()));
+        }
+        let iv = hex::decode(&self.crypto.cipherparams.iv)?;
+        if iv.len() != 16 {
+            return Err(KeystoreError::UnsupportedCipher(format!(
+                "invalid iv length: {}",
+                iv.len()
+            )));
+        }
+        let ciphertext = hex::decode(&self.crypto.ciphertext)?;
+        let mac = hex::decode(&self.crypto.mac)?;
+
+        let mut derived_key = derive_key(&self.crypto.kdfparams, password)?;
+        if calc_mac(&derived_key, &ciphertext)[..] != mac[..] {
+            zeroize_slice(&mut derived_key);
+            return Err(KeystoreError::WrongPassword);
+        }
+        let mut data = ciphertext;
+        Aes128Ctr::new_from_slices(&derived_key[0..16], &iv)
+            .expect("aes-128-ctr key and iv length")
+            .apply_keystream(&mut data);
+        zeroize_slice(&mut derived_key);
+        Ok(data)
+    }
+
+    /// Decrypt the secret key, for ckb-cli keystore it's the master secret key.
+    pub fn decrypt_secret_key(
+        &self,
+        password: &[u8],
+    ) -> Result<secp256k1::SecretKey, KeystoreError> {
+        let mut data = self.decrypt(password)?;
+        let key = match data.len() {
+            32 | 64 => secp256k1::SecretKey::from_slice(&data[0..32]),
+            _ => Err(secp256k1::Error::InvalidSecretKey),
+        };
+        zeroize_slice(&mut data);
+        Ok(key?)
+    }
+
+    /// Decrypt the BIP32 master key of ckb-cli keystore.
+    pub fn decrypt_master_key(&self, password: &[u8]) -> Result<ExtendedPrivKey, KeystoreError> {
+        let mut data = self.decrypt(password)?;
+        if data.len() != 64 {
+            zeroize_slice(&mut data);
+            return Err(KeystoreError::Other(anyhow::anyhow!(
+                "the keystore does not contain a master key"
+            )));
+        }
+        let private_key = secp256k1::SecretKey::from_slice(&data[0..32]);
+        let mut chain_code = [0u8; 32];
+        chain_code.copy_from_slice(&data[32..64]);
+        zeroize_slice(&mut data);
+        Ok(ExtendedPrivKey {
+            depth: 0,
+            child_number: 0.into(),
+            chain_code,
+            private_key: private_key?,
+        })
+    }
+
+    pub fn from_json_str(json: &str) -> Result<Keystore, KeystoreError> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn to_json_string(&self) -> Result<String, KeystoreError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Keystore, KeystoreError> {
+        Keystore::from_json_str(&fs::read_to_string(path)?)
+    }
+
+    /// Save the keystore to a file only readable and writable by the owner.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), KeystoreError> {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options.open(path)?;
+        // The mode only applies to a new file
+        #[cfg(unix)]
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        file.write_all(self.to_json_string()?.as_bytes())?;
+        Ok(())
+    }
+}
+
+fn pubkey_hash(key: &secp256k1::SecretKey) -> H160 {
+    let pubkey = secp256k1::PublicKey::from_secret_key(&SECP256K1, key);
+    blake160(&pubkey.serialize())
+}
+
+fn derive_key(kdfparams: &KdfParams, password: &[u8]) -> Result<Vec<u8>, KeystoreError> {
+    match kdfparams {
+        KdfParams::Scrypt {
+            dklen,
+            n,
+            p,
+            r,
+            salt,
+        } => {
+            if *dklen != DKLEN || !n.is_power_of_two() || *n < 2 {
+                return Err(KeystoreError::InvalidKdfParams(format!(
+                    "dklen: {}, n: {}",
+                    dklen, n
+                )));
+            }
+            let memory = 128u64
+                .saturating_mul(u64::from(*r))
+                .saturating_mul(u64::from(*n))
+                .saturating_mul(u64::from(*p));
+            if memory > MAX_SCRYPT_MEMORY {
+                return Err(KeystoreError::InvalidKdfParams(format!(
+                    "n: {}, r: {}, p: {}, the memory exceeds {} bytes",
+                    n, r, p, MAX_SCRYPT_MEMORY
+                )));
+            }
+            let log_n = n.trailing_zeros() as u8;
+            let params = scrypt::Params::new(log_n, *r, *p)
+                .map_err(|err| KeystoreError::InvalidKdfParams(err.to_string()))?;
+            let mut derived_key = vec![0u8; *dklen as usize];
+            scrypt::scrypt(password, &hex::decode(salt)?, &params, &mut derived_key)
+                .map_err(|err| KeystoreError::InvalidKdfParams(err.to_string()))?;
+            Ok(derived_key)
+        }
+        KdfParams::Pbkdf2 {
+            c,
+            dklen,
+            prf,
+            salt,
+        } => {
+            if *dklen != DKLEN || prf != PRF_HMAC_SHA256 {
+                return Err(KeystoreError::InvalidKdfParams(format!(
+                    "dklen: {}, prf: {}",
+                    dklen, prf
+                )));
+            }
+            if *c == 0 || *c > MAX_PBKDF2_ROUNDS {
+                return Err(KeystoreError::InvalidKdfParams(format!("c: {}", c)));
+            }
+            let mut derived_key = vec![0u8; *dklen as usize];
+            pbkdf2::pbkdf2::<Hmac<Sha256>>(password, &hex::decode(salt)?, *c, &mut derived_key);
+            Ok(derived_key)
+        }
+    }
+}
+
+fn calc_mac(derived_key: &[u8], ciphertext: &[u8]) -> [u8; 32] {
+    let mut hasher = Keccak256::new();
+    hasher.update(&derived_key[16..32]);
+    hasher.update(ciphertext);
+    hasher.finalize().into()
+}
+
+/// Ask the password of the account, return `None` to refuse unlocking.
+pub type PasswordCallback = Box<dyn Fn(&H160) -> Option<String> + Send + Sync>;
+
+/// A signer holds encrypted keystores, the id is `blake160(pubkey)`.
+///
+/// An account is unlocked on demand by the password from the callback, the
+/// decrypted key is kept until `unlock_timeout` elapsed or `lock` is called.
+pub struct KeystoreSigner {
+    keystores: HashMap<H160, Keystore>,
+    unlocked: Mutex<HashMap<H160, (secp256k1::SecretKey, Instant)>>,
+    password_callback: PasswordCallback,
+    unlock_timeout: Duration,
+}
+
+impl KeystoreSigner {
+    pub fn new(password_callback: PasswordCallback, unlock_timeout: Duration) -> KeystoreSigner {
+        KeystoreSigner {
+            keystores: HashMap::default(),
+            unlocked: Mutex::new(HashMap::default()),
+            password_callback,
+            unlock_timeout,
+        }
+    }
+
+    /// Add a keystore with `hash160` field, return the id.
+    pub fn add_keystore(&mut self, keystore: Keystore) -> Result<H160, KeystoreError> {
+        let id = keystore.hash160.clone().ok_or_else(|| {
+            KeystoreError::Other(anyhow::anyhow!("hash160 field is missing in keystore"))
+        })?;
+        self.keystores.insert(id.clone(), keystore);
+        Ok(id)
+    }
+
+    /// Add a keystore with the given id (e.g. an ethereum keystore).
+    pub fn add_keystore_with_id(&mut self, id: H160, keystore: Keystore) {
+        self.keystores.insert(id, keystore);
+    }
+
+    /// Unlock the account by password, the decrypted key must match the id.
+    pub fn unlock(&self, id: &H160, password: &[u8]) -> Result<(), KeystoreError> {
+        let keystore = self
+            .keystores
+            .get(id)
+            .ok_or_else(|| KeystoreError::AccountLocked(id.clone()))?;
+        let mut key = keystore.decrypt_secret_key(password)?;
+        if &pubkey_hash(&key) != id {
+            zeroize_privkey(&mut key);
+            return Err(KeystoreError::WrongPassword);
+        }
+        let expire_at = Instant::now() + self.unlock_timeout;
+        if let Some((mut old_key, _)) = self.unlocked.lock().insert(id.clone(), (key, expire_at)) {
+            zeroize_privkey(&mut old_key);
+        }
+        Ok(())
+    }
+
+    pub fn is_unlocked(&self, id: &H160) -> bool {
+        self.unlocked
+            .lock()
+            .get(id)
+            .map(|(_, expire_at)| *expire_at > Instant::now())
+            .unwrap_or(false)
+    }
+
+    pub fn lock(&self, id: &H160) {
+        if let Some((mut key, _)) = self.unlocked.lock().remove(id) {
+            zeroize_privkey(&mut key);
+        }
+    }
+
+    pub fn lock_all(&self) {
+        for (_, (mut key, _)) in self.unlocked.lock().drain() {
+            zeroize_privkey(&mut key);
+        }
+    }
+
+    fn unlocked_key(&self, id: &H160) -> Result<secp256k1::SecretKey, KeystoreError> {
+        {
+            let mut unlocked = self.unlocked.lock();
+            let expired = match unlocked.get(id) {
+                Some((key, expire_at)) if *expire_at > Instant::now() => return Ok(*key),
+                Some(_) => true,
+                None => false,
+            };
+            if expired {
+                if let Some((mut key, _)) = unlocked.remove(id) {
+                    zeroize_privkey(&mut key);
+                }
+            }
+        }
+        let password =
+            (self.password_callback)(id).ok_or_else(|| KeystoreError::AccountLocked(id.clone()))?;
+        let mut password = password.into_bytes();
+        let result = self.unlock(id, &password);
+        zeroize_slice(&mut password);
+        result?;
+        self.unlocked
+            .lock()
+            .get(id)
+            .map(|(key, _)| *key)
+            .ok_or_else(|| KeystoreError::AccountLocked(id.clone()))
+    }
+}
+
+impl Signer for KeystoreSigner {
+    fn match_id(&self, id: &[u8]) -> bool {
+        id.len() == 20 && self.keystores.contains_key(&H160::from_slice(id).unwrap())
+    }
+
+    fn sign(
+        &self,
+        id: &[u8],
+        message: &[u8],
+        recoverable: bool,
+        tx: &TransactionView,
+    ) -> Result<Bytes, SignerError> {
+        if !self.match_id(id) {
+            return Err(SignerError::IdNotFound);
+        }
+        let mut key = self
+            .unlocked_key(&H160::from_slice(id).unwrap())
+            .map_err(|err| SignerError::Other(anyhow::anyhow!(err)))?;
+        let result =
+            SecpCkbRawKeySigner::new_with_secret_keys(vec![key]).sign(id, message, recoverable, tx);
+        zeroize_privkey(&mut key);
+        result
+    }
+}
+
+impl Drop for KeystoreSigner {
+    fn drop(&mut self) {
+        self.lock_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ckb_types::core::TransactionBuilder;
+
+    #[test]
+    fn test_decrypt_pbkdf2_vector() {
+        // The pbkdf2 test vector of Web3 Secret Storage Definition
+        let json = r#"{
+            "crypto" : {
+                "cipher" : "aes-128-ctr",
+                "cipherparams" : {
+                    "iv" : "6087dab2f9fdbbfaddc31a909735c1e6"
+                },
+                "ciphertext" : "5318b4d5bcd28de64ee5559e671353e16f075ecae9f99c7a79a38af5f869aa46",
+                "kdf" : "pbkdf2",
+                "kdfparams" : {
+                    "c" : 262144,
+                    "dklen" : 32,
+                    "prf" : "hmac-sha256",
+                    "salt" : "ae3cd4e7013836a3df6bd7241b12db061dbe2c6785853cce422d148a624ce0bd"
+                },
+                "mac" : "517ead924a9d0dc3124507e3393d175ce3ff7c1e96529c6c555ce9e51205e9b2"
+            },
+            "id" : "3198bc9c-6672-5ab3-d995-4942343ae5b6",
+            "version" : 3
+        }"#;
+        let keystore = Keystore::from_json_str(json).unwrap();
+        let key = keystore.decrypt_secret_key(b"testpassword").unwrap();
+        assert_eq!(
+            hex::encode(key.secret_bytes()),
+            "7a28b5ba57c53603b0b07b56bba752f7784bf506fa95edc395f5cf6c7514fe9d"
+        );
+        assert!(matches!(
+            keystore.decrypt(b"wrong"),
+            Err(KeystoreError::WrongPassword)
+        ));
+    }
+
+    #[test]
+    fn test_kdf_params_cost_capped() {
+        let kdfparams = KdfParams::Pbkdf2 {
+            c: MAX_PBKDF2_ROUNDS + 1,
+            dklen: DKLEN,
+            prf: PRF_HMAC_SHA256.to_string(),
+            salt: hex::encode([0u8; 32]),
+        };
+        assert!(matches!(
+            derive_key(&kdfparams, b"password"),
+            Err(KeystoreError::InvalidKdfParams(_))
+        ));
+        // 128 * r * n * p = 2 GiB
+        let kdfparams = KdfParams::Scrypt {
+            dklen: DKLEN,
+            n: 1 << 20,
+            p: 2,
+            r: 8,
+            salt: hex::encode([0u8; 32]),
+        };
+        assert!(matches!(
+            derive_key(&kdfparams, b"password"),
+            Err(KeystoreError::InvalidKdfParams(_))
+        ));
+        let kdfparams = KdfParams::Scrypt {
+            dklen: DKLEN,
+            n: 1 << 20,
+            p: u32::MAX,
+            r: u32::MAX,
+            salt: hex::encode([0u8; 32]),
+        };
+        assert!(matches!(
+            derive_key(&kdfparams, b"password"),
+            Err(KeystoreError::InvalidKdfParams(_))
+        ));
+    }
+
+    #[test]
+    fn test_save_keystore() {
+        let key = secp256k1::SecretKey::from_slice(&[7u8; 32]).unwrap();
+        let keystore =
+            Keystore::encrypt_secret_key(&key, b"password", ScryptParams::light()).unwrap();
+        let path =
+            std::env::temp_dir().join(format!("ckb-sdk-keystore-{}.json", std::process::id()));
+        keystore.save(&path).unwrap();
+        #[cfg(unix)]
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+        assert_eq!(Keystore::load(&path).unwrap(), keystore);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_keystore_signer() {
+        let key = secp256k1::SecretKey::from_slice(&[7u8; 32]).unwrap();
+        let keystore =
+            Keystore::encrypt_secret_key(&key, b"password", ScryptParams::light()).unwrap();
+        let keystore = Keystore::from_json_str(&keystore.to_json_string().unwrap()).unwrap();
+
+        let mut signer = KeystoreSigner::new(
+            Box::new(|_id: &H160| Some("password".to_string())),
+            Duration::from_secs(60),
+        );
+        let id = signer.add_keystore(keystore).unwrap();
+        assert_eq!(id, pubkey_hash(&key));
+        assert!(signer.match_id(id.as_bytes()));
+        assert!(!signer.is_unlocked(&id));
+
+        let tx = TransactionBuilder::default().build();
+        let message = [1u8; 32];
+        let expected = SecpCkbRawKeySigner::new_with_secret_keys(vec![key])
+            .sign(id.as_bytes(), &message, true, &tx)
+            .unwrap();
+        assert_eq!(
+            signer.sign(id.as_bytes(), &message, true, &tx).unwrap(),
+            expected
+        );
+        assert!(signer.is_unlocked(&id));
+        signer.lock(&id);
+        assert!(!signer.is_unlocked(&id));
+        assert!(matches!(
+            signer.unlock(&id, b"wrong"),
+            Err(KeystoreError::WrongPassword)
+        ));
+
+        let mut signer = KeystoreSigner::new(Box::new(|_id: &H160| None), Duration::from_secs(60));
+        signer.add_keystore_with_id(
+            id.clone(),
+            Keystore::encrypt_secret_key(&key, b"password", ScryptParams::light()).unwrap(),
+        );
+        assert!(signer.sign(id.as_bytes(), &message, true, &tx).is_err());
+    }
+}
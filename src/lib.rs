@@ -1,10 +1,16 @@
+pub mod balance;
 pub mod constants;
 pub mod core;
+pub mod history;
+pub mod indexer;
+pub mod keystore;
+pub mod message;
 pub mod pubsub;
 pub mod rpc;
 pub mod traits;
 pub mod transaction;
 pub mod tx_builder;
+pub mod tx_proof;
 pub mod types;
 pub mod unlock;
 pub mod util;
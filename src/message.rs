@@ -0,0 +1,256 @@
+//! Off-chain message signing, e.g. login challenges and ownership proofs.
+//!
+//! The message is hashed as `blake2b_256("Nervos Message:" | message)` (ckb
+//! personalization), so a signed message is never a valid transaction
+//! signature. The Nervos Ledger app signs the same hash.
+//!
+//! The signature is the 65 bytes recoverable secp256k1 signature `r | s | v`,
+//! by the key of the address:
+//!   * sighash: the key of `blake160(pubkey)`
+//!   * Omnilock `PubkeyHash`: the same as sighash
+//!   * Omnilock `Ethereum`: the key of `keccak160(pubkey)`, the hash is wrapped
+//!     by [`convert_keccak256_hash`] like `personal_sign`
+
+use ckb_hash::new_blake2b;
+use ckb_types::{
+    bytes::Bytes,
+    core::{ScriptHashType, TransactionBuilder},
+    prelude::*,
+    H160,
+};
+use secp256k1::{
+    ecdsa::{RecoverableSignature, RecoveryId},
+    PublicKey,
+};
+use thiserror::Error;
+
+use crate::{
+    constants::{SECP_SIGNATURE_SIZE, SIGHASH_TYPE_HASH},
+    traits::{Signer, SignerError},
+    types::{Address, ScriptId},
+    unlock::{omni_lock::Identity, IdentityFlag},
+    util::{blake160, convert_keccak256_hash, keccak160},
+    SECP256K1,
+};
+
+/// The prefix of the signed message
+pub const MESSAGE_PREFIX: &[u8] = b"Nervos Message:";
+
+#[derive(Error, Debug)]
+pub enum MessageSignError {
+    #[error("unsupported lock: `{0}`")]
+    UnsupportedLock(String),
+
+    #[error("invalid signature: `{0}`")]
+    InvalidSignature(String),
+
+    #[error("the signature is not signed by the address")]
+    NotMatch,
+
+    #[error(transparent)]
+    Signer(#[from] SignerError),
+}
+
+/// The key of an address used to sign messages.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum MessageKey {
+    /// `blake160(pubkey)`, of sighash or Omnilock `PubkeyHash`
+    PubkeyHash(H160),
+    /// `keccak160(pubkey)`, of Omnilock `Ethereum`
+    Ethereum(H160),
+}
+
+impl MessageKey {
+    /// Resolve the key of the address, `omnilock` is the script id of
+    /// Omnilock on the network of the address if Omnilock is accepted.
+    pub fn from_address(
+        address: &Address,
+        omnilock: Option<&ScriptId>,
+    ) -> Result<MessageKey, MessageSignError> {
+        let payload = address.payload();
+        let code_hash = payload.code_hash(Some(address.network()));
+        let hash_type = payload.hash_type();
+        let args = payload.args();
+        if code_hash.as_slice() == SIGHASH_TYPE_HASH.as_bytes() && hash_type == ScriptHashType::Type
+        {
+            if args.len() != 20 {
+                return Err(MessageSignError::UnsupportedLock(format!(
+                    "invalid sighash args length: {}",
+                    args.len()
+                )));
+            }
+            return Ok(MessageKey::PubkeyHash(H160::from_slice(&args).unwrap()));
+        }
+        if let Some(omnilock) = omnilock {
+            if code_hash.as_slice() == omnilock.code_hash.as_bytes()
+                && hash_type == omnilock.hash_type
+            {
+                let identity =
+                    Identity::from_slice(&args).map_err(MessageSignError::UnsupportedLock)?;
+                return match identity.flag() {
+                    IdentityFlag::PubkeyHash => {
+                        Ok(MessageKey::PubkeyHash(identity.auth_content().clone()))
+                    }
+                    IdentityFlag::Ethereum => {
+                        Ok(MessageKey::Ethereum(identity.auth_content().clone()))
+                    }
+                    flag => Err(MessageSignError::UnsupportedLock(format!(
+                        "omnilock identity flag: {:?}",
+                        flag
+                    ))),
+                };
+            }
+        }
+        Err(MessageSignError::UnsupportedLock(address.to_string()))
+    }
+
+    /// The signer id of the key.
+    pub fn id(&self) -> &H160 {
+        match self {
+            MessageKey::PubkeyHash(id) | MessageKey::Ethereum(id) => id,
+        }
+    }
+
+    /// The hash actually signed by the key.
+    pub fn signing_hash(&self, message: &[u8]) -> [u8; 32] {
+        let hash = hash_message(message);
+        match self {
+            MessageKey::PubkeyHash(_) => hash,
+            MessageKey::Ethereum(_) => convert_keccak256_hash(&hash).0,
+        }
+    }
+
+    fn pubkey_id(&self, pubkey: &PublicKey) -> H160 {
+        match self {
+            MessageKey::PubkeyHash(_) => blake160(&pubkey.serialize()),
+            MessageKey::Ethereum(_) => keccak160(&pubkey.serialize_uncompressed()[1..]),
+        }
+    }
+}
+
+/// `blake2b_256("Nervos Message:" | message)`
+pub fn hash_message(message: &[u8]) -> [u8; 32] {
+    let mut blake2b = new_blake2b();
+    blake2b.update(MESSAGE_PREFIX);
+    blake2b.update(message);
+    let mut hash = [0u8; 32];
+    blake2b.finalize(&mut hash);
+    hash
+}
+
+/// Sign the message by the key with any [`Signer`], the transaction passed to
+/// the signer is empty.
+pub fn sign_message(
+    signer: &dyn Signer,
+    key: &MessageKey,
+    message: &[u8],
+) -> Result<Bytes, MessageSignError> {
+    let id = key.id().as_bytes();
+    if !signer.match_id(id) {
+        return Err(SignerError::IdNotFound.into());
+    }
+    let tx = TransactionBuilder::default().build();
+    let signature = signer.sign(id, &key.signing_hash(message), true, &tx)?;
+    if signature.len() != SECP_SIGNATURE_SIZE {
+        return Err(MessageSignError::InvalidSignature(format!(
+            "signature length: {}",
+            signature.len()
+        )));
+    }
+    Ok(signature)
+}
+
+/// Recover the public key from the signature.
+pub fn recover_message_pubkey(
+    key: &MessageKey,
+    message: &[u8],
+    signature: &[u8],
+) -> Result<PublicKey, MessageSignError> {
+    if signature.len() != SECP_SIGNATURE_SIZE {
+        return Err(MessageSignError::InvalidSignature(format!(
+            "signature length: {}",
+            signature.len()
+        )));
+    }
+    let recid = RecoveryId::from_i32(signature[64] as i32)
+        .map_err(|err| MessageSignError::InvalidSignature(err.to_string()))?;
+    let signature = RecoverableSignature::from_compact(&signature[0..64], recid)
+        .map_err(|err| MessageSignError::InvalidSignature(err.to_string()))?;
+    let msg = secp256k1::Message::from_slice(&key.signing_hash(message))
+        .expect("Convert to message failed");
+    SECP256K1
+        .recover_ecdsa(&msg, &signature)
+        .map_err(|err| MessageSignError::InvalidSignature(err.to_string()))
+}
+
+/// Verify the message is signed by the address, return the public key of
+/// the signer.
+pub fn verify_message(
+    address: &Address,
+    omnilock: Option<&ScriptId>,
+    message: &[u8],
+    signature: &[u8],
+) -> Result<PublicKey, MessageSignError> {
+    let key = MessageKey::from_address(address, omnilock)?;
+    let pubkey = recover_message_pubkey(&key, message, signature)?;
+    if &key.pubkey_id(&pubkey) != key.id() {
+        return Err(MessageSignError::NotMatch);
+    }
+    Ok(pubkey)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ckb_types::{h256, packed::Script};
+
+    use crate::{traits::SecpCkbRawKeySigner, unlock::OmniLockConfig, AddressPayload, NetworkType};
+
+    #[test]
+    fn test_sign_message() {
+        let key = secp256k1::SecretKey::from_slice(&[5u8; 32]).unwrap();
+        let pubkey = PublicKey::from_secret_key(&SECP256K1, &key);
+        let message = b"login challenge: 42";
+
+        // sighash
+        let address = Address::new(
+            NetworkType::Testnet,
+            AddressPayload::from_pubkey(&pubkey),
+            true,
+        );
+        let message_key = MessageKey::from_address(&address, None).unwrap();
+        let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![key]);
+        let signature = sign_message(&signer, &message_key, message).unwrap();
+        assert_eq!(
+            verify_message(&address, None, message, &signature).unwrap(),
+            pubkey
+        );
+        assert!(matches!(
+            verify_message(&address, None, b"another message", &signature),
+            Err(MessageSignError::NotMatch)
+        ));
+
+        // Omnilock ethereum
+        let omnilock = ScriptId::new_type(h256!("0x1234"));
+        let config = OmniLockConfig::new_ethereum(keccak160(&pubkey.serialize_uncompressed()[1..]));
+        let script = Script::new_builder()
+            .code_hash(omnilock.code_hash.pack())
+            .hash_type(omnilock.hash_type.into())
+            .args(config.build_args().pack())
+            .build();
+        let address = Address::new(NetworkType::Testnet, AddressPayload::from(script), true);
+        assert!(matches!(
+            MessageKey::from_address(&address, None),
+            Err(MessageSignError::UnsupportedLock(_))
+        ));
+        let message_key = MessageKey::from_address(&address, Some(&omnilock)).unwrap();
+        assert!(matches!(message_key, MessageKey::Ethereum(_)));
+        assert!(sign_message(&signer, &message_key, message).is_err());
+        let signer = SecpCkbRawKeySigner::new_with_ethereum_secret_keys(vec![key]);
+        let signature = sign_message(&signer, &message_key, message).unwrap();
+        assert_eq!(
+            verify_message(&address, Some(&omnilock), message, &signature).unwrap(),
+            pubkey
+        );
+    }
+}
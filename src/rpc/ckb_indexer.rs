@@ -6,7 +6,7 @@ use ckb_jsonrpc_types::{
 use ckb_types::H256;
 use serde::{Deserialize, Serialize};
 
-use crate::traits::{CellQueryOptions, LiveCell, PrimaryScriptType, ValueRangeOption};
+use crate::traits::{CellQueryOptions, DataFilter, LiveCell, PrimaryScriptType, ValueRangeOption};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct SearchKey {
@@ -33,20 +33,46 @@ impl Default for ScriptSearchMode {
     }
 }
 
+/// The match mode of the output data filter
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputDataFilterMode {
+    /// The data starts with the filter
+    Prefix,
+    /// The data equals to the filter
+    Exact,
+    /// The data contains the filter
+    Partial,
+}
+
 #[derive(Serialize, Deserialize, Default, Clone, Debug)]
 pub struct SearchKeyFilter {
     pub script: Option<Script>,
     pub script_len_range: Option<[Uint64; 2]>,
+    // Skipped if not set, for the nodes not supporting it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_data: Option<JsonBytes>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_data_filter_mode: Option<OutputDataFilterMode>,
     pub output_data_len_range: Option<[Uint64; 2]>,
     pub output_capacity_range: Option<[Uint64; 2]>,
     pub block_range: Option<[BlockNumber; 2]>,
 }
+/// The data filter by data hash and the extra primary scripts are not
+/// supported by the indexer, they are left to `CellQueryOptions::match_cell`.
 impl From<CellQueryOptions> for SearchKey {
     fn from(opts: CellQueryOptions) -> SearchKey {
         let convert_range =
             |range: ValueRangeOption| [Uint64::from(range.start), Uint64::from(range.end)];
+        let (output_data, output_data_filter_mode) = match opts.data_filter.as_ref() {
+            Some(DataFilter::Prefix(data)) => (Some(data), Some(OutputDataFilterMode::Prefix)),
+            Some(DataFilter::Exact(data)) => (Some(data), Some(OutputDataFilterMode::Exact)),
+            Some(DataFilter::Partial(data)) => (Some(data), Some(OutputDataFilterMode::Partial)),
+            Some(DataFilter::Hash(_)) | None => (None, None),
+        };
         let filter = if opts.secondary_script.is_none()
             && opts.secondary_script_len_range.is_none()
+            && output_data.is_none()
             && opts.data_len_range.is_none()
             && opts.capacity_range.is_none()
             && opts.block_range.is_none()
@@ -56,23 +82,46 @@ impl From<CellQueryOptions> for SearchKey {
             Some(SearchKeyFilter {
                 script: opts.secondary_script.map(|v| v.into()),
                 script_len_range: opts.secondary_script_len_range.map(convert_range),
+                output_data: output_data.map(|data| JsonBytes::from_bytes(data.clone())),
+                output_data_filter_mode,
                 output_data_len_range: opts.data_len_range.map(convert_range),
                 output_capacity_range: opts.capacity_range.map(convert_range),
                 block_range: opts.block_range.map(convert_range),
             })
         };
+        // The data is required to filter by data
+        let with_data = if opts.data_filter.is_some() {
+            Some(true)
+        } else {
+            opts.with_data
+        };
         SearchKey {
             script: opts.primary_script.into(),
             script_type: opts.primary_type.into(),
             script_search_mode: opts.script_search_mode,
             filter,
-            with_data: opts.with_data,
+            with_data,
             group_by_transaction: None,
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+impl SearchKey {
+    /// The search key with the output data filter cleared, `None` if it has
+    /// no such filter. The nodes not supporting the filter reject the query.
+    pub fn without_output_data(&self) -> Option<SearchKey> {
+        let filter = self.filter.as_ref()?;
+        filter.output_data.as_ref()?;
+        let mut key = self.clone();
+        if let Some(filter) = key.filter.as_mut() {
+            filter.output_data = None;
+            filter.output_data_filter_mode = None;
+        }
+        Some(key)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, Hash)]
 #[serde(rename_all = "snake_case")]
 pub enum ScriptType {
     Lock,
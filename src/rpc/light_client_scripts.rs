@@ -0,0 +1,434 @@
+//! Manage the scripts a light client is syncing.
+//!
+//! The light client only knows the cells of the scripts registered by
+//! `set_scripts`, and each script is synced from its own block number up to
+//! the tip. [`LightClientScriptManager`] merges and removes scripts without
+//! clobbering the other registered scripts, chooses the start block number of
+//! new scripts, and reports the sync progress so callers can wait until the
+//! light client caught up before trusting a balance.
+//!
+//! `set_scripts` replaces the whole script list, so two managers updating the
+//! same light client concurrently may lose each other's changes.
+
+use std::{
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use ckb_types::packed::Script;
+use futures::{channel::oneshot, Future, FutureExt};
+use thiserror::Error;
+
+use super::{
+    ckb_light_client::{ScriptStatus, ScriptType},
+    LightClientRpcClient, RpcError,
+};
+
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Error, Debug)]
+pub enum ScriptManagerError {
+    #[error("rpc error: `{0}`")]
+    Rpc(#[from] RpcError),
+    #[error("scripts not synced in {timeout:?}: {progress}")]
+    Timeout {
+        timeout: Duration,
+        progress: SyncProgress,
+    },
+    #[error("waiting thread exited unexpectedly")]
+    Canceled,
+}
+
+/// The block number a new script starts syncing from.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum StartPoint {
+    /// The current tip of the light client, for scripts never used before
+    Tip,
+    /// The genesis block, to find all the history of the script
+    Genesis,
+    Number(u64),
+}
+
+impl Default for StartPoint {
+    fn default() -> Self {
+        StartPoint::Tip
+    }
+}
+
+/// The sync progress of a set of watched scripts.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SyncProgress {
+    pub tip_number: u64,
+    /// The lowest block number synced by the watched scripts, `None` when no
+    /// watched script is registered
+    pub synced_number: Option<u64>,
+    /// The watched scripts not registered in the light client
+    pub missing: Vec<(Script, ScriptType)>,
+}
+
+impl SyncProgress {
+    /// All watched scripts are registered and synced to at most `leftbehind`
+    /// blocks behind the tip.
+    pub fn is_synced(&self, leftbehind: u64) -> bool {
+        self.missing.is_empty()
+            && self
+                .synced_number
+                .map(|number| number.saturating_add(leftbehind) >= self.tip_number)
+                .unwrap_or(true)
+    }
+
+    /// The number of blocks still to sync
+    pub fn remaining_blocks(&self) -> u64 {
+        self.synced_number
+            .map(|number| self.tip_number.saturating_sub(number))
+            .unwrap_or(0)
+    }
+}
+
+impl std::fmt::Display for SyncProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "tip: {}, synced: {:?}, missing scripts: {}",
+            self.tip_number,
+            self.synced_number,
+            self.missing.len()
+        )
+    }
+}
+
+fn is_same_script(status: &ScriptStatus, script: &Script, script_type: &ScriptType) -> bool {
+    &status.script_type == script_type && &Script::from(status.script.clone()) == script
+}
+
+/// Merge `new_scripts` into the `current` registered scripts. A script
+/// already registered keeps the lower block number, so adding it again with
+/// an earlier start rescans the missed blocks. Returns whether the list
+/// changed.
+pub(crate) fn merge_scripts(current: &mut Vec<ScriptStatus>, new_scripts: &[ScriptStatus]) -> bool {
+    let mut changed = false;
+    for new_status in new_scripts {
+        let script = Script::from(new_status.script.clone());
+        match current
+            .iter_mut()
+            .find(|status| is_same_script(status, &script, &new_status.script_type))
+        {
+            Some(status) => {
+                if new_status.block_number.value() < status.block_number.value() {
+                    status.block_number = new_status.block_number;
+                    changed = true;
+                }
+            }
+            None => {
+                current.push(new_status.clone());
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+/// Remove `scripts` from the `current` registered scripts. Returns whether the
+/// list changed.
+pub(crate) fn remove_scripts(
+    current: &mut Vec<ScriptStatus>,
+    scripts: &[(Script, ScriptType)],
+) -> bool {
+    let len = current.len();
+    current.retain(|status| {
+        !scripts
+            .iter()
+            .any(|(script, script_type)| is_same_script(status, script, script_type))
+    });
+    current.len() != len
+}
+
+pub(crate) fn sync_progress(
+    registered: &[ScriptStatus],
+    watched: &[(Script, ScriptType)],
+    tip_number: u64,
+) -> SyncProgress {
+    let mut synced_number: Option<u64> = None;
+    let mut missing = Vec::new();
+    for (script, script_type) in watched {
+        match registered
+            .iter()
+            .find(|status| is_same_script(status, script, script_type))
+        {
+            Some(status) => {
+                let number = status.block_number.value();
+                synced_number = Some(synced_number.map_or(number, |n| n.min(number)));
+            }
+            None => missing.push((script.clone(), script_type.clone())),
+        }
+    }
+    SyncProgress {
+        tip_number,
+        synced_number,
+        missing,
+    }
+}
+
+fn tip_number(client: &LightClientRpcClient) -> Result<u64, RpcError> {
+    Ok(client.get_tip_header()?.inner.number.value())
+}
+
+fn progress(
+    client: &LightClientRpcClient,
+    watched: &[(Script, ScriptType)],
+) -> Result<SyncProgress, RpcError> {
+    let tip_number = tip_number(client)?;
+    let registered = client.get_scripts()?;
+    Ok(sync_progress(&registered, watched, tip_number))
+}
+
+fn wait_synced(
+    client: &LightClientRpcClient,
+    watched: &[(Script, ScriptType)],
+    leftbehind: u64,
+    timeout: Option<Duration>,
+    poll_interval: Duration,
+    is_canceled: impl Fn() -> bool,
+) -> Result<SyncProgress, ScriptManagerError> {
+    let start = Instant::now();
+    loop {
+        if is_canceled() {
+            return Err(ScriptManagerError::Canceled);
+        }
+        let progress = progress(client, watched)?;
+        if progress.is_synced(leftbehind) {
+            return Ok(progress);
+        }
+        if let Some(timeout) = timeout {
+            if start.elapsed() >= timeout {
+                return Err(ScriptManagerError::Timeout { timeout, progress });
+            }
+        }
+        thread::sleep(poll_interval);
+    }
+}
+
+pub struct LightClientScriptManager {
+    client: Arc<LightClientRpcClient>,
+    poll_interval: Duration,
+}
+
+impl LightClientScriptManager {
+    pub fn new(url: &str) -> LightClientScriptManager {
+        LightClientScriptManager {
+            client: Arc::new(LightClientRpcClient::new(url)),
+            poll_interval: DEFAULT_POLL_INTERVAL,
+        }
+    }
+
+    pub fn client(&self) -> &LightClientRpcClient {
+        &self.client
+    }
+
+    /// The interval of polling the sync progress (default = 2s)
+    pub fn poll_interval(&self) -> Duration {
+        self.poll_interval
+    }
+    pub fn set_poll_interval(&mut self, value: Duration) {
+        self.poll_interval = value;
+    }
+
+    /// Resolve the start point to a block number
+    pub fn start_number(&self, start: StartPoint) -> Result<u64, ScriptManagerError> {
+        match start {
+            StartPoint::Tip => Ok(tip_number(&self.client)?),
+            StartPoint::Genesis => Ok(0),
+            StartPoint::Number(number) => Ok(number),
+        }
+    }
+
+    /// Register the scripts, keeping the other registered scripts.
+    pub fn add_scripts(
+        &self,
+        scripts: &[(Script, ScriptType)],
+        start: StartPoint,
+    ) -> Result<(), ScriptManagerError> {
+        let block_number = self.start_number(start)?;
+        let statuses: Vec<_> = scripts
+            .iter()
+            .map(|(script, script_type)| ScriptStatus {
+                script: script.clone().into(),
+                script_type: script_type.clone(),
+                block_number: block_number.into(),
+            })
+            .collect();
+        self.add_script_statuses(&statuses)
+    }
+
+    /// Register the scripts with their own start block numbers, keeping the
+    /// other registered scripts.
+    pub fn add_script_statuses(&self, statuses: &[ScriptStatus]) -> Result<(), ScriptManagerError> {
+        let mut current = self.client.get_scripts()?;
+        if merge_scripts(&mut current, statuses) {
+            self.client.set_scripts(current)?;
+        }
+        Ok(())
+    }
+
+    /// Unregister the scripts, keeping the other registered scripts.
+    pub fn remove_scripts(
+        &self,
+        scripts: &[(Script, ScriptType)],
+    ) -> Result<(), ScriptManagerError> {
+        let mut current = self.client.get_scripts()?;
+        if remove_scripts(&mut current, scripts) {
+            self.client.set_scripts(current)?;
+        }
+        Ok(())
+    }
+
+    pub fn progress(
+        &self,
+        scripts: &[(Script, ScriptType)],
+    ) -> Result<SyncProgress, ScriptManagerError> {
+        Ok(progress(&self.client, scripts)?)
+    }
+
+    /// Block until the scripts are synced to at most `leftbehind` blocks
+    /// behind the tip, or the timeout elapsed.
+    pub fn wait_synced(
+        &self,
+        scripts: &[(Script, ScriptType)],
+        leftbehind: u64,
+        timeout: Option<Duration>,
+    ) -> Result<SyncProgress, ScriptManagerError> {
+        wait_synced(
+            &self.client,
+            scripts,
+            leftbehind,
+            timeout,
+            self.poll_interval,
+            || false,
+        )
+    }
+
+    /// Same as [`wait_synced`](Self::wait_synced), but polls in a background
+    /// thread and resolves the returned future when ready. The thread stops
+    /// polling once the future is dropped.
+    pub fn ready(
+        &self,
+        scripts: Vec<(Script, ScriptType)>,
+        leftbehind: u64,
+        timeout: Option<Duration>,
+    ) -> impl Future<Output = Result<SyncProgress, ScriptManagerError>> + Send + 'static {
+        let (sender, receiver) = oneshot::channel();
+        let client = Arc::clone(&self.client);
+        let poll_interval = self.poll_interval;
+        thread::spawn(move || {
+            let result = wait_synced(
+                &client,
+                &scripts,
+                leftbehind,
+                timeout,
+                poll_interval,
+                || sender.is_canceled(),
+            );
+            let _ = sender.send(result);
+        });
+        receiver.map(|result| result.unwrap_or(Err(ScriptManagerError::Canceled)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ckb_types::{bytes::Bytes, core::ScriptHashType, prelude::*};
+
+    fn script(byte: u8) -> Script {
+        Script::new_builder()
+            .code_hash([byte; 32].pack())
+            .hash_type(ScriptHashType::Type.into())
+            .args(Bytes::from(vec![byte]).pack())
+            .build()
+    }
+
+    fn status(byte: u8, script_type: ScriptType, block_number: u64) -> ScriptStatus {
+        ScriptStatus {
+            script: script(byte).into(),
+            script_type,
+            block_number: block_number.into(),
+        }
+    }
+
+    #[test]
+    fn test_merge_and_remove_scripts() {
+        let mut current = vec![status(1, ScriptType::Lock, 100)];
+        // a later start keeps the synced number
+        assert!(!merge_scripts(
+            &mut current,
+            &[status(1, ScriptType::Lock, 200)]
+        ));
+        assert_eq!(current[0].block_number.value(), 100);
+        // an earlier start rescans
+        assert!(merge_scripts(
+            &mut current,
+            &[status(1, ScriptType::Lock, 50)]
+        ));
+        assert_eq!(current[0].block_number.value(), 50);
+        // the same script as a type script is another entry
+        assert!(merge_scripts(
+            &mut current,
+            &[
+                status(1, ScriptType::Type, 300),
+                status(2, ScriptType::Lock, 10)
+            ]
+        ));
+        assert_eq!(current.len(), 3);
+
+        assert!(remove_scripts(
+            &mut current,
+            &[(script(1), ScriptType::Type), (script(3), ScriptType::Lock)]
+        ));
+        assert!(!remove_scripts(
+            &mut current,
+            &[(script(1), ScriptType::Type)]
+        ));
+        assert_eq!(current.len(), 2);
+        assert_eq!(current[0].script_type, ScriptType::Lock);
+    }
+
+    #[test]
+    fn test_sync_progress() {
+        let registered = vec![
+            status(1, ScriptType::Lock, 90),
+            status(2, ScriptType::Lock, 99),
+        ];
+        let progress = sync_progress(&registered, &[(script(1), ScriptType::Lock)], 100);
+        assert_eq!(progress.synced_number, Some(90));
+        assert_eq!(progress.remaining_blocks(), 10);
+        assert!(!progress.is_synced(1));
+        assert!(progress.is_synced(10));
+
+        let progress = sync_progress(
+            &registered,
+            &[(script(2), ScriptType::Lock), (script(3), ScriptType::Lock)],
+            100,
+        );
+        assert_eq!(progress.synced_number, Some(99));
+        assert_eq!(progress.missing, vec![(script(3), ScriptType::Lock)]);
+        assert!(!progress.is_synced(1));
+
+        assert!(sync_progress(&registered, &[], 100).is_synced(0));
+    }
+
+    #[test]
+    fn test_wait_synced_canceled() {
+        // the client is never requested once canceled
+        let client = LightClientRpcClient::new("http://127.0.0.1:1");
+        let result = wait_synced(
+            &client,
+            &[(script(1), ScriptType::Lock)],
+            0,
+            None,
+            DEFAULT_POLL_INTERVAL,
+            || true,
+        );
+        assert!(matches!(result, Err(ScriptManagerError::Canceled)));
+    }
+}
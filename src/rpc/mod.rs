@@ -1,12 +1,15 @@
 mod ckb;
 pub mod ckb_indexer;
 pub mod ckb_light_client;
+pub mod light_client_scripts;
+pub mod pagination;
 
 use anyhow::anyhow;
 pub use ckb::CkbRpcClient;
 pub use ckb_indexer::IndexerRpcClient;
 use ckb_jsonrpc_types::{JsonBytes, ResponseFormat};
 pub use ckb_light_client::LightClientRpcClient;
+pub use light_client_scripts::LightClientScriptManager;
 
 use thiserror::Error;
 
@@ -22,6 +25,17 @@ pub enum RpcError {
     Other(#[from] anyhow::Error),
 }
 
+impl RpcError {
+    /// The node rejects the params of the request, e.g. a field it does not
+    /// support yet.
+    pub fn is_invalid_params(&self) -> bool {
+        matches!(
+            self,
+            RpcError::Rpc(err) if err.code == jsonrpc_core::ErrorCode::InvalidParams
+        )
+    }
+}
+
 #[macro_export]
 macro_rules! jsonrpc {
     (
@@ -147,7 +161,18 @@ mod anyhow_tests {
             data: None,
         };
         let error = super::RpcError::from(json_rpc_error);
+        assert!(!error.is_invalid_params());
         let error = anyhow!(error);
         println!("{}", error)
     }
+
+    #[test]
+    fn test_invalid_params_error() {
+        let error = super::RpcError::from(jsonrpc_core::Error::invalid_params("unknown field"));
+        assert!(error.is_invalid_params());
+        let error = super::RpcError::from(jsonrpc_core::Error::internal_error());
+        assert!(!error.is_invalid_params());
+        let error = super::RpcError::Other(anyhow!("connection refused"));
+        assert!(!error.is_invalid_params());
+    }
 }
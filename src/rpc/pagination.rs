@@ -0,0 +1,379 @@
+//! Lazy iterators (blocking) and streams (async) over the paginated indexer
+//! methods `get_cells` and `get_transactions`.
+//!
+//! Pages are fetched on demand. The page size starts at
+//! [`PageOptions::page_size`] and doubles after every full page, up to
+//! [`PageOptions::max_page_size`], so a consumer reading only a few items
+//! makes small requests while a long scan makes few requests.
+//!
+//! [`PageIter::cursor`] and [`PageStream::cursor`] return the `last_cursor` of
+//! the last page fully consumed. Passing it as [`PageOptions::after`] resumes
+//! the scan, the items of a page partially consumed are yielded again.
+
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use ckb_jsonrpc_types::JsonBytes;
+use futures::{future::BoxFuture, stream::Stream, Future};
+
+use super::{
+    ckb_indexer::{Cell, Order, Pagination, SearchKey, Tx},
+    ckb_light_client, IndexerRpcClient, LightClientRpcClient, RpcError,
+};
+
+pub const DEFAULT_PAGE_SIZE: u32 = 16;
+pub const DEFAULT_MAX_PAGE_SIZE: u32 = 4096;
+
+#[derive(Clone, Debug)]
+pub struct PageOptions {
+    pub order: Order,
+    /// The size of the first page
+    pub page_size: u32,
+    pub max_page_size: u32,
+    /// The max number of items yielded in total
+    pub limit: Option<u64>,
+    /// Resume after the cursor
+    pub after: Option<JsonBytes>,
+}
+
+impl Default for PageOptions {
+    fn default() -> PageOptions {
+        PageOptions {
+            order: Order::Asc,
+            page_size: DEFAULT_PAGE_SIZE,
+            max_page_size: DEFAULT_MAX_PAGE_SIZE,
+            limit: None,
+            after: None,
+        }
+    }
+}
+
+impl PageOptions {
+    pub fn new(order: Order) -> PageOptions {
+        PageOptions {
+            order,
+            ..Default::default()
+        }
+    }
+}
+
+/// The paging state shared by the iterator and the stream.
+struct PageState<T> {
+    buffer: VecDeque<T>,
+    page_size: u32,
+    max_page_size: u32,
+    remaining: Option<u64>,
+    // The cursor after the buffered page, and after the last page consumed
+    page_cursor: Option<JsonBytes>,
+    consumed_cursor: Option<JsonBytes>,
+    done: bool,
+}
+
+impl<T> PageState<T> {
+    fn new(options: &PageOptions) -> PageState<T> {
+        PageState {
+            buffer: VecDeque::new(),
+            page_size: options.page_size.max(1),
+            max_page_size: options.max_page_size.max(options.page_size).max(1),
+            remaining: options.limit,
+            page_cursor: options.after.clone(),
+            consumed_cursor: options.after.clone(),
+            done: false,
+        }
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        if self.remaining == Some(0) {
+            self.done = true;
+            return None;
+        }
+        let item = self.buffer.pop_front()?;
+        if let Some(remaining) = self.remaining.as_mut() {
+            *remaining -= 1;
+        }
+        if self.buffer.is_empty() {
+            self.consumed_cursor = self.page_cursor.clone();
+        }
+        Some(item)
+    }
+
+    /// The `(limit, after)` of the next page, `None` if finished.
+    fn next_request(&self) -> Option<(u32, Option<JsonBytes>)> {
+        if self.done || self.remaining == Some(0) {
+            return None;
+        }
+        let limit = match self.remaining {
+            Some(remaining) if remaining < u64::from(self.page_size) => remaining as u32,
+            _ => self.page_size,
+        };
+        Some((limit, self.page_cursor.clone()))
+    }
+
+    fn on_page(&mut self, limit: u32, page: Pagination<T>) {
+        if page.objects.is_empty() {
+            self.done = true;
+            return;
+        }
+        if page.objects.len() as u32 >= limit {
+            self.page_size = self.page_size.saturating_mul(2).min(self.max_page_size);
+        }
+        self.buffer.extend(page.objects);
+        self.page_cursor = Some(page.last_cursor);
+    }
+}
+
+type FetchPage<'a, T> =
+    Box<dyn FnMut(u32, Option<JsonBytes>) -> Result<Pagination<T>, RpcError> + 'a>;
+
+/// A blocking iterator over paginated items, it stops after an error.
+pub struct PageIter<'a, T> {
+    fetch: FetchPage<'a, T>,
+    state: PageState<T>,
+}
+
+impl<'a, T> PageIter<'a, T> {
+    /// `fetch` requests a page by `(limit, after)`.
+    pub fn new<F>(fetch: F, options: &PageOptions) -> PageIter<'a, T>
+    where
+        F: FnMut(u32, Option<JsonBytes>) -> Result<Pagination<T>, RpcError> + 'a,
+    {
+        PageIter {
+            fetch: Box::new(fetch),
+            state: PageState::new(options),
+        }
+    }
+
+    /// The cursor to resume after.
+    pub fn cursor(&self) -> Option<&JsonBytes> {
+        self.state.consumed_cursor.as_ref()
+    }
+}
+
+impl<'a, T> Iterator for PageIter<'a, T> {
+    type Item = Result<T, RpcError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.state.pop() {
+                return Some(Ok(item));
+            }
+            let (limit, after) = self.state.next_request()?;
+            match (self.fetch)(limit, after) {
+                Ok(page) => self.state.on_page(limit, page),
+                Err(err) => {
+                    self.state.done = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+type FetchPageFuture<'a, T> =
+    Box<dyn FnMut(u32, Option<JsonBytes>) -> BoxFuture<'a, Result<Pagination<T>, RpcError>> + 'a>;
+
+/// An async stream over paginated items, it stops after an error.
+pub struct PageStream<'a, T> {
+    fetch: FetchPageFuture<'a, T>,
+    pending: Option<(u32, BoxFuture<'a, Result<Pagination<T>, RpcError>>)>,
+    state: PageState<T>,
+}
+
+// The items are never pinned
+impl<'a, T> Unpin for PageStream<'a, T> {}
+
+impl<'a, T> PageStream<'a, T> {
+    /// `fetch` requests a page by `(limit, after)`.
+    pub fn new<F, Fut>(mut fetch: F, options: &PageOptions) -> PageStream<'a, T>
+    where
+        F: FnMut(u32, Option<JsonBytes>) -> Fut + 'a,
+        Fut: Future<Output = Result<Pagination<T>, RpcError>> + Send + 'a,
+    {
+        PageStream {
+            fetch: Box::new(move |limit, after| Box::pin(fetch(limit, after)) as BoxFuture<'a, _>),
+            pending: None,
+            state: PageState::new(options),
+        }
+    }
+
+    /// The cursor to resume after.
+    pub fn cursor(&self) -> Option<&JsonBytes> {
+        self.state.consumed_cursor.as_ref()
+    }
+}
+
+impl<'a, T> Stream for PageStream<'a, T> {
+    type Item = Result<T, RpcError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(item) = this.state.pop() {
+                return Poll::Ready(Some(Ok(item)));
+            }
+            if let Some((limit, future)) = this.pending.as_mut() {
+                let limit = *limit;
+                let result = match future.as_mut().poll(cx) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(result) => result,
+                };
+                this.pending = None;
+                match result {
+                    Ok(page) => this.state.on_page(limit, page),
+                    Err(err) => {
+                        this.state.done = true;
+                        return Poll::Ready(Some(Err(err)));
+                    }
+                }
+                continue;
+            }
+            match this.state.next_request() {
+                Some((limit, after)) => {
+                    this.pending = Some((limit, (this.fetch)(limit, after)));
+                }
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+impl IndexerRpcClient {
+    pub fn cells_iter(&self, search_key: SearchKey, options: &PageOptions) -> PageIter<'_, Cell> {
+        let order = options.order.clone();
+        PageIter::new(
+            move |limit, after| {
+                self.get_cells(search_key.clone(), order.clone(), limit.into(), after)
+            },
+            options,
+        )
+    }
+
+    pub fn transactions_iter(
+        &self,
+        search_key: SearchKey,
+        options: &PageOptions,
+    ) -> PageIter<'_, Tx> {
+        let order = options.order.clone();
+        PageIter::new(
+            move |limit, after| {
+                self.get_transactions(search_key.clone(), order.clone(), limit.into(), after)
+            },
+            options,
+        )
+    }
+}
+
+impl LightClientRpcClient {
+    pub fn cells_iter(&self, search_key: SearchKey, options: &PageOptions) -> PageIter<'_, Cell> {
+        let order = options.order.clone();
+        PageIter::new(
+            move |limit, after| {
+                self.get_cells(search_key.clone(), order.clone(), limit.into(), after)
+            },
+            options,
+        )
+    }
+
+    pub fn transactions_iter(
+        &self,
+        search_key: SearchKey,
+        options: &PageOptions,
+    ) -> PageIter<'_, ckb_light_client::Tx> {
+        let order = options.order.clone();
+        PageIter::new(
+            move |limit, after| {
+                self.get_transactions(search_key.clone(), order.clone(), limit.into(), after)
+            },
+            options,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+    use std::convert::TryInto;
+
+    use futures::{executor::block_on, StreamExt};
+
+    /// Pages over `0..total`, the cursor is the last item.
+    fn fetch_page(total: u32, limit: u32, after: Option<JsonBytes>) -> Pagination<u32> {
+        let start = after
+            .map(|cursor| u32::from_le_bytes(cursor.as_bytes().try_into().unwrap()) + 1)
+            .unwrap_or(0);
+        let objects: Vec<u32> = (start..total).take(limit as usize).collect();
+        let last = objects.last().copied().unwrap_or(start);
+        Pagination {
+            objects,
+            last_cursor: JsonBytes::from_vec(last.to_le_bytes().to_vec()),
+        }
+    }
+
+    #[test]
+    fn test_page_iter() {
+        let requests = RefCell::new(Vec::new());
+        let options = PageOptions {
+            page_size: 2,
+            max_page_size: 8,
+            ..Default::default()
+        };
+        let items: Vec<u32> = PageIter::new(
+            |limit, after| {
+                requests.borrow_mut().push(limit);
+                Ok(fetch_page(20, limit, after))
+            },
+            &options,
+        )
+        .collect::<Result<_, _>>()
+        .unwrap();
+        assert_eq!(items, (0..20).collect::<Vec<_>>());
+        // 2 + 4 + 8 + 6, then an empty page
+        assert_eq!(*requests.borrow(), vec![2, 4, 8, 8, 8]);
+
+        // limit and resume
+        let options = PageOptions {
+            page_size: 3,
+            limit: Some(7),
+            ..Default::default()
+        };
+        let mut iter = PageIter::new(|limit, after| Ok(fetch_page(20, limit, after)), &options);
+        let items: Vec<u32> = iter.by_ref().map(Result::unwrap).collect();
+        assert_eq!(items, (0..7).collect::<Vec<_>>());
+        let options = PageOptions {
+            after: iter.cursor().cloned(),
+            ..Default::default()
+        };
+        let rest: Vec<u32> =
+            PageIter::new(|limit, after| Ok(fetch_page(20, limit, after)), &options)
+                .map(Result::unwrap)
+                .collect();
+        assert_eq!(rest, (7..20).collect::<Vec<_>>());
+
+        // stop after an error
+        let mut iter = PageIter::<u32>::new(
+            |_, _| Err(RpcError::Other(anyhow::anyhow!("unavailable"))),
+            &PageOptions::default(),
+        );
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_page_stream() {
+        let options = PageOptions {
+            page_size: 4,
+            limit: Some(10),
+            ..Default::default()
+        };
+        let stream = PageStream::new(
+            |limit, after| async move { Ok(fetch_page(30, limit, after)) },
+            &options,
+        );
+        let items: Vec<u32> = block_on(stream.map(Result::unwrap).collect());
+        assert_eq!(items, (0..10).collect::<Vec<_>>());
+    }
+}
@@ -1089,6 +1089,7 @@ fn test_udt_transfer() {
 pub mod ckb_indexer_rpc;
 pub mod ckb_rpc;
 pub mod cycle;
+pub mod multisig;
 pub mod omni_lock;
 pub mod omni_lock_util;
 pub mod transaction;
@@ -0,0 +1,113 @@
+use ckb_types::{packed::CellOutput, prelude::*};
+
+use crate::{
+    constants::ONE_CKB,
+    tests::{
+        build_multisig_script, build_sighash_script, init_context, ACCOUNT0_ARG, ACCOUNT0_KEY,
+        ACCOUNT1_ARG, ACCOUNT1_KEY, ACCOUNT2_ARG, ACCOUNT3_ARG, FEE_RATE,
+    },
+    transaction::{
+        builder::{CkbTransactionBuilder, SimpleTransactionBuilder},
+        handler::HandlerContexts,
+        input::InputIterator,
+        signer::{SignContexts, TransactionSigner},
+        TransactionBuilderConfiguration,
+    },
+    unlock::{MultisigConfig, MultisigInspector},
+    NetworkInfo, TransactionWithScriptGroups,
+};
+
+#[test]
+fn test_multisig_inspect_and_merge() {
+    let multisig_config =
+        MultisigConfig::new_with(vec![ACCOUNT0_ARG, ACCOUNT1_ARG, ACCOUNT2_ARG], 1, 2).unwrap();
+    let sender = build_multisig_script(&multisig_config);
+    let receiver = build_sighash_script(ACCOUNT3_ARG);
+    let ctx = init_context(Vec::new(), vec![(sender.clone(), Some(200 * ONE_CKB))]);
+
+    let network_info = NetworkInfo::testnet();
+    let configuration =
+        TransactionBuilderConfiguration::new_with_network(network_info.clone()).unwrap();
+    let iterator = InputIterator::new_with_cell_collector(
+        vec![sender.clone()],
+        Box::new(ctx.to_live_cells_context()) as Box<_>,
+    );
+    let mut builder = SimpleTransactionBuilder::new(configuration, iterator);
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    builder.add_output(output, ckb_types::packed::Bytes::default());
+    builder.set_change_lock(sender.clone());
+    let tx_with_groups = builder
+        .build(&HandlerContexts::new_multisig(multisig_config.clone()))
+        .expect("build failed");
+    let script_group = tx_with_groups
+        .get_script_groups()
+        .iter()
+        .find(|group| group.script == sender)
+        .unwrap()
+        .clone();
+
+    let inspector = MultisigInspector::new(multisig_config.clone());
+    let status = inspector
+        .inspect(tx_with_groups.get_tx_view(), &script_group)
+        .unwrap();
+    assert_eq!(status.valid_count(), 0);
+    assert_eq!(status.missing_signers.len(), 3);
+    assert_eq!(status.remaining(), 2);
+
+    let signer = TransactionSigner::new(&network_info);
+    let copy_tx = || {
+        TransactionWithScriptGroups::new(
+            tx_with_groups.get_tx_view().clone(),
+            tx_with_groups.get_script_groups().to_vec(),
+        )
+    };
+    let mut tx0 = copy_tx();
+    signer
+        .sign_transaction(
+            &mut tx0,
+            &SignContexts::new_multisig_h256(&ACCOUNT0_KEY, multisig_config.clone()).unwrap(),
+        )
+        .unwrap();
+    let mut tx1 = copy_tx();
+    signer
+        .sign_transaction(
+            &mut tx1,
+            &SignContexts::new_multisig_h256(&ACCOUNT1_KEY, multisig_config).unwrap(),
+        )
+        .unwrap();
+
+    // the first signer is required
+    let status = inspector.inspect(tx1.get_tx_view(), &script_group).unwrap();
+    assert_eq!(status.valid_count(), 1);
+    assert_eq!(status.signatures[0].0, 1);
+    assert_eq!(status.missing_required_signers, vec![ACCOUNT0_ARG]);
+    assert_eq!(status.missing_signers, vec![ACCOUNT0_ARG, ACCOUNT2_ARG]);
+    assert!(status.invalid_slots.is_empty());
+    assert!(!status.is_complete());
+
+    let merged = inspector
+        .merge(tx1.get_tx_view(), tx0.get_tx_view(), &script_group)
+        .unwrap();
+    let status = inspector.inspect(&merged, &script_group).unwrap();
+    assert_eq!(status.valid_count(), 2);
+    assert_eq!(status.missing_signers, vec![ACCOUNT2_ARG]);
+    assert!(status.missing_required_signers.is_empty());
+    assert!(status.is_complete());
+    ctx.verify(merged, FEE_RATE).unwrap();
+
+    // a signature of another message is invalid
+    let tx2 = tx0
+        .get_tx_view()
+        .as_advanced_builder()
+        .set_header_deps(vec![Default::default()])
+        .build();
+    let status = inspector.inspect(&tx2, &script_group).unwrap();
+    assert_eq!(status.valid_count(), 0);
+    assert_eq!(status.invalid_slots, vec![0]);
+    assert!(inspector
+        .merge(&tx2, tx0.get_tx_view(), &script_group)
+        .is_err());
+}
@@ -16,8 +16,17 @@ use crate::{
         udt::{UdtTargetReceiver, UdtTransferBuilder},
         CapacityProvider, TransferAction,
     },
-    types::xudt_rce_mol::SmtProofEntryVec,
+    types::{
+        cobuild::{Action, ActionVec, Message},
+        omni_lock::OmniLockWitnessLock,
+        xudt_rce_mol::SmtProofEntryVec,
+        ScriptGroup, ScriptGroupType,
+    },
     unlock::{
+        cobuild::{
+            cobuild_seal, fill_cobuild_message, find_cobuild_message, generate_cobuild_message,
+            resolve_input_cells,
+        },
         omni_lock::{AdminConfig, Identity},
         IdentityFlag, InfoCellData, MultisigConfig, OmniLockAcpConfig, OmniLockConfig,
         OmniLockScriptSigner, OmniLockUnlocker, OmniUnlockMode, ScriptUnlocker,
@@ -32,7 +41,7 @@ use ckb_crypto::secp::{Pubkey, SECP256K1};
 use ckb_hash::blake2b_256;
 use ckb_types::{
     bytes::Bytes,
-    core::{FeeRate, ScriptHashType},
+    core::{FeeRate, ScriptHashType, TransactionBuilder},
     packed::{Byte32, CellInput, CellOutput, Script, WitnessArgs},
     prelude::*,
     H160, H256,
@@ -147,6 +156,93 @@ fn test_omnilock_simple_hash(cfg: OmniLockConfig) {
     ctx.verify(tx, FEE_RATE).unwrap();
 }
 
+#[test]
+fn test_omnilock_cobuild_unlock() {
+    let sender_key = secp256k1::SecretKey::from_slice(ACCOUNT0_KEY.as_bytes()).unwrap();
+    let pubkey = secp256k1::PublicKey::from_secret_key(&SECP256K1, &sender_key);
+    let mut cfg = OmniLockConfig::new_pubkey_hash(blake160(&pubkey.serialize()));
+    cfg.set_cobuild(true);
+    let sender = build_omnilock_script(&cfg);
+    let mut ctx = init_context(vec![(OMNILOCK_BIN, true)], Vec::new());
+    let input0 = random_out_point();
+    let input1 = random_out_point();
+    ctx.add_simple_live_cell(input0.clone(), sender.clone(), Some(100 * ONE_CKB));
+    ctx.add_simple_live_cell(input1.clone(), sender.clone(), Some(200 * ONE_CKB));
+
+    let tx = TransactionBuilder::default()
+        .input(CellInput::new(input0, 0))
+        .input(CellInput::new(input1, 0))
+        .output(
+            CellOutput::new_builder()
+                .capacity((299 * ONE_CKB).pack())
+                .lock(build_sighash_script(ACCOUNT2_ARG))
+                .build(),
+        )
+        .output_data(Bytes::new().pack())
+        .build();
+    let mut script_group = ScriptGroup::new(&sender, ScriptGroupType::Lock);
+    script_group.input_indices.extend(vec![0, 1]);
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![sender_key]);
+    let unlocker = OmniLockUnlocker::new(
+        OmniLockScriptSigner::new(Box::new(signer), cfg.clone(), OmniUnlockMode::Normal),
+        cfg,
+    );
+
+    // The type script data in WitnessArgs can not be kept in cobuild mode
+    let witness = WitnessArgs::new_builder()
+        .input_type(Some(Bytes::from(vec![1u8])).pack())
+        .build();
+    let tx_with_type = tx
+        .as_advanced_builder()
+        .witness(witness.as_bytes().pack())
+        .build();
+    assert!(unlocker
+        .fill_placeholder_witness(&tx_with_type, &script_group, &ctx)
+        .is_err());
+
+    let message = Message::new_builder()
+        .actions(
+            ActionVec::new_builder()
+                .push(
+                    Action::new_builder()
+                        .data(Bytes::from(vec![2u8]).pack())
+                        .build(),
+                )
+                .build(),
+        )
+        .build();
+    let tx = fill_cobuild_message(&tx, 0, message.clone()).unwrap();
+    let tx = unlocker
+        .fill_placeholder_witness(&tx, &script_group, &ctx)
+        .unwrap();
+    let tx = unlocker.unlock(&tx, &script_group, &ctx).unwrap();
+    assert_eq!(tx.witnesses().len(), 1);
+    let (idx, found) = find_cobuild_message(&tx).unwrap().unwrap();
+    assert_eq!(idx, 0);
+    assert_eq!(found.as_slice(), message.as_slice());
+
+    // The bundled omni_lock binary predates cobuild, recover the signer from
+    // the seal instead of running it in ckb-vm.
+    let seal = cobuild_seal(&tx, &script_group).unwrap();
+    let signature = OmniLockWitnessLock::from_slice(&seal)
+        .unwrap()
+        .signature()
+        .to_opt()
+        .unwrap()
+        .raw_data();
+    let signing_message = generate_cobuild_message(
+        Some(&message),
+        &tx,
+        &resolve_input_cells(&tx, &ctx).unwrap(),
+    )
+    .unwrap();
+    let recid = secp256k1::ecdsa::RecoveryId::from_i32(signature[64] as i32).unwrap();
+    let recoverable =
+        secp256k1::ecdsa::RecoverableSignature::from_compact(&signature[0..64], recid).unwrap();
+    let msg = secp256k1::Message::from_slice(&signing_message).unwrap();
+    assert_eq!(SECP256K1.recover_ecdsa(&msg, &recoverable).unwrap(), pubkey);
+}
+
 #[test]
 fn test_omnilock_transfer_from_sighash_wl() {
     let sender_key = secp256k1::SecretKey::from_slice(ACCOUNT0_KEY.as_bytes())
@@ -0,0 +1,139 @@
+use ckb_jsonrpc_types::EstimateCycles;
+use ckb_types::{packed::CellOutput, prelude::*};
+use httpmock::prelude::*;
+
+use crate::{
+    constants::{ONE_CKB, SIGHASH_TYPE_HASH},
+    test_util::MockRpcResult,
+    tests::{
+        build_sighash_script, init_context, ACCOUNT1_ARG, ACCOUNT1_KEY, ACCOUNT2_ARG, FEE_RATE,
+    },
+    transaction::{
+        builder::{
+            CkbTransactionBuilder, CycleEstimator, DryRunSigner, LocalCycleEstimator,
+            RpcCycleEstimator, SimpleTransactionBuilder,
+        },
+        input::InputIterator,
+        signer::{SignContexts, TransactionSigner},
+        TransactionBuilderConfiguration,
+    },
+    NetworkInfo, ScriptId, TransactionWithScriptGroups,
+};
+
+fn dry_run_signer(network_info: &NetworkInfo) -> DryRunSigner {
+    DryRunSigner::new(
+        TransactionSigner::new(network_info),
+        SignContexts::new_sighash_h256(vec![ACCOUNT1_KEY.clone()]).unwrap(),
+    )
+}
+
+fn build_unsigned(
+    ctx: &crate::test_util::Context,
+    configuration: TransactionBuilderConfiguration,
+) -> TransactionWithScriptGroups {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(build_sighash_script(ACCOUNT2_ARG))
+        .build();
+    let iterator = InputIterator::new_with_cell_collector(
+        vec![sender.clone()],
+        Box::new(ctx.to_live_cells_context()) as Box<_>,
+    );
+    let mut builder = SimpleTransactionBuilder::new(configuration, iterator);
+    builder.add_output(output, ckb_types::packed::Bytes::default());
+    builder.set_change_lock(sender);
+    builder.build(&Default::default()).expect("build failed")
+}
+
+#[test]
+fn test_local_cycle_estimator() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender, Some(200 * ONE_CKB)),
+        ],
+    );
+    let network_info = NetworkInfo::testnet();
+    let configuration =
+        TransactionBuilderConfiguration::new_with_network(network_info.clone()).unwrap();
+    let tx_with_groups = build_unsigned(&ctx, configuration);
+    let tx = tx_with_groups.get_tx_view();
+    let script_groups = tx_with_groups.get_script_groups();
+
+    // the placeholder witness fails, and no cycles is preset
+    let mut estimator = LocalCycleEstimator::new(Box::new(ctx.clone()));
+    assert!(estimator.estimate_cycles(tx, script_groups).is_err());
+    estimator.set_group_cycles(ScriptId::new_type(SIGHASH_TYPE_HASH.clone()), 3_000_000);
+    assert_eq!(
+        estimator.estimate_cycles(tx, script_groups).unwrap(),
+        3_000_000
+    );
+
+    // measured by dry-run unlocking
+    let mut estimator = LocalCycleEstimator::new(Box::new(ctx.clone()));
+    estimator.set_signer(dry_run_signer(&network_info));
+    let cycles = estimator.estimate_cycles(tx, script_groups).unwrap();
+    let signed = dry_run_signer(&network_info)
+        .sign(tx, script_groups)
+        .unwrap();
+    assert_eq!(cycles, ctx.verify_scripts(signed).unwrap());
+
+    // balance with the estimated cycles
+    let mut configuration =
+        TransactionBuilderConfiguration::new_with_network(network_info.clone()).unwrap();
+    configuration.set_cycle_estimator(Box::new(estimator));
+    let mut tx_with_groups = build_unsigned(&ctx, configuration);
+    TransactionSigner::new(&network_info)
+        .sign_transaction(
+            &mut tx_with_groups,
+            &SignContexts::new_sighash_h256(vec![ACCOUNT1_KEY.clone()]).unwrap(),
+        )
+        .unwrap();
+    ctx.verify(tx_with_groups.get_tx_view().clone(), FEE_RATE)
+        .unwrap();
+}
+
+#[test]
+fn test_rpc_cycle_estimator() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let ctx = init_context(Vec::new(), vec![(sender, Some(200 * ONE_CKB))]);
+    let network_info = NetworkInfo::testnet();
+    let configuration =
+        TransactionBuilderConfiguration::new_with_network(network_info.clone()).unwrap();
+    let tx_with_groups = build_unsigned(&ctx, configuration);
+    let tx = tx_with_groups.get_tx_view();
+    let script_groups = tx_with_groups.get_script_groups();
+
+    // the node only accepts the unlocked transaction
+    let signed = dry_run_signer(&network_info)
+        .sign(tx, script_groups)
+        .unwrap();
+    let witness = format!(
+        "0x{}",
+        hex::encode(signed.witnesses().get(0).unwrap().raw_data())
+    );
+    let server = MockServer::start();
+    server.mock(|when, then| {
+        when.method(POST)
+            .path("/")
+            .body_contains("estimate_cycles")
+            .body_contains(witness.as_str());
+        then.status(200).body(
+            MockRpcResult::new(EstimateCycles {
+                cycles: 1_234_567.into(),
+            })
+            .to_json(),
+        );
+    });
+
+    let mut estimator = RpcCycleEstimator::new(server.base_url().as_str());
+    assert!(estimator.estimate_cycles(tx, script_groups).is_err());
+    estimator.set_signer(dry_run_signer(&network_info));
+    assert_eq!(
+        estimator.estimate_cycles(tx, script_groups).unwrap(),
+        1_234_567
+    );
+}
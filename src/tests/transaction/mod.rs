@@ -1 +1,5 @@
+pub mod cycle_estimator;
+pub mod otx;
+pub mod partial;
 pub mod sighash;
+pub mod verifier;
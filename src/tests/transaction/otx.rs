@@ -0,0 +1,69 @@
+use ckb_types::{
+    bytes::Bytes,
+    core::TransactionBuilder,
+    packed::{CellInput, CellOutput},
+    prelude::*,
+};
+
+use crate::{
+    constants::ONE_CKB,
+    test_util::random_out_point,
+    tests::{
+        build_sighash_script, init_context, omni_lock_util::build_always_success_script,
+        ACCOUNT1_ARG, ACCOUNT1_KEY, ACCOUNT2_ARG, ALWAYS_SUCCESS_BIN, FEE_RATE,
+    },
+    traits::{CellDepResolver, SecpCkbRawKeySigner},
+    transaction::otx::{OpenTransaction, OtxMerger},
+    unlock::SecpSighashUnlocker,
+};
+
+#[test]
+fn test_merge_otx() {
+    let always_success = build_always_success_script();
+    let relayer = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let mut ctx = init_context(vec![(ALWAYS_SUCCESS_BIN, false)], Vec::new());
+    let always_success_dep = ctx.resolve(&always_success).unwrap();
+    let sighash_dep = ctx.resolve(&relayer).unwrap();
+
+    let mut otxs = Vec::new();
+    for (idx, capacity) in vec![(0, 200u64), (1, 300)].into_iter() {
+        let out_point = random_out_point();
+        ctx.add_simple_live_cell(
+            out_point.clone(),
+            always_success.clone(),
+            Some(capacity * ONE_CKB),
+        );
+        let mut builder = TransactionBuilder::default()
+            .input(CellInput::new(out_point, 0))
+            .output(
+                CellOutput::new_builder()
+                    .capacity(((capacity - 50) * ONE_CKB).pack())
+                    .lock(receiver.clone())
+                    .build(),
+            )
+            .output_data(Bytes::new().pack());
+        // The first fragment commits the lock code, the relayer adds it again
+        if idx == 0 {
+            builder = builder.cell_dep(always_success_dep.clone());
+        }
+        let mut otx = OpenTransaction::from_transaction(builder.build(), &ctx).unwrap();
+        // The always success lock does not check the seal
+        otx.set_seal(always_success.calc_script_hash(), Bytes::new());
+        otxs.push(otx);
+    }
+
+    let fee_out_point = random_out_point();
+    ctx.add_simple_live_cell(fee_out_point.clone(), relayer.clone(), Some(100 * ONE_CKB));
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut merger = OtxMerger::new(CellInput::new(fee_out_point, 0), relayer, FEE_RATE);
+    merger.set_cell_deps(vec![always_success_dep, sighash_dep]);
+    let tx = merger.merge(&otxs, &unlocker, &ctx).unwrap();
+
+    assert_eq!(tx.inputs().len(), 3);
+    assert_eq!(tx.outputs().len(), 3);
+    assert_eq!(tx.cell_deps().len(), 2);
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
@@ -0,0 +1,113 @@
+use ckb_types::{packed::CellOutput, prelude::*};
+
+use crate::{
+    constants::ONE_CKB,
+    tests::{
+        build_multisig_script, build_sighash_script, init_context, ACCOUNT0_ARG, ACCOUNT0_KEY,
+        ACCOUNT1_ARG, ACCOUNT1_KEY, ACCOUNT2_ARG, ACCOUNT3_ARG, FEE_RATE,
+    },
+    transaction::{
+        builder::{CkbTransactionBuilder, SimpleTransactionBuilder},
+        handler::HandlerContexts,
+        input::InputIterator,
+        partial::{
+            merge_partial_transactions, PartialTxError, PartiallySignedTransaction,
+            SIGN_CONTEXT_KIND_MULTISIG,
+        },
+        signer::{SignContexts, TransactionSigner},
+        TransactionBuilderConfiguration,
+    },
+    unlock::MultisigConfig,
+    NetworkInfo,
+};
+
+#[test]
+fn test_partial_transaction_multisig() {
+    let multisig_config =
+        MultisigConfig::new_with(vec![ACCOUNT0_ARG, ACCOUNT1_ARG, ACCOUNT2_ARG], 0, 2).unwrap();
+    let sender = build_multisig_script(&multisig_config);
+    let receiver = build_sighash_script(ACCOUNT3_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+        ],
+    );
+
+    let network_info = NetworkInfo::testnet();
+    let configuration =
+        TransactionBuilderConfiguration::new_with_network(network_info.clone()).unwrap();
+    let iterator = InputIterator::new_with_cell_collector(
+        vec![sender.clone()],
+        Box::new(ctx.to_live_cells_context()) as Box<_>,
+    );
+    let mut builder = SimpleTransactionBuilder::new(configuration, iterator);
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    builder.add_output(output, ckb_types::packed::Bytes::default());
+    builder.set_change_lock(sender);
+    let tx_with_groups = builder
+        .build(&HandlerContexts::new_multisig(multisig_config.clone()))
+        .expect("build failed");
+
+    let ptx = PartiallySignedTransaction::from_transaction(&tx_with_groups, &ctx).unwrap();
+    assert_eq!(
+        ptx.inputs().len(),
+        tx_with_groups.get_tx_view().inputs().len()
+    );
+    // the secp256k1 data cell and the dep group cell
+    assert!(!ptx.cell_deps().is_empty());
+
+    let signer = TransactionSigner::new(&network_info);
+    // party 0 signs the JSON envelope
+    let json = ptx.to_json_string().unwrap();
+    let signed_json = signer
+        .sign_partial_json(
+            &json,
+            &SignContexts::new_multisig_h256(&ACCOUNT0_KEY, multisig_config.clone()).unwrap(),
+        )
+        .unwrap();
+    let ptx0 = PartiallySignedTransaction::from_json_str(&signed_json).unwrap();
+    assert_eq!(ptx0.contexts().len(), 1);
+    assert_eq!(ptx0.contexts()[0].kind, SIGN_CONTEXT_KIND_MULTISIG);
+    // party 1 signs the molecule envelope
+    let signed_data = signer
+        .sign_partial_molecule(
+            &ptx.to_molecule(),
+            &SignContexts::new_multisig_h256(&ACCOUNT1_KEY, multisig_config).unwrap(),
+        )
+        .unwrap();
+    let ptx1 = PartiallySignedTransaction::from_molecule(&signed_data).unwrap();
+
+    // one signature is not enough
+    assert!(ctx.verify(ptx0.tx_view().clone(), FEE_RATE).is_err());
+
+    let merged = merge_partial_transactions(&[ptx0.clone(), ptx1]).unwrap();
+    assert_eq!(merged.contexts().len(), 1);
+    ctx.verify(merged.tx_view().clone(), FEE_RATE).unwrap();
+
+    // merge again is idempotent
+    let mut merged_again = merged.clone();
+    merged_again.merge(&ptx0).unwrap();
+    assert_eq!(merged_again.tx_view().hash(), merged.tx_view().hash());
+    assert_eq!(
+        merged_again.tx_view().witness_hash(),
+        merged.tx_view().witness_hash()
+    );
+
+    // transactions not match
+    let mut other = ptx.clone();
+    other.set_tx_view(
+        ptx.tx_view()
+            .as_advanced_builder()
+            .set_header_deps(vec![Default::default()])
+            .build(),
+    );
+    assert!(matches!(
+        other.merge(&ptx0),
+        Err(PartialTxError::TxMismatch(_, _))
+    ));
+}
@@ -0,0 +1,99 @@
+use ckb_types::{packed::CellOutput, prelude::*};
+
+use crate::{
+    constants::ONE_CKB,
+    tests::{
+        build_sighash_script, init_context, ACCOUNT1_ARG, ACCOUNT1_KEY, ACCOUNT2_ARG, FEE_RATE,
+    },
+    transaction::{
+        builder::{CkbTransactionBuilder, FeeCalculator, SimpleTransactionBuilder},
+        input::InputIterator,
+        signer::{SignContexts, TransactionSigner},
+        verifier::{TransactionVerifier, TransactionVerifyError},
+        TransactionBuilderConfiguration,
+    },
+    NetworkInfo,
+};
+
+#[test]
+fn test_verify_transaction() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+        ],
+    );
+
+    let network_info = NetworkInfo::testnet();
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let configuration =
+        TransactionBuilderConfiguration::new_with_network(network_info.clone()).unwrap();
+    let iterator = InputIterator::new_with_cell_collector(
+        vec![sender.clone()],
+        Box::new(ctx.to_live_cells_context()) as Box<_>,
+    );
+    let mut builder = SimpleTransactionBuilder::new(configuration, iterator);
+    builder.add_output(output, ckb_types::packed::Bytes::default());
+    builder.set_change_lock(sender);
+    let mut tx_with_groups = builder.build(&Default::default()).expect("build failed");
+
+    let verifier = TransactionVerifier::new(&ctx, &ctx, FEE_RATE);
+    // not signed yet
+    let report = verifier.verify(tx_with_groups.get_tx_view()).unwrap();
+    assert_eq!(report.script_groups.len(), 1);
+    assert!(report.script_groups[0].result.is_err());
+    assert!(matches!(
+        report.errors.as_slice(),
+        [TransactionVerifyError::Script(_, _)]
+    ));
+
+    TransactionSigner::new(&network_info)
+        .sign_transaction(
+            &mut tx_with_groups,
+            &SignContexts::new_sighash_h256(vec![ACCOUNT1_KEY.clone()]).unwrap(),
+        )
+        .unwrap();
+    let tx = tx_with_groups.get_tx_view().clone();
+    let report = verifier.verify(&tx).unwrap();
+    assert!(report.is_ok(), "errors: {:?}", report.errors);
+    assert!(report.fee >= report.min_fee);
+    assert!(report.total_cycles() > 0);
+    // the min fee is charged by the weight of the transaction
+    assert_eq!(
+        report.min_fee,
+        FeeCalculator::new(FEE_RATE).fee_with_cycle(
+            tx.data().as_reader().serialized_size_in_block() as u64,
+            report.total_cycles()
+        )
+    );
+    assert_eq!(
+        report.into_result().unwrap(),
+        ctx.verify(tx.clone(), FEE_RATE).unwrap()
+    );
+
+    // the max cycles limits the total cycles
+    let total_cycles = ctx.verify(tx.clone(), FEE_RATE).unwrap();
+    let mut verifier = TransactionVerifier::new(&ctx, &ctx, FEE_RATE);
+    verifier.set_max_cycles(total_cycles - 1);
+    let report = verifier.verify(&tx).unwrap();
+    assert!(matches!(
+        report.errors.as_slice(),
+        [TransactionVerifyError::ExceededMaxCycles(_)]
+    ));
+    verifier.set_max_cycles(total_cycles);
+    assert!(verifier.verify(&tx).unwrap().is_ok());
+
+    // fee rate too high
+    let verifier = TransactionVerifier::new(&ctx, &ctx, FEE_RATE * 1000);
+    let report = verifier.verify(&tx).unwrap();
+    assert!(matches!(
+        report.errors.as_slice(),
+        [TransactionVerifyError::InsufficientFee { .. }]
+    ));
+}
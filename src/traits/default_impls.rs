@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
@@ -16,21 +16,25 @@ use ckb_types::{
     core::{BlockView, DepType, HeaderView, TransactionView},
     packed::{Byte32, CellDep, CellOutput, OutPoint, Script, Transaction, TransactionReader},
     prelude::*,
-    H160,
+    H160, H256,
 };
 
 use super::{
-    offchain_impls::CollectResult, OffchainCellCollector, OffchainCellDepResolver,
-    OffchainTransactionDependencyProvider,
+    offchain_impls::{CollectResult, ReorgEvent},
+    reservation::CellReservation,
+    OffchainCellCollector, OffchainCellDepResolver, OffchainTransactionDependencyProvider,
 };
 use crate::rpc::ckb_indexer::{Order, SearchKey, Tip};
+use crate::rpc::pagination::{PageOptions, DEFAULT_PAGE_SIZE};
 use crate::rpc::{CkbRpcClient, IndexerRpcClient};
 use crate::traits::{
     CellCollector, CellCollectorError, CellDepResolver, CellQueryOptions, HeaderDepResolver,
     LiveCell, QueryOrder, Signer, SignerError, TransactionDependencyError,
     TransactionDependencyProvider,
 };
-use crate::types::ScriptId;
+use crate::types::{
+    ChildNumber, DerivationPath, ExtendedPrivKey, ExtendedPubKey, HdKeyError, KeyChain, ScriptId,
+};
 use crate::util::{get_max_mature_number, serialize_signature, zeroize_privkey};
 use crate::SECP256K1;
 use crate::{
@@ -238,12 +242,18 @@ impl HeaderDepResolver for DefaultHeaderDepResolver {
     }
 }
 
+/// The callback notified of the offchain data rolled back by a chain
+/// reorganization.
+pub type ReorgCallback = Box<dyn FnMut(&ReorgEvent) + Send>;
+
 /// A cell collector use ckb-indexer as backend
 pub struct DefaultCellCollector {
     indexer_client: IndexerRpcClient,
     ckb_client: CkbRpcClient,
     offchain: OffchainCellCollector,
     acceptable_indexer_leftbehind: u64,
+    reorg_callback: Option<ReorgCallback>,
+    reservation: Option<Box<dyn CellReservation>>,
 }
 
 impl DefaultCellCollector {
@@ -255,7 +265,114 @@ impl DefaultCellCollector {
             ckb_client,
             offchain: OffchainCellCollector::default(),
             acceptable_indexer_leftbehind: 1,
+            reorg_callback: None,
+            reservation: None,
+        }
+    }
+
+    /// Share the locked cells with other collectors (e.g. of other
+    /// processes) by the reservation backend.
+    pub fn set_reservation(&mut self, reservation: Box<dyn CellReservation>) {
+        self.reservation = Some(reservation);
+    }
+
+    /// Release the cells locked by a transaction, e.g. the transaction is
+    /// rejected. The outputs of the transaction are not collectable anymore.
+    pub fn release_tx(&mut self, tx: &Transaction) -> Result<(), CellCollectorError> {
+        let tx_view = tx.clone().into_view();
+        let tx_hash = tx_view.hash();
+        let inputs: Vec<OutPoint> = tx_view.input_pts_iter().collect();
+        for out_point in &inputs {
+            self.offchain
+                .locked_cells
+                .remove(&(out_point.tx_hash().unpack(), out_point.index().unpack()));
+        }
+        self.offchain
+            .live_cells
+            .retain(|(cell, _)| cell.out_point.tx_hash() != tx_hash);
+        if let Some(reservation) = self.reservation.as_mut() {
+            reservation
+                .release_tx(&tx_hash.unpack())
+                .and_then(|_| reservation.release(&inputs))
+                .map_err(|err| CellCollectorError::Internal(err.into()))?;
+        }
+        Ok(())
+    }
+
+    /// Release the reservations of the spending transactions committed (or
+    /// rejected) by the node.
+    pub fn release_committed(&mut self) -> Result<(), CellCollectorError> {
+        let reservation = match self.reservation.as_mut() {
+            Some(reservation) => reservation,
+            None => return Ok(()),
+        };
+        let tx_hashes = reservation
+            .spending_txs()
+            .map_err(|err| CellCollectorError::Internal(err.into()))?;
+        for tx_hash in tx_hashes {
+            let status = self
+                .ckb_client
+                .get_transaction_status(tx_hash.clone())
+                .map_err(|err| CellCollectorError::Internal(err.into()))?
+                .tx_status
+                .status;
+            let result = match status {
+                json_types::Status::Committed | json_types::Status::Rejected => {
+                    reservation.release_tx(&tx_hash)
+                }
+                // keep the cells of the transaction still in the pool reserved
+                json_types::Status::Pending | json_types::Status::Proposed => {
+                    reservation.renew_tx(&tx_hash)
+                }
+                _ => Ok(()),
+            };
+            result.map_err(|err| CellCollectorError::Internal(err.into()))?;
+        }
+        Ok(())
+    }
+
+    /// Set the callback notified when the locked and applied cells are
+    /// rolled back by a chain reorganization.
+    pub fn set_reorg_callback<F>(&mut self, callback: F)
+    where
+        F: FnMut(&ReorgEvent) + Send + 'static,
+    {
+        self.reorg_callback = Some(Box::new(callback));
+    }
+
+    /// Compare the tip hashes recorded with the locked and applied cells to
+    /// the canonical chain, roll back the cells recorded at and after the
+    /// fork block if a reorg is detected.
+    pub fn check_reorg(&mut self) -> Result<Option<ReorgEvent>, CellCollectorError> {
+        let ckb_client = &self.ckb_client;
+        let fork_number = self.offchain.tip_hashes.find_fork(|number| {
+            ckb_client
+                .get_block_hash(number.into())
+                .map_err(|err| CellCollectorError::Internal(err.into()))
+        })?;
+        let event = match fork_number {
+            Some(fork_number) => self.offchain.rollback(fork_number),
+            None => return Ok(None),
+        };
+        if let Some(callback) = self.reorg_callback.as_mut() {
+            callback(&event);
+        }
+        Ok(Some(event))
+    }
+
+    /// Check reorg and record the hash of the tip block.
+    fn track_tip(&mut self, tip_number: u64) -> Result<(), CellCollectorError> {
+        self.check_reorg()?;
+        if self.offchain.tip_hashes.get(tip_number).is_none() {
+            if let Some(hash) = self
+                .ckb_client
+                .get_block_hash(tip_number.into())
+                .map_err(|err| CellCollectorError::Internal(err.into()))?
+            {
+                self.offchain.tip_hashes.insert(tip_number, hash);
+            }
         }
+        Ok(())
     }
 
     /// THe acceptable ckb-indexer leftbehind block number (default = 1)
@@ -312,11 +429,13 @@ impl CellCollector for DefaultCellCollector {
             .map_err(|err| CellCollectorError::Internal(anyhow!(err)))?;
 
         self.offchain.max_mature_number = max_mature_number;
-        let tip_num = self
+        let tip_header = self
             .ckb_client
-            .get_tip_block_number()
-            .map_err(|err| CellCollectorError::Internal(anyhow!(err)))?
-            .value();
+            .get_tip_header()
+            .map_err(|err| CellCollectorError::Internal(anyhow!(err)))?;
+        let tip_num = tip_header.inner.number.value();
+        self.check_reorg()?;
+        self.offchain.tip_hashes.insert(tip_num, tip_header.hash);
         let CollectResult {
             cells,
             rest_cells,
@@ -326,6 +445,7 @@ impl CellCollector for DefaultCellCollector {
 
         if total_capacity < query.min_total_capacity {
             self.check_ckb_chain()?;
+            self.release_committed()?;
             let order = match query.order {
                 QueryOrder::Asc => Order::Asc,
                 QueryOrder::Desc => Order::Desc,
@@ -335,43 +455,84 @@ impl CellCollector for DefaultCellCollector {
                 .map(|c| (c.out_point.clone(), c))
                 .collect();
             let locked_cells = self.offchain.locked_cells.clone();
-            let search_key = SearchKey::from(query.clone());
-            const MAX_LIMIT: u32 = 4096;
-            let mut limit: u32 = query.limit.unwrap_or(16);
-            let mut last_cursor: Option<json_types::JsonBytes> = None;
-            while total_capacity < query.min_total_capacity {
-                let page = self
-                    .indexer_client
-                    .get_cells(search_key.clone(), order.clone(), limit.into(), last_cursor)
-                    .map_err(|err| CellCollectorError::Internal(err.into()))?;
-                if page.objects.is_empty() {
-                    break;
-                }
-                for cell in page.objects {
-                    let live_cell = LiveCell::from(cell);
-                    if !query.match_cell(&live_cell, max_mature_number)
-                        || locked_cells.contains_key(&(
-                            live_cell.out_point.tx_hash().unpack(),
-                            live_cell.out_point.index().unpack(),
-                        ))
-                    {
-                        continue;
-                    }
-                    let capacity: u64 = live_cell.output.capacity().unpack();
-                    // use cell from indexer to replace offchain cell
-                    if ret_cells
-                        .insert(live_cell.out_point.clone(), live_cell)
-                        .is_none()
-                    {
-                        total_capacity += capacity;
-                    }
-                    if total_capacity >= query.min_total_capacity {
-                        break;
+            let options = PageOptions {
+                order,
+                page_size: query.limit.unwrap_or(DEFAULT_PAGE_SIZE),
+                ..Default::default()
+            };
+            // Load the reservations once, the own reservations are collectable
+            let reserved_by_others = match self.reservation.as_ref() {
+                Some(reservation) => reservation
+                    .reserved_by_others()
+                    .map_err(|err| CellCollectorError::Internal(err.into()))?,
+                None => HashSet::new(),
+            };
+            let mut reserved: Vec<OutPoint> = Vec::new();
+            'queries: for sub_query in query.split_primary_scripts() {
+                let mut search_key = Some(SearchKey::from(sub_query));
+                while let Some(key) = search_key.take() {
+                    // Retry without the output data filter if the node rejects it as
+                    // invalid params, the data is still checked by `match_cell`
+                    let fallback = key.without_output_data();
+                    let mut received = false;
+                    for cell in self.indexer_client.cells_iter(key, &options) {
+                        let cell = match cell {
+                            Ok(cell) => cell,
+                            Err(err)
+                                if !received && fallback.is_some() && err.is_invalid_params() =>
+                            {
+                                search_key = fallback;
+                                break;
+                            }
+                            Err(err) => return Err(CellCollectorError::Internal(err.into())),
+                        };
+                        received = true;
+                        let live_cell = LiveCell::from(cell);
+                        if !query.match_cell(&live_cell, max_mature_number)
+                            || locked_cells.contains_key(&(
+                                live_cell.out_point.tx_hash().unpack(),
+                                live_cell.out_point.index().unpack(),
+                            ))
+                        {
+                            continue;
+                        }
+                        // the cells reserved by others are skipped
+                        if reserved_by_others.contains(&live_cell.out_point) {
+                            continue;
+                        }
+                        match self.reservation.as_mut() {
+                            // reserve it in case others reserved it after the snapshot
+                            Some(reservation) if apply_changes => {
+                                if !reservation
+                                    .reserve(&live_cell.out_point)
+                                    .map_err(|err| CellCollectorError::Internal(err.into()))?
+                                {
+                                    continue;
+                                }
+                                reserved.push(live_cell.out_point.clone());
+                            }
+                            _ => {}
+                        }
+                        let capacity: u64 = live_cell.output.capacity().unpack();
+                        // use cell from indexer to replace offchain cell
+                        if ret_cells
+                            .insert(live_cell.out_point.clone(), live_cell)
+                            .is_none()
+                        {
+                            total_capacity += capacity;
+                        }
+                        if total_capacity >= query.min_total_capacity {
+                            break 'queries;
+                        }
                     }
                 }
-                last_cursor = Some(page.last_cursor);
-                if limit < MAX_LIMIT {
-                    limit *= 2;
+            }
+            // the cells are not enough, leave them to others
+            if total_capacity < query.min_total_capacity && !reserved.is_empty() {
+                if let Some(reservation) = self.reservation.as_mut() {
+                    reservation
+                        .release(&reserved)
+                        .map_err(|err| CellCollectorError::Internal(err.into()))?;
                 }
             }
             cells = ret_cells.into_values().collect();
@@ -397,10 +558,23 @@ impl CellCollector for DefaultCellCollector {
         tx: Transaction,
         tip_block_number: u64,
     ) -> Result<(), CellCollectorError> {
+        self.track_tip(tip_block_number)?;
+        if let Some(reservation) = self.reservation.as_mut() {
+            let tx_view = tx.clone().into_view();
+            let inputs: Vec<OutPoint> = tx_view.input_pts_iter().collect();
+            reservation
+                .set_spending_tx(&inputs, &tx_view.hash().unpack())
+                .map_err(|err| CellCollectorError::Internal(err.into()))?;
+        }
         self.offchain.apply_tx(tx, tip_block_number)
     }
     fn reset(&mut self) {
         self.offchain.reset();
+        if let Some(reservation) = self.reservation.as_mut() {
+            if let Err(err) = reservation.release_all() {
+                log::warn!("release the cell reservations failed: {}", err);
+            }
+        }
     }
 }
 
@@ -410,6 +584,29 @@ struct DefaultTxDepProviderInner {
     cell_cache: LruCache<OutPoint, (CellOutput, Bytes)>,
     header_cache: LruCache<Byte32, HeaderView>,
     offchain_cache: OffchainTransactionDependencyProvider,
+    reorg_callback: Option<ReorgCallback>,
+}
+
+impl DefaultTxDepProviderInner {
+    fn check_reorg(&mut self) -> Result<Option<ReorgEvent>, TransactionDependencyError> {
+        let rpc_client = &self.rpc_client;
+        let fork_number = self.offchain_cache.tip_hashes.find_fork(|number| {
+            rpc_client
+                .get_block_hash(number.into())
+                .map_err(|err| TransactionDependencyError::Other(err.into()))
+        })?;
+        let event = match fork_number {
+            Some(fork_number) => self.offchain_cache.rollback(fork_number),
+            None => return Ok(None),
+        };
+        // The committed transactions and live cells cached may be reorged away
+        self.tx_cache.clear();
+        self.cell_cache.clear();
+        if let Some(callback) = self.reorg_callback.as_mut() {
+            callback(&event);
+        }
+        Ok(Some(event))
+    }
 }
 
 /// A transaction dependency provider use ckb rpc client as backend, and with LRU cache supported
@@ -437,6 +634,7 @@ impl DefaultTransactionDependencyProvider {
             cell_cache: LruCache::new(cache_capacity),
             header_cache: LruCache::new(cache_capacity),
             offchain_cache: OffchainTransactionDependencyProvider::new(),
+            reorg_callback: None,
         };
         DefaultTransactionDependencyProvider {
             inner: Arc::new(Mutex::new(inner)),
@@ -449,10 +647,44 @@ impl DefaultTransactionDependencyProvider {
         tip_block_number: u64,
     ) -> Result<(), TransactionDependencyError> {
         let mut inner = self.inner.lock();
+        inner.check_reorg()?;
+        if inner
+            .offchain_cache
+            .tip_hashes
+            .get(tip_block_number)
+            .is_none()
+        {
+            let hash: Option<H256> = inner
+                .rpc_client
+                .get_block_hash(tip_block_number.into())
+                .map_err(|err| TransactionDependencyError::Other(err.into()))?;
+            if let Some(hash) = hash {
+                inner
+                    .offchain_cache
+                    .tip_hashes
+                    .insert(tip_block_number, hash);
+            }
+        }
         inner.offchain_cache.apply_tx(tx, tip_block_number)?;
         Ok(())
     }
 
+    /// Set the callback notified when the applied transactions are rolled
+    /// back by a chain reorganization.
+    pub fn set_reorg_callback<F>(&self, callback: F)
+    where
+        F: FnMut(&ReorgEvent) + Send + 'static,
+    {
+        self.inner.lock().reorg_callback = Some(Box::new(callback));
+    }
+
+    /// Compare the tip hashes recorded with the applied transactions to the
+    /// canonical chain, roll back the transactions applied at and after the
+    /// fork block and clear the caches if a reorg is detected.
+    pub fn check_reorg(&self) -> Result<Option<ReorgEvent>, TransactionDependencyError> {
+        self.inner.lock().check_reorg()
+    }
+
     pub fn get_cell_with_data(
         &self,
         out_point: &OutPoint,
@@ -632,6 +864,129 @@ impl Drop for SecpCkbRawKeySigner {
         }
     }
 }
+
+/// A signer derive secp256k1 keys from a BIP32 account key on demand, the id
+/// is `blake160(pubkey)`.
+///
+/// The ids of `m/44'/309'/account'/change/index` are precomputed from the
+/// account extended public key, `gap_limit` addresses after the last used
+/// index of each key chain are watched.
+#[derive(Clone)]
+pub struct SecpHdKeySigner {
+    account_key: ExtendedPrivKey,
+    account_pubkey: ExtendedPubKey,
+    gap_limit: u32,
+    // The next index to be precomputed of each key chain
+    next_indices: HashMap<KeyChain, u32>,
+    paths: HashMap<H160, (KeyChain, u32)>,
+}
+
+impl SecpHdKeySigner {
+    /// Create the signer by master key and BIP44 account index
+    pub fn new(
+        master: &ExtendedPrivKey,
+        account: u32,
+        gap_limit: u32,
+    ) -> Result<SecpHdKeySigner, HdKeyError> {
+        let account_key = master.derive_path(&DerivationPath::ckb_account(account))?;
+        let account_pubkey = account_key.to_extended_pubkey();
+        let mut signer = SecpHdKeySigner {
+            account_key,
+            account_pubkey,
+            gap_limit,
+            next_indices: HashMap::default(),
+            paths: HashMap::default(),
+        };
+        signer.mark_used(KeyChain::External, None)?;
+        signer.mark_used(KeyChain::Change, None)?;
+        Ok(signer)
+    }
+
+    /// Create the signer by BIP39 english mnemonic
+    pub fn from_mnemonic(
+        phrase: &str,
+        passphrase: &str,
+        account: u32,
+        gap_limit: u32,
+    ) -> Result<SecpHdKeySigner, HdKeyError> {
+        let master = ExtendedPrivKey::from_mnemonic(phrase, passphrase)?;
+        SecpHdKeySigner::new(&master, account, gap_limit)
+    }
+
+    /// Mark the index of the key chain as used, the address window is extended
+    /// to `gap_limit` addresses after it.
+    pub fn mark_used(&mut self, chain: KeyChain, index: Option<u32>) -> Result<(), HdKeyError> {
+        let end = index
+            .map(|index| index.saturating_add(1))
+            .unwrap_or(0)
+            .saturating_add(self.gap_limit);
+        let chain_pubkey = self
+            .account_pubkey
+            .derive_child(ChildNumber::Normal(chain as u32))?;
+        let next_index = self.next_indices.entry(chain).or_insert(0);
+        while *next_index < end {
+            let pubkey = chain_pubkey.derive_child(ChildNumber::Normal(*next_index))?;
+            self.paths
+                .insert(pubkey.pubkey_hash(), (chain, *next_index));
+            *next_index += 1;
+        }
+        Ok(())
+    }
+
+    /// The `blake160(pubkey)` of the key
+    pub fn pubkey_hash(&self, chain: KeyChain, index: u32) -> Result<H160, HdKeyError> {
+        Ok(self
+            .account_pubkey
+            .derive_child(ChildNumber::Normal(chain as u32))?
+            .derive_child(ChildNumber::Normal(index))?
+            .pubkey_hash())
+    }
+
+    /// The key chain and index of the id in the address window
+    pub fn get_path(&self, id: &H160) -> Option<(KeyChain, u32)> {
+        self.paths.get(id).cloned()
+    }
+
+    pub fn account_pubkey(&self) -> &ExtendedPubKey {
+        &self.account_pubkey
+    }
+
+    fn derive_secret_key(&self, id: &[u8]) -> Result<ExtendedPrivKey, SignerError> {
+        let (chain, index) = H160::from_slice(id)
+            .ok()
+            .and_then(|id| self.get_path(&id))
+            .ok_or(SignerError::IdNotFound)?;
+        self.account_key
+            .derive_child(ChildNumber::Normal(chain as u32))
+            .and_then(|key| key.derive_child(ChildNumber::Normal(index)))
+            .map_err(|err| SignerError::Other(anyhow!(err)))
+    }
+}
+
+impl Signer for SecpHdKeySigner {
+    fn match_id(&self, id: &[u8]) -> bool {
+        id.len() == 20 && self.paths.contains_key(&H160::from_slice(id).unwrap())
+    }
+
+    fn sign(
+        &self,
+        id: &[u8],
+        message: &[u8],
+        recoverable: bool,
+        tx: &TransactionView,
+    ) -> Result<Bytes, SignerError> {
+        if !self.match_id(id) {
+            return Err(SignerError::IdNotFound);
+        }
+        let key = self.derive_secret_key(id)?;
+        SecpCkbRawKeySigner::new_with_secret_keys(vec![key.private_key]).sign(
+            id,
+            message,
+            recoverable,
+            tx,
+        )
+    }
+}
 #[cfg(test)]
 mod anyhow_tests {
     use anyhow::anyhow;
@@ -642,3 +997,34 @@ mod anyhow_tests {
         assert_eq!("data not found: `DataHashNotFound`", error.to_string());
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ckb_types::core::TransactionBuilder;
+
+    #[test]
+    fn test_hd_key_signer() {
+        let phrase = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+        let mut signer = SecpHdKeySigner::from_mnemonic(phrase, "", 0, 5).unwrap();
+        let id = signer.pubkey_hash(KeyChain::Change, 4).unwrap();
+        assert!(signer.match_id(id.as_bytes()));
+        assert_eq!(signer.get_path(&id), Some((KeyChain::Change, 4)));
+        let id = signer.pubkey_hash(KeyChain::External, 7).unwrap();
+        assert!(!signer.match_id(id.as_bytes()));
+        signer.mark_used(KeyChain::External, Some(2)).unwrap();
+        assert!(signer.match_id(id.as_bytes()));
+
+        let key = ExtendedPrivKey::from_mnemonic(phrase, "")
+            .unwrap()
+            .derive_path(&DerivationPath::ckb(0, KeyChain::External, 7))
+            .unwrap();
+        let raw_signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![key.private_key]);
+        let tx = TransactionBuilder::default().build();
+        let message = [1u8; 32];
+        assert_eq!(
+            signer.sign(id.as_bytes(), &message, true, &tx).unwrap(),
+            raw_signer.sign(id.as_bytes(), &message, true, &tx).unwrap()
+        );
+    }
+}
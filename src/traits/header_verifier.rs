@@ -0,0 +1,469 @@
+//! Verify the headers returned by a node from a trusted checkpoint, so the
+//! headers can be trusted without trusting the node.
+//!
+//! Each header is checked against its parent: the parent hash and number
+//! linkage, the epoch number/index/length transition, the compact target (it
+//! is fixed in an epoch and changes by at most `TAU` times between epochs) and
+//! the proof of work. The exact difficulty adjustment needs the uncles of the
+//! last epoch, which are not in the headers, so only its bounds are checked.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use ckb_pow::{Pow, PowEngine};
+use ckb_types::{
+    core::{EpochNumberWithFraction, HeaderView},
+    packed::Byte32,
+    prelude::*,
+    utilities::{compact_to_difficulty, difficulty_to_compact},
+    U256,
+};
+use parking_lot::Mutex;
+use thiserror::Error;
+
+use super::HeaderDepResolver;
+use crate::tx_proof::TrustedHeaderProvider;
+
+/// The epoch length bounds of the consensus
+pub const MIN_EPOCH_LENGTH: u64 = 300;
+pub const MAX_EPOCH_LENGTH: u64 = 1800;
+/// The max difficulty change ratio between two epochs
+pub const TAU: u64 = 2;
+
+#[derive(Error, Debug)]
+pub enum HeaderVerifyError {
+    #[error("header `{hash}` does not link to the parent, expected parent: `{expected}`, actual: `{actual}`")]
+    ParentMismatch {
+        hash: Byte32,
+        expected: Byte32,
+        actual: Byte32,
+    },
+
+    #[error("header number `{number}` is not the next of parent `{parent}`")]
+    NumberMismatch { parent: u64, number: u64 },
+
+    #[error("invalid epoch of header `{0}`: `{1}`")]
+    InvalidEpoch(Byte32, String),
+
+    #[error("invalid compact target of header `{0}`: `{1}`")]
+    InvalidDifficulty(Byte32, String),
+
+    #[error("invalid proof of work of header `{0}`")]
+    InvalidPow(Byte32),
+
+    #[error("header `{0}` is before the checkpoint")]
+    BeforeCheckpoint(u64),
+
+    #[error("header `{0}` not found")]
+    HeaderNotFound(u64),
+
+    #[error("header `{0}` is not in the verified chain")]
+    NotCanonical(Byte32),
+
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+fn fmt_epoch(epoch: &EpochNumberWithFraction) -> String {
+    format!("{}({}/{})", epoch.number(), epoch.index(), epoch.length())
+}
+
+/// The verified header chain from the checkpoint.
+pub struct HeaderChainVerifier {
+    pow: Arc<dyn PowEngine>,
+    check_epoch_length: bool,
+    headers: BTreeMap<u64, HeaderView>,
+    numbers: HashMap<Byte32, u64>,
+}
+
+impl HeaderChainVerifier {
+    /// The `checkpoint` is trusted without verification. The epoch length
+    /// bounds are not checked for the `Dummy` pow (e.g. a dev chain).
+    pub fn new(checkpoint: HeaderView, pow: &Pow) -> HeaderChainVerifier {
+        let mut numbers = HashMap::new();
+        numbers.insert(checkpoint.hash(), checkpoint.number());
+        let mut headers = BTreeMap::new();
+        headers.insert(checkpoint.number(), checkpoint);
+        HeaderChainVerifier {
+            pow: pow.engine(),
+            check_epoch_length: !matches!(pow, Pow::Dummy),
+            headers,
+            numbers,
+        }
+    }
+
+    pub fn checkpoint(&self) -> &HeaderView {
+        self.headers.values().next().expect("checkpoint")
+    }
+    pub fn tip(&self) -> &HeaderView {
+        self.headers.values().next_back().expect("checkpoint")
+    }
+
+    pub fn get_by_number(&self, number: u64) -> Option<&HeaderView> {
+        self.headers.get(&number)
+    }
+    pub fn get_by_hash(&self, hash: &Byte32) -> Option<&HeaderView> {
+        self.numbers
+            .get(hash)
+            .and_then(|number| self.headers.get(number))
+    }
+
+    /// Verify the header is a valid child of the parent.
+    pub fn verify_child(
+        &self,
+        parent: &HeaderView,
+        header: &HeaderView,
+    ) -> Result<(), HeaderVerifyError> {
+        let hash = header.hash();
+        if header.number() != parent.number() + 1 {
+            return Err(HeaderVerifyError::NumberMismatch {
+                parent: parent.number(),
+                number: header.number(),
+            });
+        }
+        if header.parent_hash() != parent.hash() {
+            return Err(HeaderVerifyError::ParentMismatch {
+                hash,
+                expected: parent.hash(),
+                actual: header.parent_hash(),
+            });
+        }
+
+        let parent_epoch = parent.epoch();
+        let epoch = header.epoch();
+        if parent_epoch.index() + 1 < parent_epoch.length() {
+            if epoch.number() != parent_epoch.number()
+                || epoch.index() != parent_epoch.index() + 1
+                || epoch.length() != parent_epoch.length()
+            {
+                return Err(HeaderVerifyError::InvalidEpoch(
+                    hash,
+                    format!(
+                        "expected next of {}, actual {}",
+                        fmt_epoch(&parent_epoch),
+                        fmt_epoch(&epoch)
+                    ),
+                ));
+            }
+            if header.compact_target() != parent.compact_target() {
+                return Err(HeaderVerifyError::InvalidDifficulty(
+                    hash,
+                    "compact target changed in an epoch".to_string(),
+                ));
+            }
+        } else {
+            if epoch.number() != parent_epoch.number() + 1 || epoch.index() != 0 {
+                return Err(HeaderVerifyError::InvalidEpoch(
+                    hash,
+                    format!(
+                        "expected start of new epoch after {}, actual {}",
+                        fmt_epoch(&parent_epoch),
+                        fmt_epoch(&epoch)
+                    ),
+                ));
+            }
+            if self.check_epoch_length
+                && !(MIN_EPOCH_LENGTH..=MAX_EPOCH_LENGTH).contains(&epoch.length())
+            {
+                return Err(HeaderVerifyError::InvalidEpoch(
+                    hash,
+                    format!("epoch length {} out of bounds", epoch.length()),
+                ));
+            }
+            let last_difficulty = parent.difficulty();
+            let tau = U256::from(TAU);
+            let lower = compact_to_difficulty(difficulty_to_compact(&last_difficulty / &tau));
+            let upper = compact_to_difficulty(difficulty_to_compact(
+                last_difficulty
+                    .checked_mul(&tau)
+                    .unwrap_or_else(U256::max_value),
+            ));
+            let difficulty = header.difficulty();
+            if difficulty < lower || difficulty > upper {
+                return Err(HeaderVerifyError::InvalidDifficulty(
+                    hash,
+                    format!(
+                        "difficulty {:#x} out of bounds [{:#x}, {:#x}]",
+                        difficulty, lower, upper
+                    ),
+                ));
+            }
+        }
+
+        if !self.pow.verify(&header.data()) {
+            return Err(HeaderVerifyError::InvalidPow(hash));
+        }
+        Ok(())
+    }
+
+    /// Verify the header against the tip and append it.
+    pub fn append(&mut self, header: HeaderView) -> Result<(), HeaderVerifyError> {
+        self.verify_child(self.tip(), &header)?;
+        self.numbers.insert(header.hash(), header.number());
+        self.headers.insert(header.number(), header);
+        Ok(())
+    }
+
+    /// Remove the headers after `number`, the checkpoint is always kept.
+    pub fn rollback(&mut self, number: u64) {
+        let number = number.max(self.checkpoint().number());
+        for (_, header) in self.headers.split_off(&(number + 1)) {
+            self.numbers.remove(&header.hash());
+        }
+    }
+}
+
+/// A [`HeaderDepResolver`] wrapper which only returns the headers verified
+/// from the checkpoint, and rejects the others.
+///
+/// The headers between the verified tip and the requested header are fetched
+/// by number from the inner resolver and verified one by one. When the node
+/// switched to another fork, the verified headers not in the new fork are
+/// verified again, but never before the checkpoint.
+pub struct VerifiedHeaderDepResolver<R> {
+    inner: R,
+    verifier: Mutex<HeaderChainVerifier>,
+}
+
+impl<R: HeaderDepResolver> VerifiedHeaderDepResolver<R> {
+    pub fn new(inner: R, checkpoint: HeaderView, pow: &Pow) -> VerifiedHeaderDepResolver<R> {
+        VerifiedHeaderDepResolver {
+            inner,
+            verifier: Mutex::new(HeaderChainVerifier::new(checkpoint, pow)),
+        }
+    }
+
+    pub fn inner(&self) -> &R {
+        &self.inner
+    }
+    pub fn inner_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// The verified tip header
+    pub fn tip(&self) -> HeaderView {
+        self.verifier.lock().tip().clone()
+    }
+
+    /// Verify the headers up to `number`.
+    pub fn sync_to(&self, number: u64) -> Result<(), HeaderVerifyError> {
+        let mut verifier = self.verifier.lock();
+        self.sync_locked(&mut verifier, number)
+    }
+
+    fn sync_locked(
+        &self,
+        verifier: &mut HeaderChainVerifier,
+        number: u64,
+    ) -> Result<(), HeaderVerifyError> {
+        let checkpoint_number = verifier.checkpoint().number();
+        if number < checkpoint_number {
+            return Err(HeaderVerifyError::BeforeCheckpoint(number));
+        }
+        while verifier.tip().number() < number {
+            let next = verifier.tip().number() + 1;
+            let header = self
+                .inner
+                .resolve_by_number(next)?
+                .ok_or(HeaderVerifyError::HeaderNotFound(next))?;
+            let tip_number = verifier.tip().number();
+            if header.parent_hash() != verifier.tip().hash() && tip_number > checkpoint_number {
+                // The node switched to another fork, verify the parent again
+                verifier.rollback(tip_number - 1);
+                continue;
+            }
+            verifier.append(header)?;
+        }
+        Ok(())
+    }
+
+    fn verify_header(&self, header: HeaderView) -> Result<HeaderView, HeaderVerifyError> {
+        let number = header.number();
+        let hash = header.hash();
+        let mut verifier = self.verifier.lock();
+        self.sync_locked(&mut verifier, number)?;
+        if verifier.get_by_number(number).map(|h| h.hash()) != Some(hash.clone())
+            && number > verifier.checkpoint().number()
+        {
+            // The verified header may be in a fork the node switched from
+            verifier.rollback(number - 1);
+            self.sync_locked(&mut verifier, number)?;
+        }
+        match verifier.get_by_number(number) {
+            Some(verified) if verified.hash() == hash => Ok(verified.clone()),
+            _ => Err(HeaderVerifyError::NotCanonical(hash)),
+        }
+    }
+}
+
+impl<R: HeaderDepResolver> HeaderDepResolver for VerifiedHeaderDepResolver<R> {
+    fn resolve_by_tx(&self, tx_hash: &Byte32) -> Result<Option<HeaderView>, anyhow::Error> {
+        match self.inner.resolve_by_tx(tx_hash)? {
+            Some(header) => Ok(Some(self.verify_header(header)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn resolve_by_number(&self, number: u64) -> Result<Option<HeaderView>, anyhow::Error> {
+        let mut verifier = self.verifier.lock();
+        self.sync_locked(&mut verifier, number)?;
+        Ok(verifier.get_by_number(number).cloned())
+    }
+}
+
+impl<R> TrustedHeaderProvider for VerifiedHeaderDepResolver<R> {
+    fn get_trusted_header(&self, block_hash: &Byte32) -> Result<Option<HeaderView>, anyhow::Error> {
+        Ok(self.verifier.lock().get_by_hash(block_hash).cloned())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::traits::OffchainHeaderDepResolver;
+    use ckb_types::core::HeaderBuilder;
+
+    fn header(
+        parent: &HeaderView,
+        epoch: (u64, u64, u64),
+        difficulty: u64,
+        nonce: u128,
+    ) -> HeaderView {
+        HeaderBuilder::default()
+            .number((parent.number() + 1).pack())
+            .parent_hash(parent.hash())
+            .epoch(
+                EpochNumberWithFraction::new(epoch.0, epoch.1, epoch.2)
+                    .full_value()
+                    .pack(),
+            )
+            .compact_target(difficulty_to_compact(U256::from(difficulty)).pack())
+            .nonce(nonce.pack())
+            .build()
+    }
+
+    fn checkpoint() -> HeaderView {
+        HeaderBuilder::default()
+            .number(100u64.pack())
+            .epoch(
+                EpochNumberWithFraction::new(1, 998, 1000)
+                    .full_value()
+                    .pack(),
+            )
+            .compact_target(difficulty_to_compact(U256::from(1000u64)).pack())
+            .build()
+    }
+
+    #[test]
+    fn test_verify_child() {
+        let checkpoint = checkpoint();
+        let mut verifier = HeaderChainVerifier::new(checkpoint.clone(), &Pow::Dummy);
+        let h101 = header(&checkpoint, (1, 999, 1000), 1000, 0);
+        verifier.append(h101.clone()).unwrap();
+
+        // compact target changed in an epoch
+        let h102 = header(&checkpoint, (1, 999, 1000), 1200, 0);
+        assert!(matches!(
+            verifier.verify_child(&checkpoint, &h102),
+            Err(HeaderVerifyError::InvalidDifficulty(..))
+        ));
+        // not linked
+        assert!(matches!(
+            verifier.verify_child(&h101, &header(&checkpoint, (1, 999, 1000), 1000, 1)),
+            Err(HeaderVerifyError::NumberMismatch { .. })
+        ));
+        let unlinked = header(&h101, (2, 0, 1000), 1000, 0)
+            .as_advanced_builder()
+            .parent_hash(checkpoint.hash())
+            .build();
+        assert!(matches!(
+            verifier.verify_child(&h101, &unlinked),
+            Err(HeaderVerifyError::ParentMismatch { .. })
+        ));
+        // epoch not started
+        assert!(matches!(
+            verifier.verify_child(&h101, &header(&h101, (2, 1, 1000), 1000, 0)),
+            Err(HeaderVerifyError::InvalidEpoch(..))
+        ));
+        // difficulty adjusted more than TAU times
+        assert!(matches!(
+            verifier.verify_child(&h101, &header(&h101, (2, 0, 1000), 2100, 0)),
+            Err(HeaderVerifyError::InvalidDifficulty(..))
+        ));
+        assert!(matches!(
+            verifier.verify_child(&h101, &header(&h101, (2, 0, 1000), 400, 0)),
+            Err(HeaderVerifyError::InvalidDifficulty(..))
+        ));
+        verifier
+            .append(header(&h101, (2, 0, 1200), 1500, 0))
+            .unwrap();
+        assert_eq!(verifier.tip().number(), 102);
+
+        verifier.rollback(50);
+        assert_eq!(verifier.tip().hash(), checkpoint.hash());
+        assert!(verifier.get_by_hash(&h101.hash()).is_none());
+    }
+
+    #[test]
+    fn test_invalid_pow() {
+        let checkpoint = checkpoint()
+            .as_advanced_builder()
+            .compact_target(0u32.pack())
+            .build();
+        let verifier = HeaderChainVerifier::new(checkpoint.clone(), &Pow::Eaglesong);
+        let child = header(&checkpoint, (1, 999, 1000), 1000, 0)
+            .as_advanced_builder()
+            .compact_target(0u32.pack())
+            .build();
+        assert!(matches!(
+            verifier.verify_child(&checkpoint, &child),
+            Err(HeaderVerifyError::InvalidPow(_))
+        ));
+    }
+
+    #[test]
+    fn test_verified_resolver() {
+        let checkpoint = checkpoint();
+        let h101 = header(&checkpoint, (1, 999, 1000), 1000, 0);
+        let h102 = header(&h101, (2, 0, 1000), 1000, 0);
+        let h102_fork = header(&h101, (2, 0, 1000), 1000, 1);
+        let h103_fork = header(&h102_fork, (2, 1, 1000), 1000, 0);
+        let tx_hash = Byte32::new([1; 32]);
+
+        let mut inner = OffchainHeaderDepResolver::default();
+        for header in vec![checkpoint.clone(), h101, h102.clone()] {
+            inner.by_number.insert(header.number(), header);
+        }
+        inner.by_tx_hash.insert(tx_hash.unpack(), h102.clone());
+        let mut resolver = VerifiedHeaderDepResolver::new(inner, checkpoint, &Pow::Dummy);
+
+        assert!(resolver.resolve_by_number(99).is_err());
+        assert_eq!(
+            resolver.resolve_by_tx(&tx_hash).unwrap().map(|h| h.hash()),
+            Some(h102.hash())
+        );
+        assert!(resolver.resolve_by_number(103).is_err());
+
+        // the node switched to the fork
+        resolver
+            .inner_mut()
+            .by_number
+            .insert(102, h102_fork.clone());
+        resolver
+            .inner_mut()
+            .by_number
+            .insert(103, h103_fork.clone());
+        assert_eq!(
+            resolver.resolve_by_number(103).unwrap().map(|h| h.hash()),
+            Some(h103_fork.hash())
+        );
+        assert!(resolver.get_trusted_header(&h102.hash()).unwrap().is_none());
+        assert!(resolver.resolve_by_tx(&tx_hash).is_err());
+
+        // an invalid header is rejected
+        let invalid = header(&h103_fork, (2, 2, 1000), 1200, 0);
+        resolver.inner_mut().by_number.insert(104, invalid);
+        assert!(resolver.resolve_by_number(104).is_err());
+        assert_eq!(resolver.tip().number(), 103);
+    }
+}
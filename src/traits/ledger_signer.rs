@@ -0,0 +1,652 @@
+//! Signer of the Nervos Ledger app, over an abstract APDU transport.
+//!
+//! The device does not sign a precomputed hash, it receives the whole
+//! transaction as an [`AnnotatedTransaction`], each input with the raw
+//! transaction creating it, and computes the sighash message itself after
+//! showing the transaction to the user.
+//!
+//! Commands (CLA `0x80`):
+//!   * `GET_APP_VERSION`: returns `major | minor | patch`
+//!   * `GET_PUBLIC_KEY`: data is the path, returns `pubkey_len (1) | pubkey`
+//!   * `SIGN`: data is the `AnnotatedTransaction`, returns `r | s | v`
+//!   * `SIGN_MESSAGE`: data is `path | display_hex (1) | message`, returns
+//!     `r | s | v` over `blake2b_256("Nervos Message:" | message)`
+//!
+//! A path is `count (1) | index (u32 BE)*`. Data larger than
+//! [`MAX_APDU_DATA_SIZE`] is split into chunks, `p1` is [`P1_FIRST`] for the
+//! first chunk, [`P1_NEXT`] for others, and [`P1_LAST_MARKER`] is set on the
+//! last one.
+
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use ckb_types::{bytes::Bytes, core::TransactionView, packed::RawTransaction, prelude::*, H160};
+use secp256k1::{
+    ecdsa::{RecoverableSignature, RecoveryId},
+    Message, PublicKey,
+};
+use thiserror::Error;
+
+use super::{Signer, SignerError, TransactionDependencyError, TransactionDependencyProvider};
+use crate::{
+    message::{hash_message, MESSAGE_PREFIX},
+    types::{
+        ledger::{
+            AnnotatedCellInput, AnnotatedCellInputVec, AnnotatedRawTransaction,
+            AnnotatedTransaction, Bip32,
+        },
+        DerivationPath,
+    },
+    util::blake160,
+    SECP256K1,
+};
+
+pub const CLA: u8 = 0x80;
+pub const INS_GET_APP_VERSION: u8 = 0x00;
+pub const INS_GET_PUBLIC_KEY: u8 = 0x02;
+pub const INS_SIGN: u8 = 0x03;
+pub const INS_SIGN_MESSAGE: u8 = 0x06;
+
+pub const P1_FIRST: u8 = 0x00;
+pub const P1_NEXT: u8 = 0x01;
+pub const P1_LAST_MARKER: u8 = 0x80;
+/// `p1` of `GET_PUBLIC_KEY` asking the user to confirm the key on device
+pub const P1_CONFIRM: u8 = 0x01;
+
+pub const SW_OK: u16 = 0x9000;
+pub const SW_REJECTED: u16 = 0x6985;
+
+/// The max data size of an APDU command
+pub const MAX_APDU_DATA_SIZE: usize = 230;
+
+/// The prefix of the message signed by `SIGN_MESSAGE`
+pub const LEDGER_MESSAGE_MAGIC: &[u8] = MESSAGE_PREFIX;
+
+#[derive(Error, Debug)]
+pub enum LedgerError {
+    #[error("transport error: `{0}`")]
+    Transport(String),
+
+    #[error("the user rejected the request on device")]
+    Rejected,
+
+    #[error("device returns status: `{0:#06x}`")]
+    Status(u16),
+
+    #[error("invalid transaction: `{0}`")]
+    InvalidTransaction(String),
+
+    #[error("invalid response: `{0}`")]
+    InvalidResponse(String),
+
+    #[error("transaction dependency error: `{0}`")]
+    TxDep(#[from] TransactionDependencyError),
+}
+
+impl From<LedgerError> for SignerError {
+    fn from(err: LedgerError) -> SignerError {
+        SignerError::Other(anyhow!(err))
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ApduCommand {
+    pub cla: u8,
+    pub ins: u8,
+    pub p1: u8,
+    pub p2: u8,
+    pub data: Vec<u8>,
+}
+
+impl ApduCommand {
+    /// `cla | ins | p1 | p2 | data_len (1) | data`
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = vec![self.cla, self.ins, self.p1, self.p2, self.data.len() as u8];
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ApduAnswer {
+    pub data: Vec<u8>,
+    pub status: u16,
+}
+
+impl ApduAnswer {
+    /// Parse `data | status (u16 BE)`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<ApduAnswer, LedgerError> {
+        if bytes.len() < 2 {
+            return Err(LedgerError::InvalidResponse(format!(
+                "answer too short: {}",
+                bytes.len()
+            )));
+        }
+        let (data, status) = bytes.split_at(bytes.len() - 2);
+        Ok(ApduAnswer {
+            data: data.to_vec(),
+            status: u16::from_be_bytes([status[0], status[1]]),
+        })
+    }
+
+    fn into_result(self) -> Result<Vec<u8>, LedgerError> {
+        match self.status {
+            SW_OK => Ok(self.data),
+            SW_REJECTED => Err(LedgerError::Rejected),
+            status => Err(LedgerError::Status(status)),
+        }
+    }
+}
+
+/// The channel to the device, e.g. USB HID, BLE or a speculos emulator.
+pub trait ApduTransport {
+    fn exchange(&self, command: &ApduCommand) -> Result<ApduAnswer, LedgerError>;
+}
+
+/// The commands of the Nervos Ledger app.
+pub struct LedgerDevice<T> {
+    transport: T,
+}
+
+impl<T: ApduTransport> LedgerDevice<T> {
+    pub fn new(transport: T) -> LedgerDevice<T> {
+        LedgerDevice { transport }
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    fn exchange(&self, ins: u8, p1: u8, data: Vec<u8>) -> Result<Vec<u8>, LedgerError> {
+        let command = ApduCommand {
+            cla: CLA,
+            ins,
+            p1,
+            p2: 0,
+            data,
+        };
+        self.transport.exchange(&command)?.into_result()
+    }
+
+    /// Send the data in chunks, return the answer of the last chunk.
+    fn exchange_chunks(&self, ins: u8, data: &[u8]) -> Result<Vec<u8>, LedgerError> {
+        let chunks: Vec<&[u8]> = if data.is_empty() {
+            vec![data]
+        } else {
+            data.chunks(MAX_APDU_DATA_SIZE).collect()
+        };
+        let last = chunks.len() - 1;
+        for (idx, chunk) in chunks.iter().enumerate() {
+            let mut p1 = if idx == 0 { P1_FIRST } else { P1_NEXT };
+            if idx == last {
+                p1 |= P1_LAST_MARKER;
+            }
+            let answer = self.exchange(ins, p1, chunk.to_vec())?;
+            if idx == last {
+                return Ok(answer);
+            }
+        }
+        unreachable!("at least one chunk")
+    }
+
+    pub fn app_version(&self) -> Result<(u8, u8, u8), LedgerError> {
+        let data = self.exchange(INS_GET_APP_VERSION, 0, Vec::new())?;
+        if data.len() < 3 {
+            return Err(LedgerError::InvalidResponse(format!(
+                "version length: {}",
+                data.len()
+            )));
+        }
+        Ok((data[0], data[1], data[2]))
+    }
+
+    /// Get the public key of the path, the user confirms it on device if
+    /// `confirm` is true.
+    pub fn get_public_key(
+        &self,
+        path: &DerivationPath,
+        confirm: bool,
+    ) -> Result<PublicKey, LedgerError> {
+        let p1 = if confirm { P1_CONFIRM } else { 0 };
+        let data = self.exchange(INS_GET_PUBLIC_KEY, p1, serialize_path(path))?;
+        let pubkey_len = *data
+            .first()
+            .ok_or_else(|| LedgerError::InvalidResponse("empty public key".to_string()))?
+            as usize;
+        let pubkey = data.get(1..1 + pubkey_len).ok_or_else(|| {
+            LedgerError::InvalidResponse(format!("public key length: {}", pubkey_len))
+        })?;
+        PublicKey::from_slice(pubkey).map_err(|err| LedgerError::InvalidResponse(err.to_string()))
+    }
+
+    /// Sign the transaction by the key of `path`, `sources` are the raw
+    /// transactions creating the inputs, in the order of the inputs. The
+    /// device recognizes the outputs locked by `change_path` as change.
+    pub fn sign_transaction(
+        &self,
+        path: &DerivationPath,
+        change_path: Option<&DerivationPath>,
+        tx: &TransactionView,
+        sources: &[RawTransaction],
+    ) -> Result<Bytes, LedgerError> {
+        let annotated = build_annotated_transaction(path, change_path, tx, sources)?;
+        let data = self.exchange_chunks(INS_SIGN, annotated.as_slice())?;
+        parse_signature(data)
+    }
+
+    /// Sign `blake2b_256("Nervos Message:" | message)` by the key of `path`,
+    /// the device shows the message in hex if `display_hex` is true.
+    pub fn sign_message(
+        &self,
+        path: &DerivationPath,
+        message: &[u8],
+        display_hex: bool,
+    ) -> Result<Bytes, LedgerError> {
+        let mut data = serialize_path(path);
+        data.push(display_hex as u8);
+        data.extend_from_slice(message);
+        let data = self.exchange_chunks(INS_SIGN_MESSAGE, &data)?;
+        parse_signature(data)
+    }
+}
+
+/// `count (1) | index (u32 BE)*`
+pub fn serialize_path(path: &DerivationPath) -> Vec<u8> {
+    let mut data = vec![path.as_slice().len() as u8];
+    for child in path.as_slice() {
+        data.extend_from_slice(&child.to_u32().to_be_bytes());
+    }
+    data
+}
+
+/// The message hash signed by `SIGN_MESSAGE`.
+pub fn ledger_message_hash(message: &[u8]) -> [u8; 32] {
+    hash_message(message)
+}
+
+fn path_to_bip32(path: Option<&DerivationPath>) -> Bip32 {
+    let indices = path
+        .map(|path| {
+            path.as_slice()
+                .iter()
+                .map(|child| child.to_u32().pack())
+                .collect()
+        })
+        .unwrap_or_default();
+    Bip32::new_builder().set(indices).build()
+}
+
+pub fn build_annotated_transaction(
+    path: &DerivationPath,
+    change_path: Option<&DerivationPath>,
+    tx: &TransactionView,
+    sources: &[RawTransaction],
+) -> Result<AnnotatedTransaction, LedgerError> {
+    if sources.len() != tx.inputs().len() {
+        return Err(LedgerError::InvalidTransaction(format!(
+            "input sources count {} not match inputs count {}",
+            sources.len(),
+            tx.inputs().len()
+        )));
+    }
+    let inputs = tx
+        .inputs()
+        .into_iter()
+        .zip(sources)
+        .map(|(input, source)| {
+            AnnotatedCellInput::new_builder()
+                .input(input)
+                .source(source.clone())
+                .build()
+        })
+        .collect::<Vec<_>>();
+    let raw = tx.data().raw();
+    let annotated_raw = AnnotatedRawTransaction::new_builder()
+        .version(raw.version())
+        .cell_deps(raw.cell_deps())
+        .header_deps(raw.header_deps())
+        .inputs(AnnotatedCellInputVec::new_builder().set(inputs).build())
+        .outputs(raw.outputs())
+        .outputs_data(raw.outputs_data())
+        .build();
+    Ok(AnnotatedTransaction::new_builder()
+        .sign_path(path_to_bip32(Some(path)))
+        .change_path(path_to_bip32(change_path))
+        .input_count((tx.inputs().len() as u32).pack())
+        .raw(annotated_raw)
+        .witnesses(tx.witnesses())
+        .build())
+}
+
+fn parse_signature(data: Vec<u8>) -> Result<Bytes, LedgerError> {
+    if data.len() != 65 {
+        return Err(LedgerError::InvalidResponse(format!(
+            "signature length: {}",
+            data.len()
+        )));
+    }
+    Ok(Bytes::from(data))
+}
+
+/// A [`Signer`] signing by the keys of the derivation paths on a Ledger.
+///
+/// The transaction creating every input is fetched by the transaction
+/// dependency provider. The signature is checked against the message, so a
+/// device computing another message is detected.
+pub struct LedgerSigner<T> {
+    device: LedgerDevice<T>,
+    paths: HashMap<H160, DerivationPath>,
+    change_path: Option<DerivationPath>,
+    tx_dep_provider: Box<dyn TransactionDependencyProvider>,
+}
+
+impl<T: ApduTransport> LedgerSigner<T> {
+    pub fn new(
+        device: LedgerDevice<T>,
+        tx_dep_provider: Box<dyn TransactionDependencyProvider>,
+    ) -> LedgerSigner<T> {
+        LedgerSigner {
+            device,
+            paths: HashMap::new(),
+            change_path: None,
+            tx_dep_provider,
+        }
+    }
+
+    pub fn device(&self) -> &LedgerDevice<T> {
+        &self.device
+    }
+
+    /// Get the public key of the path from device, return the id
+    /// `blake160(pubkey)`.
+    pub fn add_path(&mut self, path: DerivationPath) -> Result<H160, LedgerError> {
+        let pubkey = self.device.get_public_key(&path, false)?;
+        let id = blake160(&pubkey.serialize());
+        self.paths.insert(id.clone(), path);
+        Ok(id)
+    }
+
+    pub fn set_change_path(&mut self, change_path: Option<DerivationPath>) {
+        self.change_path = change_path;
+    }
+
+    fn input_sources(&self, tx: &TransactionView) -> Result<Vec<RawTransaction>, LedgerError> {
+        tx.inputs()
+            .into_iter()
+            .map(|input| {
+                let tx_hash = input.previous_output().tx_hash();
+                Ok(self.tx_dep_provider.get_transaction(&tx_hash)?.data().raw())
+            })
+            .collect()
+    }
+}
+
+impl<T: ApduTransport> Signer for LedgerSigner<T> {
+    fn match_id(&self, id: &[u8]) -> bool {
+        id.len() == 20 && self.paths.contains_key(&H160::from_slice(id).unwrap())
+    }
+
+    fn sign(
+        &self,
+        id: &[u8],
+        message: &[u8],
+        recoverable: bool,
+        tx: &TransactionView,
+    ) -> Result<Bytes, SignerError> {
+        if !self.match_id(id) {
+            return Err(SignerError::IdNotFound);
+        }
+        if message.len() != 32 {
+            return Err(SignerError::InvalidMessage(format!(
+                "expected length: 32, got: {}",
+                message.len()
+            )));
+        }
+        let path = &self.paths[&H160::from_slice(id).unwrap()];
+        let sources = self
+            .input_sources(tx)
+            .map_err(|err| SignerError::InvalidTransaction(err.to_string()))?;
+        let signature =
+            self.device
+                .sign_transaction(path, self.change_path.as_ref(), tx, &sources)?;
+
+        let recid = RecoveryId::from_i32(signature[64] as i32)
+            .map_err(|err| LedgerError::InvalidResponse(err.to_string()))?;
+        let recoverable_signature = RecoverableSignature::from_compact(&signature[0..64], recid)
+            .map_err(|err| LedgerError::InvalidResponse(err.to_string()))?;
+        let msg = Message::from_slice(message).expect("Convert to message failed");
+        let signed_by_id = SECP256K1
+            .recover_ecdsa(&msg, &recoverable_signature)
+            .map(|pubkey| blake160(&pubkey.serialize()).as_bytes() == id)
+            .unwrap_or(false);
+        if !signed_by_id {
+            return Err(SignerError::InvalidMessage(
+                "the device signed a different message".to_string(),
+            ));
+        }
+        if recoverable {
+            Ok(signature)
+        } else {
+            Ok(signature.slice(0..64))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+    use std::convert::TryInto;
+
+    use ckb_types::{
+        core::{ScriptHashType, TransactionBuilder},
+        packed::{CellInput, CellOutput, OutPoint, Script, Transaction, WitnessArgs},
+    };
+
+    use crate::{
+        constants::SIGHASH_TYPE_HASH,
+        traits::OffchainTransactionDependencyProvider,
+        types::{ChildNumber, ExtendedPrivKey, ScriptGroup, ScriptGroupType},
+        unlock::generate_message,
+    };
+
+    const MNEMONIC: &str =
+        "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+    /// An in-memory device signing the sighash message of the lock group of
+    /// the key, like the Nervos Ledger app.
+    struct MockDevice {
+        master: ExtendedPrivKey,
+        buffer: RefCell<Vec<u8>>,
+        commands: RefCell<Vec<ApduCommand>>,
+    }
+
+    impl MockDevice {
+        fn new() -> MockDevice {
+            MockDevice {
+                master: ExtendedPrivKey::from_mnemonic(MNEMONIC, "").unwrap(),
+                buffer: RefCell::new(Vec::new()),
+                commands: RefCell::new(Vec::new()),
+            }
+        }
+
+        fn key_of_path(&self, data: &[u8]) -> (secp256k1::SecretKey, usize) {
+            let count = data[0] as usize;
+            let path = DerivationPath::new(
+                data[1..1 + count * 4]
+                    .chunks(4)
+                    .map(|index| ChildNumber::from(u32::from_be_bytes(index.try_into().unwrap())))
+                    .collect(),
+            );
+            let key = self.master.derive_path(&path).unwrap().private_key;
+            (key, 1 + count * 4)
+        }
+
+        fn sign(&self, key: &secp256k1::SecretKey, message: &[u8]) -> Vec<u8> {
+            let msg = Message::from_slice(message).unwrap();
+            let (recid, data) = SECP256K1
+                .sign_ecdsa_recoverable(&msg, key)
+                .serialize_compact();
+            let mut signature = data.to_vec();
+            signature.push(recid.to_i32() as u8);
+            signature
+        }
+
+        fn sign_transaction(&self, data: &[u8]) -> Vec<u8> {
+            let annotated = AnnotatedTransaction::from_slice(data).unwrap();
+            let path = DerivationPath::new(
+                annotated
+                    .sign_path()
+                    .into_iter()
+                    .map(|index| ChildNumber::from(Unpack::<u32>::unpack(&index)))
+                    .collect(),
+            );
+            let key = self.master.derive_path(&path).unwrap().private_key;
+            let pubkey = PublicKey::from_secret_key(&SECP256K1, &key);
+            let lock = Script::new_builder()
+                .code_hash(SIGHASH_TYPE_HASH.pack())
+                .hash_type(ScriptHashType::Type.into())
+                .args(Bytes::from(blake160(&pubkey.serialize()).as_bytes().to_vec()).pack())
+                .build();
+
+            let raw = annotated.raw();
+            let mut group = ScriptGroup::new(&lock, ScriptGroupType::Lock);
+            let mut builder = TransactionBuilder::default()
+                .version(raw.version())
+                .cell_deps(raw.cell_deps())
+                .header_deps(raw.header_deps())
+                .outputs(raw.outputs())
+                .outputs_data(raw.outputs_data())
+                .witnesses(annotated.witnesses());
+            for (idx, annotated_input) in raw.inputs().into_iter().enumerate() {
+                let input = annotated_input.input();
+                let out_point = input.previous_output();
+                let source = annotated_input.source();
+                let source_hash = Transaction::new_builder()
+                    .raw(source.clone())
+                    .build()
+                    .calc_tx_hash();
+                assert_eq!(source_hash, out_point.tx_hash());
+                let index: u32 = out_point.index().unpack();
+                let cell = source.outputs().get(index as usize).unwrap();
+                if cell.lock() == lock {
+                    group.input_indices.push(idx);
+                }
+                builder = builder.input(input);
+            }
+            let tx = builder.build();
+            let message = generate_message(&tx, &group, Bytes::from(vec![0u8; 65])).unwrap();
+            self.sign(&key, &message)
+        }
+    }
+
+    impl ApduTransport for MockDevice {
+        fn exchange(&self, command: &ApduCommand) -> Result<ApduAnswer, LedgerError> {
+            self.commands.borrow_mut().push(command.clone());
+            assert_eq!(command.cla, CLA);
+            assert!(command.data.len() <= MAX_APDU_DATA_SIZE);
+            let data = match command.ins {
+                INS_GET_APP_VERSION => vec![0, 5, 0],
+                INS_GET_PUBLIC_KEY => {
+                    let (key, _) = self.key_of_path(&command.data);
+                    let pubkey = PublicKey::from_secret_key(&SECP256K1, &key);
+                    let mut data = vec![65u8];
+                    data.extend_from_slice(&pubkey.serialize_uncompressed());
+                    data
+                }
+                INS_SIGN | INS_SIGN_MESSAGE => {
+                    if command.p1 & !P1_LAST_MARKER == P1_FIRST {
+                        self.buffer.borrow_mut().clear();
+                    }
+                    self.buffer.borrow_mut().extend_from_slice(&command.data);
+                    if command.p1 & P1_LAST_MARKER == 0 {
+                        Vec::new()
+                    } else if command.ins == INS_SIGN {
+                        self.sign_transaction(&self.buffer.borrow())
+                    } else {
+                        let buffer = self.buffer.borrow();
+                        let (key, offset) = self.key_of_path(&buffer);
+                        self.sign(&key, &ledger_message_hash(&buffer[offset + 1..]))
+                    }
+                }
+                _ => {
+                    return Ok(ApduAnswer {
+                        data: Vec::new(),
+                        status: 0x6d00,
+                    })
+                }
+            };
+            let mut bytes = data;
+            bytes.extend_from_slice(&SW_OK.to_be_bytes());
+            ApduAnswer::from_bytes(&bytes)
+        }
+    }
+
+    #[test]
+    fn test_ledger_signer() {
+        let device = LedgerDevice::new(MockDevice::new());
+        assert_eq!(device.app_version().unwrap(), (0, 5, 0));
+        let path = DerivationPath::ckb(0, crate::types::KeyChain::External, 0);
+        assert_eq!(
+            serialize_path(&path)[..5],
+            [5, 0x80, 0, 0, 44][..],
+            "count and the hardened 44"
+        );
+
+        // sign message
+        let pubkey = device.get_public_key(&path, false).unwrap();
+        let message = vec![7u8; 300];
+        let signature = device.sign_message(&path, &message, true).unwrap();
+        let recid = RecoveryId::from_i32(signature[64] as i32).unwrap();
+        let recoverable = RecoverableSignature::from_compact(&signature[0..64], recid).unwrap();
+        let msg = Message::from_slice(&ledger_message_hash(&message)).unwrap();
+        assert_eq!(SECP256K1.recover_ecdsa(&msg, &recoverable).unwrap(), pubkey);
+        assert_eq!(device.transport().commands.borrow().len(), 4);
+
+        // the source transaction of the input
+        let id = blake160(&pubkey.serialize());
+        let lock = Script::new_builder()
+            .code_hash(SIGHASH_TYPE_HASH.pack())
+            .hash_type(ScriptHashType::Type.into())
+            .args(Bytes::from(id.as_bytes().to_vec()).pack())
+            .build();
+        let source = TransactionBuilder::default()
+            .output(
+                CellOutput::new_builder()
+                    .capacity(100u64.pack())
+                    .lock(lock.clone())
+                    .build(),
+            )
+            .output_data(Bytes::new().pack())
+            .build();
+        let mut provider = OffchainTransactionDependencyProvider::default();
+        provider.txs.insert(source.hash().unpack(), source.clone());
+
+        let mut signer = LedgerSigner::new(device, Box::new(provider));
+        assert_eq!(signer.add_path(path).unwrap(), id);
+        assert!(signer.match_id(id.as_bytes()));
+
+        let placeholder = WitnessArgs::new_builder()
+            .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+            .build();
+        let tx = TransactionBuilder::default()
+            .input(CellInput::new(OutPoint::new(source.hash(), 0), 0))
+            .output(CellOutput::new_builder().capacity(90u64.pack()).build())
+            .output_data(Bytes::from(vec![1u8; 500]).pack())
+            .witness(placeholder.as_bytes().pack())
+            .build();
+        let mut group = ScriptGroup::new(&lock, ScriptGroupType::Lock);
+        group.input_indices.push(0);
+        let message = generate_message(&tx, &group, Bytes::from(vec![0u8; 65])).unwrap();
+        let signature = signer.sign(id.as_bytes(), &message, true, &tx).unwrap();
+        assert_eq!(signature.len(), 65);
+        assert!(matches!(
+            signer.sign(id.as_bytes(), &[1u8; 32], true, &tx),
+            Err(SignerError::InvalidMessage(_))
+        ));
+        assert!(matches!(
+            signer.sign(&[0u8; 20], &message, true, &tx),
+            Err(SignerError::IdNotFound)
+        ));
+    }
+}
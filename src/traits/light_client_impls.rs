@@ -3,7 +3,6 @@ use std::collections::HashMap;
 use anyhow::anyhow;
 use dashmap::DashMap;
 
-use ckb_jsonrpc_types as json_types;
 use ckb_types::{
     bytes::Bytes,
     core::{HeaderView, TransactionView},
@@ -13,7 +12,9 @@ use ckb_types::{
 
 use super::{offchain_impls::CollectResult, OffchainCellCollector};
 use crate::rpc::{
-    ckb_light_client::{FetchStatus, Order, SearchKey},
+    ckb_light_client::{FetchStatus, Order, ScriptType, SearchKey},
+    light_client_scripts::sync_progress,
+    pagination::{PageOptions, DEFAULT_PAGE_SIZE},
     LightClientRpcClient,
 };
 use crate::traits::{
@@ -183,6 +184,7 @@ impl TransactionDependencyProvider for LightClientTransactionDependencyProvider
 pub struct LightClientCellCollector {
     light_client: LightClientRpcClient,
     offchain: OffchainCellCollector,
+    acceptable_script_leftbehind: Option<u64>,
 }
 
 impl LightClientCellCollector {
@@ -191,6 +193,50 @@ impl LightClientCellCollector {
         LightClientCellCollector {
             light_client,
             offchain: OffchainCellCollector::default(),
+            acceptable_script_leftbehind: Some(1),
+        }
+    }
+
+    /// The acceptable leftbehind block number of the queried scripts (default = 1),
+    /// `None` means the sync progress is not checked
+    pub fn acceptable_script_leftbehind(&self) -> Option<u64> {
+        self.acceptable_script_leftbehind
+    }
+    /// Set the acceptable leftbehind block number of the queried scripts
+    pub fn set_acceptable_script_leftbehind(&mut self, value: Option<u64>) {
+        self.acceptable_script_leftbehind = value;
+    }
+
+    /// Check if the primary scripts of the queries are registered in the light
+    /// client and synced, so the collected cells are not stale.
+    pub fn check_scripts_synced(
+        &self,
+        queries: &[CellQueryOptions],
+        tip_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        let leftbehind = match self.acceptable_script_leftbehind {
+            Some(value) => value,
+            None => return Ok(()),
+        };
+        let registered = self
+            .light_client
+            .get_scripts()
+            .map_err(|err| CellCollectorError::Internal(err.into()))?;
+        let watched: Vec<_> = queries
+            .iter()
+            .map(|query| {
+                let script_type: ScriptType = query.primary_type.clone().into();
+                (query.primary_script.clone(), script_type)
+            })
+            .collect();
+        let progress = sync_progress(&registered, &watched, tip_number);
+        if progress.is_synced(leftbehind) {
+            Ok(())
+        } else {
+            Err(CellCollectorError::Other(anyhow!(
+                "light client scripts not synced, {}",
+                progress
+            )))
         }
     }
 }
@@ -227,43 +273,53 @@ impl CellCollector for LightClientCellCollector {
                 .map(|c| (c.out_point.clone(), c))
                 .collect();
             let locked_cells = self.offchain.locked_cells.clone();
-            let search_key = SearchKey::from(query.clone());
-            const MAX_LIMIT: u32 = 4096;
-            let mut limit: u32 = query.limit.unwrap_or(16);
-            let mut last_cursor: Option<json_types::JsonBytes> = None;
-            while total_capacity < query.min_total_capacity {
-                let page = self
-                    .light_client
-                    .get_cells(search_key.clone(), order.clone(), limit.into(), last_cursor)
-                    .map_err(|err| CellCollectorError::Internal(err.into()))?;
-                if page.objects.is_empty() {
-                    break;
-                }
-                for cell in page.objects {
-                    let live_cell = LiveCell::from(cell);
-                    if !query.match_cell(&live_cell, max_mature_number)
-                        || locked_cells.contains_key(&(
-                            live_cell.out_point.tx_hash().unpack(),
-                            live_cell.out_point.index().unpack(),
-                        ))
-                    {
-                        continue;
-                    }
-                    let capacity: u64 = live_cell.output.capacity().unpack();
-                    if ret_cells
-                        .insert(live_cell.out_point.clone(), live_cell)
-                        .is_none()
-                    {
-                        total_capacity += capacity;
-                    }
-                    if total_capacity >= query.min_total_capacity {
-                        break;
+            let options = PageOptions {
+                order,
+                page_size: query.limit.unwrap_or(DEFAULT_PAGE_SIZE),
+                ..Default::default()
+            };
+            let sub_queries = query.split_primary_scripts();
+            self.check_scripts_synced(&sub_queries, tip_num)?;
+            'queries: for sub_query in sub_queries {
+                let mut search_key = Some(SearchKey::from(sub_query));
+                while let Some(key) = search_key.take() {
+                    // Retry without the output data filter if the node rejects it as
+                    // invalid params, the data is still checked by `match_cell`
+                    let fallback = key.without_output_data();
+                    let mut received = false;
+                    for cell in self.light_client.cells_iter(key, &options) {
+                        let cell = match cell {
+                            Ok(cell) => cell,
+                            Err(err)
+                                if !received && fallback.is_some() && err.is_invalid_params() =>
+                            {
+                                search_key = fallback;
+                                break;
+                            }
+                            Err(err) => return Err(CellCollectorError::Internal(err.into())),
+                        };
+                        received = true;
+                        let live_cell = LiveCell::from(cell);
+                        if !query.match_cell(&live_cell, max_mature_number)
+                            || locked_cells.contains_key(&(
+                                live_cell.out_point.tx_hash().unpack(),
+                                live_cell.out_point.index().unpack(),
+                            ))
+                        {
+                            continue;
+                        }
+                        let capacity: u64 = live_cell.output.capacity().unpack();
+                        if ret_cells
+                            .insert(live_cell.out_point.clone(), live_cell)
+                            .is_none()
+                        {
+                            total_capacity += capacity;
+                        }
+                        if total_capacity >= query.min_total_capacity {
+                            break 'queries;
+                        }
                     }
                 }
-                last_cursor = Some(page.last_cursor);
-                if limit < MAX_LIMIT {
-                    limit *= 2;
-                }
             }
             cells = ret_cells.into_values().collect();
         }
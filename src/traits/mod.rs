@@ -3,21 +3,31 @@
 
 pub mod default_impls;
 pub mod dummy_impls;
+pub mod header_verifier;
+pub mod ledger_signer;
 pub mod light_client_impls;
 pub mod offchain_impls;
+pub mod policy_signer;
+pub mod remote_signer;
+pub mod reservation;
 
 pub use default_impls::{
     DefaultCellCollector, DefaultCellDepResolver, DefaultHeaderDepResolver,
-    DefaultTransactionDependencyProvider, SecpCkbRawKeySigner,
+    DefaultTransactionDependencyProvider, ReorgCallback, SecpCkbRawKeySigner, SecpHdKeySigner,
 };
+pub use header_verifier::{HeaderChainVerifier, HeaderVerifyError, VerifiedHeaderDepResolver};
+pub use ledger_signer::{ApduTransport, LedgerDevice, LedgerError, LedgerSigner};
 pub use light_client_impls::{
     LightClientCellCollector, LightClientHeaderDepResolver,
     LightClientTransactionDependencyProvider,
 };
 pub use offchain_impls::{
     OffchainCellCollector, OffchainCellDepResolver, OffchainHeaderDepResolver,
-    OffchainTransactionDependencyProvider,
+    OffchainTransactionDependencyProvider, ReorgEvent, TipHashes,
 };
+pub use policy_signer::{check_signing_message, PolicySigner, SignPolicyRules};
+pub use remote_signer::{RemoteSigner, RemoteSignerServer};
+pub use reservation::{CellReservation, FileReservation, MemoryReservation, ReservationError};
 
 use thiserror::Error;
 
@@ -206,6 +216,34 @@ impl ValueRangeOption {
     }
 }
 
+/// The output data filter.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum DataFilter {
+    /// The data starts with the bytes
+    Prefix(Bytes),
+    /// The data equals to the bytes
+    Exact(Bytes),
+    /// The data contains the bytes
+    Partial(Bytes),
+    /// `blake2b_256(data)` equals to the hash
+    Hash(Byte32),
+}
+impl DataFilter {
+    pub fn match_data(&self, data: &[u8]) -> bool {
+        match self {
+            DataFilter::Prefix(prefix) => data.starts_with(prefix),
+            DataFilter::Exact(exact) => data == exact.as_ref(),
+            DataFilter::Partial(part) => {
+                part.is_empty()
+                    || data
+                        .windows(part.len())
+                        .any(|window| window == part.as_ref())
+            }
+            DataFilter::Hash(hash) => &blake2b_256(data)[..] == hash.as_slice(),
+        }
+    }
+}
+
 /// The primary serach script type
 ///   * if primary script type is `lock` then secondary script type is `type`
 ///   * if primary script type is `type` then secondary script type is `lock`
@@ -237,9 +275,16 @@ pub struct CellQueryOptions {
     pub secondary_script: Option<Script>,
     pub secondary_script_len_range: Option<ValueRangeOption>,
     pub data_len_range: Option<ValueRangeOption>,
+    /// Pushed down to the indexer except the data hash filter, the data
+    /// is always queried when set
+    pub data_filter: Option<DataFilter>,
     pub capacity_range: Option<ValueRangeOption>,
     pub block_range: Option<ValueRangeOption>,
 
+    /// The cells matching any of the extra primary scripts are also matched,
+    /// the collectors query the primary scripts one by one
+    pub extra_primary_scripts: Vec<Script>,
+
     pub order: QueryOrder,
     pub limit: Option<u32>,
     /// Filter cell by its maturity
@@ -258,8 +303,10 @@ impl CellQueryOptions {
             secondary_script: None,
             secondary_script_len_range: None,
             data_len_range: None,
+            data_filter: None,
             capacity_range: None,
             block_range: None,
+            extra_primary_scripts: Vec::new(),
             with_data: None,
             order: QueryOrder::Asc,
             limit: None,
@@ -274,6 +321,21 @@ impl CellQueryOptions {
     pub fn new_type(primary_script: Script) -> CellQueryOptions {
         CellQueryOptions::new(primary_script, PrimaryScriptType::Type)
     }
+    /// Split the query by the primary scripts, every query has only one
+    /// primary script.
+    pub fn split_primary_scripts(&self) -> Vec<CellQueryOptions> {
+        let mut queries = Vec::with_capacity(1 + self.extra_primary_scripts.len());
+        for script in std::iter::once(&self.primary_script).chain(&self.extra_primary_scripts) {
+            let mut query = self.clone();
+            query.primary_script = script.clone();
+            query.extra_primary_scripts = Vec::new();
+            queries.push(query);
+        }
+        queries
+    }
+    fn match_primary_script(&self, script: &Script) -> bool {
+        script == &self.primary_script || self.extra_primary_scripts.contains(script)
+    }
     pub fn match_cell(&self, cell: &LiveCell, max_mature_number: u64) -> bool {
         fn extract_raw_data(script: &Script) -> Vec<u8> {
             [
@@ -293,7 +355,7 @@ impl CellQueryOptions {
         match self.primary_type {
             PrimaryScriptType::Lock => {
                 // check primary script
-                if cell.output.lock() != self.primary_script {
+                if !self.match_primary_script(&cell.output.lock()) {
                     return false;
                 }
 
@@ -318,8 +380,9 @@ impl CellQueryOptions {
             }
             PrimaryScriptType::Type => {
                 // check primary script
-                if cell.output.type_().to_opt().as_ref() != Some(&self.primary_script) {
-                    return false;
+                match cell.output.type_().to_opt() {
+                    Some(script) if self.match_primary_script(&script) => {}
+                    _ => return false,
                 }
 
                 // if primary is `type`, secondary is `lock`
@@ -357,6 +420,11 @@ impl CellQueryOptions {
                 return false;
             }
         }
+        if let Some(filter) = self.data_filter.as_ref() {
+            if !filter.match_data(&cell.output_data) {
+                return false;
+            }
+        }
         if let Some(range) = self.capacity_range {
             let capacity: u64 = cell.output.capacity().unpack();
             if !range.match_value(capacity) {
@@ -463,3 +531,67 @@ mod anyhow_tests {
         assert_eq!("Other", error.to_string());
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rpc::ckb_indexer::{OutputDataFilterMode, SearchKey};
+
+    fn lock(byte: u8) -> Script {
+        Script::new_builder()
+            .args(Bytes::from(vec![byte; 20]).pack())
+            .build()
+    }
+
+    fn live_cell(lock: Script, data: &[u8]) -> LiveCell {
+        LiveCell {
+            output: CellOutput::new_builder().lock(lock).build(),
+            output_data: Bytes::from(data.to_vec()),
+            out_point: OutPoint::default(),
+            block_number: 1,
+            tx_index: 1,
+        }
+    }
+
+    #[test]
+    fn test_cell_query_data_filter() {
+        let mut query = CellQueryOptions::new_lock(lock(1));
+        query.extra_primary_scripts = vec![lock(2)];
+        query.data_filter = Some(DataFilter::Prefix(Bytes::from_static(b"NFT")));
+        assert!(query.match_cell(&live_cell(lock(1), b"NFT-1"), 0));
+        assert!(query.match_cell(&live_cell(lock(2), b"NFT-2"), 0));
+        assert!(!query.match_cell(&live_cell(lock(3), b"NFT-3"), 0));
+        assert!(!query.match_cell(&live_cell(lock(1), b"FT"), 0));
+
+        query.data_filter = Some(DataFilter::Partial(Bytes::from_static(b"-2")));
+        assert!(query.match_cell(&live_cell(lock(2), b"NFT-2"), 0));
+        assert!(!query.match_cell(&live_cell(lock(2), b"NFT-1"), 0));
+        query.data_filter = Some(DataFilter::Hash(blake2b_256(b"NFT-1").pack()));
+        assert!(query.match_cell(&live_cell(lock(1), b"NFT-1"), 0));
+        assert!(!query.match_cell(&live_cell(lock(1), b"NFT-2"), 0));
+
+        // the hash filter is not pushed down, the others are
+        let queries = query.split_primary_scripts();
+        assert_eq!(queries.len(), 2);
+        assert_eq!(queries[1].primary_script, lock(2));
+        let search_key = SearchKey::from(queries[0].clone());
+        assert!(search_key.filter.is_none());
+        assert_eq!(search_key.with_data, Some(true));
+        assert!(search_key.without_output_data().is_none());
+        query.data_filter = Some(DataFilter::Exact(Bytes::from_static(b"NFT-1")));
+        let search_key = SearchKey::from(query);
+        let filter = search_key.filter.clone().unwrap();
+        assert_eq!(filter.output_data.unwrap().as_bytes(), b"NFT-1");
+        assert_eq!(
+            filter.output_data_filter_mode,
+            Some(OutputDataFilterMode::Exact)
+        );
+
+        // the fallback for the nodes not supporting the data filter
+        let fallback = search_key.without_output_data().unwrap();
+        let filter = fallback.filter.unwrap();
+        assert!(filter.output_data.is_none());
+        assert!(filter.output_data_filter_mode.is_none());
+        assert_eq!(fallback.with_data, Some(true));
+    }
+}
@@ -1,6 +1,6 @@
 //! For for implement offchain operations or for testing purpose
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use ckb_types::{
     bytes::Bytes,
@@ -48,14 +48,88 @@ impl HeaderDepResolver for OffchainHeaderDepResolver {
 }
 
 const KEEP_BLOCK_PERIOD: u64 = 13;
+
+/// The tip block hashes when the offchain data are recorded, used to detect
+/// chain reorganizations by comparing with the canonical chain.
+#[derive(Default, Clone, Debug)]
+pub struct TipHashes {
+    hashes: BTreeMap<u64, H256>,
+}
+
+impl TipHashes {
+    /// Record the tip block hash, the hash recorded before is kept.
+    pub fn insert(&mut self, number: u64, hash: H256) {
+        self.hashes.entry(number).or_insert(hash);
+    }
+
+    pub fn get(&self, number: u64) -> Option<&H256> {
+        self.hashes.get(&number)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    /// Find the lowest recorded block number whose hash is not in the
+    /// canonical chain, `canonical_hash` returns the canonical hash of a block
+    /// number (`None` if the chain is shorter).
+    ///
+    /// Only the highest recorded hash is checked if it is still canonical.
+    /// The block numbers above the tip of a lagging node are unknown, they are
+    /// skipped instead of being counted as forked.
+    pub fn find_fork<F, E>(&self, mut canonical_hash: F) -> Result<Option<u64>, E>
+    where
+        F: FnMut(u64) -> Result<Option<H256>, E>,
+    {
+        let mut fork_number = None;
+        for (number, hash) in self.hashes.iter().rev() {
+            match canonical_hash(*number)? {
+                None => continue,
+                Some(canonical) if &canonical == hash => break,
+                Some(_) => fork_number = Some(*number),
+            }
+        }
+        Ok(fork_number)
+    }
+
+    /// Remove the hashes at and after the block number.
+    pub fn split_off(&mut self, number: u64) -> Vec<(u64, H256)> {
+        self.hashes.split_off(&number).into_iter().collect()
+    }
+
+    fn truncate(&mut self, current_tip_block_number: u64) {
+        self.hashes.retain(|block_num, _| {
+            *block_num >= current_tip_block_number
+                || (current_tip_block_number - block_num) <= KEEP_BLOCK_PERIOD
+        });
+    }
+}
+
+/// The offchain data rolled back by a chain reorganization.
+#[derive(Default, Clone, Debug)]
+pub struct ReorgEvent {
+    /// The lowest recorded block number not in the canonical chain, the data
+    /// recorded at and after it are rolled back
+    pub fork_number: u64,
+    /// `(block_number, block_hash)` of the blocks reorged away
+    pub detached_blocks: Vec<(u64, H256)>,
+    /// The cells created by the transactions applied
+    pub removed_cells: Vec<OutPoint>,
+    /// The cells locked as dead, they are collectable again
+    pub unlocked_cells: Vec<OutPoint>,
+    /// The transactions applied
+    pub removed_txs: Vec<H256>,
+}
+
 /// A cell collector only use offchain data
 #[derive(Default, Clone)]
 pub struct OffchainCellCollector {
-    // (block_hash, index) => tip_block_number
+    // (tx_hash, index) => tip_block_number
     pub locked_cells: HashMap<(H256, u32), u64>,
     // (live_cell, tip_block_number)
     pub live_cells: Vec<(LiveCell, u64)>,
     pub max_mature_number: u64,
+    pub tip_hashes: TipHashes,
 }
 
 pub(crate) struct CollectResult {
@@ -83,6 +157,44 @@ impl OffchainCellCollector {
                     || (current_tip_block_number - block_num) <= KEEP_BLOCK_PERIOD
             })
             .collect();
+        self.tip_hashes.truncate(current_tip_block_number);
+    }
+
+    /// Roll back the cells locked and applied at and after the fork block.
+    pub fn rollback(&mut self, fork_number: u64) -> ReorgEvent {
+        let detached_blocks = self.tip_hashes.split_off(fork_number);
+        let (removed, live_cells): (Vec<_>, Vec<_>) = self
+            .live_cells
+            .drain(..)
+            .partition(|(_cell, block_num)| *block_num >= fork_number);
+        self.live_cells = live_cells;
+        let mut unlocked_cells = Vec::new();
+        self.locked_cells.retain(|(tx_hash, index), block_num| {
+            if *block_num >= fork_number {
+                unlocked_cells.push(OutPoint::new(tx_hash.pack(), *index));
+                false
+            } else {
+                true
+            }
+        });
+        let removed_cells: Vec<OutPoint> = removed
+            .into_iter()
+            .map(|(cell, _)| cell.out_point)
+            .collect();
+        let mut removed_txs: Vec<H256> = Vec::new();
+        for out_point in &removed_cells {
+            let tx_hash: H256 = out_point.tx_hash().unpack();
+            if !removed_txs.contains(&tx_hash) {
+                removed_txs.push(tx_hash);
+            }
+        }
+        ReorgEvent {
+            fork_number,
+            detached_blocks,
+            removed_cells,
+            unlocked_cells,
+            removed_txs,
+        }
     }
 
     pub(crate) fn collect(
@@ -152,6 +264,7 @@ impl OffchainCellCollector {
     pub(crate) fn reset(&mut self) {
         self.locked_cells.clear();
         self.live_cells.clear();
+        self.tip_hashes = TipHashes::default();
     }
 }
 
@@ -161,6 +274,7 @@ pub struct OffchainTransactionDependencyProvider {
     pub tx_tip_num_map: HashMap<H256, u64>,
     pub txs: HashMap<H256, TransactionView>,
     pub cells: HashMap<(H256, u32), (CellOutput, Bytes)>,
+    pub tip_hashes: TipHashes,
 }
 
 impl OffchainTransactionDependencyProvider {
@@ -170,6 +284,7 @@ impl OffchainTransactionDependencyProvider {
             tx_tip_num_map: HashMap::new(),
             txs: HashMap::new(),
             cells: HashMap::new(),
+            tip_hashes: TipHashes::default(),
         }
     }
     /// Add newly create transaction, so it can get transaction offchain
@@ -199,7 +314,7 @@ impl OffchainTransactionDependencyProvider {
             .into_iter()
             .partition(|(_k, v)| {
                 *v >= current_tip_block_number
-                    || (v - current_tip_block_number) >= KEEP_BLOCK_PERIOD
+                    || (current_tip_block_number - v) <= KEEP_BLOCK_PERIOD
             });
         self.tx_tip_num_map = keep;
         self.txs = self
@@ -214,7 +329,30 @@ impl OffchainTransactionDependencyProvider {
             .into_iter()
             .filter(|(k, _v)| !removed.contains_key(&k.0))
             .collect();
-        // self.headers =self.headers.clone().into_iter().filter(|(k,_v)|!removed.contains(&k)).collect();
+        self.tip_hashes.truncate(current_tip_block_number);
+    }
+
+    /// Roll back the transactions applied at and after the fork block.
+    pub fn rollback(&mut self, fork_number: u64) -> ReorgEvent {
+        let detached_blocks = self.tip_hashes.split_off(fork_number);
+        let removed_txs: Vec<H256> = self
+            .tx_tip_num_map
+            .iter()
+            .filter(|(_k, v)| **v >= fork_number)
+            .map(|(k, _v)| k.clone())
+            .collect();
+        for tx_hash in &removed_txs {
+            self.tx_tip_num_map.remove(tx_hash);
+            self.txs.remove(tx_hash);
+        }
+        self.cells
+            .retain(|(tx_hash, _index), _| !removed_txs.contains(tx_hash));
+        ReorgEvent {
+            fork_number,
+            detached_blocks,
+            removed_txs,
+            ..Default::default()
+        }
     }
 }
 
@@ -255,3 +393,139 @@ impl TransactionDependencyProvider for OffchainTransactionDependencyProvider {
         )))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ckb_types::{core::TransactionBuilder, h256};
+
+    #[test]
+    fn test_truncate_keep_recent_blocks() {
+        let tx = |byte: u8| {
+            TransactionBuilder::default()
+                .input(ckb_types::packed::CellInput::new(
+                    OutPoint::new(H256([byte; 32]).pack(), 0),
+                    0,
+                ))
+                .output(CellOutput::default())
+                .output_data(Bytes::new().pack())
+                .build()
+                .data()
+        };
+        let mut provider = OffchainTransactionDependencyProvider::new();
+        provider.apply_tx(tx(1), 10).unwrap();
+        provider.apply_tx(tx(2), 20).unwrap();
+        provider.tip_hashes.insert(10, h256!("0x10"));
+        provider.tip_hashes.insert(20, h256!("0x20"));
+
+        // the data recorded within `KEEP_BLOCK_PERIOD` blocks are kept
+        provider.truncate(10 + KEEP_BLOCK_PERIOD);
+        assert_eq!(provider.txs.len(), 2);
+        assert_eq!(provider.cells.len(), 2);
+        assert!(provider.tip_hashes.get(10).is_some());
+
+        // the older data are removed, the newer data are kept
+        provider.truncate(10 + KEEP_BLOCK_PERIOD + 1);
+        assert_eq!(provider.txs.len(), 1);
+        assert_eq!(provider.cells.len(), 1);
+        assert_eq!(
+            provider.tx_tip_num_map.values().collect::<Vec<_>>(),
+            vec![&20]
+        );
+        assert!(provider.tip_hashes.get(10).is_none());
+        assert!(provider.tip_hashes.get(20).is_some());
+
+        // the data recorded after the tip are kept
+        provider.truncate(15);
+        assert_eq!(provider.txs.len(), 1);
+    }
+
+    #[test]
+    fn test_rollback_reorg() {
+        let mut collector = OffchainCellCollector::default();
+        let mut provider = OffchainTransactionDependencyProvider::new();
+        let chain = vec![h256!("0x10"), h256!("0x11"), h256!("0x12")];
+        let tx = |byte: u8| {
+            TransactionBuilder::default()
+                .input(ckb_types::packed::CellInput::new(
+                    OutPoint::new(H256([byte; 32]).pack(), 0),
+                    0,
+                ))
+                .output(CellOutput::default())
+                .output_data(Bytes::new().pack())
+                .build()
+                .data()
+        };
+        for (number, byte) in [(10u64, 1u8), (11, 2), (12, 3)] {
+            let hash = chain[number as usize - 10].clone();
+            collector.tip_hashes.insert(number, hash.clone());
+            collector.apply_tx(tx(byte), number).unwrap();
+            provider.tip_hashes.insert(number, hash);
+            provider.apply_tx(tx(byte), number).unwrap();
+        }
+
+        // no reorg, only the highest hash is checked
+        let mut checked = Vec::new();
+        let canonical = |number: u64| {
+            checked.push(number);
+            Ok::<_, ()>(chain.get(number as usize - 10).cloned())
+        };
+        assert_eq!(collector.tip_hashes.find_fork(canonical), Ok(None));
+        assert_eq!(checked, vec![12]);
+
+        // block 11 and 12 are reorged away
+        let canonical = |number: u64| {
+            Ok::<_, ()>(match number {
+                10 => Some(h256!("0x10")),
+                11 => Some(h256!("0x21")),
+                _ => None,
+            })
+        };
+        assert_eq!(collector.tip_hashes.find_fork(canonical), Ok(Some(11)));
+        let event = collector.rollback(11);
+        assert_eq!(event.detached_blocks.len(), 2);
+        assert_eq!(event.removed_cells.len(), 2);
+        assert_eq!(event.unlocked_cells.len(), 2);
+        assert_eq!(event.removed_txs.len(), 2);
+        assert_eq!(collector.live_cells.len(), 1);
+        assert_eq!(collector.locked_cells.len(), 1);
+        assert_eq!(provider.tip_hashes.find_fork(canonical), Ok(Some(11)));
+        let event = provider.rollback(11);
+        assert_eq!(event.removed_txs.len(), 2);
+        assert_eq!(provider.txs.len(), 1);
+        assert_eq!(provider.cells.len(), 1);
+    }
+
+    #[test]
+    fn test_find_fork_lagging_node() {
+        let mut tip_hashes = TipHashes::default();
+        tip_hashes.insert(10, h256!("0x10"));
+        tip_hashes.insert(11, h256!("0x11"));
+        tip_hashes.insert(12, h256!("0x12"));
+
+        // the node only synced to block 10, the blocks above are unknown
+        let mut checked = Vec::new();
+        let canonical = |number: u64| {
+            checked.push(number);
+            Ok::<_, ()>(match number {
+                10 => Some(h256!("0x10")),
+                _ => None,
+            })
+        };
+        assert_eq!(tip_hashes.find_fork(canonical), Ok(None));
+        assert_eq!(checked, vec![12, 11, 10]);
+
+        // the node knows nothing about the recorded blocks
+        assert_eq!(tip_hashes.find_fork(|_| Ok::<_, ()>(None)), Ok(None));
+
+        // a lagging node on a fork
+        let canonical = |number: u64| {
+            Ok::<_, ()>(match number {
+                10 => Some(h256!("0x10")),
+                11 => Some(h256!("0x21")),
+                _ => None,
+            })
+        };
+        assert_eq!(tip_hashes.find_fork(canonical), Ok(Some(11)));
+    }
+}
@@ -0,0 +1,652 @@
+//! A signer decorator checks what is being signed against policy rules.
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, SystemTime};
+
+use ckb_hash::blake2b_256;
+use ckb_types::{
+    bytes::Bytes,
+    core::TransactionView,
+    packed::{self, Script, WitnessArgs},
+    prelude::*,
+    H256,
+};
+use parking_lot::Mutex;
+
+use super::{Signer, SignerError, TransactionDependencyProvider};
+use crate::{
+    constants::DAO_TYPE_HASH,
+    tx_builder::{gen_script_groups, ScriptGroups},
+    types::ScriptGroup,
+    unlock::{
+        cobuild::{find_cobuild_message, generate_cobuild_message, resolve_input_cells},
+        generate_message,
+    },
+    util::convert_keccak256_hash,
+    Address, AddressPayload, NetworkType, ScriptId,
+};
+
+const ROLLING_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The nervos DAO operation of an output.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum DaoOperation {
+    Deposit,
+    /// The phase 1 of withdrawing
+    Prepare,
+}
+
+/// A decoded output.
+#[derive(Clone, Debug)]
+pub struct DecodedOutput {
+    pub address: Address,
+    pub lock_hash: H256,
+    pub capacity: u64,
+    /// The sUDT type script hash and the amount
+    pub udt: Option<(H256, u128)>,
+    pub dao: Option<DaoOperation>,
+    /// The output is locked by an own lock
+    pub is_own: bool,
+}
+
+/// The decoded transaction being signed.
+#[derive(Clone, Debug)]
+pub struct TransactionSummary {
+    pub outputs: Vec<DecodedOutput>,
+    /// The capacity of the inputs locked by own locks, available if the input
+    /// cells can be resolved
+    pub own_inputs_capacity: Option<u64>,
+    /// Available if the input cells can be resolved
+    pub fee: Option<u64>,
+    /// Inputs include nervos DAO cells (phase 2 of withdrawing)
+    pub dao_withdraw: bool,
+}
+
+impl TransactionSummary {
+    /// The capacity leaving own locks including the fee, that is the own
+    /// inputs minus the outputs back to own locks. `None` if the input cells
+    /// can not be resolved.
+    pub fn spent_capacity(&self) -> Option<u64> {
+        let own_inputs = self.own_inputs_capacity?;
+        let own_outputs = self.outputs_capacity(true);
+        let spent = own_inputs.saturating_sub(own_outputs);
+        if self.dao_withdraw {
+            // The DAO compensation is not in the inputs capacity
+            Some(spent.max(self.outputs_capacity(false)))
+        } else {
+            Some(spent)
+        }
+    }
+
+    fn outputs_capacity(&self, is_own: bool) -> u64 {
+        self.outputs
+            .iter()
+            .filter(|output| output.is_own == is_own)
+            .fold(0u64, |total, output| total.saturating_add(output.capacity))
+    }
+
+    /// The sUDT amounts sent to other locks, by type script hash
+    pub fn spent_udt(&self) -> HashMap<H256, u128> {
+        let mut amounts: HashMap<H256, u128> = HashMap::default();
+        for (type_hash, amount) in self
+            .outputs
+            .iter()
+            .filter(|output| !output.is_own)
+            .filter_map(|output| output.udt.clone())
+        {
+            let total = amounts.entry(type_hash).or_default();
+            *total = total.saturating_add(amount);
+        }
+        amounts
+    }
+}
+
+/// The policy rules, `None` means no limit.
+#[derive(Clone, Debug, Default)]
+pub struct SignPolicyRules {
+    /// The lock script hashes owned by the wallet (including change), outputs
+    /// to them are not spending.
+    pub own_locks: HashSet<H256>,
+    /// The lock script hashes allowed to send to
+    pub allowed_locks: Option<HashSet<H256>>,
+    /// The sUDT script used to decode the sUDT amount
+    pub sudt_script_id: Option<ScriptId>,
+    pub max_capacity_per_tx: Option<u64>,
+    /// The limit of spent capacity in a rolling 24 hours window
+    pub max_capacity_per_day: Option<u64>,
+    /// The sUDT limits per transaction, by type script hash
+    pub max_udt_per_tx: HashMap<H256, u128>,
+    /// Shannons per KB
+    pub max_fee_rate: Option<u64>,
+    pub allow_dao: bool,
+}
+
+impl SignPolicyRules {
+    pub fn add_own_lock(&mut self, lock: &Script) {
+        self.own_locks.insert(lock.calc_script_hash().unpack());
+    }
+
+    pub fn add_allowed_lock(&mut self, lock: &Script) {
+        self.allowed_locks
+            .get_or_insert_with(HashSet::default)
+            .insert(lock.calc_script_hash().unpack());
+    }
+}
+
+/// A signer decorator only signs the transactions satisfy the policy rules,
+/// otherwise returns `SignerError::InvalidTransaction` explaining the violation.
+///
+/// The spent capacity and the fee rate are calculated from the input cells,
+/// the capacity and fee rate limits reject all transactions if no transaction
+/// dependency provider is set. The message signed must be the signing message
+/// of the transaction (see [`check_signing_message`]), which needs the input
+/// cells too, so nothing is signed without the provider.
+pub struct PolicySigner {
+    signer: Box<dyn Signer>,
+    network: NetworkType,
+    rules: SignPolicyRules,
+    tx_dep_provider: Option<Box<dyn TransactionDependencyProvider>>,
+    // The spent capacity of signed transactions, by transaction hash
+    spent_records: Mutex<HashMap<H256, (SystemTime, u64)>>,
+}
+
+impl PolicySigner {
+    pub fn new(
+        signer: Box<dyn Signer>,
+        network: NetworkType,
+        rules: SignPolicyRules,
+    ) -> PolicySigner {
+        PolicySigner {
+            signer,
+            network,
+            rules,
+            tx_dep_provider: None,
+            spent_records: Mutex::new(HashMap::default()),
+        }
+    }
+
+    pub fn set_tx_dep_provider(&mut self, tx_dep_provider: Box<dyn TransactionDependencyProvider>) {
+        self.tx_dep_provider = Some(tx_dep_provider);
+    }
+
+    pub fn rules(&self) -> &SignPolicyRules {
+        &self.rules
+    }
+
+    /// Decode the outputs and the fee of the transaction.
+    pub fn decode(&self, tx: &TransactionView) -> Result<TransactionSummary, SignerError> {
+        let mut outputs = Vec::new();
+        for (output, data) in tx.outputs_with_data_iter() {
+            let lock = output.lock();
+            let lock_hash: H256 = lock.calc_script_hash().unpack();
+            let mut udt = None;
+            let mut dao = None;
+            if let Some(type_script) = output.type_().to_opt() {
+                let script_id = ScriptId::from(&type_script);
+                if Some(&script_id) == self.rules.sudt_script_id.as_ref() {
+                    if data.len() < 16 {
+                        return Err(SignerError::InvalidTransaction(format!(
+                            "invalid sUDT output data length: {}",
+                            data.len()
+                        )));
+                    }
+                    let mut amount_bytes = [0u8; 16];
+                    amount_bytes.copy_from_slice(&data[0..16]);
+                    udt = Some((
+                        type_script.calc_script_hash().unpack(),
+                        u128::from_le_bytes(amount_bytes),
+                    ));
+                } else if script_id == ScriptId::new_type(DAO_TYPE_HASH.clone()) {
+                    dao = if data.len() == 8 && data.iter().all(|byte| *byte == 0) {
+                        Some(DaoOperation::Deposit)
+                    } else {
+                        Some(DaoOperation::Prepare)
+                    };
+                }
+            }
+            outputs.push(DecodedOutput {
+                address: Address::new(self.network, AddressPayload::from(lock), true),
+                is_own: self.rules.own_locks.contains(&lock_hash),
+                lock_hash,
+                capacity: output.capacity().unpack(),
+                udt,
+                dao,
+            });
+        }
+
+        let mut fee = None;
+        let mut own_inputs_capacity = None;
+        let mut dao_withdraw = false;
+        if let Some(tx_dep_provider) = self.tx_dep_provider.as_ref() {
+            let mut input_total: u64 = 0;
+            let mut own_input_total: u64 = 0;
+            for input in tx.inputs() {
+                let cell = tx_dep_provider
+                    .get_cell(&input.previous_output())
+                    .map_err(|err| SignerError::InvalidTransaction(err.to_string()))?;
+                if let Some(type_script) = cell.type_().to_opt() {
+                    if ScriptId::from(&type_script) == ScriptId::new_type(DAO_TYPE_HASH.clone())
+                        && !outputs.iter().any(|output| output.dao.is_some())
+                    {
+                        dao_withdraw = true;
+                    }
+                }
+                let capacity: u64 = cell.capacity().unpack();
+                input_total = input_total.saturating_add(capacity);
+                let lock_hash: H256 = cell.lock().calc_script_hash().unpack();
+                if self.rules.own_locks.contains(&lock_hash) {
+                    own_input_total = own_input_total.saturating_add(capacity);
+                }
+            }
+            own_inputs_capacity = Some(own_input_total);
+            let output_total = outputs
+                .iter()
+                .fold(0u64, |total, output| total.saturating_add(output.capacity));
+            // The withdraw capacity includes the DAO compensation, skip it
+            if !dao_withdraw {
+                fee = Some(input_total.checked_sub(output_total).ok_or_else(|| {
+                    SignerError::InvalidTransaction(format!(
+                        "outputs capacity {} exceeds inputs capacity {}",
+                        output_total, input_total
+                    ))
+                })?);
+            }
+        }
+        Ok(TransactionSummary {
+            outputs,
+            own_inputs_capacity,
+            fee,
+            dao_withdraw,
+        })
+    }
+
+    /// Check the transaction against the rules, return the spent capacity.
+    pub fn check(&self, tx: &TransactionView) -> Result<u64, SignerError> {
+        let mut records = self.spent_records.lock();
+        self.check_with_records(tx, &mut records)
+    }
+
+    fn check_with_records(
+        &self,
+        tx: &TransactionView,
+        records: &mut HashMap<H256, (SystemTime, u64)>,
+    ) -> Result<u64, SignerError> {
+        let summary = self.decode(tx)?;
+        let rules = &self.rules;
+        for output in &summary.outputs {
+            if output.is_own {
+                continue;
+            }
+            if let Some(allowed_locks) = rules.allowed_locks.as_ref() {
+                if !allowed_locks.contains(&output.lock_hash) {
+                    return Err(violation(format!(
+                        "output to address not in allowlist: {}",
+                        output.address
+                    )));
+                }
+            }
+        }
+        if !rules.allow_dao
+            && (summary.dao_withdraw || summary.outputs.iter().any(|output| output.dao.is_some()))
+        {
+            return Err(violation("nervos DAO operation is not allowed".to_string()));
+        }
+
+        let spent = match summary.spent_capacity() {
+            Some(spent) => spent,
+            None if rules.max_capacity_per_tx.is_some() || rules.max_capacity_per_day.is_some() => {
+                return Err(violation(
+                    "can not resolve the inputs to check spent capacity".to_string(),
+                ));
+            }
+            None => 0,
+        };
+        if let Some(max_capacity) = rules.max_capacity_per_tx {
+            if spent > max_capacity {
+                return Err(violation(format!(
+                    "spent capacity {} exceeds the per transaction limit {}",
+                    spent, max_capacity
+                )));
+            }
+        }
+        for (type_hash, amount) in summary.spent_udt() {
+            if let Some(max_amount) = rules.max_udt_per_tx.get(&type_hash) {
+                if amount > *max_amount {
+                    return Err(violation(format!(
+                        "spent sUDT({:#x}) amount {} exceeds the per transaction limit {}",
+                        type_hash, amount, max_amount
+                    )));
+                }
+            }
+        }
+        if let Some(max_capacity) = rules.max_capacity_per_day {
+            let spent_today = spent_in_window(records, &tx.hash().unpack());
+            if spent_today.saturating_add(spent) > max_capacity {
+                return Err(violation(format!(
+                    "spent capacity {} + {} in 24 hours exceeds the daily limit {}",
+                    spent_today, spent, max_capacity
+                )));
+            }
+        }
+        if let Some(max_fee_rate) = rules.max_fee_rate {
+            if !summary.dao_withdraw {
+                let fee = summary.fee.ok_or_else(|| {
+                    violation("can not resolve the inputs to check fee rate".to_string())
+                })?;
+                let size = tx.data().as_reader().serialized_size_in_block() as u64;
+                let fee_rate = fee.saturating_mul(1000) / size;
+                if fee_rate > max_fee_rate {
+                    return Err(violation(format!(
+                        "fee rate {} exceeds the limit {}",
+                        fee_rate, max_fee_rate
+                    )));
+                }
+            }
+        }
+        Ok(spent)
+    }
+}
+
+/// The spent capacity of other signed transactions in the rolling window
+fn spent_in_window(records: &mut HashMap<H256, (SystemTime, u64)>, tx_hash: &H256) -> u64 {
+    let now = SystemTime::now();
+    records.retain(|_, (signed_at, _)| {
+        now.duration_since(*signed_at)
+            .map(|elapsed| elapsed < ROLLING_WINDOW)
+            .unwrap_or(true)
+    });
+    records
+        .iter()
+        .filter(|(hash, _)| *hash != tx_hash)
+        .fold(0u64, |total, (_, (_, spent))| total.saturating_add(*spent))
+}
+
+/// Check `message` is the signing message of a lock group of `tx` unlocked by
+/// the key `id`, so what is signed is the transaction the policy checked.
+///
+/// The message is recomputed for the lock groups carrying `id` in the args
+/// (`args[0..20]` as the sighash and anyone-can-pay locks, `args[1..21]` as
+/// Omni lock) or a multisig config including `id` in the witness lock, in both
+/// the `WitnessArgs` layout and the cobuild layout. Other locks are refused.
+pub fn check_signing_message(
+    tx: &TransactionView,
+    id: &[u8],
+    message: &[u8],
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+) -> Result<(), SignerError> {
+    let ScriptGroups { lock_groups, .. } = gen_script_groups(tx, tx_dep_provider)
+        .map_err(|err| SignerError::InvalidTransaction(err.to_string()))?;
+    let mut cobuild_message = None;
+    for group in lock_groups.values() {
+        let args = group.script.args().raw_data();
+        let is_omni_lock = args.len() >= 21 && &args[1..21] == id;
+        let mut zero_locks = Vec::new();
+        let witness_lock = group_witness_lock(tx, group);
+        if (args.len() >= 20 && &args[0..20] == id) || is_omni_lock {
+            zero_locks.push(Bytes::from(vec![0u8; 65]));
+            if let Some(lock) = witness_lock.as_ref() {
+                zero_locks.push(Bytes::from(vec![0u8; lock.len()]));
+            }
+        }
+        if let Some(zero_lock) = witness_lock
+            .as_ref()
+            .and_then(|lock| multisig_zero_lock(lock, &args, id))
+        {
+            zero_locks.push(zero_lock);
+        }
+        if zero_locks.is_empty() {
+            continue;
+        }
+
+        let mut expected = Vec::new();
+        let witness_idx = group.input_indices[0];
+        let mut witnesses: Vec<packed::Bytes> = tx.witnesses().into_iter().collect();
+        while witnesses.len() <= witness_idx {
+            witnesses.push(Default::default());
+        }
+        let tx_new = tx.as_advanced_builder().set_witnesses(witnesses).build();
+        for zero_lock in zero_locks {
+            if let Ok(group_message) = generate_message(&tx_new, group, zero_lock) {
+                expected.push(group_message);
+            }
+        }
+        if cobuild_message.is_none() {
+            cobuild_message = Some(build_cobuild_message(tx, tx_dep_provider)?);
+        }
+        expected.extend(cobuild_message.clone().flatten());
+        let matched = expected.iter().any(|expected| {
+            expected.as_ref() == message
+                || (is_omni_lock && convert_keccak256_hash(expected).as_bytes() == message)
+        });
+        if matched {
+            return Ok(());
+        }
+    }
+    Err(SignerError::InvalidMessage(
+        "not the signing message of a lock group of the transaction".to_string(),
+    ))
+}
+
+/// The lock field of the first witness of the group in `WitnessArgs` layout.
+fn group_witness_lock(tx: &TransactionView, group: &ScriptGroup) -> Option<Bytes> {
+    let witness = tx.witnesses().get(group.input_indices[0])?.raw_data();
+    WitnessArgs::from_slice(&witness)
+        .ok()?
+        .lock()
+        .to_opt()
+        .map(|lock| lock.raw_data())
+}
+
+/// The zero lock of the secp256k1 multisig witness lock `config | signatures`,
+/// if the config matches the lock args and includes `id`.
+fn multisig_zero_lock(lock: &[u8], args: &[u8], id: &[u8]) -> Option<Bytes> {
+    if lock.len() < 4 || args.len() < 20 {
+        return None;
+    }
+    let config_len = 4 + lock[3] as usize * 20;
+    if lock.len() != config_len + lock[2] as usize * 65 {
+        return None;
+    }
+    let config = &lock[0..config_len];
+    if blake2b_256(config)[0..20] != args[0..20]
+        || !config[4..].chunks(20).any(|address| address == id)
+    {
+        return None;
+    }
+    let mut zero_lock = vec![0u8; lock.len()];
+    zero_lock[0..config_len].copy_from_slice(config);
+    Some(Bytes::from(zero_lock))
+}
+
+/// The cobuild signing message, `None` if the inputs data can not be resolved.
+fn build_cobuild_message(
+    tx: &TransactionView,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+) -> Result<Option<Bytes>, SignerError> {
+    let inputs = match resolve_input_cells(tx, tx_dep_provider) {
+        Ok(inputs) => inputs,
+        Err(_) => return Ok(None),
+    };
+    let message = find_cobuild_message(tx)
+        .map_err(|err| SignerError::InvalidTransaction(err.to_string()))?
+        .map(|(_, message)| message);
+    generate_cobuild_message(message.as_ref(), tx, &inputs)
+        .map(Some)
+        .map_err(|err| SignerError::InvalidTransaction(err.to_string()))
+}
+
+fn violation(reason: String) -> SignerError {
+    SignerError::InvalidTransaction(format!("policy violation: {}", reason))
+}
+
+impl Signer for PolicySigner {
+    fn match_id(&self, id: &[u8]) -> bool {
+        self.signer.match_id(id)
+    }
+
+    fn sign(
+        &self,
+        id: &[u8],
+        message: &[u8],
+        recoverable: bool,
+        tx: &TransactionView,
+    ) -> Result<Bytes, SignerError> {
+        let tx_dep_provider = self.tx_dep_provider.as_ref().ok_or_else(|| {
+            SignerError::InvalidMessage(
+                "can not resolve the inputs to check the signing message".to_string(),
+            )
+        })?;
+        check_signing_message(tx, id, message, tx_dep_provider.as_ref())?;
+        // Check and record under the same lock, so the transactions signed
+        // concurrently can not exceed the daily limit together.
+        let mut records = self.spent_records.lock();
+        let spent = self.check_with_records(tx, &mut records)?;
+        let signature = self.signer.sign(id, message, recoverable, tx)?;
+        // A transaction may be signed multiple times (one for each script group)
+        records
+            .entry(tx.hash().unpack())
+            .or_insert_with(|| (SystemTime::now(), spent));
+        Ok(signature)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        constants::SIGHASH_TYPE_HASH,
+        traits::{OffchainTransactionDependencyProvider, SecpCkbRawKeySigner},
+    };
+    use ckb_types::{
+        core::{ScriptHashType, TransactionBuilder},
+        packed::{CellInput, CellOutput, OutPoint},
+    };
+
+    fn sighash_lock(args: &[u8]) -> Script {
+        Script::new_builder()
+            .code_hash(SIGHASH_TYPE_HASH.pack())
+            .hash_type(ScriptHashType::Type.into())
+            .args(Bytes::copy_from_slice(args).pack())
+            .build()
+    }
+
+    // The message signed by `SecpSighashScriptSigner` for the only input
+    fn signing_message(tx: &TransactionView, lock: &Script) -> Bytes {
+        let mut group = ScriptGroup::from_lock_script(lock);
+        group.input_indices.push(0);
+        let tx = tx.as_advanced_builder().witness(Default::default()).build();
+        generate_message(&tx, &group, Bytes::from(vec![0u8; 65])).unwrap()
+    }
+
+    fn build_tx(input: &OutPoint, outputs: Vec<(Script, u64)>) -> TransactionView {
+        outputs
+            .into_iter()
+            .fold(
+                TransactionBuilder::default().input(CellInput::new(input.clone(), 0)),
+                |builder, (lock, capacity)| {
+                    builder
+                        .output(
+                            CellOutput::new_builder()
+                                .lock(lock)
+                                .capacity(capacity.pack())
+                                .build(),
+                        )
+                        .output_data(Default::default())
+                },
+            )
+            .build()
+    }
+
+    #[test]
+    fn test_policy_signer() {
+        let key = secp256k1::SecretKey::from_slice(&[5u8; 32]).unwrap();
+        let pubkey = secp256k1::PublicKey::from_secret_key(&crate::SECP256K1, &key);
+        let id = crate::util::blake160(&pubkey.serialize());
+        let own_lock = sighash_lock(id.as_bytes());
+        let friend_lock = sighash_lock(&[2u8; 20]);
+        let mut rules = SignPolicyRules {
+            max_capacity_per_tx: Some(1000),
+            max_capacity_per_day: Some(1500),
+            ..Default::default()
+        };
+        rules.add_own_lock(&own_lock);
+        rules.add_allowed_lock(&friend_lock);
+        let new_signer = || {
+            PolicySigner::new(
+                Box::new(SecpCkbRawKeySigner::new_with_secret_keys(vec![key])),
+                NetworkType::Testnet,
+                rules.clone(),
+            )
+        };
+        let input = OutPoint::new(H256([1u8; 32]).pack(), 0);
+        let mut tx_dep_provider = OffchainTransactionDependencyProvider::new();
+        tx_dep_provider.cells.insert(
+            (H256([1u8; 32]), 0),
+            (
+                CellOutput::new_builder()
+                    .lock(own_lock.clone())
+                    .capacity(10000u64.pack())
+                    .build(),
+                Bytes::new(),
+            ),
+        );
+        let mut signer = new_signer();
+        signer.set_tx_dep_provider(Box::new(tx_dep_provider));
+
+        // the fee is spent too
+        let tx = build_tx(
+            &input,
+            vec![(friend_lock.clone(), 800), (own_lock.clone(), 9100)],
+        );
+        let summary = signer.decode(&tx).unwrap();
+        assert_eq!(summary.fee, Some(100));
+        assert_eq!(summary.spent_capacity(), Some(900));
+        let message = signing_message(&tx, &own_lock);
+        signer.sign(id.as_bytes(), &message, true, &tx).unwrap();
+        // sign the same transaction again is not counted twice
+        signer.sign(id.as_bytes(), &message, true, &tx).unwrap();
+
+        // the message of another transaction along with an allowed one
+        let drain_tx = build_tx(&input, vec![(sighash_lock(&[3u8; 20]), 9900)]);
+        let drain_message = signing_message(&drain_tx, &own_lock);
+        let err = signer
+            .sign(id.as_bytes(), &drain_message, true, &tx)
+            .unwrap_err();
+        assert!(matches!(err, SignerError::InvalidMessage(_)));
+        let err = signer
+            .sign(id.as_bytes(), &[1u8; 32], true, &tx)
+            .unwrap_err();
+        assert!(matches!(err, SignerError::InvalidMessage(_)));
+
+        // a small output with a large fee
+        let tx = build_tx(
+            &input,
+            vec![(friend_lock.clone(), 500), (own_lock.clone(), 8400)],
+        );
+        let message = signing_message(&tx, &own_lock);
+        let err = signer.sign(id.as_bytes(), &message, true, &tx).unwrap_err();
+        assert!(err.to_string().contains("per transaction limit"));
+        let tx = build_tx(
+            &input,
+            vec![(friend_lock.clone(), 600), (own_lock.clone(), 9300)],
+        );
+        let message = signing_message(&tx, &own_lock);
+        let err = signer.sign(id.as_bytes(), &message, true, &tx).unwrap_err();
+        assert!(err.to_string().contains("daily limit"));
+        let tx = build_tx(
+            &input,
+            vec![(sighash_lock(&[3u8; 20]), 1), (own_lock.clone(), 9900)],
+        );
+        let message = signing_message(&tx, &own_lock);
+        let err = signer.sign(id.as_bytes(), &message, true, &tx).unwrap_err();
+        assert!(err.to_string().contains("allowlist"));
+
+        // fail closed without the input cells
+        let signer = new_signer();
+        let tx = build_tx(&input, vec![(friend_lock, 1), (own_lock.clone(), 9900)]);
+        assert_eq!(signer.decode(&tx).unwrap().spent_capacity(), None);
+        let message = signing_message(&tx, &own_lock);
+        let err = signer.sign(id.as_bytes(), &message, true, &tx).unwrap_err();
+        assert!(err.to_string().contains("can not resolve the inputs"));
+    }
+}
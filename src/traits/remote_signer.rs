@@ -0,0 +1,493 @@
+//! Delegate signing to another process by JSON-RPC 2.0 over HTTP.
+//!
+//! Methods (positional params):
+//!   * `match_id(id: JsonBytes) -> bool`
+//!   * `sign(id: JsonBytes, message: JsonBytes, recoverable: bool, tx: Transaction) -> JsonBytes`
+//!
+//! The transaction is sent along with the message, so the server is able to
+//! apply its own policy before signing. The server recomputes the message
+//! from the transaction and refuses to sign any other message, see
+//! [`check_signing_message`]. The [`SignerError`] variants are mapped to the
+//! server error codes defined here.
+//!
+//! Every request must carry `Authorization: Bearer <token>` with the token
+//! configured on both sides. The transport is plain HTTP, so the server should
+//! only listen on a trusted network, or sit behind a TLS terminating proxy.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use ckb_jsonrpc_types::{self as json_types, JsonBytes};
+use ckb_types::{bytes::Bytes, core::TransactionView, packed, prelude::*};
+use jsonrpc_core::{Error, ErrorCode, Id, MethodCall, Output, Params, Value, Version};
+
+use super::{check_signing_message, Signer, SignerError, TransactionDependencyProvider};
+use crate::rpc::RpcError;
+
+/// Error code of [`SignerError::IdNotFound`]
+pub const ERROR_CODE_ID_NOT_FOUND: i64 = -32001;
+/// Error code of [`SignerError::InvalidMessage`]
+pub const ERROR_CODE_INVALID_MESSAGE: i64 = -32002;
+/// Error code of [`SignerError::InvalidTransaction`]
+pub const ERROR_CODE_INVALID_TRANSACTION: i64 = -32003;
+/// Error code of the signing request rejected by the server policy
+pub const ERROR_CODE_POLICY_REJECTED: i64 = -32004;
+/// Error code of [`SignerError::Other`]
+pub const ERROR_CODE_OTHER: i64 = -32000;
+
+/// The default max size of a request body
+pub const DEFAULT_MAX_BODY_SIZE: usize = 1024 * 1024;
+/// The default read and write timeout of a connection
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+/// The default max number of connections handled at the same time
+pub const DEFAULT_MAX_CONNECTIONS: usize = 64;
+
+const MAX_HEADERS_SIZE: u64 = 16 * 1024;
+
+crate::jsonrpc!(pub struct RemoteSignerRpcClient {
+    pub fn match_id(&self, id: JsonBytes) -> bool;
+    pub fn sign(&self, id: JsonBytes, message: JsonBytes, recoverable: bool, tx: json_types::Transaction) -> JsonBytes;
+});
+
+/// A signer forwards `match_id` and `sign` to a remote signer server.
+pub struct RemoteSigner {
+    client: RemoteSignerRpcClient,
+}
+
+impl RemoteSigner {
+    /// `auth_token` is sent as the bearer token of every request.
+    pub fn new(url: &str, auth_token: &str) -> RemoteSigner {
+        let mut headers = reqwest::header::HeaderMap::new();
+        let mut auth_value =
+            reqwest::header::HeaderValue::from_str(&format!("Bearer {}", auth_token))
+                .expect("auth token contains invalid header characters");
+        auth_value.set_sensitive(true);
+        headers.insert(reqwest::header::AUTHORIZATION, auth_value);
+        let mut client = RemoteSignerRpcClient::new(url);
+        client.client = reqwest::blocking::Client::builder()
+            .default_headers(headers)
+            .timeout(DEFAULT_TIMEOUT)
+            .build()
+            .expect("build remote signer http client");
+        RemoteSigner { client }
+    }
+}
+
+impl Signer for RemoteSigner {
+    fn match_id(&self, id: &[u8]) -> bool {
+        match self
+            .client
+            .match_id(JsonBytes::from_bytes(Bytes::copy_from_slice(id)))
+        {
+            Ok(matched) => matched,
+            Err(err) => {
+                log::warn!("remote signer match_id failed: {}", err);
+                false
+            }
+        }
+    }
+
+    fn sign(
+        &self,
+        id: &[u8],
+        message: &[u8],
+        recoverable: bool,
+        tx: &TransactionView,
+    ) -> Result<Bytes, SignerError> {
+        self.client
+            .sign(
+                JsonBytes::from_bytes(Bytes::copy_from_slice(id)),
+                JsonBytes::from_bytes(Bytes::copy_from_slice(message)),
+                recoverable,
+                tx.data().into(),
+            )
+            .map(JsonBytes::into_bytes)
+            .map_err(|err| match err {
+                RpcError::Rpc(err) => match err.code {
+                    ErrorCode::ServerError(ERROR_CODE_ID_NOT_FOUND) => SignerError::IdNotFound,
+                    ErrorCode::ServerError(ERROR_CODE_INVALID_MESSAGE) => {
+                        SignerError::InvalidMessage(err.message)
+                    }
+                    ErrorCode::ServerError(ERROR_CODE_INVALID_TRANSACTION)
+                    | ErrorCode::ServerError(ERROR_CODE_POLICY_REJECTED) => {
+                        SignerError::InvalidTransaction(err.message)
+                    }
+                    _ => SignerError::Other(err.into()),
+                },
+                err => SignerError::Other(err.into()),
+            })
+    }
+}
+
+/// Check a signing request `(id, message, tx)` before signing, return the
+/// reason if rejected.
+pub type SignPolicy =
+    Box<dyn Fn(&[u8], &[u8], &TransactionView) -> Result<(), String> + Send + Sync>;
+
+/// A reference remote signer server wrapping a local signer.
+pub struct RemoteSignerServer {
+    signer: Box<dyn Signer + Send + Sync>,
+    policy: Option<SignPolicy>,
+    tx_dep_provider: Option<Box<dyn TransactionDependencyProvider + Send + Sync>>,
+    auth_token: String,
+    timeout: Duration,
+    max_body_size: usize,
+    max_connections: usize,
+    connections: AtomicUsize,
+}
+
+impl RemoteSignerServer {
+    /// Requests without `Authorization: Bearer <auth_token>` are rejected,
+    /// the token must not be empty.
+    pub fn new(signer: Box<dyn Signer + Send + Sync>, auth_token: String) -> RemoteSignerServer {
+        assert!(!auth_token.is_empty(), "empty auth token");
+        RemoteSignerServer {
+            signer,
+            policy: None,
+            tx_dep_provider: None,
+            auth_token,
+            timeout: DEFAULT_TIMEOUT,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            connections: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn set_policy(&mut self, policy: SignPolicy) {
+        self.policy = Some(policy);
+    }
+
+    /// Set the provider of the input cells, which are required to check the
+    /// message is the signing message of the transaction. All signing
+    /// requests are refused without it.
+    pub fn set_tx_dep_provider(
+        &mut self,
+        tx_dep_provider: Box<dyn TransactionDependencyProvider + Send + Sync>,
+    ) {
+        self.tx_dep_provider = Some(tx_dep_provider);
+    }
+
+    /// Set the read and write timeout of a connection.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn set_max_body_size(&mut self, max_body_size: usize) {
+        self.max_body_size = max_body_size;
+    }
+
+    /// Set the max number of connections handled at the same time, the
+    /// connections beyond are answered with `503`.
+    pub fn set_max_connections(&mut self, max_connections: usize) {
+        self.max_connections = max_connections;
+    }
+
+    /// Handle a JSON-RPC request, return the JSON-RPC response.
+    pub fn handle_request(&self, request: &str) -> String {
+        let output = match serde_json::from_str::<MethodCall>(request) {
+            Ok(call) => Output::from(
+                self.handle_call(&call.method, call.params),
+                call.id,
+                Some(Version::V2),
+            ),
+            Err(_) => Output::from(Err(Error::parse_error()), Id::Null, Some(Version::V2)),
+        };
+        serde_json::to_string(&output).expect("serialize jsonrpc output")
+    }
+
+    fn handle_call(&self, method: &str, params: Params) -> Result<Value, Error> {
+        match method {
+            "match_id" => {
+                let (id,): (JsonBytes,) = params.parse()?;
+                Ok(Value::Bool(self.signer.match_id(id.as_bytes())))
+            }
+            "sign" => {
+                let (id, message, recoverable, tx): (
+                    JsonBytes,
+                    JsonBytes,
+                    bool,
+                    json_types::Transaction,
+                ) = params.parse()?;
+                let tx = packed::Transaction::from(tx).into_view();
+                if !self.signer.match_id(id.as_bytes()) {
+                    return Err(signer_error(SignerError::IdNotFound));
+                }
+                // The policy only applies if the message is from the transaction
+                let tx_dep_provider = self.tx_dep_provider.as_ref().ok_or_else(|| {
+                    signer_error(SignerError::InvalidMessage(
+                        "can not resolve the inputs to check the signing message".to_string(),
+                    ))
+                })?;
+                check_signing_message(
+                    &tx,
+                    id.as_bytes(),
+                    message.as_bytes(),
+                    tx_dep_provider.as_ref(),
+                )
+                .map_err(signer_error)?;
+                if let Some(policy) = self.policy.as_ref() {
+                    policy(id.as_bytes(), message.as_bytes(), &tx)
+                        .map_err(|reason| server_error(ERROR_CODE_POLICY_REJECTED, reason))?;
+                }
+                let signature = self
+                    .signer
+                    .sign(id.as_bytes(), message.as_bytes(), recoverable, &tx)
+                    .map_err(signer_error)?;
+                Ok(serde_json::to_value(JsonBytes::from_bytes(signature))
+                    .expect("serialize signature"))
+            }
+            _ => Err(Error::method_not_found()),
+        }
+    }
+
+    /// Serve JSON-RPC over HTTP, one request per connection, each connection
+    /// is handled in its own thread.
+    pub fn serve_http(&self, listener: TcpListener) -> io::Result<()> {
+        std::thread::scope(|scope| {
+            for stream in listener.incoming() {
+                let mut stream = stream?;
+                if self.connections.fetch_add(1, Ordering::SeqCst) >= self.max_connections {
+                    self.connections.fetch_sub(1, Ordering::SeqCst);
+                    let _ = stream
+                        .set_write_timeout(Some(self.timeout))
+                        .and_then(|_| write_response(&mut stream, "503 Service Unavailable", ""));
+                    continue;
+                }
+                scope.spawn(move || {
+                    if let Err(err) = self.handle_http_connection(stream) {
+                        log::warn!("remote signer server connection error: {}", err);
+                    }
+                    self.connections.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+            Ok(())
+        })
+    }
+
+    fn handle_http_connection(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut content_length = None;
+        let mut authorized = false;
+        let mut headers_size = 0;
+        let mut line = String::new();
+        // The request line and headers
+        loop {
+            line.clear();
+            let size = (&mut reader)
+                .take(MAX_HEADERS_SIZE - headers_size)
+                .read_line(&mut line)?;
+            if size == 0 {
+                if headers_size == MAX_HEADERS_SIZE {
+                    return write_response(&mut stream, "431 Request Header Fields Too Large", "");
+                }
+                return Ok(());
+            }
+            headers_size += size as u64;
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    match value.trim().parse::<usize>() {
+                        Ok(length) => content_length = Some(length),
+                        Err(_) => return write_response(&mut stream, "400 Bad Request", ""),
+                    }
+                } else if name.eq_ignore_ascii_case("authorization") {
+                    authorized = value
+                        .trim()
+                        .strip_prefix("Bearer ")
+                        .map(|token| constant_time_eq(token.as_bytes(), self.auth_token.as_bytes()))
+                        .unwrap_or(false);
+                }
+            }
+        }
+        if !authorized {
+            return write_response(&mut stream, "401 Unauthorized", "");
+        }
+        let content_length = match content_length {
+            Some(length) if length > self.max_body_size => {
+                return write_response(&mut stream, "413 Payload Too Large", "");
+            }
+            Some(length) => length,
+            None => return write_response(&mut stream, "411 Length Required", ""),
+        };
+        let mut body = vec![0u8; content_length];
+        reader.read_exact(&mut body)?;
+        let response = self.handle_request(&String::from_utf8_lossy(&body));
+        write_response(&mut stream, "200 OK", &response)
+    }
+}
+
+fn write_response(stream: &mut TcpStream, status: &str, body: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn signer_error(err: SignerError) -> Error {
+    let code = match err {
+        SignerError::IdNotFound => ERROR_CODE_ID_NOT_FOUND,
+        SignerError::InvalidMessage(_) => ERROR_CODE_INVALID_MESSAGE,
+        SignerError::InvalidTransaction(_) => ERROR_CODE_INVALID_TRANSACTION,
+        SignerError::Other(_) => ERROR_CODE_OTHER,
+    };
+    server_error(code, err.to_string())
+}
+
+fn server_error(code: i64, message: String) -> Error {
+    Error {
+        code: ErrorCode::ServerError(code),
+        message,
+        data: None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        constants::SIGHASH_TYPE_HASH,
+        traits::{OffchainTransactionDependencyProvider, SecpCkbRawKeySigner},
+        types::ScriptGroup,
+        unlock::generate_message,
+    };
+    use ckb_types::{
+        core::{ScriptHashType, TransactionBuilder},
+        packed::{CellInput, CellOutput, OutPoint, Script},
+        H160, H256,
+    };
+
+    fn key_signer() -> (H160, SecpCkbRawKeySigner) {
+        let key = secp256k1::SecretKey::from_slice(&[3u8; 32]).unwrap();
+        let pubkey = secp256k1::PublicKey::from_secret_key(&crate::SECP256K1, &key);
+        let id = crate::util::blake160(&pubkey.serialize());
+        (id, SecpCkbRawKeySigner::new_with_secret_keys(vec![key]))
+    }
+
+    fn sighash_lock(id: &H160) -> Script {
+        Script::new_builder()
+            .code_hash(SIGHASH_TYPE_HASH.pack())
+            .hash_type(ScriptHashType::Type.into())
+            .args(Bytes::copy_from_slice(id.as_bytes()).pack())
+            .build()
+    }
+
+    // The message signed by `SecpSighashScriptSigner` for the only input
+    fn signing_message(tx: &TransactionView, lock: &Script) -> Bytes {
+        let mut group = ScriptGroup::from_lock_script(lock);
+        group.input_indices.push(0);
+        let tx = tx.as_advanced_builder().witness(Default::default()).build();
+        generate_message(&tx, &group, Bytes::from(vec![0u8; 65])).unwrap()
+    }
+
+    #[test]
+    fn test_remote_signer() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (id, local_signer) = key_signer();
+        let lock = sighash_lock(&id);
+        let input = OutPoint::new(H256([1u8; 32]).pack(), 0);
+        let mut tx_dep_provider = OffchainTransactionDependencyProvider::new();
+        tx_dep_provider.cells.insert(
+            (H256([1u8; 32]), 0),
+            (
+                CellOutput::new_builder()
+                    .lock(lock.clone())
+                    .capacity(10000u64.pack())
+                    .build(),
+                Bytes::new(),
+            ),
+        );
+        std::thread::spawn(move || {
+            let (_, signer) = key_signer();
+            let mut server = RemoteSignerServer::new(Box::new(signer), "token".to_string());
+            server.set_max_body_size(4096);
+            server.set_tx_dep_provider(Box::new(tx_dep_provider));
+            server.set_policy(Box::new(
+                |_id: &[u8], _message: &[u8], tx: &TransactionView| {
+                    if tx.outputs().is_empty() {
+                        Ok(())
+                    } else {
+                        Err("outputs not allowed".to_string())
+                    }
+                },
+            ));
+            server.serve_http(listener).unwrap();
+        });
+
+        let remote_signer = RemoteSigner::new(&url, "token");
+        assert!(remote_signer.match_id(id.as_bytes()));
+        assert!(!remote_signer.match_id(&[0u8; 20]));
+        let tx = TransactionBuilder::default()
+            .input(CellInput::new(input.clone(), 0))
+            .build();
+        let message = signing_message(&tx, &lock);
+        // wrong token
+        let unauthorized_signer = RemoteSigner::new(&url, "wrong");
+        assert!(!unauthorized_signer.match_id(id.as_bytes()));
+        assert!(matches!(
+            unauthorized_signer.sign(id.as_bytes(), &message, true, &tx),
+            Err(SignerError::Other(_))
+        ));
+
+        assert_eq!(
+            remote_signer
+                .sign(id.as_bytes(), &message, true, &tx)
+                .unwrap(),
+            local_signer
+                .sign(id.as_bytes(), &message, true, &tx)
+                .unwrap()
+        );
+        assert!(matches!(
+            remote_signer.sign(&[0u8; 20], &message, true, &tx),
+            Err(SignerError::IdNotFound)
+        ));
+        assert!(matches!(
+            remote_signer.sign(id.as_bytes(), &message[0..16], true, &tx),
+            Err(SignerError::InvalidMessage(_))
+        ));
+        // the message is not from the transaction
+        let other_tx = tx
+            .as_advanced_builder()
+            .output(Default::default())
+            .output_data(Default::default())
+            .build();
+        assert!(matches!(
+            remote_signer.sign(id.as_bytes(), &signing_message(&other_tx, &lock), true, &tx),
+            Err(SignerError::InvalidMessage(_))
+        ));
+        // rejected by the policy
+        assert!(matches!(
+            remote_signer.sign(
+                id.as_bytes(),
+                &signing_message(&other_tx, &lock),
+                true,
+                &other_tx
+            ),
+            Err(SignerError::InvalidTransaction(_))
+        ));
+        // body too large
+        let tx = tx
+            .as_advanced_builder()
+            .witness(Bytes::from(vec![0u8; 4096]).pack())
+            .build();
+        assert!(matches!(
+            remote_signer.sign(id.as_bytes(), &message, true, &tx),
+            Err(SignerError::Other(_))
+        ));
+    }
+}
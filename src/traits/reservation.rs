@@ -0,0 +1,472 @@
+//! Cell reservations shared by the cell collectors of several processes.
+//!
+//! A cell collected to build a transaction is reserved until:
+//!   * the reservation expires (TTL), e.g. the worker crashed
+//!   * it is released explicitly, e.g. the transaction is rejected
+//!   * the spending transaction is committed, see
+//!     [`DefaultCellCollector::release_committed`](super::DefaultCellCollector::release_committed)
+//!
+//! [`FileReservation`] keeps the reservations in a JSON file guarded by an
+//! exclusive OS lock on a lock file, so the processes of the same wallet
+//! never collect the same cells. The OS releases the lock of a crashed
+//! process.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ckb_types::{packed::OutPoint, prelude::*, H256};
+use fs2::FileExt;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+pub const DEFAULT_RESERVATION_TTL: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Error, Debug)]
+pub enum ReservationError {
+    #[error("io error: `{0}`")]
+    Io(#[from] std::io::Error),
+
+    #[error("json error: `{0}`")]
+    Json(#[from] serde_json::Error),
+}
+
+/// The storage of the cell reservations.
+pub trait CellReservation: Send {
+    /// Reserve the cell, return false if it is reserved by others.
+    fn reserve(&mut self, out_point: &OutPoint) -> Result<bool, ReservationError>;
+
+    /// Record the transaction spending the reserved cells, the reservations
+    /// are renewed.
+    fn set_spending_tx(
+        &mut self,
+        out_points: &[OutPoint],
+        tx_hash: &H256,
+    ) -> Result<(), ReservationError>;
+
+    /// Renew the own reservations of the cells spent by the transaction,
+    /// e.g. it is still pending.
+    fn renew_tx(&mut self, tx_hash: &H256) -> Result<(), ReservationError>;
+
+    /// The cell is reserved by anyone.
+    fn is_reserved(&self, out_point: &OutPoint) -> Result<bool, ReservationError>;
+
+    /// The cells reserved by the other owners, load them at once to check
+    /// many cells.
+    fn reserved_by_others(&self) -> Result<HashSet<OutPoint>, ReservationError>;
+
+    fn release(&mut self, out_points: &[OutPoint]) -> Result<(), ReservationError>;
+
+    /// Release the cells spent by the transaction, after it is rejected or
+    /// committed.
+    fn release_tx(&mut self, tx_hash: &H256) -> Result<(), ReservationError>;
+
+    /// The spending transactions of the own reservations.
+    fn spending_txs(&self) -> Result<Vec<H256>, ReservationError>;
+
+    /// Release all the own reservations.
+    fn release_all(&mut self) -> Result<(), ReservationError>;
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+fn random_owner() -> String {
+    let mut nonce = [0u8; 8];
+    let _ = getrandom::getrandom(&mut nonce);
+    format!("{}-{}", std::process::id(), hex::encode(nonce))
+}
+
+fn out_point_key(out_point: &OutPoint) -> (H256, u32) {
+    (out_point.tx_hash().unpack(), out_point.index().unpack())
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Reservation {
+    tx_hash: H256,
+    index: u32,
+    owner: String,
+    /// Unix time in milliseconds
+    expires_at: u64,
+    spending_tx: Option<H256>,
+}
+
+/// The reservations of all the owners.
+#[derive(Clone, Debug, Default)]
+struct ReservationTable {
+    entries: HashMap<(H256, u32), Reservation>,
+}
+
+impl ReservationTable {
+    fn from_list(list: Vec<Reservation>) -> ReservationTable {
+        let entries = list
+            .into_iter()
+            .map(|entry| ((entry.tx_hash.clone(), entry.index), entry))
+            .collect();
+        ReservationTable { entries }
+    }
+
+    fn to_list(&self) -> Vec<Reservation> {
+        self.entries.values().cloned().collect()
+    }
+
+    fn purge_expired(&mut self, now: u64) {
+        self.entries.retain(|_, entry| entry.expires_at > now);
+    }
+
+    fn reserve(&mut self, out_point: &OutPoint, owner: &str, expires_at: u64) -> bool {
+        let key = out_point_key(out_point);
+        match self.entries.get_mut(&key) {
+            Some(entry) if entry.owner != owner => false,
+            Some(entry) => {
+                entry.expires_at = expires_at;
+                true
+            }
+            None => {
+                self.entries.insert(
+                    key.clone(),
+                    Reservation {
+                        tx_hash: key.0,
+                        index: key.1,
+                        owner: owner.to_string(),
+                        expires_at,
+                        spending_tx: None,
+                    },
+                );
+                true
+            }
+        }
+    }
+
+    fn set_spending_tx(
+        &mut self,
+        out_points: &[OutPoint],
+        tx_hash: &H256,
+        owner: &str,
+        expires_at: u64,
+    ) {
+        for out_point in out_points {
+            if self.reserve(out_point, owner, expires_at) {
+                if let Some(entry) = self.entries.get_mut(&out_point_key(out_point)) {
+                    entry.spending_tx = Some(tx_hash.clone());
+                }
+            }
+        }
+    }
+
+    fn renew_tx(&mut self, tx_hash: &H256, owner: &str, expires_at: u64) {
+        for entry in self.entries.values_mut() {
+            if entry.owner == owner && entry.spending_tx.as_ref() == Some(tx_hash) {
+                entry.expires_at = expires_at;
+            }
+        }
+    }
+
+    fn release(&mut self, out_points: &[OutPoint], owner: &str) {
+        for out_point in out_points {
+            let key = out_point_key(out_point);
+            if self.entries.get(&key).map(|entry| entry.owner.as_str()) == Some(owner) {
+                self.entries.remove(&key);
+            }
+        }
+    }
+
+    fn release_tx(&mut self, tx_hash: &H256) {
+        self.entries
+            .retain(|_, entry| entry.spending_tx.as_ref() != Some(tx_hash));
+    }
+
+    fn reserved_by_others(&self, owner: &str) -> HashSet<OutPoint> {
+        self.entries
+            .values()
+            .filter(|entry| entry.owner != owner)
+            .map(|entry| OutPoint::new(entry.tx_hash.pack(), entry.index))
+            .collect()
+    }
+
+    fn spending_txs(&self, owner: &str) -> Vec<H256> {
+        let mut tx_hashes: Vec<H256> = Vec::new();
+        for entry in self.entries.values().filter(|entry| entry.owner == owner) {
+            if let Some(tx_hash) = entry.spending_tx.as_ref() {
+                if !tx_hashes.contains(tx_hash) {
+                    tx_hashes.push(tx_hash.clone());
+                }
+            }
+        }
+        tx_hashes
+    }
+}
+
+/// The reservations in memory, shared by the clones (e.g. the collectors of
+/// several threads).
+#[derive(Clone)]
+pub struct MemoryReservation {
+    table: Arc<Mutex<ReservationTable>>,
+    owner: String,
+    ttl: Duration,
+}
+
+impl MemoryReservation {
+    pub fn new(ttl: Duration) -> MemoryReservation {
+        MemoryReservation {
+            table: Arc::new(Mutex::new(ReservationTable::default())),
+            owner: random_owner(),
+            ttl,
+        }
+    }
+
+    /// A reservation sharing the table with another owner.
+    pub fn new_owner(&self) -> MemoryReservation {
+        MemoryReservation {
+            table: Arc::clone(&self.table),
+            owner: random_owner(),
+            ttl: self.ttl,
+        }
+    }
+
+    fn with_table<T>(&self, f: impl FnOnce(&mut ReservationTable, &str, u64) -> T) -> T {
+        let now = now_millis();
+        let mut table = self.table.lock();
+        table.purge_expired(now);
+        f(&mut table, &self.owner, now + self.ttl.as_millis() as u64)
+    }
+}
+
+impl CellReservation for MemoryReservation {
+    fn reserve(&mut self, out_point: &OutPoint) -> Result<bool, ReservationError> {
+        Ok(self.with_table(|table, owner, expires_at| table.reserve(out_point, owner, expires_at)))
+    }
+    fn set_spending_tx(
+        &mut self,
+        out_points: &[OutPoint],
+        tx_hash: &H256,
+    ) -> Result<(), ReservationError> {
+        self.with_table(|table, owner, expires_at| {
+            table.set_spending_tx(out_points, tx_hash, owner, expires_at)
+        });
+        Ok(())
+    }
+    fn renew_tx(&mut self, tx_hash: &H256) -> Result<(), ReservationError> {
+        self.with_table(|table, owner, expires_at| table.renew_tx(tx_hash, owner, expires_at));
+        Ok(())
+    }
+    fn is_reserved(&self, out_point: &OutPoint) -> Result<bool, ReservationError> {
+        Ok(self.with_table(|table, _, _| table.entries.contains_key(&out_point_key(out_point))))
+    }
+    fn reserved_by_others(&self) -> Result<HashSet<OutPoint>, ReservationError> {
+        Ok(self.with_table(|table, owner, _| table.reserved_by_others(owner)))
+    }
+    fn release(&mut self, out_points: &[OutPoint]) -> Result<(), ReservationError> {
+        self.with_table(|table, owner, _| table.release(out_points, owner));
+        Ok(())
+    }
+    fn release_tx(&mut self, tx_hash: &H256) -> Result<(), ReservationError> {
+        self.with_table(|table, _, _| table.release_tx(tx_hash));
+        Ok(())
+    }
+    fn spending_txs(&self) -> Result<Vec<H256>, ReservationError> {
+        Ok(self.with_table(|table, owner, _| table.spending_txs(owner)))
+    }
+    fn release_all(&mut self) -> Result<(), ReservationError> {
+        self.with_table(|table, owner, _| table.entries.retain(|_, entry| entry.owner != owner));
+        Ok(())
+    }
+}
+
+/// The reservations in a JSON file shared by processes, every operation
+/// holds the lock file `<path>.lock`.
+pub struct FileReservation {
+    path: PathBuf,
+    owner: String,
+    ttl: Duration,
+}
+
+impl FileReservation {
+    pub fn new<P: AsRef<Path>>(path: P, ttl: Duration) -> FileReservation {
+        FileReservation {
+            path: path.as_ref().to_path_buf(),
+            owner: random_owner(),
+            ttl,
+        }
+    }
+
+    /// The owner id of the reservations, unique per instance by default.
+    pub fn owner(&self) -> &str {
+        &self.owner
+    }
+
+    pub fn set_owner(&mut self, owner: String) {
+        self.owner = owner;
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn lock_path(&self) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(".lock");
+        PathBuf::from(path)
+    }
+
+    /// Block until the exclusive lock of the lock file is acquired. The lock
+    /// file is kept, removing it would let another process lock a new one.
+    fn acquire_lock(&self) -> Result<LockFile, ReservationError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(self.lock_path())?;
+        file.lock_exclusive()?;
+        Ok(LockFile { file })
+    }
+
+    fn with_table<T>(
+        &self,
+        modify: bool,
+        f: impl FnOnce(&mut ReservationTable, &str, u64) -> T,
+    ) -> Result<T, ReservationError> {
+        let _lock = self.acquire_lock()?;
+        let mut table = if self.path.exists() {
+            ReservationTable::from_list(serde_json::from_slice(&fs::read(&self.path)?)?)
+        } else {
+            ReservationTable::default()
+        };
+        let now = now_millis();
+        table.purge_expired(now);
+        let result = f(&mut table, &self.owner, now + self.ttl.as_millis() as u64);
+        if modify {
+            let tmp_path = self.path.with_extension("tmp");
+            fs::write(&tmp_path, serde_json::to_vec(&table.to_list())?)?;
+            fs::rename(&tmp_path, &self.path)?;
+        }
+        Ok(result)
+    }
+}
+
+struct LockFile {
+    file: File,
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+impl CellReservation for FileReservation {
+    fn reserve(&mut self, out_point: &OutPoint) -> Result<bool, ReservationError> {
+        self.with_table(true, |table, owner, expires_at| {
+            table.reserve(out_point, owner, expires_at)
+        })
+    }
+    fn set_spending_tx(
+        &mut self,
+        out_points: &[OutPoint],
+        tx_hash: &H256,
+    ) -> Result<(), ReservationError> {
+        self.with_table(true, |table, owner, expires_at| {
+            table.set_spending_tx(out_points, tx_hash, owner, expires_at)
+        })
+    }
+    fn renew_tx(&mut self, tx_hash: &H256) -> Result<(), ReservationError> {
+        self.with_table(true, |table, owner, expires_at| {
+            table.renew_tx(tx_hash, owner, expires_at)
+        })
+    }
+    fn is_reserved(&self, out_point: &OutPoint) -> Result<bool, ReservationError> {
+        self.with_table(false, |table, _, _| {
+            table.entries.contains_key(&out_point_key(out_point))
+        })
+    }
+    fn reserved_by_others(&self) -> Result<HashSet<OutPoint>, ReservationError> {
+        self.with_table(false, |table, owner, _| table.reserved_by_others(owner))
+    }
+    fn release(&mut self, out_points: &[OutPoint]) -> Result<(), ReservationError> {
+        self.with_table(true, |table, owner, _| table.release(out_points, owner))
+    }
+    fn release_tx(&mut self, tx_hash: &H256) -> Result<(), ReservationError> {
+        self.with_table(true, |table, _, _| table.release_tx(tx_hash))
+    }
+    fn spending_txs(&self) -> Result<Vec<H256>, ReservationError> {
+        self.with_table(false, |table, owner, _| table.spending_txs(owner))
+    }
+    fn release_all(&mut self) -> Result<(), ReservationError> {
+        self.with_table(true, |table, owner, _| {
+            table.entries.retain(|_, entry| entry.owner != owner)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn out_point(byte: u8) -> OutPoint {
+        OutPoint::new(H256([byte; 32]).pack(), 0)
+    }
+
+    #[test]
+    fn test_file_reservation() {
+        let dir = std::env::temp_dir().join(format!("ckb-sdk-reservation-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("reservations.json");
+        let mut worker1 = FileReservation::new(&path, DEFAULT_RESERVATION_TTL);
+        let mut worker2 = FileReservation::new(&path, DEFAULT_RESERVATION_TTL);
+
+        assert!(worker1.reserve(&out_point(1)).unwrap());
+        assert!(worker1.reserve(&out_point(2)).unwrap());
+        assert!(!worker2.reserve(&out_point(1)).unwrap());
+        assert!(worker2.is_reserved(&out_point(2)).unwrap());
+        assert!(worker1.reserved_by_others().unwrap().is_empty());
+        let reserved = worker2.reserved_by_others().unwrap();
+        assert_eq!(reserved.len(), 2);
+        assert!(reserved.contains(&out_point(1)));
+
+        // released after the spending tx is rejected or committed
+        let tx_hash = H256([9u8; 32]);
+        worker1
+            .set_spending_tx(&[out_point(1), out_point(2)], &tx_hash)
+            .unwrap();
+        assert_eq!(worker1.spending_txs().unwrap(), vec![tx_hash.clone()]);
+        assert!(worker2.spending_txs().unwrap().is_empty());
+        worker2.release(&[out_point(1)]).unwrap();
+        assert!(worker2.is_reserved(&out_point(1)).unwrap());
+        // renewed while the spending tx is pending
+        worker1.renew_tx(&tx_hash).unwrap();
+        worker2.renew_tx(&tx_hash).unwrap();
+        assert_eq!(worker1.spending_txs().unwrap(), vec![tx_hash.clone()]);
+        worker1.release_tx(&tx_hash).unwrap();
+        assert!(worker2.reserve(&out_point(1)).unwrap());
+
+        // expired
+        let mut worker3 = FileReservation::new(&path, Duration::from_millis(0));
+        assert!(worker3.reserve(&out_point(3)).unwrap());
+        assert!(worker2.reserve(&out_point(3)).unwrap());
+        worker2.release_all().unwrap();
+        assert!(!worker1.is_reserved(&out_point(1)).unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_memory_reservation() {
+        let mut worker1 = MemoryReservation::new(DEFAULT_RESERVATION_TTL);
+        let mut worker2 = worker1.new_owner();
+        assert!(worker1.reserve(&out_point(1)).unwrap());
+        assert!(!worker2.reserve(&out_point(1)).unwrap());
+        assert!(worker1.reserved_by_others().unwrap().is_empty());
+        assert!(worker2
+            .reserved_by_others()
+            .unwrap()
+            .contains(&out_point(1)));
+        worker1.release(&[out_point(1)]).unwrap();
+        assert!(worker2.reserve(&out_point(1)).unwrap());
+    }
+}
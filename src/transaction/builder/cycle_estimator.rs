@@ -0,0 +1,173 @@
+use std::collections::{HashMap, HashSet};
+
+use ckb_script::{ScriptGroupType as VmScriptGroupType, TransactionScriptsVerifier};
+use ckb_types::{core::cell::resolve_transaction, core::TransactionView, prelude::*};
+
+use crate::{
+    rpc::CkbRpcClient,
+    traits::TransactionDependencyProvider,
+    transaction::signer::{SignContexts, TransactionSigner},
+    tx_builder::BalanceTxCapacityError,
+    ScriptGroup, ScriptGroupType, ScriptId, TransactionWithScriptGroups,
+};
+
+/// Estimate the cycles a transaction will consume, so the builder can price
+/// the transaction by `max(serialized_size, cycles * bytes_per_cycle)`.
+pub trait CycleEstimator {
+    /// Estimate the total cycles of all the `script_groups` in `tx`.
+    fn estimate_cycles(
+        &self,
+        tx: &TransactionView,
+        script_groups: &[ScriptGroup],
+    ) -> Result<u64, BalanceTxCapacityError>;
+}
+
+/// Sign a copy of the transaction being balanced with the real keys, so the
+/// lock scripts run the same code path as in the final transaction. The
+/// signed copy is only used to measure the cycles.
+pub struct DryRunSigner {
+    signer: TransactionSigner,
+    contexts: SignContexts,
+}
+
+impl DryRunSigner {
+    pub fn new(signer: TransactionSigner, contexts: SignContexts) -> DryRunSigner {
+        DryRunSigner { signer, contexts }
+    }
+
+    pub fn sign(
+        &self,
+        tx: &TransactionView,
+        script_groups: &[ScriptGroup],
+    ) -> Result<TransactionView, BalanceTxCapacityError> {
+        let mut tx_with_groups =
+            TransactionWithScriptGroups::new(tx.clone(), script_groups.to_vec());
+        self.signer
+            .sign_transaction(&mut tx_with_groups, &self.contexts)
+            .map_err(|err| {
+                BalanceTxCapacityError::VerifyScript(format!("dry-run unlock error: {}", err))
+            })?;
+        Ok(tx_with_groups.get_tx_view().clone())
+    }
+}
+
+/// Estimate cycles by running every script group in a local ckb-vm.
+///
+/// While building a transaction the lock scripts only have placeholder
+/// witnesses, which can not pass the verification. Set a [`DryRunSigner`] to
+/// unlock the transaction before measuring. A script group still failing to
+/// verify is priced by the cycles set by `set_group_cycles` for its script
+/// id, and the estimation fails if no cycles is set, so an expensive script
+/// is never priced as free.
+pub struct LocalCycleEstimator {
+    tx_dep_provider: Box<dyn TransactionDependencyProvider>,
+    signer: Option<DryRunSigner>,
+    group_cycles: HashMap<ScriptId, u64>,
+}
+
+impl LocalCycleEstimator {
+    pub fn new(tx_dep_provider: Box<dyn TransactionDependencyProvider>) -> LocalCycleEstimator {
+        LocalCycleEstimator {
+            tx_dep_provider,
+            signer: None,
+            group_cycles: HashMap::default(),
+        }
+    }
+
+    /// Unlock the transaction by the signer before measuring.
+    pub fn set_signer(&mut self, signer: DryRunSigner) {
+        self.signer = Some(signer);
+    }
+
+    /// Set the cycles used when a script group with the script id failed to verify.
+    pub fn set_group_cycles(&mut self, script_id: ScriptId, cycles: u64) {
+        self.group_cycles.insert(script_id, cycles);
+    }
+}
+
+impl CycleEstimator for LocalCycleEstimator {
+    fn estimate_cycles(
+        &self,
+        tx: &TransactionView,
+        script_groups: &[ScriptGroup],
+    ) -> Result<u64, BalanceTxCapacityError> {
+        let tx = match self.signer.as_ref() {
+            Some(signer) => signer.sign(tx, script_groups)?,
+            None => tx.clone(),
+        };
+        let tx_dep_provider = self.tx_dep_provider.as_ref();
+        let rtx = resolve_transaction(tx, &mut HashSet::new(), &tx_dep_provider, &tx_dep_provider)
+            .map_err(|err| {
+                BalanceTxCapacityError::VerifyScript(format!(
+                    "Resolve transaction error: {:?}",
+                    err
+                ))
+            })?;
+
+        let verifier = TransactionScriptsVerifier::new(&rtx, &tx_dep_provider);
+        let mut total_cycles = 0u64;
+        for script_group in script_groups {
+            let group_type = match script_group.group_type {
+                ScriptGroupType::Lock => VmScriptGroupType::Lock,
+                ScriptGroupType::Type => VmScriptGroupType::Type,
+            };
+            let script_hash = script_group.script.calc_script_hash();
+            let cycles = match verifier.verify_single(group_type, &script_hash, u64::max_value()) {
+                Ok(cycles) => cycles,
+                Err(err) => {
+                    let script_id = ScriptId::from(&script_group.script);
+                    log::debug!(
+                        "estimate cycles of script group {} failed: {}, use preset cycles",
+                        script_hash,
+                        err
+                    );
+                    self.group_cycles.get(&script_id).cloned().ok_or_else(|| {
+                        BalanceTxCapacityError::VerifyScript(format!(
+                            "can not estimate cycles of script group {}: {}, set a dry-run signer or the cycles of script {:?}",
+                            script_hash, err, script_id
+                        ))
+                    })?
+                }
+            };
+            total_cycles = total_cycles.saturating_add(cycles);
+        }
+        Ok(total_cycles)
+    }
+}
+
+/// Estimate cycles by the `estimate_cycles` rpc of ckb node. The whole
+/// transaction must pass the verification, so set a [`DryRunSigner`] unless
+/// the transaction is already unlocked.
+pub struct RpcCycleEstimator {
+    ckb_client: CkbRpcClient,
+    signer: Option<DryRunSigner>,
+}
+
+impl RpcCycleEstimator {
+    pub fn new(ckb_client: &str) -> RpcCycleEstimator {
+        RpcCycleEstimator {
+            ckb_client: CkbRpcClient::new(ckb_client),
+            signer: None,
+        }
+    }
+
+    /// Unlock the transaction by the signer before sending to the node.
+    pub fn set_signer(&mut self, signer: DryRunSigner) {
+        self.signer = Some(signer);
+    }
+}
+
+impl CycleEstimator for RpcCycleEstimator {
+    fn estimate_cycles(
+        &self,
+        tx: &TransactionView,
+        script_groups: &[ScriptGroup],
+    ) -> Result<u64, BalanceTxCapacityError> {
+        let tx = match self.signer.as_ref() {
+            Some(signer) => signer.sign(tx, script_groups)?,
+            None => tx.clone(),
+        };
+        let result = self.ckb_client.estimate_cycles(tx.data().into())?;
+        Ok(result.cycles.value())
+    }
+}
@@ -2,6 +2,8 @@ use ckb_types::core::FeeRate;
 
 use crate::{core::TransactionBuilder, tx_builder::bytes_per_cycle};
 
+use super::fee_estimator::{FeeEstimator, FeeEstimatorError};
+
 pub struct FeeCalculator {
     fee_rate: u64,
 }
@@ -10,6 +12,27 @@ impl FeeCalculator {
     pub fn new(fee_rate: u64) -> Self {
         Self { fee_rate }
     }
+
+    /// Create a fee calculator with the fee rate estimated by `estimator`,
+    /// the estimated fee rate is clamped into `[floor, cap]`.
+    pub fn from_estimator(
+        estimator: &dyn FeeEstimator,
+        target_blocks: u64,
+        floor: u64,
+        cap: u64,
+    ) -> Result<Self, FeeEstimatorError> {
+        if floor > cap {
+            return Err(FeeEstimatorError::InvalidBounds(floor, cap));
+        }
+        let fee_rate = estimator.estimate_fee_rate(target_blocks)?;
+        Ok(Self::new(fee_rate.max(floor).min(cap)))
+    }
+
+    #[inline]
+    pub fn fee_rate(&self) -> u64 {
+        self.fee_rate
+    }
+
     pub fn fee(&self, weight: u64) -> u64 {
         let fee_rate = FeeRate::from_u64(self.fee_rate);
         fee_rate.fee(weight).as_u64()
@@ -0,0 +1,273 @@
+use anyhow::anyhow;
+use ckb_jsonrpc_types::{FeeRateStatics, Uint64};
+use ckb_types::{core::BlockView, packed::Byte32, prelude::*};
+use thiserror::Error;
+
+use crate::{
+    rpc::CkbRpcClient,
+    traits::{
+        DefaultTransactionDependencyProvider, TransactionDependencyError,
+        TransactionDependencyProvider,
+    },
+    RpcError,
+};
+
+/// The default target block count used by the ckb node when estimating fee rate.
+pub const DEFAULT_TARGET_BLOCKS: u64 = 21;
+
+#[derive(Error, Debug)]
+pub enum FeeEstimatorError {
+    #[error("rpc error: `{0}`")]
+    Rpc(#[from] RpcError),
+
+    #[error("transaction dependency error: `{0}`")]
+    TxDep(#[from] TransactionDependencyError),
+
+    #[error("invalid fee rate bounds, floor: `{0}`, cap: `{1}`")]
+    InvalidBounds(u64, u64),
+
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// Which statistic of the sampled fee rates is used as the estimation.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Hash)]
+pub enum FeeRateStatistic {
+    Mean,
+    Median,
+}
+
+impl Default for FeeRateStatistic {
+    fn default() -> Self {
+        FeeRateStatistic::Median
+    }
+}
+
+impl FeeRateStatistic {
+    fn pick(&self, statics: &FeeRateStatics) -> u64 {
+        match self {
+            FeeRateStatistic::Mean => statics.mean.value(),
+            FeeRateStatistic::Median => statics.median.value(),
+        }
+    }
+
+    /// Calculate the statistic from the samples, return `None` if there is no sample.
+    pub fn calculate(&self, samples: &mut [u64]) -> Option<u64> {
+        if samples.is_empty() {
+            return None;
+        }
+        match self {
+            FeeRateStatistic::Mean => {
+                let total: u128 = samples.iter().map(|rate| *rate as u128).sum();
+                Some((total / samples.len() as u128) as u64)
+            }
+            FeeRateStatistic::Median => {
+                samples.sort_unstable();
+                let mid = samples.len() / 2;
+                if samples.len() % 2 == 0 {
+                    Some(((samples[mid - 1] as u128 + samples[mid] as u128) / 2) as u64)
+                } else {
+                    Some(samples[mid])
+                }
+            }
+        }
+    }
+}
+
+/// A fee rate estimator, the returned fee rate is in shannons per KB.
+pub trait FeeEstimator {
+    /// Estimate the fee rate for a transaction expected to be committed
+    /// within `target_blocks` blocks.
+    fn estimate_fee_rate(&self, target_blocks: u64) -> Result<u64, FeeEstimatorError>;
+}
+
+/// Estimate fee rate by the statistics calculated by the ckb node
+/// (`get_fee_rate_statics` rpc), fallback to `tx_pool_info.min_fee_rate`
+/// when the node has no statistics yet.
+pub struct NodeFeeEstimator {
+    ckb_client: CkbRpcClient,
+    statistic: FeeRateStatistic,
+}
+
+impl NodeFeeEstimator {
+    pub fn new(ckb_client: &str, statistic: FeeRateStatistic) -> NodeFeeEstimator {
+        NodeFeeEstimator {
+            ckb_client: CkbRpcClient::new(ckb_client),
+            statistic,
+        }
+    }
+
+    pub fn statistic(&self) -> FeeRateStatistic {
+        self.statistic
+    }
+}
+
+impl FeeEstimator for NodeFeeEstimator {
+    fn estimate_fee_rate(&self, target_blocks: u64) -> Result<u64, FeeEstimatorError> {
+        // `get_fee_rate_statics` returns null when there is not enough data.
+        let statics: Option<FeeRateStatics> = self
+            .ckb_client
+            .post("get_fee_rate_statics", (Some(Uint64::from(target_blocks)),))?;
+        match statics {
+            Some(statics) => Ok(self.statistic.pick(&statics)),
+            None => Ok(self.ckb_client.tx_pool_info()?.min_fee_rate.value()),
+        }
+    }
+}
+
+/// Estimate fee rate by sampling the transactions committed in recent blocks.
+///
+/// The fee of every sampled transaction is calculated from its resolved
+/// inputs, transactions with header deps (e.g. dao withdraw) are skipped
+/// since their input capacity can not be resolved from outputs directly.
+/// Fallback to `tx_pool_info.min_fee_rate` when there is no sample.
+pub struct LocalFeeEstimator {
+    ckb_client: CkbRpcClient,
+    tx_dep_provider: DefaultTransactionDependencyProvider,
+    statistic: FeeRateStatistic,
+    /// The maximum number of blocks to sample
+    max_sample_blocks: u64,
+}
+
+impl LocalFeeEstimator {
+    /// Arguments:
+    ///   * `ckb_client` is the ckb http jsonrpc server url
+    ///   * `max_sample_blocks` is the maximum number of recent blocks to sample
+    pub fn new(
+        ckb_client: &str,
+        statistic: FeeRateStatistic,
+        max_sample_blocks: u64,
+    ) -> LocalFeeEstimator {
+        LocalFeeEstimator {
+            ckb_client: CkbRpcClient::new(ckb_client),
+            tx_dep_provider: DefaultTransactionDependencyProvider::new(ckb_client, 256),
+            statistic,
+            max_sample_blocks,
+        }
+    }
+
+    pub fn statistic(&self) -> FeeRateStatistic {
+        self.statistic
+    }
+
+    pub fn max_sample_blocks(&self) -> u64 {
+        self.max_sample_blocks
+    }
+
+    fn input_capacity(&self, tx_hash: &Byte32, index: u32) -> Result<u64, FeeEstimatorError> {
+        let tx = self.tx_dep_provider.get_transaction(tx_hash)?;
+        let output = tx.output(index as usize).ok_or_else(|| {
+            TransactionDependencyError::NotFound(format!(
+                "output index `{}` of transaction `{}`",
+                index, tx_hash
+            ))
+        })?;
+        Ok(output.capacity().unpack())
+    }
+
+    /// Collect fee rates (shannons/KB) of all transactions except cellbase in the block.
+    pub fn sample_block(&self, block: &BlockView) -> Result<Vec<u64>, FeeEstimatorError> {
+        let mut samples = Vec::new();
+        for tx in block.transactions().iter().skip(1) {
+            if !tx.header_deps().is_empty() {
+                continue;
+            }
+            let mut input_total = 0u64;
+            for out_point in tx.input_pts_iter() {
+                let capacity =
+                    self.input_capacity(&out_point.tx_hash(), out_point.index().unpack())?;
+                input_total = input_total
+                    .checked_add(capacity)
+                    .ok_or_else(|| anyhow!("input capacity overflow"))?;
+            }
+            let output_total = tx
+                .outputs_capacity()
+                .map_err(|err| anyhow!("output capacity error: {}", err))?
+                .as_u64();
+            if let Some(fee) = input_total.checked_sub(output_total) {
+                let size = tx.data().as_reader().serialized_size_in_block() as u64;
+                samples.push(fee.saturating_mul(1000) / size);
+            }
+        }
+        Ok(samples)
+    }
+}
+
+impl FeeEstimator for LocalFeeEstimator {
+    fn estimate_fee_rate(&self, target_blocks: u64) -> Result<u64, FeeEstimatorError> {
+        let sample_blocks = target_blocks.max(1).min(self.max_sample_blocks.max(1));
+        let tip_number = self.ckb_client.get_tip_block_number()?.value();
+        let mut samples = Vec::new();
+        let start = tip_number.saturating_sub(sample_blocks - 1);
+        for number in start..=tip_number {
+            if let Some(block) = self.ckb_client.get_block_by_number(number.into())? {
+                let block: BlockView = block.into();
+                samples.extend(self.sample_block(&block)?);
+            }
+        }
+        match self.statistic.calculate(&mut samples) {
+            Some(fee_rate) => Ok(fee_rate),
+            None => Ok(self.ckb_client.tx_pool_info()?.min_fee_rate.value()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::MockRpcResult;
+    use crate::transaction::builder::FeeCalculator;
+    use httpmock::prelude::*;
+
+    #[test]
+    fn test_fee_rate_statistic() {
+        assert_eq!(None, FeeRateStatistic::Median.calculate(&mut []));
+        assert_eq!(
+            Some(2000),
+            FeeRateStatistic::Median.calculate(&mut [3000, 1000, 2000])
+        );
+        assert_eq!(
+            Some(2500),
+            FeeRateStatistic::Median.calculate(&mut [4000, 1000, 2000, 3000])
+        );
+        assert_eq!(
+            Some(2500),
+            FeeRateStatistic::Mean.calculate(&mut [1000, 4000, 2000, 3000])
+        );
+    }
+
+    #[test]
+    fn test_node_fee_estimator() {
+        let server = MockServer::start();
+        let statics = FeeRateStatics {
+            mean: 3000.into(),
+            median: 1500.into(),
+        };
+        server.mock(|when, then| {
+            when.method(POST)
+                .path("/")
+                .body_contains("get_fee_rate_statics");
+            then.status(200).body(MockRpcResult::new(statics).to_json());
+        });
+
+        let url = server.base_url();
+        let median = NodeFeeEstimator::new(url.as_str(), FeeRateStatistic::Median);
+        assert_eq!(
+            1500,
+            median.estimate_fee_rate(DEFAULT_TARGET_BLOCKS).unwrap()
+        );
+        let mean = NodeFeeEstimator::new(url.as_str(), FeeRateStatistic::Mean);
+        assert_eq!(3000, mean.estimate_fee_rate(DEFAULT_TARGET_BLOCKS).unwrap());
+
+        let calculator = FeeCalculator::from_estimator(&mean, 10, 1000, 2000).unwrap();
+        assert_eq!(2000, calculator.fee_rate());
+        let calculator = FeeCalculator::from_estimator(&median, 10, 2000, 5000).unwrap();
+        assert_eq!(2000, calculator.fee_rate());
+        let calculator = FeeCalculator::from_estimator(&median, 10, 1000, 5000).unwrap();
+        assert_eq!(1500, calculator.fee_rate());
+        assert!(matches!(
+            FeeCalculator::from_estimator(&median, 10, 5000, 1000),
+            Err(FeeEstimatorError::InvalidBounds(5000, 1000))
+        ));
+    }
+}
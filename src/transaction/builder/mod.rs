@@ -16,8 +16,14 @@ use ckb_types::{
     packed::{self, Byte32, CellOutput, Script},
     prelude::{Builder, Entity, Pack, Unpack},
 };
+pub mod cycle_estimator;
 pub mod fee_calculator;
+pub mod fee_estimator;
+pub use cycle_estimator::{CycleEstimator, DryRunSigner, LocalCycleEstimator, RpcCycleEstimator};
 pub use fee_calculator::FeeCalculator;
+pub use fee_estimator::{
+    FeeEstimator, FeeEstimatorError, FeeRateStatistic, LocalFeeEstimator, NodeFeeEstimator,
+};
 
 pub trait CkbTransactionBuilder {
     fn build(
@@ -113,6 +119,30 @@ impl SimpleTransactionBuilder {
         Ok(())
     }
 
+    /// Calculate the fee of current transaction, the estimated cycles is also
+    /// taken into account if a cycle estimator is configured.
+    fn calculate_fee(
+        tx_builder: &TransactionBuilder,
+        configuration: &TransactionBuilderConfiguration,
+        calculator: &FeeCalculator,
+        lock_groups: &HashMap<Byte32, ScriptGroup>,
+        type_groups: &HashMap<Byte32, ScriptGroup>,
+    ) -> Result<u64, TxBuilderError> {
+        if let Some(cycle_estimator) = configuration.get_cycle_estimator() {
+            let tx = tx_builder.clone().build();
+            let script_groups: Vec<ScriptGroup> = lock_groups
+                .values()
+                .chain(type_groups.values())
+                .cloned()
+                .collect();
+            let cycles = cycle_estimator.estimate_cycles(&tx, &script_groups)?;
+            let tx_size = tx.data().as_reader().serialized_size_in_block() as u64;
+            Ok(calculator.fee_with_cycle(tx_size, cycles))
+        } else {
+            Ok(calculator.fee_with_tx_builder(tx_builder))
+        }
+    }
+
     fn add_output_capacity(
         tx_builder: &mut TransactionBuilder,
         script: &Script,
@@ -192,7 +222,13 @@ impl CkbTransactionBuilder for SimpleTransactionBuilder {
             }
             inputs_capacity += celloutput_capacity!(previous_output);
             // check if there is enough capacity for output capacity and change
-            let fee = calculator.fee_with_tx_builder(&self.tx);
+            let fee = Self::calculate_fee(
+                &self.tx,
+                &self.configuration,
+                &calculator,
+                &lock_groups,
+                &type_groups,
+            )?;
             let change_capacity =
                 (inputs_capacity + self.reward).checked_sub(outputs_capacity + fee);
             if let Some(mut change_capacity) = change_capacity {
@@ -236,7 +272,13 @@ impl CkbTransactionBuilder for SimpleTransactionBuilder {
                         self.tx.output(change_output);
                         self.tx.output_data(change_output_data);
                     }
-                    let new_fee = calculator.fee_with_tx_builder(&self.tx);
+                    let new_fee = Self::calculate_fee(
+                        &self.tx,
+                        &self.configuration,
+                        &calculator,
+                        &lock_groups,
+                        &type_groups,
+                    )?;
                     if let Some(new_change) =
                         (inputs_capacity + self.reward).checked_sub(outputs_capacity + new_fee)
                     {
@@ -0,0 +1,334 @@
+//! The cobuild building packet, passed among the parties constructing a
+//! transaction together.
+//!
+//! Each party adds its cells, actions and script infos to the packet, the
+//! resolved inputs travel along with the transaction so every party is able
+//! to compute the cobuild signing message offline.
+
+use ckb_types::{
+    bytes::Bytes,
+    core::{HeaderView, TransactionView},
+    molecule::error::VerificationError,
+    packed::{self, Byte32, CellInput, CellOutput, OutPoint},
+    prelude::*,
+};
+use thiserror::Error;
+
+use crate::{
+    traits::{TransactionDependencyError, TransactionDependencyProvider},
+    types::cobuild::{
+        self, Action, ActionVec, BuildingPacketUnion, BuildingPacketV1, Message, ResolvedInputs,
+        ScriptInfo, ScriptInfoVec, Uint32Opt,
+    },
+    unlock::{
+        cobuild::{
+            fill_cobuild_message, find_cobuild_message, generate_cobuild_message,
+            resolve_input_cells,
+        },
+        ScriptSignError,
+    },
+};
+
+#[derive(Error, Debug)]
+pub enum BuildingPacketError {
+    #[error("invalid molecule data: `{0}`")]
+    Molecule(#[from] VerificationError),
+
+    #[error("transaction dependency error: `{0}`")]
+    TxDep(#[from] TransactionDependencyError),
+
+    #[error("sign script error: `{0}`")]
+    ScriptSign(#[from] ScriptSignError),
+
+    #[error("resolved inputs count `{0}` not match transaction inputs count `{1}`")]
+    ResolvedInputsMismatch(usize, usize),
+}
+
+/// A transaction under construction, with its cobuild message.
+#[derive(Clone, Debug)]
+pub struct BuildingPacket {
+    message: Message,
+    payload: TransactionView,
+    resolved_inputs: Vec<(CellOutput, Bytes)>,
+    change_output: Option<u32>,
+    script_infos: Vec<ScriptInfo>,
+    lock_actions: Vec<Action>,
+}
+
+impl BuildingPacket {
+    pub fn new(
+        payload: TransactionView,
+        resolved_inputs: Vec<(CellOutput, Bytes)>,
+    ) -> Result<Self, BuildingPacketError> {
+        if resolved_inputs.len() != payload.inputs().len() {
+            return Err(BuildingPacketError::ResolvedInputsMismatch(
+                resolved_inputs.len(),
+                payload.inputs().len(),
+            ));
+        }
+        Ok(BuildingPacket {
+            message: Message::default(),
+            payload,
+            resolved_inputs,
+            change_output: None,
+            script_infos: Vec::new(),
+            lock_actions: Vec::new(),
+        })
+    }
+
+    /// Resolve the inputs of the transaction by `tx_dep_provider`.
+    pub fn from_transaction(
+        payload: TransactionView,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<Self, BuildingPacketError> {
+        let resolved_inputs = resolve_input_cells(&payload, tx_dep_provider)?;
+        BuildingPacket::new(payload, resolved_inputs)
+    }
+
+    pub fn message(&self) -> &Message {
+        &self.message
+    }
+    pub fn payload(&self) -> &TransactionView {
+        &self.payload
+    }
+    pub fn resolved_inputs(&self) -> &[(CellOutput, Bytes)] {
+        &self.resolved_inputs
+    }
+    pub fn change_output(&self) -> Option<u32> {
+        self.change_output
+    }
+    pub fn script_infos(&self) -> &[ScriptInfo] {
+        &self.script_infos
+    }
+    pub fn lock_actions(&self) -> &[Action] {
+        &self.lock_actions
+    }
+
+    /// Add an input with its resolved cell.
+    pub fn add_input(&mut self, input: CellInput, output: CellOutput, data: Bytes) {
+        self.payload = self.payload.as_advanced_builder().input(input).build();
+        self.resolved_inputs.push((output, data));
+    }
+
+    pub fn add_output(&mut self, output: CellOutput, data: Bytes) {
+        self.payload = self
+            .payload
+            .as_advanced_builder()
+            .output(output)
+            .output_data(data.pack())
+            .build();
+    }
+
+    /// Replace the transaction, e.g. after adding cell deps or witnesses.
+    pub fn set_payload(
+        &mut self,
+        payload: TransactionView,
+        resolved_inputs: Vec<(CellOutput, Bytes)>,
+    ) -> Result<(), BuildingPacketError> {
+        if resolved_inputs.len() != payload.inputs().len() {
+            return Err(BuildingPacketError::ResolvedInputsMismatch(
+                resolved_inputs.len(),
+                payload.inputs().len(),
+            ));
+        }
+        self.payload = payload;
+        self.resolved_inputs = resolved_inputs;
+        Ok(())
+    }
+
+    /// Add an action of a type script to the message.
+    pub fn add_action(&mut self, action: Action) {
+        let actions = self.message.actions().as_builder().push(action).build();
+        self.message = self.message.clone().as_builder().actions(actions).build();
+    }
+
+    /// Add an action of a lock script, it is not a part of the message.
+    pub fn add_lock_action(&mut self, action: Action) {
+        self.lock_actions.push(action);
+    }
+
+    pub fn add_script_info(&mut self, script_info: ScriptInfo) {
+        self.script_infos.push(script_info);
+    }
+
+    pub fn set_change_output(&mut self, change_output: Option<u32>) {
+        self.change_output = change_output;
+    }
+
+    /// Put the message into the payload as the `SighashAll` witness at
+    /// `witness_idx`, usually the first witness of a lock group which signs
+    /// in the cobuild mode.
+    pub fn fill_message_witness(&mut self, witness_idx: usize) -> Result<(), BuildingPacketError> {
+        self.payload = fill_cobuild_message(&self.payload, witness_idx, self.message.clone())?;
+        Ok(())
+    }
+
+    /// The cobuild signing message of the payload.
+    pub fn signing_message(&self) -> Result<Bytes, BuildingPacketError> {
+        let message = find_cobuild_message(&self.payload)?.map(|(_, message)| message);
+        Ok(generate_cobuild_message(
+            message.as_ref(),
+            &self.payload,
+            &self.resolved_inputs,
+        )?)
+    }
+
+    pub fn to_molecule(&self) -> cobuild::BuildingPacket {
+        let resolved_inputs = ResolvedInputs::new_builder()
+            .outputs(
+                packed::CellOutputVec::new_builder()
+                    .set(
+                        self.resolved_inputs
+                            .iter()
+                            .map(|(output, _)| output.clone())
+                            .collect(),
+                    )
+                    .build(),
+            )
+            .outputs_data(
+                packed::BytesVec::new_builder()
+                    .set(
+                        self.resolved_inputs
+                            .iter()
+                            .map(|(_, data)| data.pack())
+                            .collect(),
+                    )
+                    .build(),
+            )
+            .build();
+        let change_output = Uint32Opt::new_builder()
+            .set(self.change_output.map(|idx| idx.pack()))
+            .build();
+        let packet = BuildingPacketV1::new_builder()
+            .message(self.message.clone())
+            .payload(self.payload.data())
+            .resolved_inputs(resolved_inputs)
+            .change_output(change_output)
+            .script_infos(
+                ScriptInfoVec::new_builder()
+                    .set(self.script_infos.clone())
+                    .build(),
+            )
+            .lock_actions(
+                ActionVec::new_builder()
+                    .set(self.lock_actions.clone())
+                    .build(),
+            )
+            .build();
+        cobuild::BuildingPacket::new_builder().set(packet).build()
+    }
+
+    pub fn from_molecule(data: &[u8]) -> Result<Self, BuildingPacketError> {
+        let packet = cobuild::BuildingPacket::from_slice(data)?;
+        let BuildingPacketUnion::BuildingPacketV1(packet) = packet.to_enum();
+        let resolved_inputs = packet.resolved_inputs();
+        if resolved_inputs.outputs().len() != resolved_inputs.outputs_data().len() {
+            return Err(BuildingPacketError::ResolvedInputsMismatch(
+                resolved_inputs.outputs_data().len(),
+                resolved_inputs.outputs().len(),
+            ));
+        }
+        let mut building_packet = BuildingPacket::new(
+            packet.payload().into_view(),
+            resolved_inputs
+                .outputs()
+                .into_iter()
+                .zip(resolved_inputs.outputs_data().into_iter())
+                .map(|(output, data)| (output, data.raw_data()))
+                .collect(),
+        )?;
+        building_packet.message = packet.message();
+        building_packet.change_output = packet.change_output().to_opt().map(|idx| idx.unpack());
+        building_packet.script_infos = packet.script_infos().into_iter().collect();
+        building_packet.lock_actions = packet.lock_actions().into_iter().collect();
+        Ok(building_packet)
+    }
+
+    fn find_input(
+        &self,
+        out_point: &OutPoint,
+    ) -> Result<&(CellOutput, Bytes), TransactionDependencyError> {
+        self.payload
+            .inputs()
+            .into_iter()
+            .position(|input| &input.previous_output() == out_point)
+            .map(|idx| &self.resolved_inputs[idx])
+            .ok_or_else(|| TransactionDependencyError::NotFound(format!("cell: {}", out_point)))
+    }
+}
+
+/// The packet provides its resolved inputs, so the cobuild unlockers can sign
+/// the payload with it.
+impl TransactionDependencyProvider for BuildingPacket {
+    fn get_transaction(
+        &self,
+        tx_hash: &Byte32,
+    ) -> Result<TransactionView, TransactionDependencyError> {
+        Err(TransactionDependencyError::NotFound(format!(
+            "transaction: {}",
+            tx_hash
+        )))
+    }
+    fn get_cell(&self, out_point: &OutPoint) -> Result<CellOutput, TransactionDependencyError> {
+        self.find_input(out_point).map(|(output, _)| output.clone())
+    }
+    fn get_cell_data(&self, out_point: &OutPoint) -> Result<Bytes, TransactionDependencyError> {
+        self.find_input(out_point).map(|(_, data)| data.clone())
+    }
+    fn get_header(&self, block_hash: &Byte32) -> Result<HeaderView, TransactionDependencyError> {
+        Err(TransactionDependencyError::NotFound(format!(
+            "header: {}",
+            block_hash
+        )))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ckb_types::{core::TransactionBuilder, h256};
+
+    #[test]
+    fn test_building_packet() {
+        let out_point = OutPoint::new(h256!("0x1").pack(), 0);
+        let output = CellOutput::new_builder().capacity(100u64.pack()).build();
+        let mut packet =
+            BuildingPacket::new(TransactionBuilder::default().build(), Vec::new()).unwrap();
+        packet.add_input(
+            CellInput::new(out_point.clone(), 0),
+            output.clone(),
+            Bytes::from(vec![1u8]),
+        );
+        packet.add_output(output.clone(), Bytes::new());
+        packet.add_action(
+            Action::new_builder()
+                .data(Bytes::from(vec![2u8]).pack())
+                .build(),
+        );
+        packet.set_change_output(Some(0));
+        let hash_without_message = packet.signing_message().unwrap();
+        packet.fill_message_witness(0).unwrap();
+        assert_ne!(packet.signing_message().unwrap(), hash_without_message);
+
+        assert_eq!(
+            packet.get_cell(&out_point).unwrap().as_slice(),
+            output.as_slice()
+        );
+        assert_eq!(
+            packet.get_cell_data(&out_point).unwrap(),
+            Bytes::from(vec![1u8])
+        );
+
+        let decoded = BuildingPacket::from_molecule(packet.to_molecule().as_slice()).unwrap();
+        assert_eq!(decoded.payload().hash(), packet.payload().hash());
+        assert_eq!(decoded.message().as_slice(), packet.message().as_slice());
+        assert_eq!(decoded.change_output(), Some(0));
+        assert_eq!(decoded.resolved_inputs().len(), 1);
+        assert_eq!(decoded.resolved_inputs()[0].0.as_slice(), output.as_slice());
+        assert_eq!(decoded.resolved_inputs()[0].1, Bytes::from(vec![1u8]));
+        assert_eq!(
+            decoded.signing_message().unwrap(),
+            packet.signing_message().unwrap()
+        );
+    }
+}
@@ -2,18 +2,28 @@ use ckb_types::packed::Script;
 
 use crate::{tx_builder::TxBuilderError, NetworkInfo};
 
-use self::{builder::FeeCalculator, handler::ScriptHandler};
+use self::{
+    builder::{CycleEstimator, FeeCalculator, FeeEstimator, FeeEstimatorError},
+    handler::ScriptHandler,
+};
 
 pub mod builder;
+pub mod cobuild;
 pub mod handler;
 pub mod input;
+pub mod otx;
+pub mod partial;
 pub mod signer;
+pub mod verifier;
 
 pub struct TransactionBuilderConfiguration {
     pub network: NetworkInfo,
     pub script_handlers: Vec<Box<dyn ScriptHandler>>,
     pub fee_rate: u64,
     pub small_change_action: SmallChangeAction,
+    /// When set, the fee is calculated by both the transaction size and the
+    /// estimated cycles in every balancing iteration.
+    pub cycle_estimator: Option<Box<dyn CycleEstimator>>,
 }
 
 /// Define what to do when change capacity is too small to create a new cell.
@@ -56,6 +66,7 @@ impl TransactionBuilderConfiguration {
             script_handlers,
             fee_rate: 1000,
             small_change_action: SmallChangeAction::FindMoreInput,
+            cycle_estimator: None,
         })
     }
     fn generate_system_handlers(
@@ -91,6 +102,31 @@ impl TransactionBuilderConfiguration {
     pub fn get_fee_rate(&self) -> u64 {
         self.fee_rate
     }
+    #[inline]
+    pub fn set_fee_rate(&mut self, fee_rate: u64) {
+        self.fee_rate = fee_rate;
+    }
+
+    /// Set the fee rate by an estimator, the estimated fee rate is clamped into `[floor, cap]`.
+    pub fn set_fee_rate_with_estimator(
+        &mut self,
+        estimator: &dyn FeeEstimator,
+        target_blocks: u64,
+        floor: u64,
+        cap: u64,
+    ) -> Result<u64, FeeEstimatorError> {
+        let calculator = FeeCalculator::from_estimator(estimator, target_blocks, floor, cap)?;
+        self.fee_rate = calculator.fee_rate();
+        Ok(self.fee_rate)
+    }
+
+    pub fn set_cycle_estimator(&mut self, cycle_estimator: Box<dyn CycleEstimator>) {
+        self.cycle_estimator = Some(cycle_estimator);
+    }
+    #[inline]
+    pub fn get_cycle_estimator(&self) -> Option<&dyn CycleEstimator> {
+        self.cycle_estimator.as_deref()
+    }
 
     pub fn fee_calculator(&self) -> FeeCalculator {
         FeeCalculator::new(self.fee_rate)
@@ -0,0 +1,763 @@
+//! Open transactions (OTX).
+//!
+//! Every user builds an [`OpenTransaction`] fragment with its own inputs and
+//! outputs, and seals it in the cobuild `Otx` layout. A relayer validates the
+//! fragments and merges them into one transaction with [`OtxMerger`], paying
+//! the fee by its own input, which is added last.
+//!
+//! The signing message of a fragment is `blake2b_256` with personalization
+//! `ckb-tcob-otxhash` of:
+//!   * `message`
+//!   * `inputs_count (u32 LE) | (input | output | data_len (u32 LE) | data)*`
+//!   * `outputs_count (u32 LE) | (output | data_len (u32 LE) | data)*`
+//!   * `cell_deps_count (u32 LE) | cell_dep*`
+//!   * `header_deps_count (u32 LE) | header_dep*`
+//!
+//! It only depends on the fragment itself, so the fragment stays valid
+//! wherever the relayer puts it. A cell dep or header dep can be committed by
+//! one fragment only, the shared ones are better left to the relayer, which
+//! adds them after the fragments and deduplicates them.
+//!
+//! Only the locks verifying the cobuild `Otx` layout can be sealed, e.g. the
+//! Omni lock in cobuild mode. They find their seals in the `Otx` witnesses,
+//! the system secp256k1 sighash and multisig locks can not and are rejected.
+//!
+//! The merged transaction layout:
+//!   * inputs: the inputs of every fragment, then the relayer's fee input
+//!   * outputs: the outputs of every fragment, then the relayer's change output
+//!   * cell deps and header deps: the ones of every fragment, then the
+//!     relayer's
+//!   * witnesses: empty witnesses of the fragment inputs, the fee input
+//!     witness, `OtxStart`, then one `Otx` witness for every fragment
+
+use std::convert::TryFrom;
+
+use anyhow::anyhow;
+use ckb_hash::{Blake2b, Blake2bBuilder};
+use ckb_types::{
+    bytes::Bytes,
+    core::{Capacity, ScriptHashType, TransactionView},
+    molecule::error::VerificationError,
+    packed::{self, Byte32, CellDep, CellInput, CellOutput, OutPoint, Script},
+    prelude::*,
+};
+use thiserror::Error;
+
+use super::{builder::FeeCalculator, cobuild::BuildingPacket};
+use crate::{
+    constants::{MULTISIG_TYPE_HASH, SIGHASH_TYPE_HASH},
+    traits::{TransactionDependencyError, TransactionDependencyProvider},
+    types::cobuild::{
+        Message, Otx, OtxStart, SealPair, SealPairVec, WitnessLayout, WitnessLayoutReader,
+        WitnessLayoutUnion,
+    },
+    unlock::{
+        cobuild::resolve_input_cells, OmniLockScriptSigner, ScriptSignError, ScriptSigner,
+        ScriptUnlocker, UnlockError,
+    },
+    ScriptGroup, ScriptGroupType,
+};
+
+/// The personalization of the open transaction signing message
+pub const OTX_PERSONALIZATION: &[u8; 16] = b"ckb-tcob-otxhash";
+
+#[derive(Error, Debug)]
+pub enum OtxError {
+    #[error("invalid molecule data: `{0}`")]
+    Molecule(#[from] VerificationError),
+
+    #[error("transaction dependency error: `{0}`")]
+    TxDep(#[from] TransactionDependencyError),
+
+    #[error("sign script error: `{0}`")]
+    ScriptSign(#[from] ScriptSignError),
+
+    #[error("unlock error: `{0}`")]
+    Unlock(#[from] UnlockError),
+
+    #[error("resolved inputs count `{0}` not match transaction inputs count `{1}`")]
+    ResolvedInputsMismatch(usize, usize),
+
+    #[error("invalid open transaction at index `{0}`: {1}")]
+    InvalidFragment(usize, String),
+
+    #[error("duplicated input: `{0}`")]
+    DuplicatedInput(OutPoint),
+
+    #[error("capacity overflow")]
+    CapacityOverflow,
+
+    #[error("insufficient capacity, change: `{0}`, occupied: `{1}`")]
+    InsufficientCapacity(u64, u64),
+
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// An open transaction fragment.
+#[derive(Clone, Debug)]
+pub struct OpenTransaction {
+    tx: TransactionView,
+    resolved_inputs: Vec<(CellOutput, Bytes)>,
+    message: Message,
+    seals: Vec<(Byte32, Bytes)>,
+}
+
+impl OpenTransaction {
+    /// Only the inputs, outputs, cell deps and header deps of `tx` are used.
+    pub fn new(
+        tx: TransactionView,
+        resolved_inputs: Vec<(CellOutput, Bytes)>,
+    ) -> Result<Self, OtxError> {
+        if resolved_inputs.len() != tx.inputs().len() {
+            return Err(OtxError::ResolvedInputsMismatch(
+                resolved_inputs.len(),
+                tx.inputs().len(),
+            ));
+        }
+        Ok(OpenTransaction {
+            tx,
+            resolved_inputs,
+            message: Message::default(),
+            seals: Vec::new(),
+        })
+    }
+
+    pub fn from_transaction(
+        tx: TransactionView,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<Self, OtxError> {
+        let resolved_inputs = resolve_input_cells(&tx, tx_dep_provider)?;
+        OpenTransaction::new(tx, resolved_inputs)
+    }
+
+    pub fn tx(&self) -> &TransactionView {
+        &self.tx
+    }
+    pub fn resolved_inputs(&self) -> &[(CellOutput, Bytes)] {
+        &self.resolved_inputs
+    }
+    pub fn message(&self) -> &Message {
+        &self.message
+    }
+    pub fn seals(&self) -> &[(Byte32, Bytes)] {
+        &self.seals
+    }
+
+    /// Set the message, the seals signed before are invalid after it.
+    pub fn set_message(&mut self, message: Message) {
+        self.message = message;
+    }
+
+    pub fn seal(&self, script_hash: &Byte32) -> Option<&Bytes> {
+        self.seals
+            .iter()
+            .find(|(hash, _)| hash == script_hash)
+            .map(|(_, seal)| seal)
+    }
+
+    pub fn set_seal(&mut self, script_hash: Byte32, seal: Bytes) {
+        match self.seals.iter_mut().find(|(hash, _)| hash == &script_hash) {
+            Some((_, old_seal)) => *old_seal = seal,
+            None => self.seals.push((script_hash, seal)),
+        }
+    }
+
+    /// The lock script groups of the fragment inputs.
+    pub fn lock_groups(&self) -> Vec<ScriptGroup> {
+        let mut groups: Vec<ScriptGroup> = Vec::new();
+        for (idx, (output, _)) in self.resolved_inputs.iter().enumerate() {
+            let lock = output.lock();
+            match groups.iter_mut().find(|group| group.script == lock) {
+                Some(group) => group.input_indices.push(idx),
+                None => {
+                    let mut group = ScriptGroup::new(&lock, ScriptGroupType::Lock);
+                    group.input_indices.push(idx);
+                    groups.push(group);
+                }
+            }
+        }
+        groups
+    }
+
+    pub fn signing_message(&self) -> Bytes {
+        let mut blake2b = new_otx_blake2b();
+        blake2b.update(self.message.as_slice());
+        blake2b.update(&(self.tx.inputs().len() as u32).to_le_bytes());
+        for (input, (output, data)) in self.tx.inputs().into_iter().zip(&self.resolved_inputs) {
+            blake2b.update(input.as_slice());
+            blake2b.update(output.as_slice());
+            blake2b.update(&(data.len() as u32).to_le_bytes());
+            blake2b.update(data);
+        }
+        blake2b.update(&(self.tx.outputs().len() as u32).to_le_bytes());
+        for (output, data) in self.tx.outputs_with_data_iter() {
+            blake2b.update(output.as_slice());
+            blake2b.update(&(data.len() as u32).to_le_bytes());
+            blake2b.update(&data);
+        }
+        blake2b.update(&(self.tx.cell_deps().len() as u32).to_le_bytes());
+        for cell_dep in self.tx.cell_deps() {
+            blake2b.update(cell_dep.as_slice());
+        }
+        blake2b.update(&(self.tx.header_deps().len() as u32).to_le_bytes());
+        for header_dep in self.tx.header_deps() {
+            blake2b.update(header_dep.as_slice());
+        }
+        let mut hash = vec![0u8; 32];
+        blake2b.finalize(&mut hash);
+        Bytes::from(hash)
+    }
+
+    /// Seal the Omnilock groups matched by the signer, the Omnilock must
+    /// support cobuild.
+    pub fn sign_omnilock(&mut self, signer: &OmniLockScriptSigner) -> Result<(), OtxError> {
+        if !signer.config().is_cobuild() {
+            return Err(OtxError::Other(anyhow!(
+                "the Omni lock is not in cobuild mode"
+            )));
+        }
+        let message = self.signing_message();
+        for group in self.lock_groups() {
+            if !signer.match_args(group.script.args().raw_data().as_ref()) {
+                continue;
+            }
+            let script_hash = group.script.calc_script_hash();
+            let seal = self.seal(&script_hash).cloned();
+            if let Some(seal) = signer.sign_cobuild_seal(message.as_ref(), seal, &self.tx)? {
+                self.set_seal(script_hash, seal);
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the fragment is sealed by every input lock, and pays for its
+    /// own outputs.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.resolved_inputs.len() != self.tx.inputs().len() {
+            return Err(format!(
+                "resolved inputs count {} not match inputs count {}",
+                self.resolved_inputs.len(),
+                self.tx.inputs().len()
+            ));
+        }
+        if self.tx.outputs().len() != self.tx.outputs_data().len() {
+            return Err("outputs count not match outputs data count".to_string());
+        }
+        if self.tx.inputs().is_empty() {
+            return Err("no inputs".to_string());
+        }
+        for group in self.lock_groups() {
+            if is_legacy_lock(&group.script) {
+                return Err(format!("lock {} can not verify the Otx seal", group.script));
+            }
+            if self.seal(&group.script.calc_script_hash()).is_none() {
+                return Err(format!("lock {} is not sealed", group.script));
+            }
+        }
+        let inputs_capacity = self.inputs_capacity()?;
+        let outputs_capacity = self
+            .tx
+            .outputs_capacity()
+            .map_err(|err| err.to_string())?
+            .as_u64();
+        if inputs_capacity < outputs_capacity {
+            return Err(format!(
+                "inputs capacity {} less than outputs capacity {}",
+                inputs_capacity, outputs_capacity
+            ));
+        }
+        Ok(())
+    }
+
+    fn inputs_capacity(&self) -> Result<u64, String> {
+        self.resolved_inputs
+            .iter()
+            .try_fold(0u64, |total, (output, _)| {
+                let capacity: u64 = output.capacity().unpack();
+                total.checked_add(capacity)
+            })
+            .ok_or_else(|| "inputs capacity overflow".to_string())
+    }
+
+    pub fn to_otx_witness(&self) -> Otx {
+        let seals = self
+            .seals
+            .iter()
+            .map(|(script_hash, seal)| {
+                SealPair::new_builder()
+                    .script_hash(script_hash.clone())
+                    .seal(seal.pack())
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        Otx::new_builder()
+            .input_cells((self.tx.inputs().len() as u32).pack())
+            .output_cells((self.tx.outputs().len() as u32).pack())
+            .cell_deps((self.tx.cell_deps().len() as u32).pack())
+            .header_deps((self.tx.header_deps().len() as u32).pack())
+            .message(self.message.clone())
+            .seals(SealPairVec::new_builder().set(seals).build())
+            .build()
+    }
+
+    /// Encode the fragment as a cobuild building packet, the `Otx` witness is
+    /// the only witness of the payload.
+    pub fn to_building_packet(&self) -> Result<BuildingPacket, OtxError> {
+        let witness = WitnessLayout::new_builder()
+            .set(self.to_otx_witness())
+            .build();
+        let payload = self
+            .tx
+            .as_advanced_builder()
+            .set_witnesses(vec![witness.as_bytes().pack()])
+            .build();
+        BuildingPacket::new(payload, self.resolved_inputs.clone())
+            .map_err(|err| OtxError::Other(err.into()))
+    }
+
+    pub fn from_building_packet(packet: &BuildingPacket) -> Result<Self, OtxError> {
+        let payload = packet.payload();
+        let witness = payload
+            .witnesses()
+            .get(0)
+            .map(|witness| witness.raw_data())
+            .unwrap_or_default();
+        WitnessLayoutReader::verify(&witness, false)?;
+        let otx = match WitnessLayout::new_unchecked(witness).to_enum() {
+            WitnessLayoutUnion::Otx(otx) => otx,
+            _ => {
+                return Err(OtxError::InvalidFragment(
+                    0,
+                    "the first witness is not Otx".to_string(),
+                ))
+            }
+        };
+        let tx = payload
+            .as_advanced_builder()
+            .set_witnesses(Vec::new())
+            .build();
+        let mut fragment = OpenTransaction::new(tx, packet.resolved_inputs().to_vec())?;
+        fragment.message = otx.message();
+        fragment.seals = otx
+            .seals()
+            .into_iter()
+            .map(|pair| (pair.script_hash(), pair.seal().raw_data()))
+            .collect();
+        Ok(fragment)
+    }
+}
+
+/// The system locks only verify `WitnessArgs`.
+fn is_legacy_lock(script: &Script) -> bool {
+    script.hash_type() == ScriptHashType::Type.into()
+        && (script.code_hash() == SIGHASH_TYPE_HASH.pack()
+            || script.code_hash() == MULTISIG_TYPE_HASH.pack())
+}
+
+fn new_otx_blake2b() -> Blake2b {
+    Blake2bBuilder::new(32)
+        .personal(OTX_PERSONALIZATION)
+        .build()
+}
+
+/// The relayer merging the open transactions.
+pub struct OtxMerger {
+    fee_input: CellInput,
+    change_lock: Script,
+    fee_rate: u64,
+    cell_deps: Vec<CellDep>,
+    header_deps: Vec<Byte32>,
+}
+
+impl OtxMerger {
+    /// The relayer pays the fee by `fee_input`, and takes the rest capacity by
+    /// a change output of `change_lock`.
+    pub fn new(fee_input: CellInput, change_lock: Script, fee_rate: u64) -> OtxMerger {
+        OtxMerger {
+            fee_input,
+            change_lock,
+            fee_rate,
+            cell_deps: Vec::new(),
+            header_deps: Vec::new(),
+        }
+    }
+
+    /// The cell deps added after the ones of the fragments, e.g. the lock
+    /// code of the fee input, the ones already in the fragments are skipped.
+    pub fn set_cell_deps(&mut self, cell_deps: Vec<CellDep>) {
+        self.cell_deps = cell_deps;
+    }
+
+    /// The header deps added after the ones of the fragments, the ones
+    /// already in the fragments are skipped.
+    pub fn set_header_deps(&mut self, header_deps: Vec<Byte32>) {
+        self.header_deps = header_deps;
+    }
+
+    /// Validate and merge the fragments, then unlock the fee input by
+    /// `unlocker`. The `tx_dep_provider` must provide the fee input cell.
+    pub fn merge(
+        &self,
+        otxs: &[OpenTransaction],
+        unlocker: &dyn ScriptUnlocker,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, OtxError> {
+        let mut out_points: Vec<OutPoint> = Vec::new();
+        let mut cell_deps: Vec<CellDep> = Vec::new();
+        let mut header_deps: Vec<Byte32> = Vec::new();
+        let mut inputs_capacity = 0u64;
+        let mut builder = TransactionView::new_advanced_builder();
+        for (idx, otx) in otxs.iter().enumerate() {
+            otx.validate()
+                .map_err(|err| OtxError::InvalidFragment(idx, err))?;
+            inputs_capacity = otx
+                .inputs_capacity()
+                .ok()
+                .and_then(|capacity| inputs_capacity.checked_add(capacity))
+                .ok_or(OtxError::CapacityOverflow)?;
+            for input in otx.tx.inputs() {
+                let out_point = input.previous_output();
+                if out_points.contains(&out_point) {
+                    return Err(OtxError::DuplicatedInput(out_point));
+                }
+                out_points.push(out_point);
+            }
+            // The deps committed by a fragment must stay in its range
+            for cell_dep in otx.tx.cell_deps() {
+                if cell_deps
+                    .iter()
+                    .any(|dep| dep.as_slice() == cell_dep.as_slice())
+                {
+                    return Err(OtxError::InvalidFragment(
+                        idx,
+                        format!("cell dep {} is committed by another fragment", cell_dep),
+                    ));
+                }
+                cell_deps.push(cell_dep);
+            }
+            for header_dep in otx.tx.header_deps() {
+                if header_deps.contains(&header_dep) {
+                    return Err(OtxError::InvalidFragment(
+                        idx,
+                        format!("header dep {} is committed by another fragment", header_dep),
+                    ));
+                }
+                header_deps.push(header_dep);
+            }
+            builder = builder
+                .inputs(otx.tx.inputs())
+                .outputs(otx.tx.outputs())
+                .outputs_data(otx.tx.outputs_data());
+        }
+        for cell_dep in &self.cell_deps {
+            if !cell_deps
+                .iter()
+                .any(|dep| dep.as_slice() == cell_dep.as_slice())
+            {
+                cell_deps.push(cell_dep.clone());
+            }
+        }
+        for header_dep in &self.header_deps {
+            if !header_deps.contains(header_dep) {
+                header_deps.push(header_dep.clone());
+            }
+        }
+        let fee_out_point = self.fee_input.previous_output();
+        if out_points.contains(&fee_out_point) {
+            return Err(OtxError::DuplicatedInput(fee_out_point));
+        }
+        let fee_cell = tx_dep_provider.get_cell(&fee_out_point)?;
+        let fee_cell_capacity: u64 = fee_cell.capacity().unpack();
+        inputs_capacity = inputs_capacity
+            .checked_add(fee_cell_capacity)
+            .ok_or(OtxError::CapacityOverflow)?;
+
+        let fee_input_idx = out_points.len();
+        let mut witnesses = vec![packed::Bytes::default(); fee_input_idx + 1];
+        let otx_start = OtxStart::new_builder()
+            .start_input_cell(0u32.pack())
+            .start_output_cell(0u32.pack())
+            .start_cell_deps(0u32.pack())
+            .start_header_deps(0u32.pack())
+            .build();
+        witnesses.push(
+            WitnessLayout::new_builder()
+                .set(otx_start)
+                .build()
+                .as_bytes()
+                .pack(),
+        );
+        for otx in otxs {
+            witnesses.push(
+                WitnessLayout::new_builder()
+                    .set(otx.to_otx_witness())
+                    .build()
+                    .as_bytes()
+                    .pack(),
+            );
+        }
+        let change_output = CellOutput::new_builder()
+            .lock(self.change_lock.clone())
+            .build();
+        let tx = builder
+            .cell_deps(cell_deps)
+            .header_deps(header_deps)
+            .input(self.fee_input.clone())
+            .output(change_output.clone())
+            .output_data(Bytes::new().pack())
+            .set_witnesses(witnesses)
+            .build();
+
+        let mut fee_group = ScriptGroup::new(&fee_cell.lock(), ScriptGroupType::Lock);
+        fee_group.input_indices.push(fee_input_idx);
+        let tx = unlocker.fill_placeholder_witness(&tx, &fee_group, tx_dep_provider)?;
+
+        let tx_size = tx.data().as_reader().serialized_size_in_block() as u64;
+        let fee = FeeCalculator::new(self.fee_rate).fee(tx_size);
+        let outputs_capacity = tx
+            .outputs_capacity()
+            .map_err(|err| OtxError::Other(anyhow::anyhow!(err.to_string())))?
+            .as_u64();
+        let occupied_capacity = change_output
+            .occupied_capacity(Capacity::zero())
+            .expect("change occupied capacity")
+            .as_u64();
+        let change_capacity = inputs_capacity
+            .checked_sub(
+                outputs_capacity
+                    .checked_add(fee)
+                    .ok_or(OtxError::CapacityOverflow)?,
+            )
+            .ok_or(OtxError::InsufficientCapacity(0, occupied_capacity))?;
+        if change_capacity < occupied_capacity {
+            return Err(OtxError::InsufficientCapacity(
+                change_capacity,
+                occupied_capacity,
+            ));
+        }
+        let change_idx = tx.outputs().len() - 1;
+        let mut outputs: Vec<CellOutput> = tx.outputs().into_iter().collect();
+        outputs[change_idx] = change_output
+            .as_builder()
+            .capacity(change_capacity.pack())
+            .build();
+        let tx = tx.as_advanced_builder().set_outputs(outputs).build();
+        Ok(unlocker.unlock(&tx, &fee_group, tx_dep_provider)?)
+    }
+}
+
+impl TryFrom<&BuildingPacket> for OpenTransaction {
+    type Error = OtxError;
+    fn try_from(packet: &BuildingPacket) -> Result<Self, Self::Error> {
+        OpenTransaction::from_building_packet(packet)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ckb_types::{core::TransactionBuilder, h256, H160, H256};
+
+    use crate::{
+        traits::{SecpCkbRawKeySigner, Signer},
+        types::omni_lock::OmniLockWitnessLock,
+        unlock::{
+            cobuild::parse_witness_layout, OmniLockConfig, OmniUnlockMode, SecpSighashUnlocker,
+        },
+        util::blake160,
+        SECP256K1,
+    };
+
+    fn sighash_lock(key: &secp256k1::SecretKey) -> Script {
+        let pubkey = secp256k1::PublicKey::from_secret_key(&SECP256K1, key);
+        Script::new_builder()
+            .code_hash(SIGHASH_TYPE_HASH.pack())
+            .hash_type(ScriptHashType::Type.into())
+            .args(Bytes::copy_from_slice(blake160(&pubkey.serialize()).as_bytes()).pack())
+            .build()
+    }
+
+    fn omnilock(key: &secp256k1::SecretKey) -> (Script, OmniLockConfig, H160) {
+        let pubkey = secp256k1::PublicKey::from_secret_key(&SECP256K1, key);
+        let id = blake160(&pubkey.serialize());
+        let mut config = OmniLockConfig::new_pubkey_hash(id.clone());
+        config.set_cobuild(true);
+        let script = Script::new_builder()
+            .code_hash(h256!("0x10").pack())
+            .hash_type(ScriptHashType::Data1.into())
+            .args(config.build_args().pack())
+            .build();
+        (script, config, id)
+    }
+
+    fn omnilock_signer(key: secp256k1::SecretKey, config: OmniLockConfig) -> OmniLockScriptSigner {
+        OmniLockScriptSigner::new(
+            Box::new(SecpCkbRawKeySigner::new_with_secret_keys(vec![key])),
+            config,
+            OmniUnlockMode::Normal,
+        )
+    }
+
+    fn fragment(lock: Script, tx_hash: H256, cell_deps: Vec<CellDep>) -> OpenTransaction {
+        let input_cell = CellOutput::new_builder()
+            .capacity(200_00000000u64.pack())
+            .lock(lock.clone())
+            .build();
+        let tx = TransactionBuilder::default()
+            .input(CellInput::new(OutPoint::new(tx_hash.pack(), 0), 0))
+            .output(
+                CellOutput::new_builder()
+                    .capacity(150_00000000u64.pack())
+                    .lock(lock)
+                    .build(),
+            )
+            .output_data(Bytes::new().pack())
+            .cell_deps(cell_deps)
+            .build();
+        OpenTransaction::new(tx, vec![(input_cell, Bytes::new())]).unwrap()
+    }
+
+    fn cell_dep(byte: u8) -> CellDep {
+        CellDep::new_builder()
+            .out_point(OutPoint::new(H256([byte; 32]).pack(), 0))
+            .build()
+    }
+
+    #[test]
+    fn test_otx_merge() {
+        let key1 = secp256k1::SecretKey::from_slice(&[1u8; 32]).unwrap();
+        let key2 = secp256k1::SecretKey::from_slice(&[2u8; 32]).unwrap();
+        let (lock1, config1, id1) = omnilock(&key1);
+        let (lock2, config2, _) = omnilock(&key2);
+        let mut otx1 = fragment(lock1.clone(), h256!("0x1"), vec![cell_dep(1)]);
+        let mut otx2 = fragment(lock2, h256!("0x2"), Vec::new());
+        assert!(otx1.validate().is_err());
+
+        // the cell deps are committed
+        let message1 = otx1.signing_message();
+        assert_ne!(
+            fragment(lock1.clone(), h256!("0x1"), Vec::new()).signing_message(),
+            message1
+        );
+
+        // the Omni lock must be in cobuild mode
+        let mut legacy_config = config1.clone();
+        legacy_config.set_cobuild(false);
+        assert!(otx1
+            .sign_omnilock(&omnilock_signer(key1, legacy_config))
+            .is_err());
+        otx1.sign_omnilock(&omnilock_signer(key1, config1)).unwrap();
+        otx2.sign_omnilock(&omnilock_signer(key2, config2)).unwrap();
+        otx1.validate().unwrap();
+        otx2.validate().unwrap();
+
+        // the seal recovers to the lock args
+        let seal = otx1.seal(&lock1.calc_script_hash()).unwrap();
+        let seal = OmniLockWitnessLock::from_slice(seal)
+            .unwrap()
+            .signature()
+            .to_opt()
+            .unwrap()
+            .raw_data();
+        let recid = secp256k1::ecdsa::RecoveryId::from_i32(seal[64] as i32).unwrap();
+        let signature =
+            secp256k1::ecdsa::RecoverableSignature::from_compact(&seal[0..64], recid).unwrap();
+        let msg = secp256k1::Message::from_slice(&message1).unwrap();
+        let pubkey = SECP256K1.recover_ecdsa(&msg, &signature).unwrap();
+        assert_eq!(blake160(&pubkey.serialize()), id1);
+
+        // the system sighash lock can not verify the Otx seal
+        let legacy_key = secp256k1::SecretKey::from_slice(&[4u8; 32]).unwrap();
+        let legacy_lock = sighash_lock(&legacy_key);
+        let mut legacy_otx = fragment(legacy_lock.clone(), h256!("0x4"), Vec::new());
+        legacy_otx.set_seal(legacy_lock.calc_script_hash(), Bytes::from(vec![0u8; 65]));
+        assert!(legacy_otx
+            .validate()
+            .unwrap_err()
+            .contains("can not verify the Otx seal"));
+
+        // encode and decode
+        let packet = otx2.to_building_packet().unwrap();
+        let decoded = OpenTransaction::from_building_packet(&packet).unwrap();
+        assert_eq!(decoded.signing_message(), otx2.signing_message());
+        assert_eq!(decoded.seals(), otx2.seals());
+
+        let relayer_key = secp256k1::SecretKey::from_slice(&[3u8; 32]).unwrap();
+        let relayer_lock = sighash_lock(&relayer_key);
+        let fee_input = CellInput::new(OutPoint::new(h256!("0x3").pack(), 0), 0);
+        let fee_cell = CellOutput::new_builder()
+            .capacity(100_00000000u64.pack())
+            .lock(relayer_lock.clone())
+            .build();
+        let fee_provider = BuildingPacket::new(
+            TransactionBuilder::default()
+                .input(fee_input.clone())
+                .build(),
+            vec![(fee_cell, Bytes::new())],
+        )
+        .unwrap();
+        let unlocker =
+            SecpSighashUnlocker::from(Box::new(SecpCkbRawKeySigner::new_with_secret_keys(vec![
+                relayer_key,
+            ])) as Box<dyn Signer>);
+        let mut merger = OtxMerger::new(fee_input.clone(), relayer_lock, 1000);
+        merger.set_cell_deps(vec![cell_dep(1), cell_dep(2)]);
+        assert!(matches!(
+            merger.merge(&[otx1.clone(), otx1.clone()], &unlocker, &fee_provider),
+            Err(OtxError::DuplicatedInput(_))
+        ));
+        let mut otx3 = fragment(lock1.clone(), h256!("0x5"), vec![cell_dep(1)]);
+        otx3.set_seal(lock1.calc_script_hash(), Bytes::new());
+        assert!(matches!(
+            merger.merge(&[otx1.clone(), otx3], &unlocker, &fee_provider),
+            Err(OtxError::InvalidFragment(1, _))
+        ));
+        let tx = merger
+            .merge(&[otx1.clone(), otx2], &unlocker, &fee_provider)
+            .unwrap();
+
+        assert_eq!(tx.inputs().len(), 3);
+        assert_eq!(tx.inputs().get(2).unwrap().as_slice(), fee_input.as_slice());
+        assert_eq!(tx.outputs().len(), 3);
+        // the fragment's cell dep first, then the relayer's deduplicated
+        assert_eq!(tx.cell_deps().len(), 2);
+        assert_eq!(
+            tx.cell_deps().get(0).unwrap().as_slice(),
+            cell_dep(1).as_slice()
+        );
+        assert_eq!(
+            tx.cell_deps().get(1).unwrap().as_slice(),
+            cell_dep(2).as_slice()
+        );
+        assert_eq!(tx.witnesses().len(), 6);
+        assert!(!tx.witnesses().get(2).unwrap().raw_data().is_empty());
+        assert!(matches!(
+            parse_witness_layout(&tx.witnesses().get(3).unwrap().raw_data()),
+            Some(WitnessLayoutUnion::OtxStart(_))
+        ));
+        match parse_witness_layout(&tx.witnesses().get(4).unwrap().raw_data()) {
+            Some(WitnessLayoutUnion::Otx(otx)) => {
+                let cell_deps: u32 = otx.cell_deps().unpack();
+                assert_eq!(cell_deps, 1);
+            }
+            _ => panic!("the Otx witness of the first fragment"),
+        }
+        let change: u64 = tx.outputs().get(2).unwrap().capacity().unpack();
+        let fee = 500_00000000u64 - 300_00000000 - change;
+        assert!(fee > 0 && fee < 1_00000000);
+
+        // the fragment signing message is independent of its position
+        let merged_otx1 = OpenTransaction::new(
+            TransactionBuilder::default()
+                .input(tx.inputs().get(0).unwrap())
+                .output(tx.outputs().get(0).unwrap())
+                .output_data(tx.outputs_data().get(0).unwrap())
+                .cell_dep(tx.cell_deps().get(0).unwrap())
+                .build(),
+            otx1.resolved_inputs().to_vec(),
+        )
+        .unwrap();
+        assert_eq!(merged_otx1.signing_message(), message1);
+    }
+}
@@ -0,0 +1,716 @@
+//! A serializable envelope of a partially signed transaction.
+//!
+//! The envelope carries everything an offline (air-gapped) signer or another
+//! multisig co-signer needs to check and sign the transaction: the transaction
+//! itself, the script groups, the resolved input cells and cell deps, the
+//! header deps and the signing contexts metadata. It can be encoded as
+//! versioned JSON or molecule.
+
+use std::collections::HashSet;
+
+use anyhow::anyhow;
+use ckb_jsonrpc_types as json_types;
+use ckb_types::{
+    bytes::Bytes,
+    core::{DepType, HeaderView, ScriptHashType, TransactionView},
+    molecule::error::VerificationError,
+    packed::{self, Byte32, CellOutput, OutPoint, OutPointVec, Script, WitnessArgs},
+    prelude::*,
+};
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    constants::MULTISIG_TYPE_HASH,
+    traits::{TransactionDependencyError, TransactionDependencyProvider},
+    types::partial_tx,
+    unlock::UnlockError,
+    ScriptGroup, ScriptGroupType, TransactionWithScriptGroups,
+};
+
+/// Current version of the partially signed transaction envelope.
+pub const PARTIAL_TX_VERSION: u32 = 1;
+
+/// Signer kind of sighash script groups in [`SignContextInfo`].
+pub const SIGN_CONTEXT_KIND_SIGHASH: &str = "sighash";
+/// Signer kind of multisig script groups in [`SignContextInfo`], the data is the
+/// multisig config witness data.
+pub const SIGN_CONTEXT_KIND_MULTISIG: &str = "multisig";
+
+#[derive(Error, Debug)]
+pub enum PartialTxError {
+    #[error("unsupported partial transaction version: `{0}`")]
+    UnsupportedVersion(u32),
+
+    #[error("invalid json: `{0}`")]
+    Json(#[from] serde_json::Error),
+
+    #[error("invalid molecule data: `{0}`")]
+    Molecule(#[from] VerificationError),
+
+    #[error("transaction dependency error: `{0}`")]
+    TxDep(#[from] TransactionDependencyError),
+
+    #[error("transaction mismatch, expected: `{0}`, got: `{1}`")]
+    TxMismatch(Byte32, Byte32),
+
+    #[error("script groups mismatch")]
+    ScriptGroupsMismatch,
+
+    #[error("unlock error: `{0}`")]
+    Unlock(#[from] UnlockError),
+
+    #[error("witness conflict at index `{0}`: {1}")]
+    WitnessConflict(usize, String),
+
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// A resolved input cell or cell dep.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ResolvedCell {
+    pub out_point: OutPoint,
+    pub output: CellOutput,
+    pub data: Bytes,
+}
+
+/// The metadata of how a script group is (or will be) signed.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct SignContextInfo {
+    /// Index of the script group in the envelope
+    pub script_group_index: usize,
+    /// The signer kind, e.g. [`SIGN_CONTEXT_KIND_SIGHASH`]
+    pub kind: String,
+    /// Signer specific data
+    pub data: Bytes,
+}
+
+impl SignContextInfo {
+    pub fn new(script_group_index: usize, kind: &str, data: Bytes) -> Self {
+        Self {
+            script_group_index,
+            kind: kind.to_string(),
+            data,
+        }
+    }
+}
+
+/// A partially signed transaction with all the information needed to sign it.
+#[derive(Clone, Debug)]
+pub struct PartiallySignedTransaction {
+    version: u32,
+    tx: TransactionView,
+    script_groups: Vec<ScriptGroup>,
+    inputs: Vec<ResolvedCell>,
+    cell_deps: Vec<ResolvedCell>,
+    header_deps: Vec<HeaderView>,
+    contexts: Vec<SignContextInfo>,
+}
+
+impl PartiallySignedTransaction {
+    pub fn new(
+        tx: TransactionView,
+        script_groups: Vec<ScriptGroup>,
+        inputs: Vec<ResolvedCell>,
+        cell_deps: Vec<ResolvedCell>,
+        header_deps: Vec<HeaderView>,
+    ) -> Self {
+        Self {
+            version: PARTIAL_TX_VERSION,
+            tx,
+            script_groups,
+            inputs,
+            cell_deps,
+            header_deps,
+            contexts: Vec::new(),
+        }
+    }
+
+    /// Build the envelope by resolving the inputs, cell deps (include the
+    /// cells referenced by dep groups) and header deps from `tx_dep_provider`.
+    pub fn from_transaction(
+        transaction: &TransactionWithScriptGroups,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<Self, PartialTxError> {
+        let tx = transaction.get_tx_view();
+        let resolve_cell = |out_point: OutPoint| -> Result<ResolvedCell, PartialTxError> {
+            let output = tx_dep_provider.get_cell(&out_point)?;
+            let data = tx_dep_provider.get_cell_data(&out_point)?;
+            Ok(ResolvedCell {
+                out_point,
+                output,
+                data,
+            })
+        };
+
+        let inputs = tx
+            .input_pts_iter()
+            .map(&resolve_cell)
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut cell_deps = Vec::new();
+        let mut resolved = HashSet::new();
+        for cell_dep in tx.cell_deps_iter() {
+            let out_point = cell_dep.out_point();
+            if !resolved.insert(out_point.clone()) {
+                continue;
+            }
+            let cell = resolve_cell(out_point)?;
+            if cell_dep.dep_type() == DepType::DepGroup.into() {
+                let sub_out_points = OutPointVec::from_slice(&cell.data)?;
+                cell_deps.push(cell);
+                for sub_out_point in sub_out_points.into_iter() {
+                    if resolved.insert(sub_out_point.clone()) {
+                        cell_deps.push(resolve_cell(sub_out_point)?);
+                    }
+                }
+            } else {
+                cell_deps.push(cell);
+            }
+        }
+        let header_deps = tx
+            .header_deps_iter()
+            .map(|block_hash| tx_dep_provider.get_header(&block_hash))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::new(
+            tx.clone(),
+            transaction.get_script_groups().to_vec(),
+            inputs,
+            cell_deps,
+            header_deps,
+        ))
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+    pub fn tx_view(&self) -> &TransactionView {
+        &self.tx
+    }
+    pub fn set_tx_view(&mut self, tx: TransactionView) {
+        self.tx = tx;
+    }
+    pub fn script_groups(&self) -> &[ScriptGroup] {
+        &self.script_groups
+    }
+    pub fn inputs(&self) -> &[ResolvedCell] {
+        &self.inputs
+    }
+    pub fn cell_deps(&self) -> &[ResolvedCell] {
+        &self.cell_deps
+    }
+    pub fn header_deps(&self) -> &[HeaderView] {
+        &self.header_deps
+    }
+    pub fn contexts(&self) -> &[SignContextInfo] {
+        &self.contexts
+    }
+
+    /// Add a signing context metadata, duplicated one is ignored.
+    pub fn add_context(&mut self, context: SignContextInfo) {
+        if !self.contexts.contains(&context) {
+            self.contexts.push(context);
+        }
+    }
+
+    pub fn to_transaction_with_groups(&self) -> TransactionWithScriptGroups {
+        TransactionWithScriptGroups::new(self.tx.clone(), self.script_groups.clone())
+    }
+
+    /// Merge the signatures from another party into current one.
+    ///
+    /// Both envelopes must contain the same transaction (witnesses excluded)
+    /// and the same script groups. For every witness, the `input_type` and
+    /// `output_type` fields must be equal, and the signatures in `lock` field
+    /// are merged: the 65 bytes signature slots of multisig lock (after the
+    /// multisig config) are combined, otherwise the placeholder (all zero)
+    /// lock is replaced by the signed one.
+    pub fn merge(&mut self, other: &PartiallySignedTransaction) -> Result<(), PartialTxError> {
+        if self.version != other.version {
+            return Err(PartialTxError::UnsupportedVersion(other.version));
+        }
+        if self.tx.hash() != other.tx.hash() {
+            return Err(PartialTxError::TxMismatch(self.tx.hash(), other.tx.hash()));
+        }
+        if self.script_groups != other.script_groups {
+            return Err(PartialTxError::ScriptGroupsMismatch);
+        }
+        let witnesses: Vec<packed::Bytes> = self.tx.witnesses().into_iter().collect();
+        let other_witnesses: Vec<packed::Bytes> = other.tx.witnesses().into_iter().collect();
+        let len = witnesses.len().max(other_witnesses.len());
+        let mut merged = Vec::with_capacity(len);
+        for idx in 0..len {
+            let witness = match (witnesses.get(idx), other_witnesses.get(idx)) {
+                (Some(a), Some(b)) => {
+                    let is_multisig = self.is_multisig_witness(idx);
+                    merge_witness(idx, a, b, is_multisig)?
+                }
+                (Some(a), None) => a.clone(),
+                (None, Some(b)) => b.clone(),
+                (None, None) => unreachable!(),
+            };
+            merged.push(witness);
+        }
+        self.tx = self.tx.as_advanced_builder().set_witnesses(merged).build();
+        for context in &other.contexts {
+            self.add_context(context.clone());
+        }
+        Ok(())
+    }
+
+    fn is_multisig_witness(&self, witness_idx: usize) -> bool {
+        self.script_groups.iter().any(|group| {
+            group.group_type == ScriptGroupType::Lock
+                && group.input_indices.first() == Some(&witness_idx)
+                && is_multisig_script(&group.script)
+        })
+    }
+
+    pub fn to_json(&self) -> PartiallySignedTransactionJson {
+        PartiallySignedTransactionJson::from(self)
+    }
+
+    pub fn to_json_string(&self) -> Result<String, PartialTxError> {
+        Ok(serde_json::to_string_pretty(&self.to_json())?)
+    }
+
+    pub fn from_json(json: PartiallySignedTransactionJson) -> Result<Self, PartialTxError> {
+        let version = json.version.value();
+        if version != PARTIAL_TX_VERSION {
+            return Err(PartialTxError::UnsupportedVersion(version));
+        }
+        let resolved_cell = |cell: ResolvedCellJson| ResolvedCell {
+            out_point: cell.out_point.into(),
+            output: cell.output.into(),
+            data: cell.data.into_bytes(),
+        };
+        Ok(Self {
+            version,
+            tx: packed::Transaction::from(json.transaction).into_view(),
+            script_groups: json
+                .script_groups
+                .into_iter()
+                .map(|group| ScriptGroup {
+                    script: group.script.into(),
+                    group_type: group.group_type,
+                    input_indices: group
+                        .input_indices
+                        .iter()
+                        .map(|idx| idx.value() as usize)
+                        .collect(),
+                    output_indices: group
+                        .output_indices
+                        .iter()
+                        .map(|idx| idx.value() as usize)
+                        .collect(),
+                })
+                .collect(),
+            inputs: json.inputs.into_iter().map(resolved_cell).collect(),
+            cell_deps: json.cell_deps.into_iter().map(resolved_cell).collect(),
+            header_deps: json.header_deps.into_iter().map(Into::into).collect(),
+            contexts: json
+                .contexts
+                .into_iter()
+                .map(|context| SignContextInfo {
+                    script_group_index: context.script_group_index.value() as usize,
+                    kind: context.kind,
+                    data: context.data.into_bytes(),
+                })
+                .collect(),
+        })
+    }
+
+    pub fn from_json_str(json: &str) -> Result<Self, PartialTxError> {
+        Self::from_json(serde_json::from_str(json)?)
+    }
+
+    pub fn to_molecule(&self) -> Bytes {
+        let to_u32_vec = |indices: &[usize]| {
+            partial_tx::Uint32Vec::new_builder()
+                .set(indices.iter().map(|idx| (*idx as u32).pack()).collect())
+                .build()
+        };
+        let to_cell_vec = |cells: &[ResolvedCell]| {
+            partial_tx::ResolvedCellVec::new_builder()
+                .set(
+                    cells
+                        .iter()
+                        .map(|cell| {
+                            partial_tx::ResolvedCell::new_builder()
+                                .out_point(cell.out_point.clone())
+                                .output(cell.output.clone())
+                                .data(cell.data.pack())
+                                .build()
+                        })
+                        .collect(),
+                )
+                .build()
+        };
+        let script_groups = self
+            .script_groups
+            .iter()
+            .map(|group| {
+                let group_type: u8 = match group.group_type {
+                    ScriptGroupType::Lock => 0,
+                    ScriptGroupType::Type => 1,
+                };
+                partial_tx::ScriptGroupInfo::new_builder()
+                    .script(group.script.clone())
+                    .group_type(group_type.into())
+                    .input_indices(to_u32_vec(&group.input_indices))
+                    .output_indices(to_u32_vec(&group.output_indices))
+                    .build()
+            })
+            .collect();
+        let contexts = self
+            .contexts
+            .iter()
+            .map(|context| {
+                partial_tx::SignContextInfo::new_builder()
+                    .script_group_index((context.script_group_index as u32).pack())
+                    .kind(context.kind.as_bytes().pack())
+                    .data(context.data.pack())
+                    .build()
+            })
+            .collect();
+        partial_tx::PartialTx::new_builder()
+            .version(self.version.pack())
+            .tx(self.tx.data())
+            .script_groups(
+                partial_tx::ScriptGroupInfoVec::new_builder()
+                    .set(script_groups)
+                    .build(),
+            )
+            .inputs(to_cell_vec(&self.inputs))
+            .cell_deps(to_cell_vec(&self.cell_deps))
+            .header_deps(
+                partial_tx::HeaderVec::new_builder()
+                    .set(
+                        self.header_deps
+                            .iter()
+                            .map(|header| header.data())
+                            .collect(),
+                    )
+                    .build(),
+            )
+            .contexts(
+                partial_tx::SignContextInfoVec::new_builder()
+                    .set(contexts)
+                    .build(),
+            )
+            .build()
+            .as_bytes()
+    }
+
+    pub fn from_molecule(data: &[u8]) -> Result<Self, PartialTxError> {
+        let ptx = partial_tx::PartialTx::from_slice(data)?;
+        let version: u32 = ptx.version().unpack();
+        if version != PARTIAL_TX_VERSION {
+            return Err(PartialTxError::UnsupportedVersion(version));
+        }
+        let from_u32_vec = |indices: partial_tx::Uint32Vec| -> Vec<usize> {
+            indices
+                .into_iter()
+                .map(|idx| {
+                    let idx: u32 = idx.unpack();
+                    idx as usize
+                })
+                .collect()
+        };
+        let from_cell_vec = |cells: partial_tx::ResolvedCellVec| -> Vec<ResolvedCell> {
+            cells
+                .into_iter()
+                .map(|cell| ResolvedCell {
+                    out_point: cell.out_point(),
+                    output: cell.output(),
+                    data: cell.data().raw_data(),
+                })
+                .collect()
+        };
+        let mut script_groups = Vec::new();
+        for group in ptx.script_groups().into_iter() {
+            let group_type = match group.group_type().as_slice()[0] {
+                0 => ScriptGroupType::Lock,
+                1 => ScriptGroupType::Type,
+                value => return Err(anyhow!("invalid script group type: {}", value).into()),
+            };
+            script_groups.push(ScriptGroup {
+                script: group.script(),
+                group_type,
+                input_indices: from_u32_vec(group.input_indices()),
+                output_indices: from_u32_vec(group.output_indices()),
+            });
+        }
+        let mut contexts = Vec::new();
+        for context in ptx.contexts().into_iter() {
+            let index: u32 = context.script_group_index().unpack();
+            let kind = String::from_utf8(context.kind().raw_data().to_vec())
+                .map_err(|err| anyhow!("invalid sign context kind: {}", err))?;
+            contexts.push(SignContextInfo {
+                script_group_index: index as usize,
+                kind,
+                data: context.data().raw_data(),
+            });
+        }
+        Ok(Self {
+            version,
+            tx: ptx.tx().into_view(),
+            script_groups,
+            inputs: from_cell_vec(ptx.inputs()),
+            cell_deps: from_cell_vec(ptx.cell_deps()),
+            header_deps: ptx
+                .header_deps()
+                .into_iter()
+                .map(|header| header.into_view())
+                .collect(),
+            contexts,
+        })
+    }
+
+    fn find_cell(&self, out_point: &OutPoint) -> Result<&ResolvedCell, TransactionDependencyError> {
+        self.inputs
+            .iter()
+            .chain(self.cell_deps.iter())
+            .find(|cell| &cell.out_point == out_point)
+            .ok_or_else(|| TransactionDependencyError::NotFound(format!("cell: {}", out_point)))
+    }
+}
+
+/// The envelope provides its resolved cells and headers, so it can be used
+/// to verify or sign the transaction offline.
+impl TransactionDependencyProvider for PartiallySignedTransaction {
+    fn get_transaction(
+        &self,
+        tx_hash: &Byte32,
+    ) -> Result<TransactionView, TransactionDependencyError> {
+        Err(TransactionDependencyError::NotFound(format!(
+            "transaction: {}",
+            tx_hash
+        )))
+    }
+    fn get_cell(&self, out_point: &OutPoint) -> Result<CellOutput, TransactionDependencyError> {
+        self.find_cell(out_point).map(|cell| cell.output.clone())
+    }
+    fn get_cell_data(&self, out_point: &OutPoint) -> Result<Bytes, TransactionDependencyError> {
+        self.find_cell(out_point).map(|cell| cell.data.clone())
+    }
+    fn get_header(&self, block_hash: &Byte32) -> Result<HeaderView, TransactionDependencyError> {
+        self.header_deps
+            .iter()
+            .find(|header| &header.hash() == block_hash)
+            .cloned()
+            .ok_or_else(|| TransactionDependencyError::NotFound(format!("header: {}", block_hash)))
+    }
+}
+
+fn is_multisig_script(script: &Script) -> bool {
+    script.code_hash() == MULTISIG_TYPE_HASH.pack()
+        && script.hash_type() == ScriptHashType::Type.into()
+}
+
+fn merge_witness(
+    idx: usize,
+    witness: &packed::Bytes,
+    other: &packed::Bytes,
+    is_multisig: bool,
+) -> Result<packed::Bytes, PartialTxError> {
+    if witness == other {
+        return Ok(witness.clone());
+    }
+    let data = witness.raw_data();
+    let other_data = other.raw_data();
+    if data.is_empty() {
+        return Ok(other.clone());
+    } else if other_data.is_empty() {
+        return Ok(witness.clone());
+    }
+    let parse = |data: &[u8]| {
+        WitnessArgs::from_slice(data).map_err(|err| {
+            PartialTxError::WitnessConflict(idx, format!("invalid WitnessArgs: {}", err))
+        })
+    };
+    let witness_args = parse(&data)?;
+    let other_args = parse(&other_data)?;
+    if witness_args.input_type().as_slice() != other_args.input_type().as_slice()
+        || witness_args.output_type().as_slice() != other_args.output_type().as_slice()
+    {
+        return Err(PartialTxError::WitnessConflict(
+            idx,
+            "input_type or output_type not match".to_string(),
+        ));
+    }
+    let lock = match (witness_args.lock().to_opt(), other_args.lock().to_opt()) {
+        (Some(lock), Some(other_lock)) => Some(merge_lock(
+            idx,
+            &lock.raw_data(),
+            &other_lock.raw_data(),
+            is_multisig,
+        )?),
+        (Some(lock), None) => Some(lock.raw_data()),
+        (None, Some(other_lock)) => Some(other_lock.raw_data()),
+        (None, None) => None,
+    };
+    Ok(witness_args
+        .as_builder()
+        .lock(lock.pack())
+        .build()
+        .as_bytes()
+        .pack())
+}
+
+fn merge_lock(
+    idx: usize,
+    lock: &[u8],
+    other: &[u8],
+    is_multisig: bool,
+) -> Result<Bytes, PartialTxError> {
+    let is_empty = |data: &[u8]| data.iter().all(|b| *b == 0);
+    if lock == other || is_empty(other) {
+        return Ok(Bytes::from(lock.to_vec()));
+    } else if is_empty(lock) {
+        return Ok(Bytes::from(other.to_vec()));
+    }
+    if lock.len() != other.len() {
+        return Err(PartialTxError::WitnessConflict(
+            idx,
+            format!("lock length not match: {} vs {}", lock.len(), other.len()),
+        ));
+    }
+    let prefix_len = if is_multisig {
+        // reserved byte, require_first_n, threshold, pubkeys count, pubkey hashes
+        if lock.len() < 4 {
+            return Err(PartialTxError::WitnessConflict(
+                idx,
+                "invalid multisig lock".to_string(),
+            ));
+        }
+        4 + 20 * lock[3] as usize
+    } else {
+        0
+    };
+    if prefix_len > lock.len()
+        || (lock.len() - prefix_len) % 65 != 0
+        || lock[..prefix_len] != other[..prefix_len]
+    {
+        return Err(PartialTxError::WitnessConflict(
+            idx,
+            "both lock fields are signed".to_string(),
+        ));
+    }
+    let mut signatures: Vec<&[u8]> = Vec::new();
+    for signature in lock[prefix_len..]
+        .chunks(65)
+        .chain(other[prefix_len..].chunks(65))
+    {
+        if !is_empty(signature) && !signatures.contains(&signature) {
+            signatures.push(signature);
+        }
+    }
+    let slots = (lock.len() - prefix_len) / 65;
+    if signatures.len() > slots {
+        return Err(PartialTxError::WitnessConflict(
+            idx,
+            format!("too many signatures: {} > {}", signatures.len(), slots),
+        ));
+    }
+    let mut merged = lock[..prefix_len].to_vec();
+    for signature in &signatures {
+        merged.extend_from_slice(signature);
+    }
+    merged.resize(lock.len(), 0);
+    Ok(Bytes::from(merged))
+}
+
+/// Merge the signatures of several partially signed transactions.
+pub fn merge_partial_transactions(
+    ptxs: &[PartiallySignedTransaction],
+) -> Result<PartiallySignedTransaction, PartialTxError> {
+    let (first, rest) = ptxs
+        .split_first()
+        .ok_or_else(|| anyhow!("no partial transaction to merge"))?;
+    let mut merged = first.clone();
+    for ptx in rest {
+        merged.merge(ptx)?;
+    }
+    Ok(merged)
+}
+
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+pub struct ScriptGroupJson {
+    pub script: json_types::Script,
+    pub group_type: ScriptGroupType,
+    pub input_indices: Vec<json_types::Uint32>,
+    pub output_indices: Vec<json_types::Uint32>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+pub struct ResolvedCellJson {
+    pub out_point: json_types::OutPoint,
+    pub output: json_types::CellOutput,
+    pub data: json_types::JsonBytes,
+}
+
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+pub struct SignContextInfoJson {
+    pub script_group_index: json_types::Uint32,
+    pub kind: String,
+    pub data: json_types::JsonBytes,
+}
+
+/// The JSON representation of [`PartiallySignedTransaction`].
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+pub struct PartiallySignedTransactionJson {
+    pub version: json_types::Uint32,
+    pub transaction: json_types::Transaction,
+    pub script_groups: Vec<ScriptGroupJson>,
+    pub inputs: Vec<ResolvedCellJson>,
+    pub cell_deps: Vec<ResolvedCellJson>,
+    pub header_deps: Vec<json_types::HeaderView>,
+    pub contexts: Vec<SignContextInfoJson>,
+}
+
+impl From<&PartiallySignedTransaction> for PartiallySignedTransactionJson {
+    fn from(ptx: &PartiallySignedTransaction) -> Self {
+        let to_indices = |indices: &[usize]| {
+            indices
+                .iter()
+                .map(|idx| (*idx as u32).into())
+                .collect::<Vec<_>>()
+        };
+        let resolved_cell = |cell: &ResolvedCell| ResolvedCellJson {
+            out_point: cell.out_point.clone().into(),
+            output: cell.output.clone().into(),
+            data: json_types::JsonBytes::from_bytes(cell.data.clone()),
+        };
+        PartiallySignedTransactionJson {
+            version: ptx.version.into(),
+            transaction: ptx.tx.data().into(),
+            script_groups: ptx
+                .script_groups
+                .iter()
+                .map(|group| ScriptGroupJson {
+                    script: group.script.clone().into(),
+                    group_type: group.group_type,
+                    input_indices: to_indices(&group.input_indices),
+                    output_indices: to_indices(&group.output_indices),
+                })
+                .collect(),
+            inputs: ptx.inputs.iter().map(resolved_cell).collect(),
+            cell_deps: ptx.cell_deps.iter().map(resolved_cell).collect(),
+            header_deps: ptx
+                .header_deps
+                .iter()
+                .map(|header| header.clone().into())
+                .collect(),
+            contexts: ptx
+                .contexts
+                .iter()
+                .map(|context| SignContextInfoJson {
+                    script_group_index: (context.script_group_index as u32).into(),
+                    kind: context.kind.clone(),
+                    data: json_types::JsonBytes::from_bytes(context.data.clone()),
+                })
+                .collect(),
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use ckb_types::{core, H256};
+use ckb_types::{bytes::Bytes, core, prelude::*, H256};
 use std::collections::HashMap;
 
 use crate::{
@@ -9,6 +9,11 @@ use crate::{
 
 use self::sighash::Secp256k1Blake160SighashAllSigner;
 
+use super::partial::{
+    PartialTxError, PartiallySignedTransaction, SignContextInfo, SIGN_CONTEXT_KIND_MULTISIG,
+    SIGN_CONTEXT_KIND_SIGHASH,
+};
+
 use super::handler::Type2Any;
 pub mod multisig;
 pub mod sighash;
@@ -122,4 +127,81 @@ impl TransactionSigner {
         transaction.set_tx_view(tx);
         Ok(signed_groups_indices)
     }
+
+    /// Sign a partially signed transaction, the signing contexts metadata of
+    /// the signed script groups is recorded in the envelope.
+    pub fn sign_partial_transaction(
+        &self,
+        ptx: &mut PartiallySignedTransaction,
+        contexts: &SignContexts,
+    ) -> Result<Vec<usize>, UnlockError> {
+        let mut transaction = ptx.to_transaction_with_groups();
+        let signed_groups_indices = self.sign_transaction(&mut transaction, contexts)?;
+        ptx.set_tx_view(transaction.get_tx_view().clone());
+        for idx in &signed_groups_indices {
+            if let Some(info) = sign_context_info(*idx, &ptx.script_groups()[*idx], contexts) {
+                ptx.add_context(info);
+            }
+        }
+        Ok(signed_groups_indices)
+    }
+
+    /// Read a partially signed transaction in JSON, sign it and write it back to JSON.
+    pub fn sign_partial_json(
+        &self,
+        json: &str,
+        contexts: &SignContexts,
+    ) -> Result<String, PartialTxError> {
+        let mut ptx = PartiallySignedTransaction::from_json_str(json)?;
+        self.sign_partial_transaction(&mut ptx, contexts)?;
+        ptx.to_json_string()
+    }
+
+    /// Read a partially signed transaction in molecule, sign it and write it back to molecule.
+    pub fn sign_partial_molecule(
+        &self,
+        data: &[u8],
+        contexts: &SignContexts,
+    ) -> Result<Bytes, PartialTxError> {
+        let mut ptx = PartiallySignedTransaction::from_molecule(data)?;
+        self.sign_partial_transaction(&mut ptx, contexts)?;
+        Ok(ptx.to_molecule())
+    }
+}
+
+fn sign_context_info(
+    script_group_index: usize,
+    script_group: &ScriptGroup,
+    contexts: &SignContexts,
+) -> Option<SignContextInfo> {
+    let script_id = ScriptId::from(&script_group.script);
+    if script_id == ScriptId::new_type(constants::SIGHASH_TYPE_HASH.clone()) {
+        Some(SignContextInfo::new(
+            script_group_index,
+            SIGN_CONTEXT_KIND_SIGHASH,
+            Bytes::new(),
+        ))
+    } else if script_id == ScriptId::new_type(constants::MULTISIG_TYPE_HASH.clone()) {
+        let args = script_group.script.args().raw_data();
+        contexts
+            .contexts
+            .iter()
+            .filter_map(|context| {
+                context
+                    .as_ref()
+                    .as_any()
+                    .downcast_ref::<multisig::Secp256k1Blake160MultisigAllSignerContext>()
+            })
+            .map(|context| context.multisig_config())
+            .find(|config| args.len() >= 20 && config.hash160().as_bytes() == &args[0..20])
+            .map(|config| {
+                SignContextInfo::new(
+                    script_group_index,
+                    SIGN_CONTEXT_KIND_MULTISIG,
+                    Bytes::from(config.to_witness_data()),
+                )
+            })
+    } else {
+        None
+    }
 }
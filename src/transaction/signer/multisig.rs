@@ -24,6 +24,10 @@ impl Secp256k1Blake160MultisigAllSignerContext {
         }
     }
 
+    pub fn multisig_config(&self) -> &MultisigConfig {
+        &self.multisig_config
+    }
+
     pub fn build_multisig_unlocker(&self) -> SecpMultisigUnlocker {
         let signer = SecpCkbRawKeySigner::new_with_secret_keys(self.keys.clone());
         let multisig_signer =
@@ -0,0 +1,387 @@
+//! Verify a transaction locally before sending it to the node.
+
+use std::collections::HashSet;
+
+use ckb_script::{ScriptError, ScriptGroupType as VmScriptGroupType, TransactionScriptsVerifier};
+use ckb_types::{
+    core::{
+        cell::resolve_transaction, Capacity, Cycle, EpochNumberWithFraction, HeaderView,
+        TransactionView,
+    },
+    packed::Byte32,
+    prelude::*,
+};
+use thiserror::Error;
+
+use crate::{
+    rpc::{CkbRpcClient, RpcError},
+    traits::{HeaderDepResolver, TransactionDependencyError, TransactionDependencyProvider},
+    transaction::builder::FeeCalculator,
+    tx_builder::{gen_script_groups, tx_fee, TransactionFeeError},
+    ScriptGroup, ScriptGroupType, Since, SinceType,
+};
+
+/// The max cycles of a transaction in ckb mainnet
+pub const DEFAULT_MAX_TX_VERIFY_CYCLES: Cycle = 70_000_000;
+/// The cellbase maturity in ckb mainnet (4 epochs)
+pub const DEFAULT_CELLBASE_MATURITY: u64 = 4;
+
+#[derive(Error, Debug)]
+pub enum TransactionVerifyError {
+    #[error("resolve transaction error: `{0}`")]
+    Resolve(String),
+
+    #[error("transaction dependency provider error: `{0}`")]
+    TxDep(#[from] TransactionDependencyError),
+
+    #[error("transaction fee error: `{0}`")]
+    Fee(#[from] TransactionFeeError),
+
+    #[error("output #{0} capacity is less than its occupied capacity")]
+    InsufficientCellCapacity(usize),
+
+    #[error("transaction fee is too low, min-fee: `{min_fee}`, actual-fee: `{fee}`")]
+    InsufficientFee { fee: u64, min_fee: u64 },
+
+    #[error("input #{0} has invalid since: `{1}`")]
+    InvalidSince(usize, String),
+
+    #[error("input #{0} is immature by since: `{1:#x}`")]
+    Immature(usize, u64),
+
+    #[error("input #{0} is an immature cellbase output")]
+    CellbaseImmature(usize),
+
+    #[error("script group `{0}` verify failed: `{1}`")]
+    Script(Byte32, String),
+
+    #[error("transaction exceeded the max cycles: `{0}`")]
+    ExceededMaxCycles(Cycle),
+
+    #[error("rpc error: `{0}`")]
+    Rpc(#[from] RpcError),
+
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// The chain tip used to check since and cellbase maturity.
+#[derive(Clone, Debug)]
+pub struct TipInfo {
+    pub number: u64,
+    pub epoch: EpochNumberWithFraction,
+    /// The median time of the past 37 blocks up to the tip, in milliseconds
+    pub median_timestamp: u64,
+}
+
+impl TipInfo {
+    /// The `median_timestamp` is the result of `get_block_median_time` of the
+    /// tip, the tip timestamp is later than it and must not be used instead.
+    pub fn new(header: &HeaderView, median_timestamp: u64) -> TipInfo {
+        TipInfo {
+            number: header.number(),
+            epoch: header.epoch(),
+            median_timestamp,
+        }
+    }
+
+    /// Fetch the tip header and its median time from the ckb node.
+    pub fn from_rpc(ckb_client: &CkbRpcClient) -> Result<TipInfo, TransactionVerifyError> {
+        let header: HeaderView = ckb_client.get_tip_header()?.into();
+        let median_timestamp = block_median_time(ckb_client, &header.hash())?;
+        Ok(TipInfo::new(&header, median_timestamp))
+    }
+}
+
+fn block_median_time(
+    ckb_client: &CkbRpcClient,
+    block_hash: &Byte32,
+) -> Result<u64, TransactionVerifyError> {
+    ckb_client
+        .get_block_median_time(block_hash.unpack())?
+        .map(|timestamp| timestamp.value())
+        .ok_or_else(|| anyhow::anyhow!("median time of block {} not found", block_hash).into())
+}
+
+/// The verify result of a script group.
+#[derive(Clone, Debug)]
+pub struct ScriptGroupVerifyResult {
+    pub script_group: ScriptGroup,
+    pub script_hash: Byte32,
+    /// The consumed cycles or the error message of ckb-vm
+    pub result: Result<Cycle, String>,
+}
+
+/// The report of a transaction verification.
+#[derive(Debug, Default)]
+pub struct TransactionVerifyReport {
+    /// The lock script groups first, then the type script groups
+    pub script_groups: Vec<ScriptGroupVerifyResult>,
+    pub fee: u64,
+    pub min_fee: u64,
+    /// All the errors found, including the script errors
+    pub errors: Vec<TransactionVerifyError>,
+}
+
+impl TransactionVerifyReport {
+    /// Total cycles of the script groups verified successfully
+    pub fn total_cycles(&self) -> Cycle {
+        self.script_groups
+            .iter()
+            .filter_map(|group| group.result.as_ref().ok())
+            .sum()
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// Return the total cycles or the first error.
+    pub fn into_result(self) -> Result<Cycle, TransactionVerifyError> {
+        let total_cycles = self.total_cycles();
+        match self.errors.into_iter().next() {
+            Some(err) => Err(err),
+            None => Ok(total_cycles),
+        }
+    }
+}
+
+/// Verify a transaction the way a ckb node does:
+///   * resolve all the inputs, cell deps and header deps
+///   * run every lock and type script group in ckb-vm
+///   * check the output capacity and the capacity balance
+///   * check the fee rate
+///   * check the since and cellbase maturity rules (only if tip is set)
+pub struct TransactionVerifier<'a> {
+    tx_dep_provider: &'a dyn TransactionDependencyProvider,
+    header_dep_resolver: &'a dyn HeaderDepResolver,
+    fee_rate: u64,
+    max_cycles: Cycle,
+    cellbase_maturity: EpochNumberWithFraction,
+    tip: Option<TipInfo>,
+    ckb_client: Option<&'a CkbRpcClient>,
+}
+
+impl<'a> TransactionVerifier<'a> {
+    pub fn new(
+        tx_dep_provider: &'a dyn TransactionDependencyProvider,
+        header_dep_resolver: &'a dyn HeaderDepResolver,
+        fee_rate: u64,
+    ) -> TransactionVerifier<'a> {
+        TransactionVerifier {
+            tx_dep_provider,
+            header_dep_resolver,
+            fee_rate,
+            max_cycles: DEFAULT_MAX_TX_VERIFY_CYCLES,
+            cellbase_maturity: EpochNumberWithFraction::new(DEFAULT_CELLBASE_MATURITY, 0, 1),
+            tip: None,
+            ckb_client: None,
+        }
+    }
+
+    pub fn set_max_cycles(&mut self, max_cycles: Cycle) {
+        self.max_cycles = max_cycles;
+    }
+
+    pub fn set_cellbase_maturity(&mut self, cellbase_maturity: EpochNumberWithFraction) {
+        self.cellbase_maturity = cellbase_maturity;
+    }
+
+    /// Set the chain tip, the since and cellbase maturity rules are checked
+    /// against it.
+    pub fn set_tip(&mut self, tip: TipInfo) {
+        self.tip = Some(tip);
+    }
+
+    /// Set the ckb node to fetch the median time of the committed blocks for
+    /// the relative timestamp since. If not set, the committed block
+    /// timestamp is used instead, which is later than the median time, so an
+    /// input may be reported immature while it is already mature.
+    pub fn set_ckb_client(&mut self, ckb_client: &'a CkbRpcClient) {
+        self.ckb_client = Some(ckb_client);
+    }
+
+    /// Verify the transaction, return `Err` only when the transaction can not
+    /// be resolved, otherwise all the errors are collected in the report.
+    pub fn verify(
+        &self,
+        tx: &TransactionView,
+    ) -> Result<TransactionVerifyReport, TransactionVerifyError> {
+        let tx_dep_provider = self.tx_dep_provider;
+        let rtx = resolve_transaction(
+            tx.clone(),
+            &mut HashSet::new(),
+            &tx_dep_provider,
+            &tx_dep_provider,
+        )
+        .map_err(|err| TransactionVerifyError::Resolve(format!("{:?}", err)))?;
+
+        let mut report = TransactionVerifyReport::default();
+        let verifier = TransactionScriptsVerifier::new(&rtx, &tx_dep_provider);
+        let script_groups = gen_script_groups(tx, tx_dep_provider)?;
+        let mut lock_groups: Vec<_> = script_groups.lock_groups.into_iter().collect();
+        lock_groups.sort_by_key(|(_, group)| group.input_indices[0]);
+        let mut type_groups: Vec<_> = script_groups.type_groups.into_iter().collect();
+        type_groups.sort_by_key(|(script_hash, _)| script_hash.as_slice().to_vec());
+        // The max cycles limits the total cycles of all the script groups
+        let mut remaining_cycles = self.max_cycles;
+        for (script_hash, script_group) in lock_groups.into_iter().chain(type_groups) {
+            let group_type = match script_group.group_type {
+                ScriptGroupType::Lock => VmScriptGroupType::Lock,
+                ScriptGroupType::Type => VmScriptGroupType::Type,
+            };
+            let mut exceeded = false;
+            let result = match verifier.verify_single(group_type, &script_hash, remaining_cycles) {
+                Ok(cycles) => {
+                    remaining_cycles = remaining_cycles.saturating_sub(cycles);
+                    Ok(cycles)
+                }
+                Err(ScriptError::ExceededMaximumCycles(limit)) => {
+                    exceeded = true;
+                    report
+                        .errors
+                        .push(TransactionVerifyError::ExceededMaxCycles(self.max_cycles));
+                    Err(ScriptError::ExceededMaximumCycles(limit).to_string())
+                }
+                Err(err) => {
+                    let err = err.to_string();
+                    report.errors.push(TransactionVerifyError::Script(
+                        script_hash.clone(),
+                        err.clone(),
+                    ));
+                    Err(err)
+                }
+            };
+            report.script_groups.push(ScriptGroupVerifyResult {
+                script_group,
+                script_hash,
+                result,
+            });
+            if exceeded {
+                break;
+            }
+        }
+
+        for (idx, (output, data)) in tx.outputs_with_data_iter().enumerate() {
+            match output.is_lack_of_capacity(Capacity::bytes(data.len()).expect("capacity")) {
+                Ok(false) => {}
+                _ => report
+                    .errors
+                    .push(TransactionVerifyError::InsufficientCellCapacity(idx)),
+            }
+        }
+
+        // The node charges the fee by the weight of the transaction, which
+        // is the larger one of the size and the cycles converted to bytes.
+        report.min_fee = FeeCalculator::new(self.fee_rate).fee_with_cycle(
+            tx.data().as_reader().serialized_size_in_block() as u64,
+            report.total_cycles(),
+        );
+        match tx_fee(tx.clone(), self.tx_dep_provider, self.header_dep_resolver) {
+            Ok(fee) => {
+                report.fee = fee;
+                if fee < report.min_fee {
+                    report.errors.push(TransactionVerifyError::InsufficientFee {
+                        fee,
+                        min_fee: report.min_fee,
+                    });
+                }
+            }
+            Err(err) => report.errors.push(err.into()),
+        }
+
+        if let Some(tip) = self.tip.as_ref() {
+            for (idx, input) in tx.inputs().into_iter().enumerate() {
+                if let Err(err) = self.verify_input_maturity(
+                    tip,
+                    idx,
+                    input.since().unpack(),
+                    &input.previous_output().tx_hash(),
+                ) {
+                    report.errors.push(err);
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    fn committed_header(
+        &self,
+        idx: usize,
+        tx_hash: &Byte32,
+    ) -> Result<HeaderView, TransactionVerifyError> {
+        self.header_dep_resolver
+            .resolve_by_tx(tx_hash)?
+            .ok_or_else(|| {
+                TransactionVerifyError::InvalidSince(
+                    idx,
+                    format!("can not resolve the committed header of {}", tx_hash),
+                )
+            })
+    }
+
+    fn verify_input_maturity(
+        &self,
+        tip: &TipInfo,
+        idx: usize,
+        since_value: u64,
+        tx_hash: &Byte32,
+    ) -> Result<(), TransactionVerifyError> {
+        if self.tx_dep_provider.get_transaction(tx_hash)?.is_cellbase() {
+            let header = self.committed_header(idx, tx_hash)?;
+            let mature_epoch = header.epoch().to_rational() + self.cellbase_maturity.to_rational();
+            if tip.epoch.to_rational() < mature_epoch {
+                return Err(TransactionVerifyError::CellbaseImmature(idx));
+            }
+        }
+
+        if since_value == 0 {
+            return Ok(());
+        }
+        let since = Since::from_raw_value(since_value);
+        let (since_type, value) = match since.extract_metric() {
+            Some(metric) if since.flags_is_valid() => metric,
+            _ => {
+                return Err(TransactionVerifyError::InvalidSince(
+                    idx,
+                    "invalid flags".to_string(),
+                ))
+            }
+        };
+        let mature = if since.is_absolute() {
+            match since_type {
+                SinceType::BlockNumber => tip.number >= value,
+                SinceType::EpochNumberWithFraction => {
+                    tip.epoch.to_rational()
+                        >= EpochNumberWithFraction::from_full_value(value).to_rational()
+                }
+                // The timestamp in since is in seconds
+                SinceType::Timestamp => tip.median_timestamp >= value.saturating_mul(1000),
+            }
+        } else {
+            let header = self.committed_header(idx, tx_hash)?;
+            match since_type {
+                SinceType::BlockNumber => tip.number >= header.number().saturating_add(value),
+                SinceType::EpochNumberWithFraction => {
+                    tip.epoch.to_rational()
+                        >= header.epoch().to_rational()
+                            + EpochNumberWithFraction::from_full_value(value).to_rational()
+                }
+                // Relative to the median time before the committed block
+                SinceType::Timestamp => {
+                    let base_timestamp = match self.ckb_client {
+                        Some(ckb_client) => block_median_time(ckb_client, &header.parent_hash())?,
+                        None => header.timestamp(),
+                    };
+                    tip.median_timestamp
+                        >= base_timestamp.saturating_add(value.saturating_mul(1000))
+                }
+            }
+        };
+        if mature {
+            Ok(())
+        } else {
+            Err(TransactionVerifyError::Immature(idx, since_value))
+        }
+    }
+}
@@ -0,0 +1,311 @@
+//! Client side verification of the transaction inclusion proofs returned by
+//! `get_transaction_proof` and `get_transaction_and_witness_proof`.
+//!
+//! The `verify_*` RPC methods ask the same node which built the proof, so a
+//! malicious or buggy node can fake the inclusion. Here the CBMT root is
+//! rebuilt from the proof and checked against the `transactions_root` of a
+//! header from a [`TrustedHeaderProvider`], e.g. headers checked by a header
+//! chain verifier from a checkpoint.
+//!
+//! The `transactions_root` of a block is `merkle_root([raw_transactions_root,
+//! witnesses_root])`, where the raw root is over the transaction hashes and
+//! the witnesses root over the witness hashes.
+
+use std::collections::HashMap;
+
+use ckb_jsonrpc_types::{
+    MerkleProof as JsonMerkleProof, TransactionAndWitnessProof, TransactionProof,
+};
+use ckb_types::{
+    core::{HeaderView, TransactionView},
+    packed::Byte32,
+    prelude::*,
+    utilities::{merkle_root, MerkleProof},
+};
+use thiserror::Error;
+
+use crate::rpc::{CkbRpcClient, RpcError};
+
+#[derive(Error, Debug)]
+pub enum TxProofError {
+    #[error("rpc error: `{0}`")]
+    Rpc(#[from] RpcError),
+
+    #[error("block `{0}` is not trusted")]
+    UntrustedBlock(Byte32),
+
+    #[error("invalid proof: `{0}`")]
+    InvalidProof(String),
+
+    #[error("transactions root mismatch, expected: `{expected}`, actual: `{actual}`")]
+    RootMismatch { expected: Byte32, actual: Byte32 },
+
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// Provides the headers already trusted by the client.
+pub trait TrustedHeaderProvider {
+    /// Get the trusted header by block hash, `None` if the block is not trusted
+    fn get_trusted_header(&self, block_hash: &Byte32) -> Result<Option<HeaderView>, anyhow::Error>;
+}
+
+impl TrustedHeaderProvider for HashMap<Byte32, HeaderView> {
+    fn get_trusted_header(&self, block_hash: &Byte32) -> Result<Option<HeaderView>, anyhow::Error> {
+        Ok(self.get(block_hash).cloned())
+    }
+}
+
+/// A transaction verified to be committed in a trusted block.
+#[derive(Debug, Clone)]
+pub struct VerifiedInclusion {
+    pub tx_hash: Byte32,
+    pub header: HeaderView,
+}
+
+impl VerifiedInclusion {
+    pub fn block_hash(&self) -> Byte32 {
+        self.header.hash()
+    }
+    pub fn block_number(&self) -> u64 {
+        self.header.number()
+    }
+}
+
+fn merkle_proof_root(proof: &JsonMerkleProof, leaves: &[Byte32]) -> Result<Byte32, TxProofError> {
+    if proof.indices.len() != leaves.len() {
+        return Err(TxProofError::InvalidProof(format!(
+            "proof has {} indices, but {} leaves given",
+            proof.indices.len(),
+            leaves.len()
+        )));
+    }
+    let proof = MerkleProof::new(
+        proof.indices.iter().map(|index| index.value()).collect(),
+        proof.lemmas.iter().map(|lemma| lemma.pack()).collect(),
+    );
+    proof
+        .root(leaves)
+        .ok_or_else(|| TxProofError::InvalidProof("can not build the merkle root".to_string()))
+}
+
+fn check_transactions_root(
+    header: &HeaderView,
+    raw_transactions_root: Byte32,
+    witnesses_root: Byte32,
+) -> Result<(), TxProofError> {
+    let actual = merkle_root(&[raw_transactions_root, witnesses_root]);
+    let expected = header.transactions_root();
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(TxProofError::RootMismatch { expected, actual })
+    }
+}
+
+fn check_block_hash(header: &HeaderView, block_hash: &Byte32) -> Result<(), TxProofError> {
+    if &header.hash() == block_hash {
+        Ok(())
+    } else {
+        Err(TxProofError::InvalidProof(format!(
+            "proof of block `{}` checked against header `{}`",
+            block_hash,
+            header.hash()
+        )))
+    }
+}
+
+/// Verify the transactions are committed in the block of `header`.
+///
+/// The `tx_hashes` must be in the order of the proof indices (descending
+/// position in the block), which is unambiguous for a proof of one
+/// transaction.
+pub fn verify_transaction_proof(
+    proof: &TransactionProof,
+    tx_hashes: &[Byte32],
+    header: &HeaderView,
+) -> Result<(), TxProofError> {
+    check_block_hash(header, &proof.block_hash.pack())?;
+    let raw_transactions_root = merkle_proof_root(&proof.proof, tx_hashes)?;
+    check_transactions_root(header, raw_transactions_root, proof.witnesses_root.pack())
+}
+
+/// Verify the transactions, including their witnesses, are committed in the
+/// block of `header`.
+///
+/// The `transactions` must be in the order of the proof indices, see
+/// [`verify_transaction_proof`].
+pub fn verify_transaction_and_witness_proof(
+    proof: &TransactionAndWitnessProof,
+    transactions: &[TransactionView],
+    header: &HeaderView,
+) -> Result<(), TxProofError> {
+    check_block_hash(header, &proof.block_hash.pack())?;
+    let tx_hashes: Vec<_> = transactions.iter().map(|tx| tx.hash()).collect();
+    let witness_hashes: Vec<_> = transactions.iter().map(|tx| tx.witness_hash()).collect();
+    let raw_transactions_root = merkle_proof_root(&proof.transactions_proof, &tx_hashes)?;
+    let witnesses_root = merkle_proof_root(&proof.witnesses_proof, &witness_hashes)?;
+    check_transactions_root(header, raw_transactions_root, witnesses_root)
+}
+
+/// Fetch the inclusion proofs from a ckb node and verify them against the
+/// trusted headers.
+pub struct TxProofVerifier<P> {
+    ckb_client: CkbRpcClient,
+    headers: P,
+}
+
+impl<P: TrustedHeaderProvider> TxProofVerifier<P> {
+    pub fn new(ckb_client: &str, headers: P) -> TxProofVerifier<P> {
+        TxProofVerifier {
+            ckb_client: CkbRpcClient::new(ckb_client),
+            headers,
+        }
+    }
+
+    pub fn headers(&self) -> &P {
+        &self.headers
+    }
+    pub fn headers_mut(&mut self) -> &mut P {
+        &mut self.headers
+    }
+
+    fn trusted_header(&self, block_hash: &Byte32) -> Result<HeaderView, TxProofError> {
+        self.headers
+            .get_trusted_header(block_hash)?
+            .ok_or_else(|| TxProofError::UntrustedBlock(block_hash.clone()))
+    }
+
+    /// Verify the transaction is committed in a trusted block. The proof
+    /// RPC fails if the transaction is not committed.
+    pub fn verify_inclusion(&self, tx_hash: &Byte32) -> Result<VerifiedInclusion, TxProofError> {
+        let proof = self
+            .ckb_client
+            .get_transaction_proof(vec![tx_hash.unpack()], None)?;
+        let header = self.trusted_header(&proof.block_hash.pack())?;
+        verify_transaction_proof(&proof, &[tx_hash.clone()], &header)?;
+        Ok(VerifiedInclusion {
+            tx_hash: tx_hash.clone(),
+            header,
+        })
+    }
+
+    /// Verify the transaction is committed with the same witnesses in a
+    /// trusted block.
+    pub fn verify_inclusion_with_witness(
+        &self,
+        tx: &TransactionView,
+    ) -> Result<VerifiedInclusion, TxProofError> {
+        let proof = self
+            .ckb_client
+            .get_transaction_and_witness_proof(vec![tx.hash().unpack()], None)?;
+        let header = self.trusted_header(&proof.block_hash.pack())?;
+        verify_transaction_and_witness_proof(&proof, &[tx.clone()], &header)?;
+        Ok(VerifiedInclusion {
+            tx_hash: tx.hash(),
+            header,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ckb_types::{
+        bytes::Bytes,
+        core::{BlockView, TransactionBuilder},
+        packed::CellOutput,
+        utilities::CBMT,
+        H256,
+    };
+
+    fn build_block() -> BlockView {
+        let txs: Vec<_> = (0..5u64)
+            .map(|i| {
+                TransactionBuilder::default()
+                    .output(CellOutput::new_builder().capacity(i.pack()).build())
+                    .output_data(Bytes::new().pack())
+                    .witness(Bytes::from(vec![i as u8]).pack())
+                    .build()
+            })
+            .collect();
+        BlockView::new_advanced_builder()
+            .number(10u64.pack())
+            .transactions(txs)
+            .build()
+    }
+
+    fn json_proof(leaves: &[Byte32], index: u32) -> JsonMerkleProof {
+        let proof = CBMT::build_merkle_proof(leaves, &[index]).unwrap();
+        JsonMerkleProof {
+            indices: proof
+                .indices()
+                .iter()
+                .map(|index| (*index).into())
+                .collect(),
+            lemmas: proof.lemmas().iter().map(|lemma| lemma.unpack()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_verify_transaction_proof() {
+        let block = build_block();
+        let header = block.header();
+        let tx_hashes = block.tx_hashes().to_vec();
+        let proof = TransactionProof {
+            block_hash: header.hash().unpack(),
+            witnesses_root: block.calc_witnesses_root().unpack(),
+            proof: json_proof(&tx_hashes, 3),
+        };
+        verify_transaction_proof(&proof, &[tx_hashes[3].clone()], &header).unwrap();
+        assert!(matches!(
+            verify_transaction_proof(&proof, &[tx_hashes[2].clone()], &header),
+            Err(TxProofError::RootMismatch { .. })
+        ));
+        assert!(matches!(
+            verify_transaction_proof(&proof, &tx_hashes[..2], &header),
+            Err(TxProofError::InvalidProof(_))
+        ));
+
+        let fake_proof = TransactionProof {
+            witnesses_root: H256::default(),
+            ..proof
+        };
+        assert!(matches!(
+            verify_transaction_proof(&fake_proof, &[tx_hashes[3].clone()], &header),
+            Err(TxProofError::RootMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_verify_transaction_and_witness_proof() {
+        let block = build_block();
+        let header = block.header();
+        let tx = block.transaction(1).unwrap();
+        let proof = TransactionAndWitnessProof {
+            block_hash: header.hash().unpack(),
+            transactions_proof: json_proof(block.tx_hashes(), 1),
+            witnesses_proof: json_proof(block.tx_witness_hashes(), 1),
+        };
+        verify_transaction_and_witness_proof(&proof, &[tx.clone()], &header).unwrap();
+
+        let tampered = tx
+            .as_advanced_builder()
+            .set_witnesses(vec![Bytes::from(vec![9]).pack()])
+            .build();
+        assert!(matches!(
+            verify_transaction_and_witness_proof(&proof, &[tampered], &header),
+            Err(TxProofError::RootMismatch { .. })
+        ));
+
+        let other_header = build_block()
+            .as_advanced_builder()
+            .number(11u64.pack())
+            .build()
+            .header();
+        assert!(matches!(
+            verify_transaction_and_witness_proof(&proof, &[tx], &other_header),
+            Err(TxProofError::InvalidProof(_))
+        ));
+    }
+}
@@ -0,0 +1,383 @@
+//! BIP39 mnemonic and BIP32 hierarchical deterministic keys.
+//!
+//! CKB uses the BIP44 path `m/44'/309'/account'/change/index`, `change` is
+//! `0` for receiving (external) addresses and `1` for change addresses.
+
+use std::fmt;
+use std::str::FromStr;
+
+use ckb_types::H160;
+use hmac::{Hmac, Mac};
+use secp256k1::{PublicKey, Scalar, SecretKey};
+use sha2::Sha512;
+use thiserror::Error;
+
+use crate::{
+    util::{blake160, zeroize_privkey, zeroize_slice},
+    SECP256K1,
+};
+
+pub use bip39::{Language, Mnemonic};
+
+/// The coin type of CKB registered in SLIP-0044
+pub const CKB_COIN_TYPE: u32 = 309;
+
+const HARDENED_BIT: u32 = 0x8000_0000;
+
+#[derive(Error, Debug)]
+pub enum HdKeyError {
+    #[error("invalid mnemonic: `{0}`")]
+    InvalidMnemonic(String),
+
+    #[error("invalid derivation path: `{0}`")]
+    InvalidPath(String),
+
+    #[error("invalid key: `{0}`")]
+    InvalidKey(#[from] secp256k1::Error),
+
+    #[error("derive hardened child from public key")]
+    HardenedFromPublic,
+}
+
+/// A child number of derivation path
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
+pub enum ChildNumber {
+    Normal(u32),
+    Hardened(u32),
+}
+
+impl ChildNumber {
+    pub fn is_hardened(self) -> bool {
+        matches!(self, ChildNumber::Hardened(_))
+    }
+
+    /// The index with hardened bit
+    pub fn to_u32(self) -> u32 {
+        match self {
+            ChildNumber::Normal(index) => index,
+            ChildNumber::Hardened(index) => index | HARDENED_BIT,
+        }
+    }
+}
+
+impl From<u32> for ChildNumber {
+    fn from(value: u32) -> ChildNumber {
+        if value & HARDENED_BIT == 0 {
+            ChildNumber::Normal(value)
+        } else {
+            ChildNumber::Hardened(value ^ HARDENED_BIT)
+        }
+    }
+}
+
+impl fmt::Display for ChildNumber {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChildNumber::Normal(index) => write!(f, "{}", index),
+            ChildNumber::Hardened(index) => write!(f, "{}'", index),
+        }
+    }
+}
+
+/// The key chain in BIP44 path
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
+pub enum KeyChain {
+    /// Receiving addresses
+    External = 0,
+    /// Change addresses
+    Change = 1,
+}
+
+/// BIP32 derivation path, like `m/44'/309'/0'/0/0`
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct DerivationPath(Vec<ChildNumber>);
+
+impl DerivationPath {
+    pub fn new(path: Vec<ChildNumber>) -> DerivationPath {
+        DerivationPath(path)
+    }
+
+    /// `m/44'/309'/account'`
+    pub fn ckb_account(account: u32) -> DerivationPath {
+        DerivationPath(vec![
+            ChildNumber::Hardened(44),
+            ChildNumber::Hardened(CKB_COIN_TYPE),
+            ChildNumber::Hardened(account),
+        ])
+    }
+
+    /// `m/44'/309'/account'/change/index`
+    pub fn ckb(account: u32, chain: KeyChain, index: u32) -> DerivationPath {
+        DerivationPath::ckb_account(account)
+            .child(ChildNumber::Normal(chain as u32))
+            .child(ChildNumber::Normal(index))
+    }
+
+    pub fn child(&self, child: ChildNumber) -> DerivationPath {
+        let mut path = self.0.clone();
+        path.push(child);
+        DerivationPath(path)
+    }
+
+    pub fn as_slice(&self) -> &[ChildNumber] {
+        &self.0
+    }
+}
+
+impl FromStr for DerivationPath {
+    type Err = HdKeyError;
+
+    fn from_str(path: &str) -> Result<DerivationPath, HdKeyError> {
+        let mut parts = path.split('/');
+        if parts.next() != Some("m") {
+            return Err(HdKeyError::InvalidPath(path.to_string()));
+        }
+        let children = parts
+            .map(|part| {
+                let (index, hardened) = match part.strip_suffix(|c| c == '\'' || c == 'h') {
+                    Some(index) => (index, true),
+                    None => (part, false),
+                };
+                let index = index
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|index| index & HARDENED_BIT == 0)
+                    .ok_or_else(|| HdKeyError::InvalidPath(path.to_string()))?;
+                Ok(if hardened {
+                    ChildNumber::Hardened(index)
+                } else {
+                    ChildNumber::Normal(index)
+                })
+            })
+            .collect::<Result<Vec<_>, HdKeyError>>()?;
+        Ok(DerivationPath(children))
+    }
+}
+
+impl fmt::Display for DerivationPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "m")?;
+        for child in &self.0 {
+            write!(f, "/{}", child)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse a BIP39 english mnemonic and generate the 64 bytes seed.
+pub fn mnemonic_to_seed(phrase: &str, passphrase: &str) -> Result<[u8; 64], HdKeyError> {
+    let mnemonic = Mnemonic::parse_in(Language::English, phrase)
+        .map_err(|err| HdKeyError::InvalidMnemonic(err.to_string()))?;
+    Ok(mnemonic.to_seed(passphrase))
+}
+
+fn hmac_sha512(key: &[u8], data: &[&[u8]]) -> [u8; 64] {
+    let mut mac = Hmac::<Sha512>::new_from_slice(key).expect("hmac accepts any key length");
+    for item in data {
+        mac.update(item);
+    }
+    let mut output = [0u8; 64];
+    output.copy_from_slice(&mac.finalize().into_bytes());
+    output
+}
+
+fn split_hmac_output(output: &[u8; 64]) -> Result<(Scalar, [u8; 32]), HdKeyError> {
+    let mut tweak = [0u8; 32];
+    tweak.copy_from_slice(&output[0..32]);
+    let mut chain_code = [0u8; 32];
+    chain_code.copy_from_slice(&output[32..64]);
+    let tweak = Scalar::from_be_bytes(tweak).map_err(|_| secp256k1::Error::InvalidTweak)?;
+    Ok((tweak, chain_code))
+}
+
+/// BIP32 extended private key
+#[derive(Clone)]
+pub struct ExtendedPrivKey {
+    pub depth: u8,
+    pub child_number: ChildNumber,
+    pub chain_code: [u8; 32],
+    pub private_key: SecretKey,
+}
+
+impl ExtendedPrivKey {
+    /// Create the master key from seed
+    pub fn new_master(seed: &[u8]) -> Result<ExtendedPrivKey, HdKeyError> {
+        let mut output = hmac_sha512(b"Bitcoin seed", &[seed]);
+        let private_key = SecretKey::from_slice(&output[0..32]);
+        let mut chain_code = [0u8; 32];
+        chain_code.copy_from_slice(&output[32..64]);
+        zeroize_slice(&mut output);
+        Ok(ExtendedPrivKey {
+            depth: 0,
+            child_number: ChildNumber::Normal(0),
+            chain_code,
+            private_key: private_key?,
+        })
+    }
+
+    /// Create the master key from BIP39 english mnemonic
+    pub fn from_mnemonic(phrase: &str, passphrase: &str) -> Result<ExtendedPrivKey, HdKeyError> {
+        let mut seed = mnemonic_to_seed(phrase, passphrase)?;
+        let master = ExtendedPrivKey::new_master(&seed);
+        zeroize_slice(&mut seed);
+        master
+    }
+
+    pub fn derive_child(&self, child: ChildNumber) -> Result<ExtendedPrivKey, HdKeyError> {
+        let index = child.to_u32().to_be_bytes();
+        let mut output = if child.is_hardened() {
+            let mut key_data = self.private_key.secret_bytes();
+            let output = hmac_sha512(&self.chain_code, &[&[0u8], &key_data, &index]);
+            zeroize_slice(&mut key_data);
+            output
+        } else {
+            let public_key = PublicKey::from_secret_key(&SECP256K1, &self.private_key);
+            hmac_sha512(&self.chain_code, &[&public_key.serialize(), &index])
+        };
+        let result = split_hmac_output(&output);
+        zeroize_slice(&mut output);
+        let (tweak, chain_code) = result?;
+        Ok(ExtendedPrivKey {
+            depth: self.depth.wrapping_add(1),
+            child_number: child,
+            chain_code,
+            private_key: self.private_key.add_tweak(&tweak)?,
+        })
+    }
+
+    pub fn derive_path(&self, path: &DerivationPath) -> Result<ExtendedPrivKey, HdKeyError> {
+        path.as_slice()
+            .iter()
+            .try_fold(self.clone(), |key, child| key.derive_child(*child))
+    }
+
+    pub fn to_extended_pubkey(&self) -> ExtendedPubKey {
+        ExtendedPubKey {
+            depth: self.depth,
+            child_number: self.child_number,
+            chain_code: self.chain_code,
+            public_key: PublicKey::from_secret_key(&SECP256K1, &self.private_key),
+        }
+    }
+}
+
+impl Drop for ExtendedPrivKey {
+    fn drop(&mut self) {
+        zeroize_privkey(&mut self.private_key);
+        zeroize_slice(&mut self.chain_code);
+    }
+}
+
+impl fmt::Debug for ExtendedPrivKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ExtendedPrivKey")
+            .field("depth", &self.depth)
+            .field("child_number", &self.child_number)
+            .finish()
+    }
+}
+
+/// BIP32 extended public key
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ExtendedPubKey {
+    pub depth: u8,
+    pub child_number: ChildNumber,
+    pub chain_code: [u8; 32],
+    pub public_key: PublicKey,
+}
+
+impl ExtendedPubKey {
+    /// Only normal child can be derived from public key
+    pub fn derive_child(&self, child: ChildNumber) -> Result<ExtendedPubKey, HdKeyError> {
+        if child.is_hardened() {
+            return Err(HdKeyError::HardenedFromPublic);
+        }
+        let output = hmac_sha512(
+            &self.chain_code,
+            &[&self.public_key.serialize(), &child.to_u32().to_be_bytes()],
+        );
+        let (tweak, chain_code) = split_hmac_output(&output)?;
+        Ok(ExtendedPubKey {
+            depth: self.depth.wrapping_add(1),
+            child_number: child,
+            chain_code,
+            public_key: self.public_key.add_exp_tweak(&SECP256K1, &tweak)?,
+        })
+    }
+
+    pub fn derive_path(&self, path: &DerivationPath) -> Result<ExtendedPubKey, HdKeyError> {
+        path.as_slice()
+            .iter()
+            .try_fold(self.clone(), |key, child| key.derive_child(*child))
+    }
+
+    /// The blake160 of the compressed public key, which is the args of
+    /// secp256k1_blake160_sighash_all lock script.
+    pub fn pubkey_hash(&self) -> H160 {
+        blake160(&self.public_key.serialize())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_derivation_path() {
+        let path = DerivationPath::from_str("m/44'/309'/0'/1/2").unwrap();
+        assert_eq!(path, DerivationPath::ckb(0, KeyChain::Change, 2));
+        assert_eq!(path.to_string(), "m/44'/309'/0'/1/2");
+        assert_eq!(
+            DerivationPath::from_str("m/0h/1").unwrap().to_string(),
+            "m/0'/1"
+        );
+        assert!(DerivationPath::from_str("44'/309'").is_err());
+        assert!(DerivationPath::from_str("m/2147483648").is_err());
+    }
+
+    #[test]
+    fn test_bip39_seed() {
+        let phrase = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+        let seed = mnemonic_to_seed(phrase, "TREZOR").unwrap();
+        assert_eq!(
+            hex::encode(seed),
+            "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04"
+        );
+        assert!(mnemonic_to_seed("abandon abandon", "").is_err());
+    }
+
+    #[test]
+    fn test_bip32_vector1() {
+        let seed = [
+            0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d,
+            0x0e, 0x0f,
+        ];
+        let master = ExtendedPrivKey::new_master(&seed).unwrap();
+        assert_eq!(
+            hex::encode(master.private_key.secret_bytes()),
+            "e8f32e723decf4051aefac8e2c93c9c5b214313817cdb01a1494b917c8436b35"
+        );
+        assert_eq!(
+            hex::encode(master.chain_code),
+            "873dff81c02f525623fd1fe5167eac3a55a049de3d314bb42ee227ffed37d508"
+        );
+        let path = DerivationPath::from_str("m/0'/1").unwrap();
+        let child = master.derive_path(&path).unwrap();
+        assert_eq!(child.depth, 2);
+        assert_eq!(
+            hex::encode(child.private_key.secret_bytes()),
+            "3c6cb8d0f6a264c91ea8b5030fadaa8e538b020f0a387421a12de9319dc93368"
+        );
+
+        // public derivation matches private derivation
+        let parent = master
+            .derive_child(ChildNumber::Hardened(0))
+            .unwrap()
+            .to_extended_pubkey();
+        assert_eq!(
+            parent.derive_child(ChildNumber::Normal(1)).unwrap(),
+            child.to_extended_pubkey()
+        );
+        assert!(parent.derive_child(ChildNumber::Hardened(1)).is_err());
+    }
+}
@@ -1,9 +1,16 @@
 ///! Basic ckb sdk types
 mod address;
+#[allow(clippy::all)]
+pub mod cobuild;
+pub mod hd_key;
 mod human_capacity;
+#[allow(clippy::all)]
+pub mod ledger;
 mod network_type;
 #[allow(clippy::all)]
 pub mod omni_lock;
+#[allow(clippy::all)]
+pub mod partial_tx;
 mod script_group;
 mod script_id;
 mod since;
@@ -14,6 +21,9 @@ pub mod xudt_rce_mol;
 pub use address::{
     Address, AddressPayload, AddressType, CodeHashIndex, OldAddress, OldAddressFormat,
 };
+pub use hd_key::{
+    ChildNumber, DerivationPath, ExtendedPrivKey, ExtendedPubKey, HdKeyError, KeyChain,
+};
 pub use human_capacity::HumanCapacity;
 pub use network_type::{NetworkInfo, NetworkType};
 pub use script_group::{ScriptGroup, ScriptGroupType};
@@ -0,0 +1,317 @@
+//! Signing in the CKB transaction cobuild mode.
+//!
+//! The lock witness is a `WitnessLayout` instead of `WitnessArgs`. The
+//! transaction may have at most one `SighashAll` witness carrying the
+//! `Message`, other locks put their seal in `SighashAllOnly`.
+//!
+//! The signing message is `blake2b_256` of:
+//!   * `message` (only when a `SighashAll` witness exists)
+//!   * `tx_hash`
+//!   * `output | data_len (u32 LE) | data` of every input cell
+//!   * `witness_len (u32 LE) | witness` of every witness not covered by inputs
+//!
+//! With the personalization `ckb-tcob-sighash` when there is a message, and
+//! `ckb-tcob-sgohash` otherwise.
+
+use ckb_hash::{Blake2b, Blake2bBuilder};
+use ckb_types::{
+    bytes::Bytes,
+    core::TransactionView,
+    packed::{self, CellOutput, WitnessArgs},
+    prelude::*,
+};
+
+use super::signer::ScriptSignError;
+use crate::traits::{TransactionDependencyError, TransactionDependencyProvider};
+use crate::types::cobuild::{
+    Message, SighashAll, SighashAllOnly, WitnessLayout, WitnessLayoutReader, WitnessLayoutUnion,
+};
+use crate::types::ScriptGroup;
+
+/// The personalization of the signing message with a `Message`
+pub const SIGHASH_ALL_PERSONALIZATION: &[u8; 16] = b"ckb-tcob-sighash";
+/// The personalization of the signing message without a `Message`
+pub const SIGHASH_ALL_ONLY_PERSONALIZATION: &[u8; 16] = b"ckb-tcob-sgohash";
+
+fn new_cobuild_blake2b(personalization: &[u8; 16]) -> Blake2b {
+    Blake2bBuilder::new(32).personal(personalization).build()
+}
+
+/// Parse the witness as `WitnessLayout`, return `None` if it is not.
+pub fn parse_witness_layout(witness: &[u8]) -> Option<WitnessLayoutUnion> {
+    WitnessLayoutReader::verify(witness, false)
+        .ok()
+        .map(|_| WitnessLayout::new_unchecked(Bytes::copy_from_slice(witness)).to_enum())
+}
+
+/// Find the `Message` in the only `SighashAll` witness of the transaction.
+pub fn find_cobuild_message(
+    tx: &TransactionView,
+) -> Result<Option<(usize, Message)>, ScriptSignError> {
+    let mut found = None;
+    for (idx, witness) in tx.witnesses().into_iter().enumerate() {
+        if let Some(WitnessLayoutUnion::SighashAll(sighash_all)) =
+            parse_witness_layout(&witness.raw_data())
+        {
+            if found.is_some() {
+                return Err(ScriptSignError::InvalidCobuildWitness(format!(
+                    "more than one SighashAll witness, at index {}",
+                    idx
+                )));
+            }
+            found = Some((idx, sighash_all.message()));
+        }
+    }
+    Ok(found)
+}
+
+/// Resolve the input cells and their data in the order of the inputs.
+pub fn resolve_input_cells(
+    tx: &TransactionView,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+) -> Result<Vec<(CellOutput, Bytes)>, TransactionDependencyError> {
+    tx.inputs()
+        .into_iter()
+        .map(|input| {
+            let out_point = input.previous_output();
+            Ok((
+                tx_dep_provider.get_cell(&out_point)?,
+                tx_dep_provider.get_cell_data(&out_point)?,
+            ))
+        })
+        .collect()
+}
+
+/// Generate the cobuild signing message, `inputs` are the resolved input
+/// cells in the order of the inputs.
+pub fn generate_cobuild_message(
+    message: Option<&Message>,
+    tx: &TransactionView,
+    inputs: &[(CellOutput, Bytes)],
+) -> Result<Bytes, ScriptSignError> {
+    if inputs.len() != tx.inputs().len() {
+        return Err(ScriptSignError::Other(anyhow::anyhow!(
+            "resolved inputs count: {}, transaction inputs count: {}",
+            inputs.len(),
+            tx.inputs().len()
+        )));
+    }
+    let mut blake2b = match message {
+        Some(message) => {
+            let mut blake2b = new_cobuild_blake2b(SIGHASH_ALL_PERSONALIZATION);
+            blake2b.update(message.as_slice());
+            blake2b
+        }
+        None => new_cobuild_blake2b(SIGHASH_ALL_ONLY_PERSONALIZATION),
+    };
+    blake2b.update(tx.hash().as_slice());
+    for (output, data) in inputs {
+        blake2b.update(output.as_slice());
+        blake2b.update(&(data.len() as u32).to_le_bytes());
+        blake2b.update(data);
+    }
+    for witness in tx.witnesses().into_iter().skip(inputs.len()) {
+        let data = witness.raw_data();
+        blake2b.update(&(data.len() as u32).to_le_bytes());
+        blake2b.update(&data);
+    }
+    let mut hash = vec![0u8; 32];
+    blake2b.finalize(&mut hash);
+    Ok(Bytes::from(hash))
+}
+
+/// The seal in the first witness of the script group, if it is a
+/// `SighashAll` or `SighashAllOnly` witness.
+pub fn cobuild_seal(tx: &TransactionView, script_group: &ScriptGroup) -> Option<Bytes> {
+    let witness = tx.witnesses().get(script_group.input_indices[0])?;
+    match parse_witness_layout(&witness.raw_data())? {
+        WitnessLayoutUnion::SighashAll(sighash_all) => Some(sighash_all.seal().raw_data()),
+        WitnessLayoutUnion::SighashAllOnly(sighash_all_only) => {
+            Some(sighash_all_only.seal().raw_data())
+        }
+        _ => None,
+    }
+}
+
+/// A `WitnessArgs` witness is replaced by `WitnessLayout` only if it carries
+/// nothing but the lock, the type scripts put their data in the message
+/// actions in the cobuild mode.
+fn check_replaceable_witness(witness: &[u8], witness_idx: usize) -> Result<(), ScriptSignError> {
+    if witness.is_empty() || parse_witness_layout(witness).is_some() {
+        return Ok(());
+    }
+    let witness_args = WitnessArgs::from_slice(witness).map_err(|_| {
+        ScriptSignError::InvalidCobuildWitness(format!(
+            "witness at index {} is neither WitnessLayout nor WitnessArgs",
+            witness_idx
+        ))
+    })?;
+    if witness_args.input_type().is_some() || witness_args.output_type().is_some() {
+        return Err(ScriptSignError::InvalidCobuildWitness(format!(
+            "witness at index {} has input_type or output_type",
+            witness_idx
+        )));
+    }
+    Ok(())
+}
+
+/// Put the seal into the first witness of the script group. The seal replaces
+/// the one in a `SighashAll` witness, otherwise the witness is replaced by
+/// `SighashAllOnly`. A `WitnessArgs` witness with `input_type` or
+/// `output_type` is an error.
+pub fn fill_cobuild_seal(
+    tx: &TransactionView,
+    script_group: &ScriptGroup,
+    seal: Bytes,
+) -> Result<TransactionView, ScriptSignError> {
+    let witness_idx = script_group.input_indices[0];
+    let mut witnesses: Vec<packed::Bytes> = tx.witnesses().into_iter().collect();
+    while witnesses.len() <= witness_idx {
+        witnesses.push(Default::default());
+    }
+    check_replaceable_witness(&witnesses[witness_idx].raw_data(), witness_idx)?;
+    let layout = match parse_witness_layout(&witnesses[witness_idx].raw_data()) {
+        Some(WitnessLayoutUnion::SighashAll(sighash_all)) => {
+            let sighash_all = sighash_all.as_builder().seal(seal.pack()).build();
+            WitnessLayout::new_builder().set(sighash_all).build()
+        }
+        _ => {
+            let sighash_all_only = SighashAllOnly::new_builder().seal(seal.pack()).build();
+            WitnessLayout::new_builder().set(sighash_all_only).build()
+        }
+    };
+    witnesses[witness_idx] = layout.as_bytes().pack();
+    Ok(tx.as_advanced_builder().set_witnesses(witnesses).build())
+}
+
+/// Put the `Message` into the witness at `witness_idx` as `SighashAll`, the
+/// seal of the witness is kept if any. A `WitnessArgs` witness with
+/// `input_type` or `output_type` is an error.
+pub fn fill_cobuild_message(
+    tx: &TransactionView,
+    witness_idx: usize,
+    message: Message,
+) -> Result<TransactionView, ScriptSignError> {
+    if let Some((idx, _)) = find_cobuild_message(tx)? {
+        if idx != witness_idx {
+            return Err(ScriptSignError::InvalidCobuildWitness(format!(
+                "SighashAll witness already exists at index {}",
+                idx
+            )));
+        }
+    }
+    let mut witnesses: Vec<packed::Bytes> = tx.witnesses().into_iter().collect();
+    while witnesses.len() <= witness_idx {
+        witnesses.push(Default::default());
+    }
+    check_replaceable_witness(&witnesses[witness_idx].raw_data(), witness_idx)?;
+    let seal = match parse_witness_layout(&witnesses[witness_idx].raw_data()) {
+        Some(WitnessLayoutUnion::SighashAll(sighash_all)) => sighash_all.seal(),
+        Some(WitnessLayoutUnion::SighashAllOnly(sighash_all_only)) => sighash_all_only.seal(),
+        _ => Default::default(),
+    };
+    let sighash_all = SighashAll::new_builder()
+        .message(message)
+        .seal(seal)
+        .build();
+    witnesses[witness_idx] = WitnessLayout::new_builder()
+        .set(sighash_all)
+        .build()
+        .as_bytes()
+        .pack();
+    Ok(tx.as_advanced_builder().set_witnesses(witnesses).build())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ckb_types::{
+        core::{ScriptHashType, TransactionBuilder},
+        packed::{CellInput, OutPoint, Script},
+        H256,
+    };
+
+    use crate::types::{
+        cobuild::{Action, ActionVec},
+        ScriptGroupType,
+    };
+
+    #[test]
+    fn test_cobuild_witness() {
+        let script = Script::new_builder()
+            .hash_type(ScriptHashType::Type.into())
+            .build();
+        let mut script_group = ScriptGroup::new(&script, ScriptGroupType::Lock);
+        script_group.input_indices.push(1);
+        let tx = TransactionBuilder::default()
+            .input(CellInput::new(OutPoint::new(H256([1; 32]).pack(), 0), 0))
+            .input(CellInput::new(OutPoint::new(H256([1; 32]).pack(), 1), 0))
+            .build();
+        let inputs = vec![
+            (CellOutput::default(), Bytes::new()),
+            (CellOutput::default(), Bytes::from(vec![1u8])),
+        ];
+        assert!(find_cobuild_message(&tx).unwrap().is_none());
+        let hash_without_message = generate_cobuild_message(None, &tx, &inputs).unwrap();
+
+        let message = Message::new_builder()
+            .actions(
+                ActionVec::new_builder()
+                    .push(
+                        Action::new_builder()
+                            .script_hash(script.calc_script_hash())
+                            .data(Bytes::from(vec![2u8]).pack())
+                            .build(),
+                    )
+                    .build(),
+            )
+            .build();
+        let tx = fill_cobuild_message(&tx, 0, message.clone()).unwrap();
+        assert!(fill_cobuild_message(&tx, 1, message.clone()).is_err());
+        let (idx, found) = find_cobuild_message(&tx).unwrap().unwrap();
+        assert_eq!(idx, 0);
+        assert_eq!(found.as_slice(), message.as_slice());
+        assert_ne!(
+            generate_cobuild_message(Some(&found), &tx, &inputs).unwrap(),
+            hash_without_message
+        );
+        // the input witnesses are not committed
+        let hash = generate_cobuild_message(Some(&found), &tx, &inputs).unwrap();
+        let sealed_tx = fill_cobuild_seal(&tx, &script_group, Bytes::from(vec![3u8; 65])).unwrap();
+        assert_eq!(
+            generate_cobuild_message(Some(&found), &sealed_tx, &inputs).unwrap(),
+            hash
+        );
+        assert_eq!(
+            cobuild_seal(&sealed_tx, &script_group),
+            Some(Bytes::from(vec![3u8; 65]))
+        );
+        assert!(matches!(
+            parse_witness_layout(&sealed_tx.witnesses().get(1).unwrap().raw_data()),
+            Some(WitnessLayoutUnion::SighashAllOnly(_))
+        ));
+        assert!(parse_witness_layout(packed::WitnessArgs::default().as_slice()).is_none());
+
+        // the type script data in WitnessArgs is not dropped silently
+        let witness = WitnessArgs::new_builder()
+            .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+            .build();
+        let tx = sealed_tx
+            .as_advanced_builder()
+            .set_witnesses(vec![Default::default(), witness.as_bytes().pack()])
+            .build();
+        assert!(fill_cobuild_seal(&tx, &script_group, Bytes::from(vec![3u8; 65])).is_ok());
+        let witness = witness
+            .as_builder()
+            .input_type(Some(Bytes::from(vec![4u8])).pack())
+            .build();
+        let tx = tx
+            .as_advanced_builder()
+            .set_witnesses(vec![Default::default(), witness.as_bytes().pack()])
+            .build();
+        assert!(matches!(
+            fill_cobuild_seal(&tx, &script_group, Bytes::from(vec![3u8; 65])),
+            Err(ScriptSignError::InvalidCobuildWitness(_))
+        ));
+    }
+}
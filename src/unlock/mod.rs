@@ -1,3 +1,5 @@
+pub mod cobuild;
+mod multisig;
 pub(crate) mod omni_lock;
 pub mod rc_data;
 mod signer;
@@ -13,4 +15,5 @@ pub use unlocker::{
     ScriptUnlocker, SecpMultisigUnlocker, SecpSighashUnlocker, UnlockError,
 };
 
+pub use multisig::{MultisigInspector, MultisigLayout, MultisigStatus};
 pub use omni_lock::{IdentityFlag, InfoCellData, OmniLockAcpConfig, OmniLockConfig};
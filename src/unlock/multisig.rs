@@ -0,0 +1,315 @@
+use anyhow::anyhow;
+use ckb_types::{
+    bytes::Bytes,
+    core::TransactionView,
+    packed::{self, WitnessArgs},
+    prelude::*,
+    H160,
+};
+use secp256k1::ecdsa::{RecoverableSignature, RecoveryId};
+
+use super::{
+    omni_lock::{ConfigError, OmniLockConfig},
+    signer::{generate_message, MultisigConfig, OmniUnlockMode, ScriptSignError},
+};
+use crate::{types::omni_lock::OmniLockWitnessLock, util::blake160, ScriptGroup, SECP256K1};
+
+const SIGNATURE_SIZE: usize = 65;
+
+/// Where the multisig config and the signatures are placed in the witness.
+#[derive(Clone, Debug)]
+pub enum MultisigLayout {
+    /// `WitnessArgs.lock` is `multisig_config | signatures`
+    Secp256k1Blake160MultisigAll,
+    /// `WitnessArgs.lock` is an `OmniLockWitnessLock`, its `signature` field
+    /// is `multisig_config | signatures`
+    OmniLock {
+        config: OmniLockConfig,
+        unlock_mode: OmniUnlockMode,
+    },
+}
+
+/// The signing status of a multisig script group.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct MultisigStatus {
+    /// Valid signatures as `(index in sighash_addresses, signature)`, ordered by index
+    pub signatures: Vec<(usize, Bytes)>,
+    /// The signers not signed yet
+    pub missing_signers: Vec<H160>,
+    /// The signers in the first `require_first_n` not signed yet
+    pub missing_required_signers: Vec<H160>,
+    /// The signature slots can not be recovered to an unused signer
+    pub invalid_slots: Vec<usize>,
+    pub require_first_n: u8,
+    pub threshold: u8,
+}
+
+impl MultisigStatus {
+    pub fn valid_count(&self) -> usize {
+        self.signatures.len()
+    }
+
+    /// How many more signatures are needed to unlock the script group
+    pub fn remaining(&self) -> usize {
+        (self.threshold as usize)
+            .saturating_sub(self.valid_count())
+            .max(self.missing_required_signers.len())
+    }
+
+    /// The witness is able to unlock the script group
+    pub fn is_complete(&self) -> bool {
+        self.invalid_slots.is_empty() && self.remaining() == 0
+    }
+}
+
+/// Inspect and merge the multisig signatures in witness.
+///
+/// The signatures are verified the same way as the on-chain script: every
+/// signature must recover to a distinct signer in the multisig config, and
+/// the first `require_first_n` signers must all be present.
+#[derive(Clone, Debug)]
+pub struct MultisigInspector {
+    config: MultisigConfig,
+    layout: MultisigLayout,
+}
+
+impl MultisigInspector {
+    /// Inspector for `secp256k1_blake160_multisig_all` lock
+    pub fn new(config: MultisigConfig) -> MultisigInspector {
+        MultisigInspector {
+            config,
+            layout: MultisigLayout::Secp256k1Blake160MultisigAll,
+        }
+    }
+
+    /// Inspector for omnilock with multisig identity (normal mode) or multisig
+    /// admin identity (admin mode)
+    pub fn new_omnilock(
+        config: OmniLockConfig,
+        unlock_mode: OmniUnlockMode,
+    ) -> Result<MultisigInspector, ScriptSignError> {
+        let multisig_config = match unlock_mode {
+            OmniUnlockMode::Admin => config
+                .get_admin_config()
+                .ok_or(ConfigError::NoAdminConfig)?
+                .get_multisig_config(),
+            OmniUnlockMode::Normal => config.multisig_config(),
+        }
+        .ok_or(ConfigError::NoMultiSigConfig)?
+        .clone();
+        Ok(MultisigInspector {
+            config: multisig_config,
+            layout: MultisigLayout::OmniLock {
+                config,
+                unlock_mode,
+            },
+        })
+    }
+
+    pub fn config(&self) -> &MultisigConfig {
+        &self.config
+    }
+
+    pub fn layout(&self) -> &MultisigLayout {
+        &self.layout
+    }
+
+    fn multisig_data_len(&self) -> usize {
+        self.config.to_witness_data().len() + self.config.threshold() as usize * SIGNATURE_SIZE
+    }
+
+    fn zero_lock(&self) -> Result<Bytes, ScriptSignError> {
+        match &self.layout {
+            MultisigLayout::Secp256k1Blake160MultisigAll => {
+                let config_data = self.config.to_witness_data();
+                let mut zero_lock = vec![0u8; self.multisig_data_len()];
+                zero_lock[0..config_data.len()].copy_from_slice(&config_data);
+                Ok(Bytes::from(zero_lock))
+            }
+            MultisigLayout::OmniLock {
+                config,
+                unlock_mode,
+            } => Ok(config.zero_lock(*unlock_mode)?),
+        }
+    }
+
+    fn current_witness(
+        tx: &TransactionView,
+        script_group: &ScriptGroup,
+    ) -> Result<WitnessArgs, ScriptSignError> {
+        let witness_data = tx
+            .witnesses()
+            .get(script_group.input_indices[0])
+            .map(|witness| witness.raw_data())
+            .unwrap_or_default();
+        if witness_data.is_empty() {
+            Ok(WitnessArgs::default())
+        } else {
+            Ok(WitnessArgs::from_slice(witness_data.as_ref())?)
+        }
+    }
+
+    /// Extract `multisig_config | signatures` from the witness, return `None`
+    /// if the witness lock field is absent.
+    fn multisig_data(&self, witness: &WitnessArgs) -> Result<Option<Bytes>, ScriptSignError> {
+        let lock_field = match witness.lock().to_opt() {
+            Some(data) => data.raw_data(),
+            None => return Ok(None),
+        };
+        let data = match self.layout {
+            MultisigLayout::Secp256k1Blake160MultisigAll => lock_field,
+            MultisigLayout::OmniLock { .. } => {
+                let witness_lock = OmniLockWitnessLock::from_slice(lock_field.as_ref())?;
+                match witness_lock.signature().to_opt() {
+                    Some(data) => data.raw_data(),
+                    None => return Ok(None),
+                }
+            }
+        };
+        if data.len() != self.multisig_data_len() {
+            return Err(ScriptSignError::Other(anyhow!(
+                "invalid multisig witness length: {}, expected: {}",
+                data.len(),
+                self.multisig_data_len(),
+            )));
+        }
+        let config_data = self.config.to_witness_data();
+        if data[0..config_data.len()] != config_data[..] {
+            return Err(ScriptSignError::InvalidMultisigConfig(
+                "multisig config in witness not match".to_string(),
+            ));
+        }
+        Ok(Some(data))
+    }
+
+    /// Recover the signer index of a signature, return `None` if the signature
+    /// is invalid or not from any signer.
+    fn recover_signer(&self, message: &[u8], signature: &[u8]) -> Option<usize> {
+        let recid = RecoveryId::from_i32(i32::from(signature[SIGNATURE_SIZE - 1])).ok()?;
+        let sig =
+            RecoverableSignature::from_compact(&signature[0..SIGNATURE_SIZE - 1], recid).ok()?;
+        let msg = secp256k1::Message::from_slice(message).ok()?;
+        let pubkey = SECP256K1.recover_ecdsa(&msg, &sig).ok()?;
+        let hash = blake160(&pubkey.serialize());
+        self.config
+            .sighash_addresses()
+            .iter()
+            .position(|address| address == &hash)
+    }
+
+    /// Parse and verify the multisig signatures of the script group.
+    pub fn inspect(
+        &self,
+        tx: &TransactionView,
+        script_group: &ScriptGroup,
+    ) -> Result<MultisigStatus, ScriptSignError> {
+        let witness = Self::current_witness(tx, script_group)?;
+        let mut signed = vec![None; self.config.sighash_addresses().len()];
+        let mut invalid_slots = Vec::new();
+        if let Some(data) = self.multisig_data(&witness)? {
+            let message = generate_message(tx, script_group, self.zero_lock()?)?;
+            let config_len = self.config.to_witness_data().len();
+            for (slot, signature) in data[config_len..].chunks(SIGNATURE_SIZE).enumerate() {
+                if signature == [0u8; SIGNATURE_SIZE] {
+                    continue;
+                }
+                match self.recover_signer(message.as_ref(), signature) {
+                    Some(idx) if signed[idx].is_none() => {
+                        signed[idx] = Some(Bytes::copy_from_slice(signature));
+                    }
+                    _ => invalid_slots.push(slot),
+                }
+            }
+        }
+
+        let require_first_n = self.config.require_first_n() as usize;
+        let mut status = MultisigStatus {
+            invalid_slots,
+            require_first_n: self.config.require_first_n(),
+            threshold: self.config.threshold(),
+            ..Default::default()
+        };
+        for (idx, (address, signature)) in self
+            .config
+            .sighash_addresses()
+            .iter()
+            .zip(signed.into_iter())
+            .enumerate()
+        {
+            match signature {
+                Some(signature) => status.signatures.push((idx, signature)),
+                None => {
+                    if idx < require_first_n {
+                        status.missing_required_signers.push(address.clone());
+                    }
+                    status.missing_signers.push(address.clone());
+                }
+            }
+        }
+        Ok(status)
+    }
+
+    /// Merge the valid signatures of the script group from two copies of the
+    /// same transaction, the result is based on `tx`.
+    ///
+    /// Signatures of the first `require_first_n` signers are kept first, at
+    /// most `threshold` signatures are put into the witness.
+    pub fn merge(
+        &self,
+        tx: &TransactionView,
+        other: &TransactionView,
+        script_group: &ScriptGroup,
+    ) -> Result<TransactionView, ScriptSignError> {
+        if tx.hash() != other.hash() {
+            return Err(ScriptSignError::Other(anyhow!(
+                "transaction hash not match: {} != {}",
+                tx.hash(),
+                other.hash()
+            )));
+        }
+        let mut signatures = self.inspect(tx, script_group)?.signatures;
+        for (idx, signature) in self.inspect(other, script_group)?.signatures {
+            if signatures.iter().all(|(signed_idx, _)| *signed_idx != idx) {
+                signatures.push((idx, signature));
+            }
+        }
+        signatures.sort_by_key(|(idx, _)| *idx);
+        signatures.truncate(self.config.threshold() as usize);
+
+        let mut data = self.config.to_witness_data();
+        for (_, signature) in &signatures {
+            data.extend_from_slice(signature.as_ref());
+        }
+        data.resize(self.multisig_data_len(), 0);
+
+        let current_witness = Self::current_witness(tx, script_group)?;
+        let lock = match self.layout {
+            MultisigLayout::Secp256k1Blake160MultisigAll => Bytes::from(data),
+            MultisigLayout::OmniLock { .. } => {
+                let witness_lock = match current_witness.lock().to_opt() {
+                    Some(lock_field) => {
+                        OmniLockWitnessLock::from_slice(lock_field.raw_data().as_ref())?
+                    }
+                    None => OmniLockWitnessLock::default(),
+                };
+                witness_lock
+                    .as_builder()
+                    .signature(Some(Bytes::from(data)).pack())
+                    .build()
+                    .as_bytes()
+            }
+        };
+        let witness_idx = script_group.input_indices[0];
+        let mut witnesses: Vec<packed::Bytes> = tx.witnesses().into_iter().collect();
+        while witnesses.len() <= witness_idx {
+            witnesses.push(Default::default());
+        }
+        witnesses[witness_idx] = current_witness
+            .as_builder()
+            .lock(Some(lock).pack())
+            .build()
+            .as_bytes()
+            .pack();
+        Ok(tx.as_advanced_builder().set_witnesses(witnesses).build())
+    }
+}
@@ -419,6 +419,10 @@ pub struct OmniLockConfig {
     time_lock_config: Option<u64>,
     // 32 bytes type script hash
     info_cell: Option<H256>,
+    /// Sign in the transaction cobuild mode, the witness is `WitnessLayout`
+    /// instead of `WitnessArgs`.
+    #[serde(default)]
+    cobuild: bool,
 }
 
 impl OmniLockConfig {
@@ -442,6 +446,7 @@ impl OmniLockConfig {
             acp_config: None,
             time_lock_config: None,
             info_cell: None,
+            cobuild: false,
         }
     }
     /// Create an ethereum algorithm omnilock with pubkey
@@ -488,6 +493,7 @@ impl OmniLockConfig {
             acp_config: None,
             time_lock_config: None,
             info_cell: None,
+            cobuild: false,
         }
     }
 
@@ -539,6 +545,16 @@ impl OmniLockConfig {
         self.info_cell = None;
     }
 
+    /// Sign in the transaction cobuild mode or not, the lock script must
+    /// support cobuild.
+    pub fn set_cobuild(&mut self, cobuild: bool) {
+        self.cobuild = cobuild;
+    }
+
+    pub fn is_cobuild(&self) -> bool {
+        self.cobuild
+    }
+
     pub fn id(&self) -> &Identity {
         &self.id
     }
@@ -6,13 +6,16 @@ use ckb_types::{
     bytes::{Bytes, BytesMut},
     core::{ScriptHashType, TransactionView},
     error::VerificationError,
-    packed::{self, BytesOpt, Script, WitnessArgs},
+    packed::{self, BytesOpt, CellOutput, Script, WitnessArgs},
     prelude::*,
     H160,
 };
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use super::cobuild::{
+    cobuild_seal, fill_cobuild_seal, find_cobuild_message, generate_cobuild_message,
+};
 use crate::{constants::MULTISIG_TYPE_HASH, types::omni_lock::OmniLockWitnessLock};
 use crate::{
     traits::{Signer, SignerError},
@@ -51,6 +54,12 @@ pub enum ScriptSignError {
     #[error("there is an configuration error: `{0}`")]
     InvalidConfig(#[from] ConfigError),
 
+    #[error("invalid cobuild witness: `{0}`")]
+    InvalidCobuildWitness(String),
+
+    #[error("the Omni lock auth flag `{0:?}` is not supported")]
+    UnsupportedOmniLockAuth(IdentityFlag),
+
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
@@ -701,6 +710,117 @@ impl OmniLockScriptSigner {
             .build()
             .as_bytes())
     }
+
+    /// Sign the script group in the transaction cobuild mode, `inputs` are the
+    /// resolved input cells in the order of the inputs.
+    pub fn sign_tx_cobuild(
+        &self,
+        tx: &TransactionView,
+        script_group: &ScriptGroup,
+        inputs: &[(CellOutput, Bytes)],
+    ) -> Result<TransactionView, ScriptSignError> {
+        let message = find_cobuild_message(tx)?.map(|(_, message)| message);
+        let signing_message = generate_cobuild_message(message.as_ref(), tx, inputs)?;
+        let seal = cobuild_seal(tx, script_group);
+        match self.sign_cobuild_seal(signing_message.as_ref(), seal, tx)? {
+            Some(seal) => fill_cobuild_seal(tx, script_group, seal),
+            // Unlocked by the owner lock input
+            None => Ok(tx.clone()),
+        }
+    }
+
+    /// Sign the cobuild signing message (of `SighashAll` or `Otx`), update the
+    /// signature of the original seal. Return `None` if it is unlocked by the
+    /// owner lock input.
+    pub fn sign_cobuild_seal(
+        &self,
+        signing_message: &[u8],
+        seal: Option<Bytes>,
+        tx: &TransactionView,
+    ) -> Result<Option<Bytes>, ScriptSignError> {
+        let seal = seal
+            .map(|seal| {
+                if seal.is_empty() {
+                    Ok(OmniLockWitnessLock::default())
+                } else {
+                    OmniLockWitnessLock::from_slice(seal.as_ref())
+                }
+            })
+            .transpose()?
+            .unwrap_or_default();
+
+        let id = match self.unlock_mode {
+            OmniUnlockMode::Admin => self
+                .config
+                .get_admin_config()
+                .ok_or(ConfigError::NoAdminConfig)?
+                .get_auth()
+                .clone(),
+            OmniUnlockMode::Normal => self.config.id().clone(),
+        };
+        let seal = match id.flag() {
+            IdentityFlag::PubkeyHash => {
+                let signature =
+                    self.signer
+                        .sign(id.auth_content().as_ref(), signing_message, true, tx)?;
+                seal.as_builder().signature(Some(signature).pack()).build()
+            }
+            IdentityFlag::Ethereum => {
+                let signing_message = convert_keccak256_hash(signing_message);
+                let signature = self.signer.sign(
+                    id.auth_content().as_ref(),
+                    signing_message.as_ref(),
+                    true,
+                    tx,
+                )?;
+                seal.as_builder().signature(Some(signature).pack()).build()
+            }
+            IdentityFlag::Multisig => {
+                let multisig_config = match self.unlock_mode {
+                    OmniUnlockMode::Admin => self
+                        .config
+                        .get_admin_config()
+                        .ok_or(ConfigError::NoAdminConfig)?
+                        .get_multisig_config(),
+                    OmniUnlockMode::Normal => self.config.multisig_config(),
+                }
+                .ok_or(ConfigError::NoMultiSigConfig)?;
+                let config_data = multisig_config.to_witness_data();
+                let sig_len = config_data.len() + multisig_config.threshold() as usize * 65;
+                let mut omni_sig = seal
+                    .signature()
+                    .to_opt()
+                    .map(|data| data.raw_data().to_vec())
+                    .filter(|data| data.len() == sig_len)
+                    .unwrap_or_else(|| {
+                        let mut omni_sig = vec![0u8; sig_len];
+                        omni_sig[..config_data.len()].copy_from_slice(&config_data);
+                        omni_sig
+                    });
+                for id in multisig_config
+                    .sighash_addresses
+                    .iter()
+                    .filter(|id| self.signer.match_id(id.as_bytes()))
+                {
+                    let signature = self.signer.sign(id.as_bytes(), signing_message, true, tx)?;
+                    let slot = (config_data.len()..omni_sig.len())
+                        .step_by(65)
+                        .find(|idx| {
+                            omni_sig[*idx..*idx + 65] == signature
+                                || omni_sig[*idx..*idx + 65] == [0u8; 65]
+                        })
+                        .ok_or(ScriptSignError::TooManySignatures)?;
+                    omni_sig[slot..slot + 65].copy_from_slice(signature.as_ref());
+                }
+                seal.as_builder()
+                    .signature(Some(Bytes::from(omni_sig)).pack())
+                    .build()
+            }
+            IdentityFlag::OwnerLock => return Ok(None),
+            flag => return Err(ScriptSignError::UnsupportedOmniLockAuth(flag)),
+        };
+        Ok(Some(seal.as_bytes()))
+    }
 }
 
 impl ScriptSigner for OmniLockScriptSigner {
@@ -753,7 +873,8 @@ impl ScriptSigner for OmniLockScriptSigner {
                 // should not reach here, return true for compatible reason
                 true
             }
-            _ => todo!("other auth type not supported yet"),
+            // The Exec and Dl modes delegate the verification to other scripts
+            _ => false,
         }
     }
 
@@ -810,9 +931,7 @@ impl ScriptSigner for OmniLockScriptSigner {
                 // should not reach here, just return a clone for compatible reason.
                 Ok(tx.clone())
             }
-            _ => {
-                todo!("not supported yet");
-            }
+            flag => Err(ScriptSignError::UnsupportedOmniLockAuth(flag)),
         }
     }
 }
@@ -8,6 +8,7 @@ use ckb_types::{
 use thiserror::Error;
 
 use super::{
+    cobuild::{fill_cobuild_seal, resolve_input_cells},
     omni_lock::{ConfigError, OmniLockFlags},
     signer::{
         AcpScriptSigner, ChequeAction, ChequeScriptSigner, MultisigConfig, ScriptSignError,
@@ -707,9 +708,14 @@ impl ScriptUnlocker for OmniLockUnlocker {
         &self,
         tx: &TransactionView,
         script_group: &ScriptGroup,
-        _tx_dep_provider: &dyn TransactionDependencyProvider,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
     ) -> Result<TransactionView, UnlockError> {
-        Ok(self.signer.sign_tx(tx, script_group)?)
+        if self.signer.config().is_cobuild() {
+            let inputs = resolve_input_cells(tx, tx_dep_provider)?;
+            Ok(self.signer.sign_tx_cobuild(tx, script_group, &inputs)?)
+        } else {
+            Ok(self.signer.sign_tx(tx, script_group)?)
+        }
     }
 
     fn fill_placeholder_witness(
@@ -720,7 +726,11 @@ impl ScriptUnlocker for OmniLockUnlocker {
     ) -> Result<TransactionView, UnlockError> {
         let config = self.signer.config();
         let lock_field = config.placeholder_witness_lock(self.signer.unlock_mode())?;
-        fill_witness_lock(tx, script_group, lock_field)
+        if config.is_cobuild() {
+            Ok(fill_cobuild_seal(tx, script_group, lock_field)?)
+        } else {
+            fill_witness_lock(tx, script_group, lock_field)
+        }
     }
 }
 #[cfg(test)]